@@ -0,0 +1,49 @@
+//! Measures writing pathologically long single lines, such as minified JSON.
+//!
+//! Run with `cargo bench --bench long_lines`.
+
+use readterm::scroll_buffer::{ScrollBuffer, Settings, TimestampGutter};
+use std::time::{Duration, Instant};
+
+const LINE_LENGTH: usize = 1_000_000;
+const RUNS: u32 = 5;
+
+fn new_buffer() -> ScrollBuffer {
+    ScrollBuffer::new(Settings {
+        max_columns: 120,
+        max_lines: 40,
+        lines_to_remember: 10_000,
+        tab_width: 8,
+        record_timestamps: true,
+        timestamp_gutter: TimestampGutter::Hidden,
+    })
+}
+
+/// Times `f` over a fresh buffer for each run.
+fn measure(f: impl Fn(&mut ScrollBuffer)) -> Duration {
+    let mut total = Duration::from_secs(0);
+
+    for _ in 0..RUNS {
+        let mut buffer = new_buffer();
+        let start = Instant::now();
+        f(&mut buffer);
+        total += start.elapsed();
+    }
+
+    total / RUNS
+}
+
+fn main() {
+    let line: String = r#"{"key":[1,2,3],"value":"lorem ipsum"},"#.chars().cycle().take(LINE_LENGTH).collect();
+
+    let by_run = measure(|buffer| buffer.put_str(&line));
+    let by_character = measure(|buffer| {
+        for c in line.chars() {
+            buffer.put_character(c);
+        }
+    });
+
+    println!("{} character line, mean of {} runs", LINE_LENGTH, RUNS);
+    println!("put_str:                 {:?}", by_run);
+    println!("put_character each char: {:?}", by_character);
+}
@@ -0,0 +1,59 @@
+//! Measures how long rewrapping the scrollback takes after a resize.
+//!
+//! Run with `cargo bench --bench reflow`.
+
+use readterm::scroll_buffer::{ScrollBuffer, Settings, TimestampGutter};
+use std::time::{Duration, Instant};
+
+const SCROLLBACK_LINES: usize = 10_000;
+const BATCH_LINES: usize = 2_000;
+const RUNS: u32 = 10;
+
+fn filled_buffer() -> ScrollBuffer {
+    let mut buffer = ScrollBuffer::new(Settings {
+        max_columns: 120,
+        max_lines: 40,
+        lines_to_remember: SCROLLBACK_LINES,
+        tab_width: 8,
+        record_timestamps: false,
+        timestamp_gutter: TimestampGutter::Hidden,
+    });
+
+    for i in 0..SCROLLBACK_LINES {
+        let words = "lorem ipsum dolor sit amet ".repeat(1 + i % 6);
+        buffer.put_str(&format!("{} {}\n", i, words));
+    }
+
+    buffer
+}
+
+/// Times `f` over a fresh buffer for each run.
+fn measure(f: impl Fn(&mut ScrollBuffer)) -> Duration {
+    let mut total = Duration::from_secs(0);
+
+    for _ in 0..RUNS {
+        let mut buffer = filled_buffer();
+        let start = Instant::now();
+        f(&mut buffer);
+        total += start.elapsed();
+    }
+
+    total / RUNS
+}
+
+fn main() {
+    let resize = measure(|buffer| buffer.resize(80, 40));
+    let full = measure(|buffer| {
+        buffer.resize(80, 40);
+        while buffer.continue_reflow(BATCH_LINES).is_some() {}
+    });
+    let widen = measure(|buffer| {
+        buffer.resize(200, 40);
+        while buffer.continue_reflow(BATCH_LINES).is_some() {}
+    });
+
+    println!("{} scrollback lines, mean of {} runs", SCROLLBACK_LINES, RUNS);
+    println!("resize (visible lines only): {:?}", resize);
+    println!("resize narrower + full reflow: {:?}", full);
+    println!("resize wider + full reflow:    {:?}", widen);
+}
@@ -5,7 +5,7 @@ fn main() {
 
     let mut terminal = Terminal::new(Settings::default()).unwrap();
 
-    terminal.send_raw("echo 'foo'\n");
+    terminal.send_raw("echo 'foo'\n").unwrap();
     dump_events(&mut terminal);
 
 }
@@ -21,7 +21,7 @@ fn wait_for_events(terminal: &mut Terminal) -> Vec<readterm::Event> {
 
     // wait forever for first event
     loop {
-        let new_events = terminal.update();
+        let new_events = terminal.update().unwrap();
 
         if !new_events.is_empty() {
             events.extend(new_events);
@@ -31,7 +31,7 @@ fn wait_for_events(terminal: &mut Terminal) -> Vec<readterm::Event> {
 
     // keep listening to events until they stop.
     loop {
-        let new_events = terminal.update();
+        let new_events = terminal.update().unwrap();
 
         if !new_events.is_empty() {
             events.extend(new_events);
@@ -0,0 +1,53 @@
+//! Converts colored output piped to stdin into HTML.
+//!
+//! Run with `cargo build --color=always 2>&1 | cargo run --example pipeline > build.html`.
+
+use readterm::{Color, TextSlice};
+use std::io::{self, Read};
+
+const COLUMNS: usize = 120;
+const LINES: usize = 50;
+
+fn main() -> io::Result<()> {
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input)?;
+
+    println!("<pre>");
+    for slice in readterm::render_ansi(&input, COLUMNS, LINES) {
+        print!("{}", to_html(&slice));
+    }
+    println!("</pre>");
+    Ok(())
+}
+
+fn to_html(slice: &TextSlice) -> String {
+    let text = slice.text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let style = &slice.style;
+
+    let mut css = format!("color: {};", to_css(style.color));
+    if style.background != Color::TRANSPARENT {
+        css += &format!(" background: {};", to_css(style.background));
+    }
+    if style.bold {
+        css += " font-weight: bold;";
+    }
+    if style.italic {
+        css += " font-style: italic;";
+    }
+
+    let decorations: Vec<&str> = [
+        (style.underlined, "underline"),
+        (style.strikethrough, "line-through"),
+        (style.overline, "overline"),
+    ].iter().filter(|&&(enabled, _)| enabled).map(|&(_, decoration)| decoration).collect();
+    if !decorations.is_empty() {
+        css += &format!(" text-decoration: {};", decorations.join(" "));
+    }
+
+    format!("<span style=\"{}\">{}</span>", css, text)
+}
+
+fn to_css(color: Color) -> String {
+    let byte = |component: f32| (component * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", byte(color.red), byte(color.green), byte(color.blue))
+}
@@ -0,0 +1,167 @@
+//! Running commands to completion, for using the terminal as a shell-automation library.
+
+use crate::event::Event;
+use std::time::Duration;
+
+/// Options for `Terminal::run_command`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunOptions {
+    /// How long to wait for the command to finish.
+    pub timeout: Duration,
+    /// How long to sleep between updates while waiting for output.
+    pub poll_interval: Duration,
+}
+
+/// The result of a command run with `Terminal::run_command`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// Everything the command wrote to the terminal, with lines separated by `\n`.
+    pub output: String,
+    /// The exit code of the command, as given by `$?`.
+    pub exit_code: i32,
+}
+
+/// Reconstructs text from the characters put on the grid.
+#[derive(Clone, Debug, Default)]
+pub struct OutputCapture {
+    text: String,
+    /// The position of the last character, for drivers that report positions.
+    last_position: Option<(usize, usize)>,
+}
+
+/// The sentinels that frame the output of a command.
+#[derive(Clone, Debug)]
+pub struct Sentinel {
+    start: String,
+    end: String,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            timeout: Duration::from_secs(30),
+            poll_interval: Duration::from_millis(5),
+        }
+    }
+}
+
+impl OutputCapture {
+    /// Records the characters in a batch of events.
+    pub fn observe(&mut self, events: &[Event]) {
+        for event in events {
+            if let Event::PutCharacter { x, y, character, .. } = *event {
+                match character {
+                    '\r' => continue,
+                    '\n' => {
+                        self.text.push('\n');
+                        self.last_position = None;
+                        continue;
+                    },
+                    _ => (),
+                }
+
+                // Drivers that position characters do not send line feeds, so a move
+                // down, or back to the left as when scrolling, starts a new line.
+                if let Some((last_x, last_y)) = self.last_position {
+                    if y > last_y {
+                        self.text.extend((last_y..y).map(|_| '\n'));
+                    } else if y < last_y || x <= last_x {
+                        self.text.push('\n');
+                    }
+                }
+
+                self.text.push(character);
+                self.last_position = Some((x, y));
+            }
+        }
+    }
+
+    /// Gets the text captured so far.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Sentinel {
+    /// Creates the sentinels for a command with a unique id.
+    pub fn new(id: u64) -> Self {
+        Sentinel {
+            start: format!("__readterm_start_{}", id),
+            end: format!("__readterm_end_{}", id),
+        }
+    }
+
+    /// Wraps a command so that its output is framed by the sentinels.
+    ///
+    /// The sentinels are printed in pieces so that the echo of the
+    /// command line itself never matches. Everything is on one line, as
+    /// the echo of any later line could land in the middle of the output.
+    pub fn wrap(&self, command: &str) -> String {
+        let (start_name, id) = self.start.split_at(self.start.rfind('_').unwrap());
+        let end_name = &self.end[..self.end.len() - id.len()];
+
+        format!("printf '%s%s\\n' {} {}; {}; printf '\\n%s%s:%d\\n' {} {} \"$?\"\n",
+                start_name, id, command, end_name, id)
+    }
+
+    /// Finds the output and exit code of the command, once it has finished.
+    pub fn find(&self, captured: &str) -> Option<CommandOutput> {
+        let start = captured.find(&format!("{}\n", self.start))? + self.start.len() + 1;
+        let end = start + captured[start..].find(&format!("\n{}:", self.end))?;
+
+        let status = &captured[end + self.end.len() + 2..];
+        let status = &status[..status.find('\n')?];
+
+        Some(CommandOutput {
+            output: captured[start..end].to_owned(),
+            exit_code: status.trim().parse().ok()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Color;
+
+    fn put(x: usize, y: usize, character: char) -> Event {
+        Event::PutCharacter {
+            x, y, character,
+            bold: false,
+            italic: false,
+            underlined: false,
+            strikethrough: false,
+            overline: false,
+            color: Color::WHITE,
+            background: Color::TRANSPARENT,
+        }
+    }
+
+    #[test]
+    fn wraps_commands_without_echoing_the_sentinels() {
+        let sentinel = Sentinel::new(7);
+        let wrapped = sentinel.wrap("ls");
+
+        assert_eq!("printf '%s%s\\n' __readterm_start _7; ls; printf '\\n%s%s:%d\\n' __readterm_end _7 \"$?\"\n",
+                   wrapped);
+        assert!(!wrapped.contains("__readterm_start_7"));
+    }
+
+    #[test]
+    fn finds_framed_output() {
+        let sentinel = Sentinel::new(1);
+
+        assert_eq!(None, sentinel.find("$ echo\n__readterm_start_1\nhi\n"));
+        assert_eq!(Some(CommandOutput { output: "hi\n".to_owned(), exit_code: 3 }),
+                   sentinel.find("$ echo\n__readterm_start_1\nhi\n\n__readterm_end_1:3\n$ "));
+    }
+
+    #[test]
+    fn captures_positioned_characters_as_lines() {
+        let mut capture = OutputCapture::default();
+        capture.observe(&[put(0, 0, 'a'), put(1, 0, 'b'), put(0, 2, 'c'), put(0, 2, 'd')]);
+        capture.observe(&[put(0, 0, 'e'), put(0, 0, '\r'), put(0, 0, '\n'), put(0, 0, 'f')]);
+
+        assert_eq!("ab\n\nc\nd\ne\nf", capture.text());
+    }
+}
@@ -1,5 +1,23 @@
+#[allow(unused_imports)]
+use crate::std_prelude::*;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+/// The 16 ANSI colors, as xterm draws them.
+const ANSI_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+/// The levels of each channel in the 6x6x6 color cube.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
 /// A color.
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Color {
     pub red: f32,
     pub green: f32,
@@ -7,11 +25,49 @@ pub struct Color {
     pub alpha: f32,
 }
 
+/// The colors that running programs select by number, such as with
+/// SGR 31, so that output can be drawn in a theme like Solarized.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Palette {
+    /// Black, red, green, yellow, blue, magenta, cyan and white (SGR 30 to
+    /// 37), then their bright versions (SGR 90 to 97).
+    pub ansi: [Color; 16],
+    /// The colors from 16 to 255 that differ from the ones
+    /// `Color::from_ansi_256` gives, by index.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extended: BTreeMap<u8, Color>,
+    /// The color of text in the default color (SGR 39).
+    pub foreground: Color,
+    /// The color behind text in the default background (SGR 49).
+    ///
+    /// `Color::TRANSPARENT` leaves it to the frontend, as for blank cells.
+    pub background: Color,
+}
+
 /// A style.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Style {
     /// The color of the text.
     pub color: Color,
+    /// The color behind the text.
+    ///
+    /// `Color::TRANSPARENT` means the default background, left to the frontend.
+    pub background: Color,
+    /// Whether the text is bold.
+    pub bold: bool,
+    /// Whether the text is italic.
+    pub italic: bool,
+    /// Whether the text is underlined.
+    pub underlined: bool,
+    /// Whether the text is struck through.
+    pub strikethrough: bool,
+    /// Whether the text has a line over it.
+    pub overline: bool,
+    /// Whether the text is concealed (SGR 8), as for some password prompts.
+    ///
+    /// Concealed cells keep their characters, but are blank in slices.
+    pub concealed: bool,
 }
 
 impl Color {
@@ -20,6 +76,7 @@ impl Color {
     pub const BLUE: Color = Color { red: 0.0, green: 0.0, blue: 1.0, alpha: 1.0 };
     pub const BLACK: Color = Color { red: 0.0, green: 0.0, blue: 0.0, alpha: 1.0 };
     pub const WHITE: Color = Color { red: 1.0, green: 1.0, blue: 1.0, alpha: 1.0 };
+    pub const TRANSPARENT: Color = Color { red: 0.0, green: 0.0, blue: 0.0, alpha: 0.0 };
 
     pub fn from_packed_argb8(color: u32) -> Self {
         let alpha = (color & 0xff000000) >> 24;
@@ -41,4 +98,143 @@ impl Color {
             alpha: alpha as f32 / 255.0,
         }
     }
+
+    /// Gets a color from the 256 color palette, as xterm draws it.
+    ///
+    /// The first 16 are the ANSI colors, then a 6x6x6 color cube, then 24
+    /// shades of gray. These are what SGR `38;5;N` and `48;5;N` select.
+    pub fn from_ansi_256(index: u8) -> Self {
+        let (red, green, blue) = match index {
+            0..=15 => ANSI_COLORS[index as usize],
+            16..=231 => {
+                let index = (index - 16) as usize;
+                (CUBE_LEVELS[index / 36], CUBE_LEVELS[index / 6 % 6], CUBE_LEVELS[index % 6])
+            },
+            _ => {
+                let level = 8 + 10 * (index - 232);
+                (level, level, level)
+            },
+        };
+
+        Color::from_rgb8(red, green, blue)
+    }
+
+    /// Parses an X11 color specification, as used in OSC color sequences.
+    ///
+    /// Supports `rgb:r/g/b` with one to four hex digits per component,
+    /// and `#rgb`, `#rrggbb`, `#rrrgggbbb` and `#rrrrggggbbbb`.
+    pub fn parse_x11(spec: &str) -> Option<Self> {
+        // The spec comes from the running program, and is sliced by bytes below.
+        if !spec.is_ascii() {
+            return None;
+        }
+
+        let components: Vec<&str> = if let Some(rgb) = spec.strip_prefix("rgb:") {
+            rgb.split('/').collect()
+        } else if let Some(hex) = spec.strip_prefix('#').filter(|hex| !hex.is_empty() && hex.len() % 3 == 0) {
+            let width = hex.len() / 3;
+            (0..3).map(|i| &hex[i * width..(i + 1) * width]).collect()
+        } else {
+            return None;
+        };
+
+        if components.len() != 3 {
+            return None;
+        }
+
+        let mut channels = [0.0; 3];
+        for (channel, component) in channels.iter_mut().zip(components) {
+            if component.is_empty() || component.len() > 4 {
+                return None;
+            }
+
+            let value = u16::from_str_radix(component, 16).ok()?;
+            let max = (1u32 << (4 * component.len())) - 1;
+            *channel = value as f32 / max as f32;
+        }
+
+        Some(Color { red: channels[0], green: channels[1], blue: channels[2], alpha: 1.0 })
+    }
+
+    /// Formats the color as an X11 `rgb:rrrr/gggg/bbbb` specification,
+    /// as used in replies to OSC color queries.
+    pub fn to_x11(&self) -> String {
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 65535.0 + 0.5) as u16;
+
+        format!("rgb:{:04x}/{:04x}/{:04x}", channel(self.red), channel(self.green), channel(self.blue))
+    }
+}
+
+impl Palette {
+    /// Gets a color from the 256 color palette, with the ANSI colors taken
+    /// from this palette.
+    pub fn indexed(&self, index: u8) -> Color {
+        match self.ansi.get(index as usize) {
+            Some(&color) => color,
+            None => self.extended.get(&index).copied().unwrap_or_else(|| Color::from_ansi_256(index)),
+        }
+    }
+
+    /// Sets a color of the 256 color palette, as OSC 4 does.
+    pub fn set_indexed(&mut self, index: u8, color: Color) {
+        match self.ansi.get_mut(index as usize) {
+            Some(entry) => *entry = color,
+            None if color == Color::from_ansi_256(index) => {
+                self.extended.remove(&index);
+            },
+            None => {
+                self.extended.insert(index, color);
+            },
+        }
+    }
+}
+
+impl Default for Palette {
+    /// The colors xterm draws with, on the frontend's background.
+    fn default() -> Self {
+        let mut ansi = [Color::TRANSPARENT; 16];
+        for (index, color) in ansi.iter_mut().enumerate() {
+            *color = Color::from_ansi_256(index as u8);
+        }
+
+        Palette { ansi, extended: BTreeMap::new(), foreground: Color::WHITE, background: Color::TRANSPARENT }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn maps_the_256_color_palette() {
+        assert_eq!(Color::from_rgb8(205, 0, 0), Color::from_ansi_256(1));
+        assert_eq!(Color::from_rgb8(255, 255, 255), Color::from_ansi_256(15));
+        assert_eq!(Color::from_rgb8(0, 0, 0), Color::from_ansi_256(16));
+        assert_eq!(Color::from_rgb8(95, 135, 175), Color::from_ansi_256(16 + 36 + 2 * 6 + 3));
+        assert_eq!(Color::from_rgb8(255, 255, 255), Color::from_ansi_256(231));
+        assert_eq!(Color::from_rgb8(8, 8, 8), Color::from_ansi_256(232));
+        assert_eq!(Color::from_rgb8(238, 238, 238), Color::from_ansi_256(255));
+    }
+
+    #[test]
+    fn parses_x11_specs() {
+        assert_eq!(Some(Color::from_rgb8(255, 0, 0x80)), Color::parse_x11("#ff0080"));
+        assert_eq!(Some(Color::from_rgb8(255, 0, 0x80)), Color::parse_x11("rgb:ff/0/8080"));
+        assert_eq!(None, Color::parse_x11("#a\u{e9}\u{e9}e"));
+        assert_eq!(None, Color::parse_x11("rgb:\u{e9}/0/0"));
+        assert_eq!(None, Color::parse_x11("#ff00"));
+    }
+
+    #[test]
+    fn sets_colors_past_the_ansi_ones() {
+        let mut palette = Palette::default();
+        palette.set_indexed(1, Color::GREEN);
+        palette.set_indexed(200, Color::GREEN);
+        assert_eq!(Color::GREEN, palette.indexed(1));
+        assert_eq!(Color::GREEN, palette.indexed(200));
+        assert_eq!(Color::from_ansi_256(201), palette.indexed(201));
+
+        palette.set_indexed(200, Color::from_ansi_256(200));
+        assert!(palette.extended.is_empty());
+    }
 }
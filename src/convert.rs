@@ -0,0 +1,76 @@
+//! Converting captured output into styled text, without a running program.
+
+use crate::{
+    core::{Settings, Terminal},
+    scroll_buffer::SliceOptions,
+    Color, TextSlice,
+};
+
+/// Lays out output that may contain escape sequences, such as the colored
+/// output of a compiler, on a grid, and gets it back as styled slices.
+///
+/// The grid is `columns` wide and `lines` high, which matters to programs
+/// that move the cursor. Every line is kept, including those scrolled off
+/// the grid. Each line ends with a newline slice, trailing blank lines are
+/// dropped, and unstyled spaces at the end of each line are trimmed.
+pub fn render_ansi(bytes: &[u8], columns: usize, lines: usize) -> Vec<TextSlice> {
+    let mut terminal = Terminal::headless(Settings {
+        column_count: columns,
+        line_count: lines,
+        // Each line in the scrollback took at least one byte.
+        lines_to_remember: bytes.len(),
+        ..Settings::default()
+    });
+    terminal.feed(bytes).expect("headless terminals accept output");
+
+    let line_count = terminal.scrollback_line_count() + lines;
+    let used_line_count = terminal.iter_lines(0..line_count)
+        .filter(|line| line.cells.iter().any(|cell| cell.character != ' '))
+        .last()
+        .map_or(0, |line| line.id + 1);
+
+    let mut slices = terminal.line_slices(0..used_line_count, &SliceOptions::default());
+    trim_line_ends(&mut slices);
+    slices
+}
+
+/// Trims the unstyled spaces that pad each line to the width of the grid.
+fn trim_line_ends(slices: &mut Vec<TextSlice>) {
+    let mut index = 0;
+
+    while index + 1 < slices.len() {
+        let ends_line = slices[index + 1].text == "\n";
+        let slice = &mut slices[index];
+        let style = &slice.style;
+        let spaces_are_blank = style.background == Color::TRANSPARENT && !style.underlined && !style.strikethrough && !style.overline;
+
+        if ends_line && slice.text != "\n" && spaces_are_blank {
+            let trimmed_length = slice.text.trim_end_matches(' ').len();
+            slice.text.truncate(trimmed_length);
+
+            // The slice before may now end the line too.
+            if slice.text.is_empty() {
+                slices.remove(index);
+                index = index.saturating_sub(1);
+                continue;
+            }
+        }
+
+        index += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_colored_output_as_slices() {
+        let slices = render_ansi(b"\x1b[31merror\x1b[0m: oops\r\n\nok\r\n", 20, 2);
+        let text: String = slices.iter().map(|slice| slice.text.as_str()).collect();
+
+        assert_eq!("error: oops\n\nok\n", text);
+        assert_eq!("error", slices[0].text);
+        assert!(slices[1..].iter().all(|slice| slice.style.color != slices[0].style.color));
+    }
+}
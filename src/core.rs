@@ -1,29 +1,163 @@
 use crate::{
-    TextSlice, Style,
-    event::Event,
-    os::Driver as _,
-    scroll_buffer::{self, ScrollBuffer},
+    CellClass, Color, Palette, TextSlice, Style,
+    automation::{CommandOutput, OutputCapture, RunOptions, Sentinel},
+    cursor::{CursorPresence, CursorShape, CursorState},
+    debug::DebugState,
+    encode::{self, Key, KeyEvent, Modifiers, MouseAction, MouseButton},
+    event::{BellVolume, DynamicColor, Event, ExitStatus, ShellMark, UploadProgress},
+    latency::{LatencyProbes, LatencySample},
+    modes::{Mode, ModeState},
+    parser::StringLimits,
+    profile::{Profile, Theme},
+    os::{self, BinaryOutputPolicy, C1Controls, Driver, FlowControl, LineEndings},
+    search::Search,
+    upload::{self, Upload},
+    scroll_buffer::{self, Cell, CellContext, CopyResult, EncodedLine, Link, ShellCommand, SliceOptions, LineMetadata, LineView, LogicalPosition, MemoryFootprint, ScrollBuffer, TextMatch, Thumbnail, TimestampGutter, VisualPosition},
 };
-use std::{env, io};
+#[cfg(feature = "scripting")]
+use crate::script::{Script, ScriptAction, ScriptError};
+use std::{env, fs::File, io::{self, Read}, mem, ops::Range, path::Path, sync::{Mutex, MutexGuard, PoisonError}, thread};
+use std::time::{Duration, Instant};
 
-use crate::os::current::Driver as Driver;
+/// The DEC private modes that switch to the alternate screen, which the parser tracks.
+const ALTERNATE_SCREEN_MODES: &[usize] = &[47, 1047, 1049];
+/// The number of scrollback lines rewrapped per update after a resize.
+const REFLOW_BATCH_LINES: usize = 2_000;
+/// The longest `wait` sleeps for output at a time.
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Decides whether a finished command failed, for `set_error_classifier`.
+pub type ErrorClassifier = Box<dyn FnMut(&FinishedCommand) -> bool + Send>;
+/// Confirms a link before it is returned, for `set_link_confirmation`.
+pub type LinkConfirmation = Box<dyn FnMut(&Link) -> bool + Send>;
+/// Restyles a visible cell, for `set_style_override`.
+pub type StyleOverride = Box<dyn FnMut(&Style, CellContext) -> Style + Send>;
 
 /// A terminal.
+///
+/// Terminals are `Send` and `Sync`, and every query takes `&self`. A
+/// frontend can share one as `Arc<RwLock<Terminal>>`, taking the write lock
+/// to call `update` and read locks to draw from other threads.
 pub struct Terminal {
     /// The settings.
-    #[allow(dead_code)]
     settings: Settings,
     /// The operating-system specific driver.
-    os_driver: Driver,
+    ///
+    /// This is in a mutex so that terminals can be shared between threads;
+    /// `&mut self` methods reach it without locking.
+    os_driver: Mutex<Box<dyn Driver>>,
     /// The backing text buffer.
     scroll_buffer: ScrollBuffer,
+    /// The primary screen's buffer, set aside while the alternate screen is active.
+    primary_screen: Option<ScrollBuffer>,
+    /// The modes set by the running program.
+    modes: ModeState,
+    /// When the bell last rang, if it has not been acknowledged.
+    bell_rang_at: Option<Instant>,
+    /// The volume of the bell that rang last.
+    bell_volume: BellVolume,
+    /// When a bell was last let through, for rate limiting.
+    last_bell_at: Option<Instant>,
+    /// The volume of the bell rung by BEL, set by the running program.
+    warning_bell_volume: BellVolume,
+    /// The volume of the margin bell, set by the running program.
+    margin_bell_volume: BellVolume,
+    /// Whether the frontend has focused the terminal.
+    focused: bool,
+    /// When the cursor blink cycle last restarted.
+    cursor_blink_epoch: Instant,
+    /// Probes waiting for their echo, for latency measurement.
+    latency_probes: LatencyProbes,
+    /// The cursor shape requested by the running program.
+    cursor_shape: CursorShape,
+    /// The default text color requested by the running program.
+    foreground_color: Option<Color>,
+    /// The default background color requested by the running program.
+    background_color: Option<Color>,
+    /// The cursor color requested by the running program.
+    cursor_color: Option<Color>,
+    /// The palette, with the colors changed by the running program.
+    palette: Palette,
+    /// Whether new text is concealed, as asked by the running program.
+    concealed: bool,
+    /// Whether responses use 8-bit C1 controls, as asked by the running program.
+    eight_bit_controls: bool,
+    /// The colors the frontend should draw with.
+    theme: Theme,
+    /// How many lines the viewport is scrolled up into the scrollback.
+    scroll_offset: usize,
+    /// Whether the viewport follows new output.
+    autoscroll: Autoscroll,
+    /// Whether the driver has lost its transport.
+    disconnected: bool,
+    /// The id of the next command run with `run_command`.
+    next_command_id: u64,
+    /// The id of the line where the output of the current command started,
+    /// as marked by shell integration.
+    command_output_start: Option<usize>,
+    /// Decides which finished commands have their output classified as errors.
+    error_classifier: Option<Mutex<ErrorClassifier>>,
+    /// The target of the hyperlink the running program is writing, if any.
+    hyperlink: Option<String>,
+    /// The window title last set by the running program.
+    title: Option<String>,
+    /// Scripts that automate the terminal as output arrives.
+    #[cfg(feature = "scripting")]
+    scripts: Vec<Script>,
+    /// The first line id and text of the partial line last given to
+    /// `on_prompt`, so that it is only given once.
+    #[cfg(feature = "scripting")]
+    last_prompt: Option<(usize, String)>,
+    /// Confirms each link before `link_at` returns it.
+    ///
+    /// The hooks are in mutexes so that they can be called through `&self`,
+    /// from any thread holding a shared reference.
+    link_confirmation: Option<Mutex<LinkConfirmation>>,
+    /// Restyles each visible cell as slices are made.
+    style_override: Option<Mutex<StyleOverride>>,
+    /// The file being written to the running program, if any.
+    upload: Option<Upload>,
+    /// Counts of what has happened in the session, as of the last update.
+    stats: SessionStats,
+}
+
+/// A command that finished, as marked by shell integration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FinishedCommand {
+    /// The text the command wrote, without trailing spaces on each line.
+    pub output: String,
+    /// The exit code, if the shell reported one.
+    pub exit_code: Option<i32>,
+}
+
+/// What has happened in a terminal's session so far, for status bars and
+/// for hosts keeping an eye on many sessions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SessionStats {
+    /// The bytes of input written to the running program, including
+    /// responses to its queries.
+    pub bytes_in: u64,
+    /// The bytes of output read from the running program.
+    pub bytes_out: u64,
+    /// The lines the output has added, most of which scrolled the grid.
+    pub lines_scrolled: u64,
+    /// The commands that finished, as marked by shell integration.
+    pub commands_run: u64,
+    /// When the terminal was created.
+    pub started_at: Instant,
+    /// When input or output was last seen by an update, or when the
+    /// terminal was created if it never was.
+    pub last_activity: Instant,
 }
 
 /// Terminal settings.
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Settings {
     /// The shell to execute.
     pub shell: String,
+    /// Extra environment variables for the shell.
+    pub environment: Vec<(String, String)>,
     /// How many lines to remember in the scrollback.
     pub lines_to_remember: usize,
     /// The maximum number of lines to display at once.
@@ -32,6 +166,97 @@ pub struct Settings {
     pub column_count: usize,
     /// The number of spaces used to render tab characters.
     pub tab_width: usize,
+    /// How long the bell stays pending after it rings, for visual bells.
+    pub visual_bell_duration: Duration,
+    /// The shortest time between bells. Bells that ring sooner after the
+    /// last one are dropped, so that a storm of them is only heard once.
+    pub bell_rate_limit: Duration,
+    /// How long a blinking cursor stays on, and then off.
+    pub cursor_blink_interval: Duration,
+    /// How many columns from the right margin typing rings the margin bell,
+    /// once the running program enables it.
+    pub margin_bell_distance: usize,
+    /// Whether typed input is written into the scroll buffer immediately,
+    /// rather than only once the running program echoes it.
+    ///
+    /// Only enable this for sessions that do not echo, such as some serial
+    /// transports; otherwise every character appears twice.
+    pub local_echo: bool,
+    /// Whether input written while the running program has disabled echo
+    /// is kept out of the scrollback and exports, as for passwords.
+    ///
+    /// This only matters with local echo.
+    pub protect_secret_input: bool,
+    /// Whether interrupting the running program discards any pasted input
+    /// that has not been written yet.
+    pub cancel_input_on_interrupt: bool,
+    /// Whether orphaned descendants of the shell should be reparented to this
+    /// process and reaped, rather than left as zombies.
+    ///
+    /// On Linux this marks the whole process as a child subreaper, and it
+    /// stays one after the terminal is dropped. Orphans from anywhere in the
+    /// process tree below are then reparented here, including daemons that
+    /// the embedder's own commands start, and the embedder must reap the
+    /// ones outside the shell's session itself. Elsewhere this does nothing.
+    pub reap_orphaned_descendants: bool,
+    /// Whether to record the arrival time of each line.
+    /// Off by default to save memory.
+    pub record_line_timestamps: bool,
+    /// How to render line timestamps in the slice and text export paths.
+    pub timestamp_gutter: TimestampGutter,
+    /// What to do with output that looks like binary data.
+    pub binary_output: BinaryOutputPolicy,
+    /// How line endings in the output are treated.
+    pub line_endings: LineEndings,
+    /// What to do with XON/XOFF (^Q/^S) written to the terminal.
+    pub flow_control: FlowControl,
+    /// How bytes 0x80–0x9F in the output are treated.
+    pub c1_controls: C1Controls,
+    /// Whether the viewport follows new output.
+    pub autoscroll: Autoscroll,
+    /// Commands written after reconnecting a lost transport, to restore
+    /// state such as the working directory.
+    pub reconnect_commands: Vec<String>,
+    /// The URI schemes that `Terminal::link_at` allows, in lowercase.
+    /// Links with other schemes, such as `javascript:`, are refused.
+    pub link_schemes: Vec<String>,
+    /// What the backspace key sends.
+    pub backspace_key: BackspaceKey,
+    /// Whether erased cells take the background color of the running
+    /// program's pen (BCE), as in xterm, rather than the default.
+    pub background_color_erase: bool,
+    /// The colors that running programs select by number.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub palette: Palette,
+    /// How long the control strings in the output, such as titles and
+    /// clipboard writes, may be. Longer strings are discarded.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub string_limits: StringLimits,
+}
+
+/// What the backspace key sends to the running program.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BackspaceKey {
+    /// Follow the backarrow key mode (DECBKM) set by the running program,
+    /// which sends delete unless the mode is set.
+    Program,
+    /// Always send delete (0x7f), as most programs expect.
+    Delete,
+    /// Always send backspace (0x08), also known as ^H.
+    Backspace,
+}
+
+/// Whether the viewport follows new output.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Autoscroll {
+    /// Jump to the bottom whenever output arrives.
+    Always,
+    /// Follow output only while the viewport is at the bottom.
+    WhenAtBottom,
+    /// Keep the viewport on the same lines, as when inspecting a log.
+    Never,
 }
 
 /// A terminal action.
@@ -53,100 +278,1133 @@ pub enum Action {
     CursorUp,
     /// Sends a control code to the pseudo terminal.
     ControlCode(char),
+    /// Sends a key pressed with modifiers, as with `Terminal::send_key`.
+    Key(KeyEvent),
 }
 
 impl Terminal {
     /// Creates a new terminal.
     pub fn new(settings: Settings) -> Result<Self, io::Error> {
-        let os_driver = Driver::new(&settings)?;
-
-        Ok(Terminal {
-            os_driver,
-            scroll_buffer: ScrollBuffer::new(scroll_buffer::Settings {
-                lines_to_remember: settings.lines_to_remember,
-                max_lines: settings.line_count,
-                max_columns: settings.column_count,
-                tab_width: settings.tab_width,
-            }),
+        let os_driver = os::create_driver(&settings)?;
+        Ok(Terminal::with_driver(settings, os_driver))
+    }
+
+    /// Creates a new terminal on a PTY that the embedder already has,
+    /// instead of spawning a shell.
+    ///
+    /// # Safety
+    ///
+    /// See `os::unix::Driver::adopt`.
+    #[cfg(unix)]
+    pub unsafe fn adopt_pty(settings: Settings,
+                            fd: std::os::unix::io::RawFd,
+                            child_pid: Option<libc::pid_t>,
+                            ownership: os::PtyOwnership) -> Result<Self, io::Error> {
+        let os_driver = os::unix::Driver::adopt(&settings, fd, child_pid, ownership)?;
+        Ok(Terminal::with_driver(settings, Box::new(os_driver)))
+    }
+
+    /// Creates a terminal with no running program, whose output is given
+    /// to `feed` instead.
+    pub fn headless(settings: Settings) -> Self {
+        let os_driver = os::headless::Driver::new(&settings);
+        Terminal::with_driver(settings, Box::new(os_driver))
+    }
+
+    /// Creates a new terminal on top of a driver chosen at runtime.
+    ///
+    /// The driver should have been created with the same settings.
+    pub fn with_driver(settings: Settings, os_driver: Box<dyn Driver>) -> Self {
+        Terminal {
+            os_driver: Mutex::new(os_driver),
+            scroll_buffer: ScrollBuffer::new(scroll_buffer_settings(&settings, settings.lines_to_remember)),
+            primary_screen: None,
+            modes: ModeState::default(),
+            bell_rang_at: None,
+            bell_volume: BellVolume::High,
+            last_bell_at: None,
+            warning_bell_volume: BellVolume::High,
+            margin_bell_volume: BellVolume::High,
+            focused: true,
+            cursor_blink_epoch: Instant::now(),
+            latency_probes: LatencyProbes::default(),
+            cursor_shape: CursorShape::default(),
+            foreground_color: None,
+            background_color: None,
+            cursor_color: None,
+            palette: settings.palette.clone(),
+            concealed: false,
+            eight_bit_controls: false,
+            theme: Theme::default(),
+            scroll_offset: 0,
+            autoscroll: settings.autoscroll,
+            disconnected: false,
+            next_command_id: 0,
+            command_output_start: None,
+            error_classifier: None,
+            hyperlink: None,
+            title: None,
+            #[cfg(feature = "scripting")]
+            scripts: Vec::new(),
+            #[cfg(feature = "scripting")]
+            last_prompt: None,
+            link_confirmation: None,
+            style_override: None,
+            upload: None,
+            stats: SessionStats::new(Instant::now()),
             settings,
-        })
+        }
+    }
+
+    /// Creates a new terminal from a profile, running its startup commands.
+    pub fn from_profile(profile: &Profile) -> Result<Self, io::Error> {
+        let mut terminal = Terminal::new(profile.effective_settings())?;
+        terminal.theme = profile.theme.clone();
+
+        for command in profile.startup_commands.iter() {
+            terminal.driver().write_text(&format!("{}\n", command))?;
+        }
+
+        Ok(terminal)
+    }
+
+    /// Sets the hook that decides which finished commands have their
+    /// output classified as errors, with `CellClass::ErrorStream`.
+    ///
+    /// Commands are only seen if the shell is set up for shell integration
+    /// (OSC 133), as even stderr is merged into the output by the PTY.
+    /// `FinishedCommand::failed` classifies commands that exit with a
+    /// nonzero code.
+    pub fn set_error_classifier(&mut self, classifier: Option<ErrorClassifier>) {
+        self.error_classifier = classifier.map(Mutex::new);
+    }
+
+    /// Loads a script that automates the terminal as output arrives.
+    ///
+    /// See the `script` module for the functions a script can define.
+    #[cfg(feature = "scripting")]
+    pub fn load_script(&mut self, source: &str) -> Result<(), ScriptError> {
+        self.scripts.push(Script::new(source)?);
+        Ok(())
+    }
+
+    /// Sets the hook that confirms each link before `link_at` returns it,
+    /// such as by asking the user.
+    ///
+    /// The hook sees the exact URI and whether the running program or
+    /// detection provided it. Links it refuses are not returned.
+    pub fn set_link_confirmation(&mut self, confirmation: Option<LinkConfirmation>) {
+        self.link_confirmation = confirmation.map(Mutex::new);
+    }
+
+    /// Sets the hook that restyles each visible cell as slices are made,
+    /// such as for a palette that is safe for color blindness.
+    ///
+    /// See `ScrollBuffer::visible_slices_styled`.
+    pub fn set_style_override(&mut self, style_override: Option<StyleOverride>) {
+        self.style_override = style_override.map(Mutex::new);
+    }
+
+    /// Gets the link at a cell in the viewport, such as to open it on click.
+    ///
+    /// The URI is normalized, and the link is refused if its scheme is not
+    /// in `Settings::link_schemes` or the confirmation hook refuses it.
+    pub fn link_at(&self, x: usize, y: usize) -> Option<Link> {
+        let mut link = self.scroll_buffer.link_at(self.scroll_offset(), x, y)?;
+        link.uri = normalize_uri(&link.uri)?;
+
+        let scheme = &link.uri[..link.uri.find(':')?];
+        if !self.settings.link_schemes.iter().any(|allowed| allowed == scheme) {
+            return None;
+        }
+
+        let confirmed = match self.link_confirmation.as_ref() {
+            Some(confirm) => (*confirm.lock().unwrap_or_else(PoisonError::into_inner))(&link),
+            None => true,
+        };
+
+        if confirmed { Some(link) } else { None }
+    }
+
+    /// Gets the current value of a dynamic color: the one set by the
+    /// running program, or else the theme's.
+    pub fn dynamic_color(&self, target: DynamicColor) -> Color {
+        match target {
+            DynamicColor::Foreground => self.foreground_color.unwrap_or(self.theme.foreground),
+            DynamicColor::Background => self.background_color.unwrap_or(self.theme.background),
+            DynamicColor::Cursor => self.cursor_color.or(self.theme.cursor)
+                .unwrap_or_else(|| self.dynamic_color(DynamicColor::Foreground)),
+        }
+    }
+
+    /// Gets the palette, with any colors the running program has changed.
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Gets the colors the frontend should draw with.
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// Sets the colors the frontend should draw with.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
     }
 
     /// Writes text to the terminal.
-    pub fn write_text(&mut self, s: &str) {
-        self.scroll_buffer.put_str(s);
-        self.os_driver.write_text(s);
+    ///
+    /// The text only reaches the scroll buffer once the running program
+    /// echoes it, unless local echo is enabled. Even then, if the running
+    /// program has disabled echo and secret input is protected, the text is
+    /// never recorded into the scroll buffer.
+    ///
+    /// Fails if the running program can no longer be written to, such as
+    /// when it has exited. The other methods that write to it fail the same way.
+    pub fn write_text(&mut self, s: &str) -> Result<(), io::Error> {
+        self.write_bytes(s.as_bytes())
+    }
+
+    /// Writes bytes to the running program, such as an encoded key sequence.
+    ///
+    /// This is echoed the same way as `write_text`.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        self.cursor_blink_epoch = Instant::now();
+        self.echo_input(&String::from_utf8_lossy(bytes));
+        self.driver().write_bytes(bytes)
+    }
+
+    /// Pastes text, writing it to the running program a chunk at a time as
+    /// the terminal updates.
+    ///
+    /// Text written afterwards waits behind the paste, but control codes
+    /// and interrupts do not, so a large paste can still be interrupted.
+    pub fn paste(&mut self, s: &str) {
+        self.echo_input(s);
+        self.driver().write_bulk(s);
+    }
+
+    /// Writes a file to the running program, such as into `cat > file`,
+    /// a chunk at a time as the terminal updates.
+    ///
+    /// See `upload`.
+    pub fn write_file<P>(&mut self, path: P) -> Result<(), io::Error> where P: AsRef<Path> {
+        let file = File::open(path)?;
+        let total_bytes = file.metadata().ok().map(|metadata| metadata.len());
+
+        self.upload(file, total_bytes)
+    }
+
+    /// Writes everything a reader produces to the running program, a chunk
+    /// at a time as the terminal updates.
+    ///
+    /// The next chunk is only read once the last has mostly been written,
+    /// and not while output is stopped by XOFF, so a slow program holds the
+    /// upload back. Like a paste, it never holds up control codes, and it is
+    /// dropped with the rest of the pending input. The data is not echoed
+    /// locally, and passes through the PTY's line discipline as typed input
+    /// would. Progress is reported with `Event::UploadProgress`.
+    ///
+    /// Only one upload runs at a time; starting another while one is under
+    /// way is an error.
+    pub fn upload<R>(&mut self, reader: R, total_bytes: Option<u64>) -> Result<(), io::Error> where R: Read + Send + 'static {
+        if self.upload.is_some() {
+            return Err(io::Error::other("a file is already being written to the running program"));
+        }
+
+        self.upload = Some(Upload::new(Box::new(reader), total_bytes));
+        Ok(())
+    }
+
+    /// Gets how far the current upload has got, if there is one.
+    pub fn upload_progress(&self) -> Option<UploadProgress> {
+        self.upload.as_ref().map(Upload::progress)
+    }
+
+    /// Gets what has happened in the session so far, as of the last update.
+    pub fn session_stats(&self) -> SessionStats {
+        self.stats
+    }
+
+    /// Gets the number of bytes of pasted input not yet written.
+    pub fn pending_input_byte_count(&self) -> usize {
+        self.locked_driver().pending_input_byte_count()
+    }
+
+    /// Discards pasted input not yet written, and any upload, returning the
+    /// number of bytes dropped from the input queue.
+    pub fn cancel_pending_input(&mut self) -> usize {
+        self.upload = None;
+        self.driver().cancel_pending_input()
+    }
+
+    /// Rings the margin bell and writes locally echoed input into the scroll buffer.
+    ///
+    /// The margin bell is not rung for protected secret input, as it would
+    /// give away how long the secret is.
+    fn echo_input(&mut self, s: &str) {
+        if self.modes.margin_bell && !self.is_secret_input_protected() && self.crosses_margin(s) {
+            self.ring_bell(self.margin_bell_volume);
+        }
+
+        if self.is_local_echo_active() {
+            self.scroll_buffer.put_str(s);
+        }
+    }
+
+    /// Resizes the terminal, telling the running program about its new size.
+    ///
+    /// The visible lines are rewrapped straight away, and the scrollback
+    /// over the following updates, with `Event::ReflowProgress`.
+    ///
+    /// The terminal is at least one column wide and one line tall. Resizing
+    /// to the current size does nothing, so the running program is not
+    /// asked to redraw.
+    pub fn resize(&mut self, columns: usize, lines: usize) {
+        let (columns, lines) = (columns.max(1), lines.max(1));
+        if (columns, lines) == (self.settings.column_count, self.settings.line_count) {
+            return;
+        }
+
+        self.settings.column_count = columns;
+        self.settings.line_count = lines;
+        self.scroll_buffer.resize(columns, lines);
+        if let Some(primary_screen) = self.primary_screen.as_mut() {
+            primary_screen.resize(columns, lines);
+        }
+        self.driver().resize(columns, lines);
+    }
+
+    /// Checks whether the running program has switched to the alternate
+    /// screen, as full-screen programs such as editors do.
+    ///
+    /// The alternate screen has no scrollback, so frontends should hide
+    /// their scrollbar while it is active.
+    pub fn is_alternate_screen_active(&self) -> bool {
+        self.primary_screen.is_some()
+    }
+
+    /// Gets the number of lines the viewport can scroll up into the scrollback.
+    ///
+    /// This is always zero on the alternate screen.
+    pub fn scrollback_line_count(&self) -> usize {
+        self.scroll_buffer.scrollback_line_count()
+    }
+
+    /// Gets the driver, without locking as `&mut self` is exclusive.
+    fn driver(&mut self) -> &mut (dyn Driver + 'static) {
+        &mut **self.os_driver.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks the driver, to query it through `&self`.
+    fn locked_driver(&self) -> MutexGuard<'_, Box<dyn Driver>> {
+        self.os_driver.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Answers a DECRQM query with the state of a mode.
+    fn report_mode(&mut self, number: usize, dec_private: bool) {
+        // 0 is not recognized, 1 is set and 2 is reset.
+        let state = match (dec_private, Mode::from_dec_private(number)) {
+            (true, Some(mode)) => if self.modes.get(mode) { 1 } else { 2 },
+            (true, None) if ALTERNATE_SCREEN_MODES.contains(&number) => {
+                if self.is_alternate_screen_active() { 1 } else { 2 }
+            },
+            _ => 0,
+        };
+
+        let marker = if dec_private { "?" } else { "" };
+        self.send_response(&format!("\x1b[{}{};{}$y", marker, number, state));
+    }
+
+    /// Switches to or from the alternate screen.
+    fn set_alternate_screen(&mut self, enabled: bool) {
+        if enabled == self.is_alternate_screen_active() {
+            return;
+        }
+
+        let mut screen = match self.primary_screen.take() {
+            Some(primary_screen) => primary_screen,
+            None => ScrollBuffer::new(scroll_buffer_settings(&self.settings, 0)),
+        };
+        // The scroll region is shared by both screens, as in xterm.
+        screen.set_scroll_region(self.scroll_buffer.scroll_region());
+
+        let previous_screen = mem::replace(&mut self.scroll_buffer, screen);
+        if enabled {
+            self.primary_screen = Some(previous_screen);
+        }
+        self.scroll_offset = 0;
+    }
+
+    /// Runs a command in the shell and waits for it to finish, returning
+    /// its output and exit code.
+    ///
+    /// Completion is detected by printing sentinels around the command, so
+    /// the shell must understand POSIX `sh` syntax, and the command must fit
+    /// on one line without a trailing comment. Fails
+    /// with `TimedOut` if the command takes too long, or `UnexpectedEof` if
+    /// the session finishes first.
+    pub fn run_command(&mut self, command: &str, options: &RunOptions) -> Result<CommandOutput, io::Error> {
+        let sentinel = Sentinel::new(self.next_command_id);
+        self.next_command_id += 1;
+
+        self.driver().write_text(&sentinel.wrap(command))?;
+
+        let deadline = Instant::now() + options.timeout;
+        let mut capture = OutputCapture::default();
+
+        loop {
+            let events = self.update()?;
+            capture.observe(&events);
+
+            if let Some(output) = sentinel.find(capture.text()) {
+                return Ok(output);
+            }
+
+            if self.is_session_finished() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "the session finished before the command"));
+            }
+
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut,
+                                          format!("command did not finish within {:?}", options.timeout)));
+            }
+
+            if events.is_empty() {
+                thread::sleep(options.poll_interval);
+            }
+        }
+    }
+
+    /// Waits for the running program to exit, handling the rest of its
+    /// output, and returns how it exited if the driver can tell.
+    ///
+    /// The events from the output are handled but not returned.
+    pub fn wait(&mut self) -> Result<Option<ExitStatus>, io::Error> {
+        self.wait_until(None)
+    }
+
+    /// Waits up to `timeout` for the running program to exit, as `wait` does.
+    ///
+    /// Fails with `TimedOut` if it is still running by then.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<ExitStatus>, io::Error> {
+        self.wait_until(Some(Instant::now() + timeout))
+    }
+
+    fn wait_until(&mut self, deadline: Option<Instant>) -> Result<Option<ExitStatus>, io::Error> {
+        while !self.driver().is_session_finished() {
+            let interval = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if left > Duration::from_secs(0) => left.min(WAIT_INTERVAL),
+                    _ => return Err(io::Error::new(io::ErrorKind::TimedOut, "the program is still running")),
+                },
+                None => WAIT_INTERVAL,
+            };
+
+            self.update_timeout(interval)?;
+        }
+
+        Ok(self.driver().exit_status())
     }
 
     /// Backspaces the last character.
-    pub fn backspace(&mut self) {
-        self.scroll_buffer.backspace();
-        self.os_driver.backspace();
+    pub fn backspace(&mut self) -> Result<(), io::Error> {
+        if self.is_local_echo_active() {
+            self.scroll_buffer.backspace();
+        }
+
+        let bytes = encode::key(Key::Backspace, Modifiers::NONE, &self.key_modes());
+        self.driver().write_bytes(&bytes)
+    }
+
+    /// Sends a key pressed with modifiers, encoded as xterm would.
+    ///
+    /// Unmodified characters and backspace are echoed as `write_text` and
+    /// `backspace` would.
+    pub fn send_key(&mut self, key: Key, modifiers: Modifiers) -> Result<(), io::Error> {
+        match key {
+            Key::Char(c) if modifiers.is_empty() => self.write_text(c.encode_utf8(&mut [0; 4])),
+            Key::Backspace if modifiers.is_empty() => self.backspace(),
+            _ => {
+                self.cursor_blink_epoch = Instant::now();
+                let bytes = encode::key(key, modifiers, &self.key_modes());
+                self.driver().write_bytes(&bytes)
+            },
+        }
+    }
+
+    /// Reports a mouse button pressed over a cell on the grid to the running
+    /// program, if it enabled mouse tracking.
+    ///
+    /// Returns whether it was reported. If not, the frontend may use the
+    /// mouse itself, such as for selecting text.
+    pub fn mouse_press(&mut self, x: usize, y: usize, button: MouseButton, modifiers: Modifiers) -> Result<bool, io::Error> {
+        self.report_mouse(MouseAction::Press(button), x, y, modifiers)
+    }
+
+    /// Reports a mouse button released over a cell, as with `mouse_press`.
+    pub fn mouse_release(&mut self, x: usize, y: usize, button: MouseButton, modifiers: Modifiers) -> Result<bool, io::Error> {
+        self.report_mouse(MouseAction::Release(button), x, y, modifiers)
+    }
+
+    /// Reports the mouse moving onto a cell, with a button held or not, as with `mouse_press`.
+    pub fn mouse_move(&mut self, x: usize, y: usize, button: Option<MouseButton>, modifiers: Modifiers) -> Result<bool, io::Error> {
+        self.report_mouse(MouseAction::Move(button), x, y, modifiers)
+    }
+
+    /// Reports the mouse wheel scrolling over a cell, as with `mouse_press`.
+    ///
+    /// The button is `MouseButton::WheelUp` or `MouseButton::WheelDown`.
+    pub fn mouse_scroll(&mut self, x: usize, y: usize, button: MouseButton, modifiers: Modifiers) -> Result<bool, io::Error> {
+        self.report_mouse(MouseAction::Press(button), x, y, modifiers)
+    }
+
+    /// Sends a mouse report, if the running program asked for it.
+    fn report_mouse(&mut self, action: MouseAction, x: usize, y: usize, modifiers: Modifiers) -> Result<bool, io::Error> {
+        match encode::mouse(action, x, y, modifiers, &self.modes) {
+            Some(bytes) => {
+                self.driver().write_bytes(&bytes)?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Gets the modes that keys are encoded with, accounting for the
+    /// backspace key setting.
+    fn key_modes(&self) -> ModeState {
+        let backarrow_key = match self.settings.backspace_key {
+            BackspaceKey::Program => self.modes.backarrow_key,
+            BackspaceKey::Delete => false,
+            BackspaceKey::Backspace => true,
+        };
+
+        ModeState { backarrow_key, ..self.modes.clone() }
+    }
+
+    /// Checks if input should be written into the scroll buffer as it is typed.
+    fn is_local_echo_active(&self) -> bool {
+        self.settings.local_echo && !self.is_secret_input_protected()
+    }
+
+    /// Checks if the running program is reading a secret, such as a
+    /// password, that should not be shown or hinted at.
+    fn is_secret_input_protected(&self) -> bool {
+        self.settings.protect_secret_input && self.locked_driver().is_echo_disabled()
+    }
+
+    /// Checks if typing the input from the cursor reaches the margin bell
+    /// column from before it, following the cursor as echoing it would.
+    fn crosses_margin(&self, s: &str) -> bool {
+        let margin_column = self.settings.column_count.saturating_sub(self.settings.margin_bell_distance);
+        let (mut column, _) = self.scroll_buffer.cursor_xy();
+
+        s.chars().any(|character| {
+            let next_column = self.scroll_buffer.column_after(column, character);
+            let crossed = column < margin_column && next_column >= margin_column;
+            column = next_column;
+            crossed
+        })
     }
 
     /// Sends the ESC character code.
-    pub fn escape(&mut self) {
-        self.os_driver.escape();
+    pub fn escape(&mut self) -> Result<(), io::Error> {
+        self.driver().escape()
     }
 
     /// Moves the cursor left.
-    pub fn cursor_left(&mut self) {
-        self.os_driver.cursor_left();
+    ///
+    /// Like the other cursor keys, this is sent as `ESC O D` rather than
+    /// `CSI D` if the running program enabled application cursor keys.
+    pub fn cursor_left(&mut self) -> Result<(), io::Error> {
+        self.send_key(Key::Left, Modifiers::NONE)
     }
 
     /// Moves the cursor right.
-    pub fn cursor_right(&mut self) {
-        self.os_driver.cursor_right();
+    pub fn cursor_right(&mut self) -> Result<(), io::Error> {
+        self.send_key(Key::Right, Modifiers::NONE)
     }
 
     /// Moves the cursor up.
-    pub fn cursor_up(&mut self) {
-        self.os_driver.cursor_up();
+    pub fn cursor_up(&mut self) -> Result<(), io::Error> {
+        self.send_key(Key::Up, Modifiers::NONE)
     }
 
     /// Moves the cursor down.
-    pub fn cursor_down(&mut self) {
-        self.os_driver.cursor_down();
+    pub fn cursor_down(&mut self) -> Result<(), io::Error> {
+        self.send_key(Key::Down, Modifiers::NONE)
     }
 
     /// Sends a control code to the running process.
-    pub fn control_code(&mut self, c: char) {
-        self.os_driver.control_code(c);
+    pub fn control_code(&mut self, c: char) -> Result<(), io::Error> {
+        self.driver().control_code(c)
     }
 
-    /// Sends an interrupt signal to the running program.
-    pub fn signal_interrupt(&mut self) {
-        self.control_code('c');
+    /// Sends an interrupt signal to the running program, ahead of any
+    /// pasted input not yet written.
+    pub fn signal_interrupt(&mut self) -> Result<(), io::Error> {
+        if self.settings.cancel_input_on_interrupt {
+            self.cancel_pending_input();
+        }
+
+        self.control_code('c')
+    }
+
+    /// Sends a signal to the running program's foreground process group,
+    /// as a real terminal does for job control. Interrupts cancel pasted
+    /// input not yet written, as with `signal_interrupt`.
+    #[cfg(unix)]
+    pub fn signal(&mut self, signal: os::Signal) -> Result<(), io::Error> {
+        if signal == os::Signal::Interrupt && self.settings.cancel_input_on_interrupt {
+            self.cancel_pending_input();
+        }
+
+        self.driver().signal(signal)
     }
 
     /// Sends raw data to the underlying terminal.
-    pub fn send_raw<S>(&mut self, s: S) where S: ToString {
-        self.os_driver.send_raw(s);
+    pub fn send_raw<S>(&mut self, s: S) -> Result<(), io::Error> where S: AsRef<[u8]> {
+        self.driver().write_bytes(s.as_ref())
+    }
+
+    /// Sends a response to the running program, with 8-bit controls if it asked for them.
+    fn send_response(&mut self, response: &str) {
+        let result = if self.eight_bit_controls {
+            self.driver().send_raw(&to_eight_bit_controls(response))
+        } else {
+            self.driver().send_raw(response)
+        };
+
+        if let Err(e) = result {
+            warn!("failed to respond to the running program: {}", e);
+        }
+    }
+
+    /// Writes to the running program on the terminal's own behalf, such as
+    /// a response to a query, where there is no caller to report failure to.
+    fn write_unprompted(&mut self, bytes: &[u8]) {
+        if let Err(e) = self.driver().write_bytes(bytes) {
+            warn!("failed to write to the running program: {}", e);
+        }
     }
 
     /// Updates the terminal.
-    pub fn update(&mut self) -> Vec<Event> {
-        if self.os_driver.is_session_finished() {
-            return Vec::new();
+    ///
+    /// Fails if the running program can no longer be reached.
+    pub fn update(&mut self) -> Result<Vec<Event>, io::Error> {
+        if self.driver().is_session_finished() {
+            return Ok(Vec::new());
+        }
+
+        let events = self.driver().update()?;
+        Ok(self.handle_events(events))
+    }
+
+    /// Updates the terminal, waiting up to `timeout` for output if there is none yet.
+    ///
+    /// Frontends can pass the time until their next frame, so that an idle
+    /// terminal sleeps instead of polling.
+    pub fn update_timeout(&mut self, timeout: Duration) -> Result<Vec<Event>, io::Error> {
+        if self.driver().is_session_finished() {
+            return Ok(Vec::new());
+        }
+
+        // Keep rewrapping the scrollback rather than waiting.
+        let timeout = match self.scroll_buffer.reflow_progress().remaining_lines {
+            0 => timeout,
+            _ => Duration::from_secs(0),
+        };
+
+        let events = self.driver().update_timeout(timeout)?;
+        Ok(self.handle_events(events))
+    }
+
+    fn handle_events(&mut self, events: Vec<Event>) -> Vec<Event> {
+        let line_count = self.line_count_so_far();
+        let alternate_screen = self.is_alternate_screen_active();
+        self.latency_probes.observe(&events, Instant::now());
+
+        let mut handled_events = Vec::with_capacity(events.len());
+        for event in events {
+            // Bells that are silenced or rate limited are not passed on.
+            if event == Event::Bell && !self.can_ring_bell(self.warning_bell_volume) {
+                continue;
+            }
+
+            self.handle_event(&event);
+
+            let finished_command = match event {
+                Event::ShellIntegration(ShellMark::CommandFinished { exit_code }) => {
+                    self.stats.commands_run += 1;
+                    Some(Event::CommandFinished {
+                        exit_code,
+                        duration: self.scroll_buffer.last_command_duration(),
+                    })
+                },
+                _ => None,
+            };
+            handled_events.push(event);
+            handled_events.extend(finished_command);
         }
+        let mut events = handled_events;
 
-        let events = self.os_driver.update();
+        self.latency_probes.finish_batch(Instant::now());
+        #[cfg(feature = "scripting")]
+        {
+            if !events.is_empty() {
+                self.run_prompt_scripts();
+            }
+        }
+        events.extend(self.continue_upload());
 
-        for event in events.iter() {
-            self.handle_event(event);
+        // Rewrap a little more of the scrollback on each update after a resize.
+        if let Some(progress) = self.scroll_buffer.continue_reflow(REFLOW_BATCH_LINES) {
+            events.push(Event::ReflowProgress(progress));
         }
 
+        // Rewrapping and clearing can remove lines. The counts of the two
+        // screens cannot be compared, so switching screens adds none.
+        let new_lines = match self.is_alternate_screen_active() == alternate_screen {
+            true => self.line_count_so_far().saturating_sub(line_count),
+            false => 0,
+        };
+        if new_lines > 0 && !self.follow_output(new_lines) {
+            events.push(Event::AutoscrollSuppressed { new_lines });
+        }
+
+        self.stats.lines_scrolled += new_lines as u64;
+        self.count_bytes();
         events
     }
 
+    /// Catches the session statistics up with the bytes the driver has passed.
+    fn count_bytes(&mut self) {
+        let counts = self.driver().byte_counts();
+        if counts.written != self.stats.bytes_in || counts.read != self.stats.bytes_out {
+            self.stats.bytes_in = counts.written;
+            self.stats.bytes_out = counts.read;
+            self.stats.last_activity = Instant::now();
+        }
+    }
+
+    /// Queues the next chunk of the upload, if the last has mostly been written.
+    fn continue_upload(&mut self) -> Option<Event> {
+        self.upload.as_ref()?;
+
+        let driver = self.driver();
+        if driver.is_flow_stopped() || driver.pending_input_byte_count() >= upload::CHUNK_SIZE {
+            return None;
+        }
+
+        let upload = self.upload.as_mut()?;
+        match upload.read_chunk() {
+            Ok(Some(chunk)) => {
+                let progress = upload.progress();
+                self.driver().write_bulk_bytes(&chunk);
+                Some(Event::UploadProgress(progress))
+            },
+            Ok(None) => {
+                let progress = upload.progress();
+                self.upload = None;
+                Some(Event::UploadProgress(progress))
+            },
+            Err(e) => {
+                warn!("failed to read the file being written: {}", e);
+                let bytes_sent = upload.progress().bytes_sent;
+                self.upload = None;
+                Some(Event::UploadFailed { bytes_sent, reason: e.to_string() })
+            },
+        }
+    }
+
+    /// Gets the number of lines ever added to the scroll buffer.
+    fn line_count_so_far(&self) -> usize {
+        self.scroll_buffer.evicted_line_count() + self.scroll_buffer.total_line_count()
+    }
+
+    /// Moves the viewport for new output according to the autoscroll policy.
+    ///
+    /// Returns whether the viewport followed the output to the bottom, as
+    /// it always does on the alternate screen, which has no scrollback.
+    fn follow_output(&mut self, new_lines: usize) -> bool {
+        if self.is_alternate_screen_active() {
+            return true;
+        }
+
+        let follow = match self.autoscroll {
+            Autoscroll::Always => true,
+            Autoscroll::WhenAtBottom => self.scroll_offset() == 0,
+            Autoscroll::Never => false,
+        };
+
+        if follow {
+            self.scroll_offset = 0;
+        } else {
+            // Stay on the same lines as the new ones push them up.
+            self.scroll_offset = (self.scroll_offset() + new_lines).min(self.scroll_buffer.scrollback_line_count());
+        }
+
+        follow
+    }
+
+    /// Gets how many lines the viewport is scrolled up into the scrollback.
+    pub fn scroll_offset(&self) -> usize {
+        self.scroll_offset.min(self.scroll_buffer.scrollback_line_count())
+    }
+
+    /// Checks whether the viewport shows the bottom of the scroll buffer.
+    pub fn is_scrolled_to_bottom(&self) -> bool {
+        self.scroll_offset() == 0
+    }
+
+    /// Scrolls the viewport up into the scrollback.
+    ///
+    /// The alternate screen cannot be scrolled, but if the running program
+    /// has enabled alternate scroll mode, it is sent up arrows instead.
+    pub fn scroll_up(&mut self, lines: usize) {
+        if self.redirect_scroll(lines, Key::Up) {
+            return;
+        }
+
+        self.scroll_offset = (self.scroll_offset() + lines).min(self.scroll_buffer.scrollback_line_count());
+    }
+
+    /// Scrolls the viewport down towards the bottom.
+    ///
+    /// On the alternate screen, this may send down arrows instead, as with `scroll_up`.
+    pub fn scroll_down(&mut self, lines: usize) {
+        if self.redirect_scroll(lines, Key::Down) {
+            return;
+        }
+
+        self.scroll_offset = self.scroll_offset().saturating_sub(lines);
+    }
+
+    /// Sends arrow keys for scrolling on the alternate screen, if alternate
+    /// scroll mode is enabled.
+    ///
+    /// Returns whether the alternate screen is active, so scrolling the viewport is ignored.
+    fn redirect_scroll(&mut self, lines: usize, arrow: Key) -> bool {
+        if !self.is_alternate_screen_active() {
+            return false;
+        }
+
+        if self.modes.alternate_scroll {
+            let bytes = encode::key(arrow, Modifiers::NONE, &self.modes);
+            for _ in 0..lines {
+                self.write_unprompted(&bytes);
+            }
+        }
+        true
+    }
+
+    /// Scrolls the viewport back to the bottom.
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_offset = 0;
+    }
+
+    /// Gets the id of the line that the last prompt before a line starts on.
+    ///
+    /// Prompts are only seen if the shell is set up for shell integration (OSC 133).
+    pub fn previous_prompt(&self, from: usize) -> Option<usize> {
+        self.scroll_buffer.previous_prompt(from)
+    }
+
+    /// Gets the id of the line that the first prompt after a line starts on.
+    pub fn next_prompt(&self, from: usize) -> Option<usize> {
+        self.scroll_buffer.next_prompt(from)
+    }
+
+    /// Scrolls up to the prompt above the top of the viewport, putting it at the top.
+    ///
+    /// Returns `false` if there is no such prompt.
+    pub fn scroll_to_previous_prompt(&mut self) -> bool {
+        let top_line_id = self.scroll_buffer.visible_line_id(self.scroll_offset(), 0);
+        self.previous_prompt(top_line_id).is_some_and(|line_id| self.scroll_to_line(line_id))
+    }
+
+    /// Scrolls down to the prompt below the top of the viewport, putting it
+    /// at the top unless that would scroll past the bottom.
+    ///
+    /// Returns `false` if there is no such prompt.
+    pub fn scroll_to_next_prompt(&mut self) -> bool {
+        let top_line_id = self.scroll_buffer.visible_line_id(self.scroll_offset(), 0);
+        self.next_prompt(top_line_id).is_some_and(|line_id| self.scroll_to_line(line_id))
+    }
+
+    /// Scrolls so that a line is at the top of the viewport, or as near as it can get.
+    fn scroll_to_line(&mut self, line_id: usize) -> bool {
+        match self.scroll_buffer.scrollback_to_show(line_id) {
+            Some(scroll_offset) => {
+                self.scroll_offset = scroll_offset;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Gets whether the viewport follows new output.
+    pub fn autoscroll(&self) -> Autoscroll {
+        self.autoscroll
+    }
+
+    /// Sets whether the viewport follows new output.
+    ///
+    /// `Event::AutoscrollSuppressed` is emitted whenever it does not.
+    pub fn set_autoscroll(&mut self, autoscroll: Autoscroll) {
+        self.autoscroll = autoscroll;
+    }
+
+    /// Writes a probe string and measures how long it takes to be echoed back.
+    ///
+    /// Completed measurements are collected with `take_latency_samples`.
+    /// The probe should be something the running program echoes verbatim,
+    /// such as text typed at a shell prompt. Control codes in it, such as
+    /// a trailing enter, are not expected in the echo, but a probe of only
+    /// control codes is rejected. Probes not echoed within ten seconds are
+    /// dropped.
+    pub fn probe_echo_latency(&mut self, probe: &str) -> Result<(), io::Error> {
+        if !LatencyProbes::is_measurable(probe) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the probe has no text to be echoed"));
+        }
+
+        let sent_at = Instant::now();
+        self.driver().write_text(probe)?;
+        self.latency_probes.start(probe, sent_at);
+        Ok(())
+    }
+
+    /// Gets the number of latency probes still waiting for their echo.
+    pub fn pending_latency_probe_count(&self) -> usize {
+        self.latency_probes.pending_count()
+    }
+
+    /// Takes every completed echo latency measurement.
+    pub fn take_latency_samples(&mut self) -> Vec<LatencySample> {
+        self.latency_probes.take_samples()
+    }
+
+    /// Feeds output to a headless terminal as if a program wrote it, then
+    /// updates the terminal.
+    ///
+    /// Fails unless the terminal was created with `Terminal::headless`.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Vec<Event>, io::Error> {
+        self.driver().feed_output(bytes)?;
+        self.update()
+    }
+
+    /// Handles a synthetic event exactly as if the driver had emitted it,
+    /// returning the events an update would have.
+    ///
+    /// Useful for replaying recorded sessions, or for frontends that want to
+    /// clear the screen or place text through the same code path as the shell.
+    /// Bells are still silenced and rate limited, and finished commands
+    /// still produce `Event::CommandFinished`.
+    pub fn inject_event(&mut self, event: Event) -> Vec<Event> {
+        self.handle_events(vec![event])
+    }
+
     pub fn visible_text(&self) -> String {
-        let scrollback_line_count = 0;
+        let scrollback_line_count = self.scroll_offset();
         self.scroll_buffer.visible_text(scrollback_line_count)
     }
 
     pub fn visible_slices(&self) -> Vec<TextSlice> {
-        let scrollback_line_count = 0;
-        self.scroll_buffer.visible_slices(scrollback_line_count)
+        self.visible_slices_with(&SliceOptions::default())
+    }
+
+    /// Gets the visible slices, split wherever the style or one of the
+    /// given classes changes.
+    ///
+    /// Cells are restyled by the style override first, if one is set.
+    pub fn visible_slices_with(&self, options: &SliceOptions) -> Vec<TextSlice> {
+        let scrollback_line_count = self.scroll_offset();
+
+        match self.style_override.as_ref() {
+            Some(style_override) => {
+                let mut style_override = style_override.lock().unwrap_or_else(PoisonError::into_inner);
+                self.scroll_buffer.visible_slices_styled(scrollback_line_count, options, &mut **style_override)
+            },
+            None => self.scroll_buffer.visible_slices_with(scrollback_line_count, options),
+        }
+    }
+
+    /// Shrinks the grid to `columns` by `rows` blocks, for tab previews and
+    /// minimaps.
+    ///
+    /// See `ScrollBuffer::thumbnail`.
+    pub fn thumbnail(&self, columns: usize, rows: usize) -> Thumbnail {
+        self.scroll_buffer.thumbnail(columns, rows)
+    }
+
+    /// Adds a styled status line from the host, such as "Reconnected at 10:32",
+    /// above the cursor line.
+    ///
+    /// The line is not sent to the running program. Unsearchable lines are
+    /// skipped by searches.
+    pub fn append_external_line(&mut self, slices: &[TextSlice], searchable: bool) {
+        self.scroll_buffer.append_external_line(slices, searchable);
+    }
+
+    /// Gets the slices of a range of lines by id, including the scrollback.
+    ///
+    /// See `ScrollBuffer::line_slices`.
+    pub fn line_slices(&self, line_ids: Range<usize>, options: &SliceOptions) -> Vec<TextSlice> {
+        self.scroll_buffer.line_slices(line_ids, options)
+    }
+
+    /// Adds a range of cells on a line to the selection, tagging them with
+    /// `CellClass::Selection`.
+    pub fn select(&mut self, line_id: usize, columns: Range<usize>) {
+        self.scroll_buffer.add_class_range(CellClass::Selection, line_id, columns);
+    }
+
+    /// Clears the selection.
+    pub fn clear_selection(&mut self) {
+        self.scroll_buffer.clear_class(CellClass::Selection);
+    }
+
+    /// Copies the selected text, along with its styles and where it came from.
+    ///
+    /// See `ScrollBuffer::copy_selection`.
+    pub fn copy_selection(&self) -> Option<CopyResult> {
+        self.scroll_buffer.copy_selection()
+    }
+
+    /// Gets the commands marked by shell integration, with their output.
+    ///
+    /// See `ScrollBuffer::commands`.
+    pub fn commands(&self) -> Vec<ShellCommand> {
+        self.scroll_buffer.commands()
+    }
+
+    /// Iterates over a range of lines by id, including the scrollback.
+    ///
+    /// See `ScrollBuffer::iter_lines`.
+    pub fn iter_lines(&self, line_ids: Range<usize>) -> impl Iterator<Item = LineView<'_>> {
+        self.scroll_buffer.iter_lines(line_ids)
+    }
+
+    /// Encodes a range of lines by id compactly, such as for remote clients.
+    ///
+    /// See `ScrollBuffer::encode_lines`.
+    pub fn encode_lines(&self, line_ids: Range<usize>) -> Vec<EncodedLine> {
+        self.scroll_buffer.encode_lines(line_ids)
+    }
+
+    /// Converts a cell on a line into a position in its logical line.
+    ///
+    /// See `ScrollBuffer::logical_position`.
+    pub fn logical_position(&self, line_id: usize, column: usize) -> Option<LogicalPosition> {
+        self.scroll_buffer.logical_position(line_id, column)
+    }
+
+    /// Gets where a logical position is shown when wrapped to a width.
+    ///
+    /// See `ScrollBuffer::logical_to_visual`.
+    pub fn logical_to_visual(&self, position: LogicalPosition, width: usize) -> Option<VisualPosition> {
+        self.scroll_buffer.logical_to_visual(position, width)
+    }
+
+    /// Gets the logical position shown at a row and column when wrapped to a width.
+    ///
+    /// See `ScrollBuffer::visual_to_logical`.
+    pub fn visual_to_logical(&self, position: VisualPosition, width: usize) -> Option<LogicalPosition> {
+        self.scroll_buffer.visual_to_logical(position, width)
+    }
+
+    /// Gets the metadata of every visible line.
+    pub fn visible_line_metadata(&self) -> Vec<LineMetadata> {
+        let scrollback_line_count = self.scroll_offset();
+        self.scroll_buffer.visible_line_metadata(scrollback_line_count)
+    }
+
+    /// Finds every visible occurrence of a string whose style satisfies the predicate.
+    pub fn find_text<P>(&self, needle: &str, predicate: P) -> Vec<TextMatch>
+        where P: Fn(&Style) -> bool {
+        let scrollback_line_count = self.scroll_offset();
+        self.scroll_buffer.find_text(scrollback_line_count, needle, predicate)
+    }
+
+    /// Finds every visible match of a regular expression whose style satisfies the predicate.
+    #[cfg(feature = "regex")]
+    pub fn find_regex<P>(&self, regex: &regex::Regex, predicate: P) -> Vec<TextMatch>
+        where P: Fn(&Style) -> bool {
+        let scrollback_line_count = self.scroll_offset();
+        self.scroll_buffer.find_regex(scrollback_line_count, regex, predicate)
+    }
+
+    /// Gets everything needed to draw the cursor.
+    pub fn cursor_state(&self) -> CursorState {
+        let (x, y) = self.scroll_buffer.cursor_xy();
+
+        CursorState {
+            x,
+            y: y + self.scroll_offset(),
+            visible: self.modes.cursor_visible,
+            shape: self.cursor_shape,
+            blinking: self.modes.cursor_blink,
+            color: self.cursor_color.or(self.theme.cursor),
+        }
+    }
+
+    /// Gets the cursor shape, and whether it blinks, as set by the running
+    /// program with DECSCUSR or DECSET 12.
+    pub fn cursor_style(&self) -> (CursorShape, bool) {
+        (self.cursor_shape, self.modes.cursor_blink)
+    }
+
+    /// Gets the window title last set by the running program (OSC 0 or 2).
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Checks whether the running program wants the cursor shown (DECTCEM).
+    ///
+    /// See `cursor_presence` for whether to draw it at a moment.
+    pub fn cursor_visible(&self) -> bool {
+        self.modes.cursor_visible
+    }
+
+    /// Decides whether the frontend should draw the cursor at a moment,
+    /// so that every frontend agrees.
+    ///
+    /// This accounts for the cursor visible mode, focus, the blink cycle,
+    /// and the viewport. Typing and focusing restart the blink cycle.
+    pub fn cursor_presence(&self, now: Instant) -> CursorPresence {
+        let (_, y) = self.scroll_buffer.cursor_xy();
+
+        if !self.modes.cursor_visible {
+            return match self.is_alternate_screen_active() {
+                true => CursorPresence::DrawnByProgram,
+                false => CursorPresence::HiddenByProgram,
+            };
+        }
+        if y + self.scroll_offset() >= self.settings.line_count {
+            return CursorPresence::ScrolledAway;
+        }
+        if !self.focused {
+            return CursorPresence::Unfocused;
+        }
+
+        let interval = self.settings.cursor_blink_interval.as_millis().max(1);
+        let elapsed = now.saturating_duration_since(self.cursor_blink_epoch).as_millis();
+        if self.modes.cursor_blink && (elapsed / interval) % 2 == 1 {
+            return CursorPresence::BlinkedOff;
+        }
+
+        CursorPresence::Shown
+    }
+
+    /// Checks whether the frontend should draw the cursor at a moment.
+    pub fn should_draw_cursor(&self, now: Instant) -> bool {
+        self.cursor_presence(now).is_drawn()
+    }
+
+    /// Gets a visible cell, relative to the top-left corner.
+    pub fn cell(&self, x: usize, y: usize) -> Option<&Cell> {
+        self.scroll_buffer.cell(x, y)
     }
 
     /// Gets the cursor index.
@@ -155,7 +1413,212 @@ impl Terminal {
     }
 
     /// Checks if the underlying shell session has finished.
-    pub fn is_session_finished(&self) -> bool { self.os_driver.is_session_finished() }
+    pub fn is_session_finished(&self) -> bool { self.locked_driver().is_session_finished() }
+
+    /// Gets how the running program exited, once it has and if the driver
+    /// can tell, as also reported by `Event::SessionExited`.
+    pub fn exit_status(&self) -> Option<ExitStatus> { self.locked_driver().exit_status() }
+
+    /// Checks if the driver has lost its transport, after `Event::Disconnected`.
+    pub fn is_disconnected(&self) -> bool { self.disconnected }
+
+    /// Re-establishes a lost transport, keeping the grid and scrollback.
+    ///
+    /// Once reconnected, the reconnect commands from the settings are
+    /// written, each followed by a new line. Drivers for local processes
+    /// cannot reconnect.
+    pub fn reconnect(&mut self) -> Result<(), io::Error> {
+        self.driver().reconnect()?;
+        self.disconnected = false;
+
+        let os_driver = self.os_driver.get_mut().unwrap_or_else(PoisonError::into_inner);
+        for command in self.settings.reconnect_commands.iter() {
+            os_driver.write_text(&format!("{}\n", command))?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the running program again, such as after the shell exits,
+    /// keeping the grid and scrollback.
+    ///
+    /// Nothing the old program set up leaks into the new one. As with a
+    /// soft reset (DECSTR), the modes go back to their defaults, and the
+    /// style, saved cursor and scroll region are forgotten. The primary
+    /// screen is restored, and the palette, colors, title and bell volumes
+    /// set by the old program are dropped. Drivers that did not start their
+    /// program cannot respawn it.
+    pub fn respawn(&mut self) -> Result<(), io::Error> {
+        let settings = self.settings.clone();
+        self.driver().respawn(&settings)?;
+
+        self.modes = ModeState::default();
+        self.set_alternate_screen(false);
+        self.scroll_buffer.soft_reset();
+        self.scroll_buffer.set_reverse_wrap(self.modes.reverse_wrap);
+        self.cursor_shape = CursorShape::default();
+        self.concealed = false;
+        self.eight_bit_controls = false;
+        self.palette = self.settings.palette.clone();
+        self.foreground_color = None;
+        self.background_color = None;
+        self.cursor_color = None;
+        self.title = None;
+        self.warning_bell_volume = BellVolume::High;
+        self.margin_bell_volume = BellVolume::High;
+        self.hyperlink = None;
+        self.command_output_start = None;
+        self.upload = None;
+        self.disconnected = false;
+        self.stats = SessionStats::new(Instant::now());
+        Ok(())
+    }
+
+    /// Gets the approximate memory used by the terminal's buffers.
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        let footprint = self.scroll_buffer.memory_footprint();
+
+        match self.primary_screen.as_ref() {
+            Some(primary_screen) => footprint + primary_screen.memory_footprint(),
+            None => footprint,
+        }
+    }
+
+    /// Evicts up to `line_count` of the oldest scrollback lines, returning
+    /// how many were evicted.
+    ///
+    /// On the alternate screen, the primary screen's scrollback is evicted.
+    pub fn evict_scrollback(&mut self, line_count: usize) -> usize {
+        self.primary_scroll_buffer_mut().evict_scrollback(line_count)
+    }
+
+    /// Gets the number of lines kept in the primary screen's scrollback.
+    pub(crate) fn retained_scrollback_line_count(&self) -> usize {
+        let buffer = self.primary_screen.as_ref().unwrap_or(&self.scroll_buffer);
+        buffer.total_line_count() - self.settings.line_count.min(buffer.total_line_count())
+    }
+
+    /// Gets the approximate memory used by each line.
+    pub(crate) fn line_footprint(&self) -> usize {
+        self.scroll_buffer.line_footprint()
+    }
+
+    fn primary_scroll_buffer_mut(&mut self) -> &mut ScrollBuffer {
+        match self.primary_screen.as_mut() {
+            Some(primary_screen) => primary_screen,
+            None => &mut self.scroll_buffer,
+        }
+    }
+
+    /// Checks if the running program has disabled echo, as password prompts do.
+    ///
+    /// Frontends can use this to show a lock indicator.
+    pub fn is_echo_disabled(&self) -> bool { self.locked_driver().is_echo_disabled() }
+
+    /// Checks if output is stopped by XOFF (^S), when flow control is interpreted.
+    ///
+    /// Output resumes once XON (^Q) is written.
+    pub fn is_flow_stopped(&self) -> bool { self.locked_driver().is_flow_stopped() }
+
+    /// Starts or resumes an interactive search over the scroll buffer.
+    pub fn search(&mut self) -> Search<'_> {
+        self.scroll_buffer.search()
+    }
+
+    /// Checks if output is paused because it looks like binary data.
+    ///
+    /// This only happens with the `Pause` binary output policy, after
+    /// `Event::BinaryOutputDetected`. The running program is blocked
+    /// once the output buffers fill up.
+    pub fn is_output_paused(&self) -> bool { self.locked_driver().is_output_paused() }
+
+    /// Resumes paused output, handling the rest of the binary data with the given policy.
+    pub fn resume_output(&mut self, policy: BinaryOutputPolicy) {
+        self.driver().resume_output(policy);
+    }
+
+    /// Gets a snapshot of the terminal's internal state, for diagnostics.
+    pub fn debug_state(&self) -> DebugState {
+        let driver = self.locked_driver();
+
+        DebugState {
+            modes: self.modes.clone(),
+            cursor: self.scroll_buffer.cursor_xy(),
+            driver: driver.debug_state(),
+            total_lines: self.scroll_buffer.total_line_count(),
+            scrollback_lines: self.scroll_buffer.scrollback_line_count(),
+            evicted_lines: self.scroll_buffer.evicted_line_count(),
+            memory: self.scroll_buffer.memory_footprint(),
+            session_finished: driver.is_session_finished(),
+        }
+    }
+
+    /// Gets the modes set by the running program.
+    pub fn modes(&self) -> &ModeState { &self.modes }
+
+    /// Checks if the bell has rung recently and has not been acknowledged.
+    ///
+    /// The bell expires automatically after the visual bell duration, so
+    /// frontends can flash a tab while this is true.
+    pub fn is_bell_pending(&self) -> bool {
+        match self.bell_rang_at {
+            Some(rang_at) => rang_at.elapsed() < self.settings.visual_bell_duration,
+            None => false,
+        }
+    }
+
+    /// Gets the volume of the bell that rang last, as set by the running
+    /// program, so frontends can adjust how loud or bright it is.
+    pub fn bell_volume(&self) -> BellVolume { self.bell_volume }
+
+    /// Acknowledges the bell, clearing the pending state.
+    pub fn acknowledge_bell(&mut self) {
+        self.bell_rang_at = None;
+    }
+
+    /// Tells the terminal whether the frontend has focused it.
+    ///
+    /// If the running program has enabled focus reporting, it is sent
+    /// `CSI I` on focus and `CSI O` on focus loss.
+    pub fn set_focused(&mut self, focused: bool) {
+        if focused == self.focused {
+            return;
+        }
+
+        self.focused = focused;
+        self.cursor_blink_epoch = Instant::now();
+
+        if self.modes.focus_reporting {
+            self.send_response(if focused { "\x1b[I" } else { "\x1b[O" });
+        }
+    }
+
+    /// Checks whether the frontend has focused the terminal.
+    pub fn is_focused(&self) -> bool { self.focused }
+
+    /// Gets the background to erase with, which is the default unless
+    /// background color erase is enabled.
+    fn erase_background(&self, background: Color) -> Color {
+        if self.settings.background_color_erase { background } else { Color::TRANSPARENT }
+    }
+
+    /// Checks that a bell is not silenced, and did not ring too recently.
+    fn can_ring_bell(&self, volume: BellVolume) -> bool {
+        let too_soon = self.last_bell_at.is_some_and(|last| last.elapsed() < self.settings.bell_rate_limit);
+        volume != BellVolume::Off && !too_soon
+    }
+
+    /// Rings the bell, unless it is silenced or rang too recently.
+    fn ring_bell(&mut self, volume: BellVolume) {
+        if !self.can_ring_bell(volume) {
+            return;
+        }
+
+        let now = Instant::now();
+        self.last_bell_at = Some(now);
+        self.bell_rang_at = Some(now);
+        self.bell_volume = volume;
+    }
 
     /// Handles a terminal event.
     fn handle_event(&mut self, event: &Event) {
@@ -164,16 +1627,299 @@ impl Terminal {
         match *event {
             // FIXME: we should take into account position.
             // there are x,y values in Char
-            PutCharacter { x, y, character, color, .. } => {
+            PutCharacter { x, y, character, bold, italic, underlined, strikethrough, overline, color, background } => {
                 self.scroll_buffer.set_cursor_xy(x, y);
 
+                #[cfg(feature = "scripting")]
+                {
+                    if character == '\n' && !self.scripts.is_empty() {
+                        let line_id = self.scroll_buffer.visible_line_id(0, y);
+                        let (line_ids, text) = self.scroll_buffer.logical_line_text(line_id);
+                        self.run_scripts(line_ids, |script| script.on_line(&text));
+                    }
+                }
+
                 self.scroll_buffer.put_character_styled(character, Style {
                     color,
+                    background,
+                    bold,
+                    italic,
+                    underlined,
+                    strikethrough,
+                    overline,
+                    concealed: self.concealed,
                 });
+                if let Some(uri) = self.hyperlink.as_ref() {
+                    self.scroll_buffer.add_link(x, y, uri);
+                }
             },
             ClearScreen => {
                 self.scroll_buffer.clear_visible();
             },
+            CopyRect { source, x, y } => {
+                self.scroll_buffer.copy_rect(source, x, y);
+            },
+            FillRect { area, character, ref style } => {
+                self.scroll_buffer.fill_rect(area, character, Style { concealed: self.concealed, ..style.clone() });
+            },
+            EraseInLine { x, y, mode, background } => {
+                self.scroll_buffer.set_cursor_xy(x, y);
+                self.scroll_buffer.erase_in_line(mode, self.erase_background(background));
+            },
+            EraseInDisplay { x, y, mode, background } => {
+                self.scroll_buffer.set_cursor_xy(x, y);
+                self.scroll_buffer.erase_in_display(mode, self.erase_background(background));
+            },
+            SaveCursor => {
+                self.scroll_buffer.save_cursor();
+            },
+            RestoreCursor => {
+                self.scroll_buffer.restore_cursor();
+            },
+            InsertCharacters { x, y, count } => {
+                self.scroll_buffer.set_cursor_xy(x, y);
+                self.scroll_buffer.insert_chars(count);
+            },
+            DeleteCharacters { x, y, count } => {
+                self.scroll_buffer.set_cursor_xy(x, y);
+                self.scroll_buffer.delete_chars(count);
+            },
+            InsertLines { y, count } => {
+                self.scroll_buffer.set_cursor_xy(0, y);
+                self.scroll_buffer.insert_lines(count);
+            },
+            DeleteLines { y, count } => {
+                self.scroll_buffer.set_cursor_xy(0, y);
+                self.scroll_buffer.delete_lines(count);
+            },
+            EraseRect { area, background } => {
+                self.scroll_buffer.erase_rect_with_background(area, self.erase_background(background));
+            },
+            SetWarningBellVolume(volume) => {
+                self.warning_bell_volume = volume;
+            },
+            SetMarginBellVolume(volume) => {
+                self.margin_bell_volume = volume;
+            },
+            SetHyperlink { ref uri } => {
+                self.hyperlink = uri.clone();
+            },
+            SetTitle(ref title) => {
+                self.title = Some(title.clone());
+            },
+            SetConcealed { enabled } => {
+                self.concealed = enabled;
+            },
+            SetAlternateScreen { enabled } => {
+                self.set_alternate_screen(enabled);
+            },
+            SetScrollRegion { top, bottom } => {
+                self.scroll_buffer.set_scroll_region(top..bottom);
+            },
+            ModeQueried { number, dec_private } => {
+                self.report_mode(number, dec_private);
+            },
+            SetMode { mode, enabled } => {
+                self.modes.set(mode, enabled);
+                self.scroll_buffer.set_reverse_wrap(self.modes.reverse_wrap);
+            },
+            SetEightBitControls { enabled } => {
+                self.eight_bit_controls = enabled && self.settings.c1_controls == C1Controls::Recognize;
+            },
+            SetCursorStyle { shape, blinking } => {
+                self.cursor_shape = shape;
+                self.modes.cursor_blink = blinking;
+            },
+            DynamicColorChanged { target, color } => {
+                match target {
+                    DynamicColor::Foreground => self.foreground_color = color,
+                    DynamicColor::Background => self.background_color = color,
+                    DynamicColor::Cursor => self.cursor_color = color,
+                }
+            },
+            DynamicColorQueried(target) => {
+                let color = self.dynamic_color(target);
+                self.send_response(&format!("\x1b]{};{}\x1b\\", target.osc_number(), color.to_x11()));
+            },
+            PaletteChanged { index, color } => {
+                let color = color.unwrap_or_else(|| self.settings.palette.indexed(index));
+                self.palette.set_indexed(index, color);
+            },
+            PaletteQueried(index) => {
+                let color = self.palette.indexed(index);
+                self.send_response(&format!("\x1b]4;{};{}\x1b\\", index, color.to_x11()));
+            },
+            ShellIntegration(mark) => {
+                self.handle_shell_mark(mark);
+            },
+            Disconnected => {
+                self.disconnected = true;
+            },
+            Bell => {
+                self.ring_bell(self.warning_bell_volume);
+            },
+            SoftFontIgnored { font_number } => {
+                info!("ignoring soft font {}, as soft fonts are not supported", font_number);
+            },
+            ParserReset { reason } => {
+                warn!("the output looked garbled ({:?}), so the parser was reset", reason);
+            },
+            ControlStringDiscarded { kind, length } => {
+                warn!("discarding a {:?} string of {} bytes, which is over the limit", kind, length);
+            },
+            SessionExited { code, signal } => {
+                info!("the running program exited with code {:?} and signal {:?}", code, signal);
+            },
+            DescendantExited { .. } | ScrollbackEvicted { .. } | DcsPassthrough(..) | BinaryOutputDetected | ResizeRequested { .. } | CommandFinished { .. } | ReflowProgress(..) |
+                AutoscrollSuppressed { .. } | UploadProgress(..) | UploadFailed { .. } | InputReplayed(..) => (),
+        }
+    }
+
+    /// Calls `on_prompt` if output stopped partway through a line, as at a
+    /// prompt, unless the scripts have already seen it.
+    #[cfg(feature = "scripting")]
+    fn run_prompt_scripts(&mut self) {
+        let (x, y) = self.scroll_buffer.cursor_xy();
+        if self.scripts.is_empty() || x == 0 {
+            return;
+        }
+
+        let line_id = self.scroll_buffer.visible_line_id(0, y);
+        let (line_ids, text) = self.scroll_buffer.logical_line_text(line_id);
+        let prompt = (line_ids.start, text);
+        if prompt.1.is_empty() || self.last_prompt.as_ref() == Some(&prompt) {
+            return;
+        }
+
+        self.run_scripts(line_ids, |script| script.on_prompt(&prompt.1));
+        self.last_prompt = Some(prompt);
+    }
+
+    /// Runs a hook of every script, then does what they asked for.
+    ///
+    /// Marks go on the lines the hook is about. Failing scripts are logged.
+    #[cfg(feature = "scripting")]
+    fn run_scripts<F>(&mut self, line_ids: Range<usize>, mut hook: F)
+        where F: FnMut(&mut Script) -> Result<Vec<ScriptAction>, ScriptError> {
+        let mut actions = Vec::new();
+        for script in self.scripts.iter_mut() {
+            match hook(script) {
+                Ok(script_actions) => actions.extend(script_actions),
+                Err(e) => warn!("{}", e),
+            }
+        }
+
+        for action in actions {
+            match action {
+                ScriptAction::Send(text) => self.write_unprompted(text.as_bytes()),
+                ScriptAction::MarkLine => self.scroll_buffer.add_line_class(CellClass::Marker, line_ids.clone()),
+            }
+        }
+    }
+
+    /// Tracks command output, classifying it once the command finishes.
+    fn handle_shell_mark(&mut self, mark: ShellMark) {
+        self.scroll_buffer.record_shell_mark(mark);
+        let (x, y) = self.scroll_buffer.cursor_xy();
+        let cursor_line_id = self.scroll_buffer.visible_line_id(0, y);
+
+        match mark {
+            ShellMark::OutputStart => {
+                self.command_output_start = Some(cursor_line_id);
+            },
+            ShellMark::CommandFinished { exit_code } => {
+                #[cfg(feature = "scripting")]
+                self.run_scripts(cursor_line_id..cursor_line_id + 1, |script| script.on_command_finished(exit_code));
+
+                let start = match self.command_output_start.take() {
+                    Some(start) => start,
+                    None => return,
+                };
+                // Output normally ends with a newline, leaving the cursor on an empty line.
+                let end = if x > 0 { cursor_line_id + 1 } else { cursor_line_id };
+
+                let command = FinishedCommand {
+                    output: self.scroll_buffer.lines_text(start..end),
+                    exit_code,
+                };
+
+                if let Some(classifier) = self.error_classifier.as_mut() {
+                    if (*classifier.get_mut().unwrap_or_else(PoisonError::into_inner))(&command) {
+                        self.scroll_buffer.add_line_class(CellClass::ErrorStream, start..end);
+                    }
+                }
+            },
+            ShellMark::PromptStart | ShellMark::CommandStart => (),
+        }
+    }
+}
+
+impl FinishedCommand {
+    /// Checks if the command exited with a nonzero code.
+    pub fn failed(&self) -> bool {
+        self.exit_code.is_some_and(|code| code != 0)
+    }
+}
+
+/// Normalizes a URI, lowercasing its scheme.
+///
+/// Returns `None` if it has no valid scheme, or contains whitespace or
+/// control characters that could disguise where it goes.
+fn normalize_uri(uri: &str) -> Option<String> {
+    let (scheme, rest) = uri.split_at(uri.find(':')?);
+    let valid_scheme = scheme.chars().next().is_some_and(|c| c.is_ascii_alphabetic()) &&
+        scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+
+    if !valid_scheme || rest.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return None;
+    }
+
+    Some(format!("{}{}", scheme.to_ascii_lowercase(), rest))
+}
+
+/// Gets the settings of a screen's scroll buffer.
+fn scroll_buffer_settings(settings: &Settings, lines_to_remember: usize) -> scroll_buffer::Settings {
+    scroll_buffer::Settings {
+        lines_to_remember,
+        max_lines: settings.line_count,
+        max_columns: settings.column_count,
+        tab_width: settings.tab_width,
+        record_timestamps: settings.record_line_timestamps,
+        timestamp_gutter: settings.timestamp_gutter,
+    }
+}
+
+/// Replaces the 7-bit escapes in a response with 8-bit C1 controls,
+/// such as `ESC [` with CSI.
+///
+/// The controls are sent UTF-8 encoded, as xterm does in UTF-8 mode.
+fn to_eight_bit_controls(response: &str) -> String {
+    let mut converted = String::with_capacity(response.len());
+    let mut chars = response.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match chars.peek().cloned() {
+            Some(next @ '@'..='_') if c == '\x1b' => {
+                chars.next();
+                converted.push((next as u8 + 0x40) as char);
+            },
+            _ => converted.push(c),
+        }
+    }
+
+    converted
+}
+
+impl SessionStats {
+    /// Creates the statistics for a session starting at `now`.
+    fn new(now: Instant) -> Self {
+        SessionStats {
+            bytes_in: 0,
+            bytes_out: 0,
+            lines_scrolled: 0,
+            commands_run: 0,
+            started_at: now,
+            last_activity: now,
         }
     }
 }
@@ -188,16 +1934,38 @@ impl Default for Settings {
 
         Settings {
             shell,
+            environment: Vec::new(),
             lines_to_remember: 10_000,
             line_count: 100,
             column_count: 85,
             tab_width: 2,
+            visual_bell_duration: Duration::from_millis(150),
+            bell_rate_limit: Duration::from_millis(100),
+            cursor_blink_interval: Duration::from_millis(530),
+            margin_bell_distance: 8,
+            local_echo: false,
+            protect_secret_input: true,
+            cancel_input_on_interrupt: true,
+            reap_orphaned_descendants: false,
+            record_line_timestamps: false,
+            timestamp_gutter: TimestampGutter::Hidden,
+            binary_output: BinaryOutputPolicy::Replace,
+            line_endings: LineEndings::Preserve,
+            flow_control: FlowControl::Forward,
+            c1_controls: C1Controls::Text,
+            autoscroll: Autoscroll::WhenAtBottom,
+            reconnect_commands: Vec::new(),
+            link_schemes: vec!["http".to_owned(), "https".to_owned(), "mailto".to_owned()],
+            backspace_key: BackspaceKey::Program,
+            background_color_erase: true,
+            palette: Palette::default(),
+            string_limits: StringLimits::default(),
         }
     }
 }
 
 impl Action {
-    pub fn apply(self, term: &mut Terminal) {
+    pub fn apply(self, term: &mut Terminal) -> Result<(), io::Error> {
         match self {
             Action::WriteText(ref text) => term.write_text(text),
             Action::Backspace => term.backspace(),
@@ -207,6 +1975,7 @@ impl Action {
             Action::CursorUp => term.cursor_up(),
             Action::CursorDown => term.cursor_down(),
             Action::ControlCode(c) => term.control_code(c),
+            Action::Key(event) => term.send_key(event.key, event.modifiers),
         }
     }
 }
@@ -0,0 +1,73 @@
+//! Cursor state requested by the running program.
+
+use crate::Color;
+
+/// The shape of the cursor.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum CursorShape {
+    /// A block covering the whole cell.
+    #[default]
+    Block,
+    /// A line under the cell.
+    Underline,
+    /// A vertical bar to the left of the cell.
+    Bar,
+}
+
+/// Everything a frontend needs to draw the cursor.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct CursorState {
+    /// The column, relative to the left.
+    pub x: usize,
+    /// The line, relative to the top of the viewport.
+    pub y: usize,
+    /// Whether the cursor should be shown at all (DECTCEM).
+    pub visible: bool,
+    /// The shape of the cursor (DECSCUSR).
+    pub shape: CursorShape,
+    /// Whether the cursor should blink (DECSET 12 or DECSCUSR).
+    pub blinking: bool,
+    /// The cursor color set by the program (OSC 12), or `None` for the default.
+    pub color: Option<Color>,
+}
+
+/// Whether the cursor should be drawn, and why, from `Terminal::cursor_presence`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CursorPresence {
+    /// Draw the cursor as its state describes.
+    Shown,
+    /// Draw the cursor as a hollow outline, as the terminal is not focused.
+    Unfocused,
+    /// Hidden for this half of the blink cycle.
+    BlinkedOff,
+    /// The running program hid the cursor (DECTCEM).
+    HiddenByProgram,
+    /// The running program hid the cursor on the alternate screen, where
+    /// full-screen programs usually draw their own, such as a reverse-video block.
+    DrawnByProgram,
+    /// The viewport is scrolled so that the cursor line is out of view.
+    ScrolledAway,
+}
+
+impl CursorPresence {
+    /// Checks whether the frontend should draw the cursor.
+    pub fn is_drawn(self) -> bool {
+        matches!(self, CursorPresence::Shown | CursorPresence::Unfocused)
+    }
+}
+
+impl CursorShape {
+    /// Decodes the shape and blinking state from a DECSCUSR parameter.
+    pub fn from_decscusr(parameter: usize) -> Option<(CursorShape, bool)> {
+        match parameter {
+            0 | 1 => Some((CursorShape::Block, true)),
+            2 => Some((CursorShape::Block, false)),
+            3 => Some((CursorShape::Underline, true)),
+            4 => Some((CursorShape::Underline, false)),
+            5 => Some((CursorShape::Bar, true)),
+            6 => Some((CursorShape::Bar, false)),
+            _ => None,
+        }
+    }
+}
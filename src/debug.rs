@@ -0,0 +1,37 @@
+//! Snapshots of internal state for diagnosing rendering bugs.
+
+use crate::{modes::ModeState, scroll_buffer::MemoryFootprint};
+
+/// A snapshot of the terminal's internal state.
+///
+/// Frontends can display this in a diagnostics panel, or attach it to bug reports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DebugState {
+    /// The modes set by the running program.
+    pub modes: ModeState,
+    /// The cursor position as `(x, y)`, relative to the top-left.
+    pub cursor: (usize, usize),
+    /// The state of the escape sequence parser.
+    pub driver: DriverDebugState,
+    /// The number of lines in the buffer, including scrollback.
+    pub total_lines: usize,
+    /// The number of lines in the scrollback.
+    pub scrollback_lines: usize,
+    /// The number of lines evicted from the scrollback so far.
+    pub evicted_lines: usize,
+    /// The approximate memory used by the buffer.
+    pub memory: MemoryFootprint,
+    /// Whether the shell session has finished.
+    pub session_finished: bool,
+}
+
+/// A snapshot of an operating-system specific driver's internal state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DriverDebugState {
+    /// The name of the parser's current state, such as `"ground"`.
+    pub parser_state: &'static str,
+    /// The number of bytes received but not yet turned into events.
+    pub pending_bytes: usize,
+}
@@ -0,0 +1,399 @@
+//! Encoding keys and mouse reports into the bytes a terminal sends to the
+//! running program.
+//!
+//! These follow xterm, so frontends that write to a PTY themselves can send
+//! exactly what `Terminal::send_key` and the mouse methods would.
+
+use crate::modes::ModeState;
+use core::convert::TryFrom;
+#[allow(unused_imports)]
+use crate::std_prelude::*;
+
+/// A key on the keyboard.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Key {
+    /// A key that types a character, already shifted.
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// A function key, from F1 to F24.
+    Function(u8),
+    /// A key on the numeric keypad: a digit, one of `*+,-./=`, or `\r`
+    /// for its Enter key.
+    Keypad(char),
+}
+
+/// A key pressed with modifiers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+/// The modifier keys held down with a key.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+    pub meta: bool,
+}
+
+impl KeyEvent {
+    /// Creates a key event.
+    pub fn new(key: Key, modifiers: Modifiers) -> Self {
+        KeyEvent { key, modifiers }
+    }
+
+    /// Encodes the key, as with `key`.
+    pub fn encode(&self, modes: &ModeState) -> Vec<u8> {
+        key(self.key, self.modifiers, modes)
+    }
+}
+
+impl From<Key> for KeyEvent {
+    fn from(key: Key) -> Self {
+        KeyEvent::new(key, Modifiers::NONE)
+    }
+}
+
+/// A mouse button.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    /// The wheel scrolling up, which is reported as a button press.
+    WheelUp,
+    /// The wheel scrolling down.
+    WheelDown,
+}
+
+/// Something the mouse did, to report to the running program.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MouseAction {
+    Press(MouseButton),
+    Release(MouseButton),
+    /// The mouse moved, with a button held or not.
+    Move(Option<MouseButton>),
+}
+
+impl Modifiers {
+    /// No modifiers.
+    pub const NONE: Modifiers = Modifiers { shift: false, alt: false, ctrl: false, meta: false };
+
+    /// Checks if no modifiers are held.
+    pub fn is_empty(&self) -> bool {
+        *self == Modifiers::NONE
+    }
+
+    /// Gets the modifier parameter of a CSI sequence, as in `CSI 1 ; 5 A`
+    /// for Ctrl+Up, or `None` without modifiers.
+    pub fn parameter(&self) -> Option<u8> {
+        let bits = self.shift as u8 | (self.alt as u8) << 1 | (self.ctrl as u8) << 2 | (self.meta as u8) << 3;
+        if bits == 0 { None } else { Some(bits + 1) }
+    }
+}
+
+/// Encodes a key pressed with modifiers, given the modes the running program set.
+///
+/// Alt and meta prefix characters with ESC, and ctrl turns them into control
+/// codes. Other keys carry their modifiers as a CSI parameter.
+pub fn key(key: Key, modifiers: Modifiers, modes: &ModeState) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    match key {
+        Key::Char(c) => {
+            if modifiers.alt || modifiers.meta {
+                bytes.push(ESC);
+            }
+
+            match control_byte(c).filter(|_| modifiers.ctrl) {
+                Some(byte) => bytes.push(byte),
+                None => bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        },
+        Key::Enter | Key::Escape | Key::Backspace => {
+            if modifiers.alt || modifiers.meta {
+                bytes.push(ESC);
+            }
+
+            bytes.push(match key {
+                Key::Enter => b'\r',
+                Key::Escape => ESC,
+                // Ctrl swaps what backspace sends, as in xterm.
+                _ => match modes.backarrow_key != modifiers.ctrl {
+                    true => 0x08,
+                    false => 0x7f,
+                },
+            });
+        },
+        Key::Tab if modifiers.shift => bytes.extend(b"\x1b[Z"),
+        Key::Tab => {
+            if modifiers.alt || modifiers.meta {
+                bytes.push(ESC);
+            }
+            bytes.push(b'\t');
+        },
+        Key::Up => cursor_key(&mut bytes, b'A', modifiers, modes),
+        Key::Down => cursor_key(&mut bytes, b'B', modifiers, modes),
+        Key::Right => cursor_key(&mut bytes, b'C', modifiers, modes),
+        Key::Left => cursor_key(&mut bytes, b'D', modifiers, modes),
+        Key::Home => cursor_key(&mut bytes, b'H', modifiers, modes),
+        Key::End => cursor_key(&mut bytes, b'F', modifiers, modes),
+        Key::Insert => tilde_key(&mut bytes, 2, modifiers),
+        Key::Delete => tilde_key(&mut bytes, 3, modifiers),
+        Key::PageUp => tilde_key(&mut bytes, 5, modifiers),
+        Key::PageDown => tilde_key(&mut bytes, 6, modifiers),
+        Key::Function(number @ 1..=4) => {
+            let final_byte = b'P' + number - 1;
+
+            // Unmodified, these are sent as SS3 in ANSI mode.
+            match (modes.ansi, modifiers.parameter()) {
+                (true, None) => bytes.extend(&[ESC, b'O', final_byte]),
+                _ => cursor_key(&mut bytes, final_byte, modifiers, modes),
+            }
+        },
+        Key::Keypad(c) => match keypad_final_byte(c).filter(|_| modes.application_keypad) {
+            Some(final_byte) => {
+                let introducer = if modes.ansi { b'O' } else { b'?' };
+                bytes.extend(&[ESC, introducer, final_byte]);
+            },
+            None if c == '\r' => return self::key(Key::Enter, modifiers, modes),
+            None => return self::key(Key::Char(c), modifiers, modes),
+        },
+        // F13 to F24 are sent as F1 to F12 with shift, as in xterm.
+        Key::Function(number @ 13..=24) => {
+            return self::key(Key::Function(number - 12), Modifiers { shift: true, ..modifiers }, modes);
+        },
+        Key::Function(number) => {
+            let code = match number {
+                5 => 15,
+                6..=10 => number + 11,
+                11 | 12 => number + 12,
+                _ => return bytes,
+            };
+            tilde_key(&mut bytes, code, modifiers);
+        },
+    }
+
+    bytes
+}
+
+/// Encodes a mouse report for a cell on the grid, given the modes the
+/// running program set.
+///
+/// Returns `None` if the program did not ask for the action to be reported,
+/// or if the cell is too far out to encode without SGR mode.
+pub fn mouse(action: MouseAction, column: usize, line: usize, modifiers: Modifiers, modes: &ModeState) -> Option<Vec<u8>> {
+    let reported = match action {
+        MouseAction::Press(_) => modes.is_mouse_tracking(),
+        MouseAction::Release(_) => modes.mouse_click || modes.mouse_drag || modes.mouse_motion,
+        MouseAction::Move(Some(_)) => modes.mouse_drag || modes.mouse_motion,
+        MouseAction::Move(None) => modes.mouse_motion,
+    };
+    if !reported {
+        return None;
+    }
+
+    let (button, release) = match action {
+        MouseAction::Press(button) => (button_code(button), false),
+        // Without SGR, releases cannot say which button was released.
+        MouseAction::Release(_) if !modes.mouse_sgr => (3, true),
+        MouseAction::Release(button) => (button_code(button), true),
+        MouseAction::Move(button) => (button.map_or(3, button_code) + 32, false),
+    };
+
+    // X10 style reports carry no modifiers.
+    let only_presses = !(modes.mouse_click || modes.mouse_drag || modes.mouse_motion);
+    let modifiers = match only_presses {
+        true => 0,
+        false => (modifiers.shift as u8) << 2 | ((modifiers.alt || modifiers.meta) as u8) << 3 | (modifiers.ctrl as u8) << 4,
+    };
+    let code = button | modifiers;
+
+    if modes.mouse_sgr {
+        let final_byte = if release { 'm' } else { 'M' };
+        return Some(format!("\x1b[<{};{};{}{}", code, column + 1, line + 1, final_byte).into_bytes());
+    }
+
+    // Positions are sent as single bytes, offset by 32 and counted from 1.
+    let position_byte = |position: usize| u8::try_from(position + 33).ok();
+    let (column, line) = (position_byte(column)?, position_byte(line)?);
+    Some(vec![ESC, b'[', b'M', code + 32, column, line])
+}
+
+/// Gets the code a button is reported with, before modifiers.
+fn button_code(button: MouseButton) -> u8 {
+    match button {
+        MouseButton::Left => 0,
+        MouseButton::Middle => 1,
+        MouseButton::Right => 2,
+        MouseButton::WheelUp => 64,
+        MouseButton::WheelDown => 65,
+    }
+}
+
+/// Gets the control code typed with ctrl and a character, such as 0x03 for `c`.
+pub fn control_byte(c: char) -> Option<u8> {
+    match c.to_ascii_uppercase() {
+        ' ' => Some(0x00),
+        c @ '@'..='_' => Some(c as u8 & 0x1f),
+        '?' => Some(0x7f),
+        _ => None,
+    }
+}
+
+const ESC: u8 = 0x1b;
+
+/// Encodes a key such as a cursor key as `CSI A`, `ESC O A` in application
+/// cursor key mode, or `ESC A` in VT52 mode.
+fn cursor_key(bytes: &mut Vec<u8>, final_byte: u8, modifiers: Modifiers, modes: &ModeState) {
+    match (modes.ansi, modifiers.parameter()) {
+        (false, _) => bytes.extend(&[ESC, final_byte]),
+        (true, None) if modes.application_cursor_keys => bytes.extend(&[ESC, b'O', final_byte]),
+        (true, None) => bytes.extend(&[ESC, b'[', final_byte]),
+        (true, Some(parameter)) => {
+            bytes.extend(format!("\x1b[1;{}", parameter).as_bytes());
+            bytes.push(final_byte);
+        },
+    }
+}
+
+/// Gets the final byte a keypad key is sent with in application keypad mode,
+/// as in `ESC O p` for 0.
+fn keypad_final_byte(c: char) -> Option<u8> {
+    match c {
+        '0'..='9' => Some(b'p' + (c as u8 - b'0')),
+        '*' => Some(b'j'),
+        '+' => Some(b'k'),
+        ',' => Some(b'l'),
+        '-' => Some(b'm'),
+        '.' => Some(b'n'),
+        '/' => Some(b'o'),
+        '=' => Some(b'X'),
+        '\r' => Some(b'M'),
+        _ => None,
+    }
+}
+
+/// Encodes an editing or function key as `CSI code ~`.
+fn tilde_key(bytes: &mut Vec<u8>, code: u8, modifiers: Modifiers) {
+    match modifiers.parameter() {
+        Some(parameter) => bytes.extend(format!("\x1b[{};{}~", code, parameter).as_bytes()),
+        None => bytes.extend(format!("\x1b[{}~", code).as_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode(k: Key, modifiers: Modifiers) -> Vec<u8> {
+        key(k, modifiers, &ModeState::default())
+    }
+
+    const SHIFT: Modifiers = Modifiers { shift: true, ..Modifiers::NONE };
+    const ALT: Modifiers = Modifiers { alt: true, ..Modifiers::NONE };
+    const CTRL: Modifiers = Modifiers { ctrl: true, ..Modifiers::NONE };
+
+    #[test]
+    fn encodes_characters_with_ctrl_and_alt() {
+        assert_eq!(b"a".to_vec(), encode(Key::Char('a'), Modifiers::NONE));
+        assert_eq!("é".as_bytes().to_vec(), encode(Key::Char('é'), SHIFT));
+        assert_eq!(vec![0x03], encode(Key::Char('c'), CTRL));
+        assert_eq!(vec![0x00], encode(Key::Char(' '), CTRL));
+        assert_eq!(vec![0x1b], encode(Key::Char('['), CTRL));
+        assert_eq!(b"\x1bx".to_vec(), encode(Key::Char('x'), ALT));
+        assert_eq!(vec![0x1b, 0x01], encode(Key::Char('a'), Modifiers { ctrl: true, meta: true, ..Modifiers::NONE }));
+    }
+
+    #[test]
+    fn encodes_editing_keys() {
+        assert_eq!(b"\r".to_vec(), encode(Key::Enter, Modifiers::NONE));
+        assert_eq!(b"\x1b[Z".to_vec(), encode(Key::Tab, SHIFT));
+        assert_eq!(vec![0x7f], encode(Key::Backspace, Modifiers::NONE));
+        assert_eq!(vec![0x08], encode(Key::Backspace, CTRL));
+        assert_eq!(vec![0x08], key(Key::Backspace, Modifiers::NONE, &ModeState { backarrow_key: true, ..ModeState::default() }));
+        assert_eq!(b"\x1b[3~".to_vec(), encode(Key::Delete, Modifiers::NONE));
+        assert_eq!(b"\x1b[5;5~".to_vec(), encode(Key::PageUp, CTRL));
+    }
+
+    #[test]
+    fn encodes_cursor_keys_like_xterm() {
+        assert_eq!(b"\x1b[A".to_vec(), encode(Key::Up, Modifiers::NONE));
+        assert_eq!(b"\x1b[1;2D".to_vec(), encode(Key::Left, SHIFT));
+        assert_eq!(b"\x1b[1;5C".to_vec(), encode(Key::Right, CTRL));
+        assert_eq!(b"\x1b[1;3H".to_vec(), encode(Key::Home, ALT));
+        assert_eq!(b"\x1bB".to_vec(), key(Key::Down, Modifiers::NONE, &ModeState { ansi: false, ..ModeState::default() }));
+    }
+
+    #[test]
+    fn encodes_application_cursor_keys_and_keypad() {
+        let modes = ModeState { application_cursor_keys: true, application_keypad: true, ..ModeState::default() };
+
+        assert_eq!(b"\x1bOA".to_vec(), key(Key::Up, Modifiers::NONE, &modes));
+        assert_eq!(b"\x1bOH".to_vec(), key(Key::Home, Modifiers::NONE, &modes));
+        assert_eq!(b"\x1b[1;5A".to_vec(), key(Key::Up, CTRL, &modes));
+        assert_eq!(b"\x1bOp".to_vec(), key(Key::Keypad('0'), Modifiers::NONE, &modes));
+        assert_eq!(b"\x1bOM".to_vec(), key(Key::Keypad('\r'), Modifiers::NONE, &modes));
+
+        assert_eq!(b"7".to_vec(), encode(Key::Keypad('7'), Modifiers::NONE));
+        assert_eq!(b"\r".to_vec(), encode(Key::Keypad('\r'), Modifiers::NONE));
+    }
+
+    #[test]
+    fn encodes_function_keys_like_xterm() {
+        assert_eq!(b"\x1bOP".to_vec(), encode(Key::Function(1), Modifiers::NONE));
+        assert_eq!(b"\x1b[1;2S".to_vec(), encode(Key::Function(4), SHIFT));
+        assert_eq!(b"\x1b[15~".to_vec(), encode(Key::Function(5), Modifiers::NONE));
+        assert_eq!(b"\x1b[21~".to_vec(), encode(Key::Function(10), Modifiers::NONE));
+        assert_eq!(b"\x1b[24;5~".to_vec(), encode(Key::Function(12), CTRL));
+        assert_eq!(b"\x1b[1;2P".to_vec(), encode(Key::Function(13), Modifiers::NONE));
+        assert_eq!(b"\x1b[24;6~".to_vec(), encode(Key::Function(24), CTRL));
+        assert!(encode(Key::Function(25), Modifiers::NONE).is_empty());
+        assert!(encode(Key::Function(0), Modifiers::NONE).is_empty());
+    }
+
+    #[test]
+    fn reports_the_mouse_only_as_asked() {
+        let press = MouseAction::Press(MouseButton::Left);
+        let release = MouseAction::Release(MouseButton::Left);
+        assert_eq!(None, mouse(press, 0, 0, Modifiers::NONE, &ModeState::default()));
+
+        let x10 = ModeState { mouse_press: true, ..ModeState::default() };
+        assert_eq!(Some(b"\x1b[M !\"".to_vec()), mouse(press, 0, 1, CTRL, &x10));
+        assert_eq!(None, mouse(release, 0, 1, Modifiers::NONE, &x10));
+
+        let click = ModeState { mouse_click: true, ..ModeState::default() };
+        assert_eq!(Some(b"\x1b[M#!!".to_vec()), mouse(release, 0, 0, Modifiers::NONE, &click));
+        assert_eq!(Some(b"\x1b[M`%%".to_vec()), mouse(MouseAction::Press(MouseButton::WheelUp), 4, 4, Modifiers::NONE, &click));
+        assert_eq!(None, mouse(MouseAction::Move(Some(MouseButton::Left)), 0, 0, Modifiers::NONE, &click));
+        assert_eq!(None, mouse(press, 223, 0, Modifiers::NONE, &click));
+
+        let drag = ModeState { mouse_drag: true, ..ModeState::default() };
+        assert_eq!(Some(b"\x1b[M@!!".to_vec()), mouse(MouseAction::Move(Some(MouseButton::Left)), 0, 0, Modifiers::NONE, &drag));
+        assert_eq!(None, mouse(MouseAction::Move(None), 0, 0, Modifiers::NONE, &drag));
+    }
+
+    #[test]
+    fn reports_the_mouse_with_sgr_sequences() {
+        let modes = ModeState { mouse_motion: true, mouse_sgr: true, ..ModeState::default() };
+
+        assert_eq!(Some(b"\x1b[<18;300;2M".to_vec()), mouse(MouseAction::Press(MouseButton::Right), 299, 1, CTRL, &modes));
+        assert_eq!(Some(b"\x1b[<2;1;1m".to_vec()), mouse(MouseAction::Release(MouseButton::Right), 0, 0, Modifiers::NONE, &modes));
+        assert_eq!(Some(b"\x1b[<39;1;1M".to_vec()), mouse(MouseAction::Move(None), 0, 0, SHIFT, &modes));
+    }
+}
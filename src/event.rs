@@ -1,4 +1,7 @@
-use crate::Color;
+use crate::{cursor::CursorShape, modes::Mode, parser::ControlStringKind, scroll_buffer::ReflowProgress, Color, Rect, Style};
+#[allow(unused_imports)]
+use crate::std_prelude::*;
+use core::time::Duration;
 
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -11,7 +14,366 @@ pub enum Event {
         italic: bool,
         underlined: bool,
         strikethrough: bool,
+        overline: bool,
         color: Color,
+        /// The background color, `Color::TRANSPARENT` for the default.
+        background: Color,
     },
     ClearScreen,
+    /// Copies a rectangular area to another location (DECCRA).
+    CopyRect {
+        source: Rect,
+        /// The destination column of the top-left corner.
+        x: usize,
+        /// The destination line of the top-left corner.
+        y: usize,
+    },
+    /// Fills a rectangular area with a character (DECFRA), drawn with the pen.
+    FillRect {
+        area: Rect,
+        character: char,
+        style: Style,
+    },
+    /// Erases part of the cursor line (EL), with the cursor at `(x, y)`.
+    EraseInLine {
+        x: usize,
+        y: usize,
+        mode: EraseMode,
+        /// The background to fill the erased cells with, or `Color::TRANSPARENT`.
+        background: Color,
+    },
+    /// Erases part of the display (ED), with the cursor at `(x, y)`.
+    EraseInDisplay {
+        x: usize,
+        y: usize,
+        mode: EraseMode,
+        /// The background to fill the erased cells with, or `Color::TRANSPARENT`.
+        background: Color,
+    },
+    /// The running program saved the cursor and its style (DECSC).
+    SaveCursor,
+    /// The running program restored the cursor and style it saved (DECRC).
+    RestoreCursor,
+    /// Inserts blank characters at `(x, y)`, shifting the rest of the line right (ICH).
+    InsertCharacters {
+        x: usize,
+        y: usize,
+        count: usize,
+    },
+    /// Deletes characters at `(x, y)`, shifting the rest of the line left (DCH).
+    DeleteCharacters {
+        x: usize,
+        y: usize,
+        count: usize,
+    },
+    /// Inserts blank lines at line `y`, shifting the lines below it down
+    /// within the scroll region (IL).
+    InsertLines {
+        y: usize,
+        count: usize,
+    },
+    /// Deletes lines at line `y`, shifting the lines below it up within
+    /// the scroll region (DL).
+    DeleteLines {
+        y: usize,
+        count: usize,
+    },
+    /// Erases a rectangular area, such as with ECH or DECERA.
+    EraseRect {
+        area: Rect,
+        /// The background to fill the area with, from the pen at the time
+        /// of the erase, or `Color::TRANSPARENT` for the default.
+        background: Color,
+    },
+    /// The bell character was received.
+    Bell,
+    /// The running program switched to or from the alternate screen,
+    /// which has no scrollback.
+    SetAlternateScreen {
+        enabled: bool,
+    },
+    /// The running program started or stopped concealing text (SGR 8 and 28).
+    SetConcealed {
+        enabled: bool,
+    },
+    /// The running program sent a device control string (DCS), such as
+    /// an application-defined payload.
+    ///
+    /// This holds the bytes between `ESC P` and the terminator. A tmux
+    /// passthrough (`ESC P tmux; ...`) is unwrapped to the sequence inside.
+    DcsPassthrough(Vec<u8>),
+    /// The running program downloaded a soft font (DECDLD), which is not
+    /// supported. Text in the soft font is shown in the normal font instead.
+    SoftFontIgnored {
+        font_number: usize,
+    },
+    /// The running program asked whether a mode is set (DECRQM).
+    ModeQueried {
+        number: usize,
+        /// Whether it is a DEC private mode, as in `CSI ? Ps $ p`.
+        dec_private: bool,
+    },
+    /// The running program started a hyperlink (OSC 8), or ended it with `None`.
+    SetHyperlink {
+        uri: Option<String>,
+    },
+    /// The running program set the window title (OSC 0 or 2).
+    SetTitle(String),
+    /// The running program set or reset a mode.
+    SetMode {
+        mode: Mode,
+        enabled: bool,
+    },
+    /// The running program chose whether responses use 8-bit C1 controls
+    /// (S8C1T) or 7-bit escapes (S7C1T).
+    SetEightBitControls {
+        enabled: bool,
+    },
+    /// The running program set the volume of the bell rung by BEL (DECSWBV).
+    SetWarningBellVolume(BellVolume),
+    /// The running program set the volume of the margin bell (DECSMBV).
+    SetMarginBellVolume(BellVolume),
+    /// The running program set the cursor shape (DECSCUSR).
+    SetCursorStyle {
+        shape: CursorShape,
+        blinking: bool,
+    },
+    /// The running program set a dynamic color (OSC 10, 11 or 12), or
+    /// reset it to the theme's color (OSC 110, 111 or 112).
+    DynamicColorChanged {
+        target: DynamicColor,
+        color: Option<Color>,
+    },
+    /// The running program asked for the current value of a dynamic color.
+    ///
+    /// The terminal answers from the theme, unless the program has set the color.
+    DynamicColorQueried(DynamicColor),
+    /// The running program set a color of the palette (OSC 4), or reset it
+    /// to the one in the settings (OSC 104).
+    PaletteChanged {
+        index: u8,
+        color: Option<Color>,
+    },
+    /// The running program asked for the current value of a color of the
+    /// palette (OSC 4 with `?`).
+    PaletteQueried(u8),
+    /// A descendant of the shell was reaped after dying unexpectedly,
+    /// either from a signal or with a nonzero exit code.
+    DescendantExited {
+        /// The process ID of the descendant.
+        pid: i32,
+        /// The exit code, if the process exited normally.
+        code: Option<i32>,
+        /// The signal number, if the process was killed by a signal.
+        signal: Option<i32>,
+    },
+    /// The running program exited. If neither field is set, the driver
+    /// could only tell that it was gone, such as from a PTY hanging up.
+    SessionExited {
+        /// The exit code, if the program exited normally.
+        code: Option<i32>,
+        /// The signal number, if the program was killed by a signal.
+        signal: Option<i32>,
+    },
+    /// The output started to look like binary data rather than text.
+    ///
+    /// If the binary output policy is to pause, no more output is read
+    /// until the host resumes it.
+    BinaryOutputDetected,
+    /// Scrollback was evicted to stay within a memory budget shared with
+    /// other terminals.
+    ScrollbackEvicted {
+        lines: usize,
+    },
+    /// The driver lost its transport, such as an SSH connection or serial link.
+    ///
+    /// The grid is kept, and `Terminal::reconnect` tries to re-establish it.
+    Disconnected,
+    /// The running program set the lines that scroll (DECSTBM), from `top`
+    /// up to but not including `bottom`.
+    SetScrollRegion {
+        top: usize,
+        bottom: usize,
+    },
+    /// The running program asked for the terminal to be resized (`CSI 8 ; rows ; cols t`).
+    ///
+    /// The host can accept by calling `Terminal::resize`; until then the
+    /// terminal keeps its current size.
+    ResizeRequested {
+        cols: usize,
+        rows: usize,
+    },
+    /// The shell marked a point in its command cycle (OSC 133).
+    ShellIntegration(ShellMark),
+    /// A command marked by shell integration finished, after running for
+    /// `duration` if its start was marked too.
+    ///
+    /// This follows `ShellIntegration(CommandFinished { .. })`, so that
+    /// frontends can show how long each command took.
+    CommandFinished {
+        exit_code: Option<i32>,
+        duration: Option<Duration>,
+    },
+    /// More of the scrollback was rewrapped after a resize.
+    ///
+    /// Lines still waiting are shown cut to the new width.
+    ReflowProgress(ReflowProgress),
+    /// New output arrived, but the viewport stayed where it was because
+    /// of the autoscroll policy.
+    AutoscrollSuppressed {
+        new_lines: usize,
+    },
+    /// The output looked garbled, so the parser went back to its ground
+    /// state and carried on from there.
+    ParserReset {
+        reason: ParserResetReason,
+    },
+    /// An OSC or DCS string was ignored because its payload, `length`
+    /// bytes long, was over `Settings::string_limits`.
+    ControlStringDiscarded {
+        kind: ControlStringKind,
+        length: usize,
+    },
+    /// More of a file was queued to be written to the running program, or
+    /// all of it was, by `Terminal::write_file`.
+    UploadProgress(UploadProgress),
+    /// A file being written to the running program could not be read, so
+    /// the rest of it was not sent.
+    UploadFailed {
+        bytes_sent: u64,
+        reason: String,
+    },
+    /// Input from a recorded session, at the point it was typed.
+    ///
+    /// Only `os::recording::Replay` emits this, when replaying the input
+    /// track as well as the output.
+    InputReplayed(Vec<u8>),
+}
+
+/// A color that running programs can set and query with OSC sequences.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DynamicColor {
+    /// The default text color (OSC 10).
+    Foreground,
+    /// The default background color (OSC 11).
+    Background,
+    /// The cursor color (OSC 12).
+    Cursor,
+}
+
+/// How loud the running program asked for a bell to be.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BellVolume {
+    /// The bell is silenced.
+    Off,
+    Low,
+    High,
+}
+
+/// Which part of the line or display an erase covers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EraseMode {
+    /// From the cursor to the end, including the cursor (mode 0).
+    ToEnd,
+    /// From the start to the cursor, including the cursor (mode 1).
+    ToStart,
+    /// All of it (mode 2).
+    All,
+    /// The scrollback, leaving the visible grid alone (ED 3).
+    Scrollback,
+}
+
+/// How far writing a file to the running program has got.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UploadProgress {
+    /// The bytes read from the file and queued to be written.
+    pub bytes_sent: u64,
+    /// The size of the file, if it is known.
+    pub total_bytes: Option<u64>,
+    /// Whether the whole file has been read.
+    pub finished: bool,
+}
+
+/// How the running program exited.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExitStatus {
+    /// The exit code, if the program exited normally.
+    pub code: Option<i32>,
+    /// The signal number, if the program was killed by a signal.
+    pub signal: Option<i32>,
+}
+
+/// Why the parser was reset.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ParserResetReason {
+    /// A control string ran on far longer than any real one, so its
+    /// terminator was probably lost, and the output after it was hidden.
+    UnterminatedString,
+    /// Many sequences in a row were not recognized, as happens when the
+    /// output is not meant for a terminal.
+    UnknownSequences,
+}
+
+/// A point in the shell's command cycle, as marked by shell integration.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShellMark {
+    /// The prompt is about to be drawn (`A`).
+    PromptStart,
+    /// The prompt has been drawn, and the user is typing a command (`B`).
+    CommandStart,
+    /// The command was entered, and its output follows (`C`).
+    OutputStart,
+    /// The command finished (`D`), with its exit code if the shell reported one.
+    CommandFinished {
+        exit_code: Option<i32>,
+    },
+}
+
+impl BellVolume {
+    /// Gets the volume for a DECSWBV or DECSMBV parameter, where 1 is off,
+    /// 2 to 4 are low, and 0 or 5 to 8 are high.
+    pub fn from_parameter(parameter: usize) -> Option<Self> {
+        match parameter {
+            1 => Some(BellVolume::Off),
+            2..=4 => Some(BellVolume::Low),
+            0 | 5..=8 => Some(BellVolume::High),
+            _ => None,
+        }
+    }
+}
+
+impl EraseMode {
+    /// Gets the mode for an ED or EL parameter.
+    pub fn from_parameter(parameter: usize) -> Option<Self> {
+        match parameter {
+            0 => Some(EraseMode::ToEnd),
+            1 => Some(EraseMode::ToStart),
+            2 => Some(EraseMode::All),
+            3 => Some(EraseMode::Scrollback),
+            _ => None,
+        }
+    }
+}
+
+impl DynamicColor {
+    /// Every dynamic color, in the order of their OSC numbers.
+    pub const ALL: &'static [DynamicColor] = &[
+        DynamicColor::Foreground,
+        DynamicColor::Background,
+        DynamicColor::Cursor,
+    ];
+
+    /// Gets the OSC number that sets and queries the color.
+    pub fn osc_number(&self) -> usize {
+        match *self {
+            DynamicColor::Foreground => 10,
+            DynamicColor::Background => 11,
+            DynamicColor::Cursor => 12,
+        }
+    }
+
+    /// Gets the color with an OSC number, such as `10` for the foreground.
+    pub fn from_osc_number(number: usize) -> Option<Self> {
+        DynamicColor::ALL.iter().cloned().find(|color| color.osc_number() == number)
+    }
 }
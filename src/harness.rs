@@ -0,0 +1,937 @@
+//! Headless end-to-end testing of frontends, with a scripted child process.
+
+use crate::{
+    core::{Action, Settings, Terminal},
+    cursor::CursorState,
+    debug::DriverDebugState,
+    event::{Event, ExitStatus},
+    os::{console::Console, escape::Interceptor, BinaryOutputPolicy, ByteCounts, Driver},
+};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Drives a terminal whose child process is a script, recording every
+/// event and a snapshot of the grid after each step.
+///
+/// This lets frontends test their rendering and input mapping against
+/// readterm deterministically, without a shell.
+pub struct TestHarness {
+    terminal: Terminal,
+    child: Arc<Mutex<Child>>,
+    steps: Vec<TestStep>,
+}
+
+/// What the terminal did in one step of a `TestHarness`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestStep {
+    /// The events the terminal emitted.
+    pub events: Vec<Event>,
+    /// The visible text afterwards.
+    pub visible_text: String,
+    /// The cursor afterwards.
+    pub cursor: CursorState,
+}
+
+/// The state of the scripted child, shared between the harness and its driver.
+#[derive(Debug)]
+struct Child {
+    /// Events waiting for the next update.
+    output: VecDeque<Event>,
+    /// Everything the terminal has written to the child.
+    input: Vec<u8>,
+    /// Where the next expected input is searched for.
+    unmatched_input_start: usize,
+    /// Output to write once each trigger is received, in order.
+    responses: VecDeque<(Vec<u8>, String)>,
+    /// Turns the child's output into events, as the real drivers do.
+    console: Console,
+    /// Handles the escape sequences that the parser does not support.
+    interceptor: Interceptor,
+    echo_disabled: bool,
+    finished: bool,
+    /// How the child will exit on the next update, if it is about to.
+    exiting: Option<ExitStatus>,
+    /// How the child exited, once it has.
+    exit_status: Option<ExitStatus>,
+    /// The bytes of text the child has written.
+    output_byte_count: u64,
+}
+
+/// A driver whose child process is a script.
+struct ScriptedDriver {
+    child: Arc<Mutex<Child>>,
+}
+
+impl TestHarness {
+    /// Creates a harness for a terminal with the given settings.
+    pub fn new(settings: Settings) -> Self {
+        let child = Arc::new(Mutex::new(Child {
+            output: VecDeque::new(),
+            input: Vec::new(),
+            unmatched_input_start: 0,
+            responses: VecDeque::new(),
+            console: Console::new(settings.column_count, settings.line_count, settings.palette.clone(), settings.string_limits),
+            interceptor: Interceptor::new(settings.c1_controls, settings.string_limits),
+            echo_disabled: false,
+            finished: false,
+            exiting: None,
+            exit_status: None,
+            output_byte_count: 0,
+        }));
+        let driver = ScriptedDriver { child: child.clone() };
+
+        TestHarness {
+            terminal: Terminal::with_driver(settings, Box::new(driver)),
+            child,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Gets the terminal under test.
+    pub fn terminal(&self) -> &Terminal { &self.terminal }
+
+    /// Gets the terminal under test mutably, such as to change its settings.
+    pub fn terminal_mut(&mut self) -> &mut Terminal { &mut self.terminal }
+
+    /// Makes the child write text, which the terminal sees on the next step.
+    ///
+    /// The text may contain escape sequences, which are parsed as the real
+    /// drivers parse them.
+    pub fn child_writes(&mut self, text: &str) {
+        self.child().write(text);
+    }
+
+    /// Makes the child emit events directly, such as a bell or a mode change.
+    pub fn child_emits(&mut self, events: Vec<Event>) {
+        self.child().output.extend(events);
+    }
+
+    /// Makes the child write text once it has received `trigger`, such as
+    /// a prompt after a command.
+    ///
+    /// Responses are expected in the order they are added.
+    pub fn respond_to(&mut self, trigger: &str, text: &str) {
+        self.child().responses.push_back((trigger.as_bytes().to_vec(), text.to_owned()));
+    }
+
+    /// Makes the child stop echoing what it receives, as during a password
+    /// prompt, or start again. The child echoes by default.
+    pub fn set_echo_disabled(&mut self, echo_disabled: bool) {
+        self.child().echo_disabled = echo_disabled;
+    }
+
+    /// Makes the child exit.
+    pub fn finish(&mut self) {
+        self.child().finished = true;
+    }
+
+    /// Makes the child exit with the given status, which the terminal sees
+    /// on the next step after any output still waiting.
+    pub fn exit(&mut self, status: ExitStatus) {
+        self.child().exiting = Some(status);
+    }
+
+    /// Applies an action as a frontend would on input, then steps.
+    pub fn apply(&mut self, action: Action) -> &TestStep {
+        action.apply(&mut self.terminal).expect("the scripted child accepts input");
+        self.step()
+    }
+
+    /// Updates the terminal once, recording what it did.
+    pub fn step(&mut self) -> &TestStep {
+        let events = self.terminal.update().expect("the scripted child can be updated");
+
+        self.steps.push(TestStep {
+            events,
+            visible_text: self.terminal.visible_text(),
+            cursor: self.terminal.cursor_state(),
+        });
+        self.steps.last().unwrap()
+    }
+
+    /// Gets every step so far.
+    pub fn steps(&self) -> &[TestStep] { &self.steps }
+
+    /// Gets every event emitted so far, in order.
+    pub fn events(&self) -> Vec<Event> {
+        self.steps.iter().flat_map(|step| step.events.iter().cloned()).collect()
+    }
+
+    /// Gets everything the terminal has written to the child.
+    pub fn input(&self) -> Vec<u8> {
+        self.child().input.clone()
+    }
+
+    /// Asserts that the visible text, without trailing spaces on each line, is as expected.
+    pub fn assert_visible_text(&self, expected: &str) {
+        let visible_text = self.terminal.visible_text();
+        let actual: Vec<_> = visible_text.lines().map(str::trim_end).collect();
+        let actual = actual.join("\n");
+
+        assert_eq!(expected.trim_end_matches('\n'), actual.trim_end_matches('\n'), "unexpected visible text");
+    }
+
+    /// Asserts that an event was emitted at some point.
+    pub fn assert_emitted(&self, event: &Event) {
+        let events = self.events();
+        assert!(events.contains(event), "{:?} was not emitted, only {:?}", event, events);
+    }
+
+    /// Asserts that the terminal wrote exactly the given bytes to the child.
+    pub fn assert_input(&self, expected: &[u8]) {
+        assert_eq!(String::from_utf8_lossy(expected), String::from_utf8_lossy(&self.input()),
+                   "unexpected input to the child");
+    }
+
+    fn child(&self) -> MutexGuard<'_, Child> {
+        self.child.lock().unwrap()
+    }
+}
+
+impl Child {
+    /// Turns output into events, as a real driver would.
+    ///
+    /// Each line feed also returns the cursor, as a PTY does by default.
+    fn write(&mut self, text: &str) {
+        self.output_byte_count += text.len() as u64;
+
+        let bytes = text.replace('\n', "\r\n");
+        let output = self.interceptor.write(bytes.as_bytes(), &mut self.console);
+        self.output.extend(output.events);
+        // Answers to queries go back to the child without being echoed.
+        self.input.extend_from_slice(&output.response);
+    }
+
+    /// Receives input, echoing it and writing any responses it triggers.
+    fn receive(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+
+        if !self.echo_disabled {
+            self.write(&String::from_utf8_lossy(bytes));
+        }
+
+        while let Some((trigger, _)) = self.responses.front() {
+            let unmatched = &self.input[self.unmatched_input_start..];
+            let end = match unmatched.windows(trigger.len().max(1)).position(|window| window == &trigger[..]) {
+                Some(position) => position + trigger.len(),
+                None => break,
+            };
+
+            self.unmatched_input_start += end;
+            let (_, text) = self.responses.pop_front().unwrap();
+            self.write(&text);
+        }
+    }
+}
+
+impl Driver for ScriptedDriver {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        let mut child = self.child.lock().unwrap();
+        if child.finished {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the child has exited"));
+        }
+
+        child.receive(bytes);
+        Ok(())
+    }
+
+    fn backspace(&mut self) -> Result<(), io::Error> {
+        self.write_bytes(b"\x7f")
+    }
+
+    fn escape(&mut self) -> Result<(), io::Error> {
+        self.write_bytes(b"\x1b")
+    }
+
+    fn cursor_left(&mut self) -> Result<(), io::Error> {
+        self.write_bytes(b"\x1b[D")
+    }
+
+    fn cursor_right(&mut self) -> Result<(), io::Error> {
+        self.write_bytes(b"\x1b[C")
+    }
+
+    fn cursor_up(&mut self) -> Result<(), io::Error> {
+        self.write_bytes(b"\x1b[A")
+    }
+
+    fn cursor_down(&mut self) -> Result<(), io::Error> {
+        self.write_bytes(b"\x1b[B")
+    }
+
+    fn control_code(&mut self, c: char) -> Result<(), io::Error> {
+        self.write_bytes(&[c.to_ascii_uppercase() as u8 & 0x1f])
+    }
+
+    fn signal_interrupt(&mut self) -> Result<(), io::Error> {
+        self.control_code('c')
+    }
+
+    fn write_bulk(&mut self, s: &str) {
+        self.child.lock().unwrap().receive(s.as_bytes());
+    }
+
+    fn write_bulk_bytes(&mut self, bytes: &[u8]) {
+        self.child.lock().unwrap().receive(bytes);
+    }
+
+    fn pending_input_byte_count(&self) -> usize { 0 }
+
+    fn cancel_pending_input(&mut self) -> usize { 0 }
+
+    fn resize(&mut self, columns: usize, lines: usize) {
+        self.child.lock().unwrap().console.resize(columns, lines);
+    }
+
+    fn update(&mut self) -> Result<Vec<Event>, io::Error> {
+        let mut child = self.child.lock().unwrap();
+        let mut events: Vec<Event> = child.output.drain(..).collect();
+
+        if let Some(status) = child.exiting.take() {
+            child.finished = true;
+            child.exit_status = Some(status);
+            events.push(Event::SessionExited { code: status.code, signal: status.signal });
+        }
+        Ok(events)
+    }
+
+    fn is_session_finished(&self) -> bool { self.child.lock().unwrap().finished }
+
+    fn exit_status(&self) -> Option<ExitStatus> { self.child.lock().unwrap().exit_status }
+
+    fn respawn(&mut self, settings: &Settings) -> Result<(), io::Error> {
+        let mut child = self.child.lock().unwrap();
+        child.console = Console::new(settings.column_count, settings.line_count, settings.palette.clone(), settings.string_limits);
+        child.interceptor = Interceptor::new(settings.c1_controls, settings.string_limits);
+        child.finished = false;
+        child.exiting = None;
+        child.exit_status = None;
+        child.echo_disabled = false;
+        Ok(())
+    }
+
+    fn is_echo_disabled(&self) -> bool { self.child.lock().unwrap().echo_disabled }
+
+    fn is_output_paused(&self) -> bool { false }
+
+    fn resume_output(&mut self, _: BinaryOutputPolicy) { }
+
+    fn is_flow_stopped(&self) -> bool { false }
+
+    fn debug_state(&self) -> DriverDebugState {
+        let child = self.child.lock().unwrap();
+        DriverDebugState {
+            parser_state: child.interceptor.state_name(),
+            pending_bytes: child.interceptor.pending_byte_count(),
+        }
+    }
+
+    fn byte_counts(&self) -> ByteCounts {
+        let child = self.child.lock().unwrap();
+        ByteCounts { written: child.input.len() as u64, read: child.output_byte_count }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Autoscroll, BellVolume, Color, ControlStringKind, CursorShape, DynamicColor, Mode, ModeState, Palette, ShellMark, StringLimits, Theme, UploadProgress};
+    use crate::encode::{Key, KeyEvent, Modifiers, MouseButton};
+    use std::time::Duration;
+
+    fn settings() -> Settings {
+        Settings { column_count: 10, line_count: 3, ..Settings::default() }
+    }
+
+    #[test]
+    fn records_the_grid_after_each_step() {
+        let mut harness = TestHarness::new(settings());
+        harness.child_writes("$ ");
+        harness.respond_to("ls\r", "\nfile\n$ ");
+
+        assert_eq!("$", harness.step().visible_text.trim_end());
+        harness.apply(Action::WriteText("ls\r".to_owned()));
+
+        harness.assert_visible_text("$ ls\nfile\n$");
+        harness.assert_input(b"ls\r");
+        assert_eq!((2, 2), (harness.steps()[1].cursor.x, harness.steps()[1].cursor.y));
+    }
+
+    #[test]
+    fn scrolls_and_wraps_like_a_parser() {
+        let mut harness = TestHarness::new(settings());
+        harness.set_echo_disabled(true);
+        harness.child_writes("1\n2\n3\n0123456789ab\x07");
+        harness.step();
+
+        harness.assert_visible_text("3\n0123456789\nab");
+        harness.assert_emitted(&Event::Bell);
+    }
+
+    #[test]
+    fn alternate_screen_leaves_the_scrollback_alone() {
+        let mut harness = TestHarness::new(settings());
+        harness.child_writes("1\n2\n3\n4");
+        harness.step();
+
+        harness.child_emits(vec![Event::SetAlternateScreen { enabled: true }]);
+        harness.child_writes("\rvim");
+        harness.step();
+        harness.assert_visible_text("\n\nvim");
+        assert!(harness.terminal().is_alternate_screen_active());
+        assert_eq!(0, harness.terminal().scrollback_line_count());
+
+        harness.child_emits(vec![Event::SetAlternateScreen { enabled: false }]);
+        harness.step();
+        harness.assert_visible_text("2\n3\n4");
+        assert_eq!(1, harness.terminal().scrollback_line_count());
+    }
+
+    #[test]
+    fn navigates_between_prompts() {
+        let mut harness = TestHarness::new(settings());
+        for command in &["a", "b", "c", ""] {
+            harness.child_emits(vec![Event::ShellIntegration(ShellMark::PromptStart)]);
+            harness.child_writes(&format!("$ {}", command));
+            if !command.is_empty() {
+                harness.child_writes("\nout\n");
+            }
+        }
+        harness.step();
+        assert_eq!(Some(4), harness.terminal().previous_prompt(6));
+        assert_eq!(Some(6), harness.terminal().next_prompt(4));
+
+        let terminal = harness.terminal_mut();
+        assert!(terminal.scroll_to_previous_prompt());
+        assert!(terminal.visible_text().starts_with("$ b"));
+        assert!(terminal.scroll_to_previous_prompt());
+        assert!(terminal.visible_text().starts_with("$ a"));
+        assert!(!terminal.scroll_to_previous_prompt());
+
+        assert!(terminal.scroll_to_next_prompt());
+        assert!(terminal.scroll_to_next_prompt());
+        assert!(terminal.is_scrolled_to_bottom());
+    }
+
+    #[test]
+    fn keeps_the_latest_window_title() {
+        let mut harness = TestHarness::new(settings());
+        assert_eq!(None, harness.terminal().title());
+
+        harness.child_emits(vec![Event::SetTitle("bash".to_owned())]);
+        harness.child_emits(vec![Event::SetTitle("vim".to_owned())]);
+        harness.step();
+        assert_eq!(Some("vim"), harness.terminal().title());
+    }
+
+    #[test]
+    fn rate_limits_and_silences_bells() {
+        let mut harness = TestHarness::new(settings());
+        harness.set_echo_disabled(true);
+
+        harness.child_writes("\x07\x07\x07");
+        assert_eq!(vec![Event::Bell], harness.step().events);
+        assert!(harness.terminal().is_bell_pending());
+
+        let mut harness = TestHarness::new(Settings { bell_rate_limit: Duration::from_secs(0), ..settings() });
+        harness.child_emits(vec![Event::SetWarningBellVolume(BellVolume::Off), Event::Bell]);
+        assert_eq!(vec![Event::SetWarningBellVolume(BellVolume::Off)], harness.step().events);
+        assert!(!harness.terminal().is_bell_pending());
+
+        harness.child_emits(vec![Event::SetWarningBellVolume(BellVolume::Low), Event::Bell]);
+        harness.step();
+        assert_eq!(BellVolume::Low, harness.terminal().bell_volume());
+    }
+
+    #[test]
+    fn follows_the_cursor_style() {
+        let mut harness = TestHarness::new(settings());
+        harness.child_emits(vec![
+            Event::SetCursorStyle { shape: CursorShape::Bar, blinking: false },
+            Event::SetMode { mode: Mode::CursorVisible, enabled: false },
+        ]);
+        harness.step();
+
+        assert_eq!((CursorShape::Bar, false), harness.terminal().cursor_style());
+        assert!(!harness.terminal().cursor_visible());
+    }
+
+    #[test]
+    fn maps_actions_to_input() {
+        let mut harness = TestHarness::new(settings());
+        harness.set_echo_disabled(true);
+
+        harness.apply(Action::ControlCode('c'));
+        harness.apply(Action::CursorUp);
+        harness.apply(Action::Backspace);
+
+        harness.assert_input(b"\x03\x1b[A\x7f");
+    }
+
+    #[test]
+    fn indexed_and_true_colors_reach_the_style() {
+        let mut terminal = Terminal::headless(settings());
+        terminal.feed(b"\x1b[38;5;196;48;5;236ma\x1b[38;2;1;2;3;48;2;250;128;0mb").unwrap();
+
+        let styles: Vec<_> = terminal.visible_slices().into_iter().take(2)
+            .map(|slice| (slice.text, slice.style.color, slice.style.background))
+            .collect();
+        assert_eq!(vec![
+            ("a".to_owned(), Color::from_rgb8(255, 0, 0), Color::from_rgb8(48, 48, 48)),
+            ("b".to_owned(), Color::from_rgb8(1, 2, 3), Color::from_rgb8(250, 128, 0)),
+        ], styles);
+    }
+
+    #[test]
+    fn tints_the_cursor_as_requested() {
+        let mut harness = TestHarness::new(settings());
+        harness.set_echo_disabled(true);
+
+        harness.child_emits(vec![Event::DynamicColorChanged { target: DynamicColor::Cursor, color: Some(Color::RED) }]);
+        assert_eq!(Some(Color::RED), harness.step().cursor.color);
+
+        harness.child_emits(vec![Event::DynamicColorQueried(DynamicColor::Cursor)]);
+        harness.step();
+        harness.assert_input(format!("\x1b]12;{}\x1b\\", Color::RED.to_x11()).as_bytes());
+
+        harness.child_emits(vec![Event::DynamicColorChanged { target: DynamicColor::Cursor, color: None }]);
+        assert_eq!(None, harness.step().cursor.color);
+    }
+
+    #[test]
+    fn uploads_files_a_chunk_at_a_time() {
+        let mut harness = TestHarness::new(settings());
+        harness.set_echo_disabled(true);
+        let contents = vec![b'x'; 5000];
+
+        harness.terminal_mut().upload(std::io::Cursor::new(contents.clone()), Some(5000)).unwrap();
+        assert!(harness.terminal_mut().upload(std::io::empty(), None).is_err());
+
+        let progress = |bytes_sent, finished| Event::UploadProgress(UploadProgress { bytes_sent, total_bytes: Some(5000), finished });
+        assert_eq!(vec![progress(4096, false)], harness.step().events);
+        assert_eq!(vec![progress(5000, false)], harness.step().events);
+        assert_eq!(vec![progress(5000, true)], harness.step().events);
+        harness.assert_input(&contents);
+        assert_eq!(None, harness.terminal().upload_progress());
+    }
+
+    #[test]
+    fn tracks_and_answers_for_the_palette() {
+        let mut harness = TestHarness::new(settings());
+        harness.set_echo_disabled(true);
+        let xterm_red = Palette::default().ansi[1];
+
+        harness.child_emits(vec![Event::PaletteChanged { index: 1, color: Some(Color::GREEN) }, Event::PaletteQueried(1)]);
+        harness.step();
+        harness.assert_input(format!("\x1b]4;1;{}\x1b\\", Color::GREEN.to_x11()).as_bytes());
+
+        harness.child_emits(vec![Event::PaletteChanged { index: 200, color: Some(Color::GREEN) }, Event::PaletteQueried(200)]);
+        harness.step();
+        let green = Color::GREEN.to_x11();
+        harness.assert_input(format!("\x1b]4;1;{}\x1b\\\x1b]4;200;{}\x1b\\", green, green).as_bytes());
+
+        harness.child_emits(vec![Event::PaletteChanged { index: 1, color: None }, Event::PaletteChanged { index: 200, color: None }]);
+        harness.step();
+        assert_eq!(xterm_red, harness.terminal().palette().ansi[1]);
+        assert_eq!(Color::from_ansi_256(200), harness.terminal().palette().indexed(200));
+    }
+
+    #[test]
+    fn does_not_echo_secrets_locally() {
+        let mut harness = TestHarness::new(Settings { local_echo: true, ..settings() });
+        harness.child_writes("pw: ");
+        harness.step();
+        harness.set_echo_disabled(true);
+
+        harness.apply(Action::WriteText("hunter2".to_owned()));
+        harness.apply(Action::Backspace);
+        harness.assert_visible_text("pw:");
+
+        harness.set_echo_disabled(false);
+        harness.respond_to("\r", "\nok\n$ ");
+        harness.apply(Action::WriteText("\r".to_owned()));
+        harness.apply(Action::WriteText("ls".to_owned()));
+        harness.assert_visible_text("pw:\nok\n$ ls");
+        harness.assert_input(b"hunter2\x7f\rls");
+
+        let terminal = harness.terminal_mut();
+        let line_count = terminal.debug_state().total_lines;
+        for line_id in 0..line_count {
+            terminal.select(line_id, 0..10);
+        }
+        let copied = terminal.copy_selection().unwrap().text;
+        let encoded: String = terminal.encode_lines(0..line_count).into_iter().map(|line| line.text).collect();
+
+        assert_eq!("pw:\nok\n$ ls", copied.trim_end());
+        assert!(!encoded.contains("hunter"), "{:?}", encoded);
+    }
+
+    #[test]
+    fn snapshots_the_state_for_diagnostics() {
+        let mut terminal = Terminal::headless(settings());
+        terminal.feed(b"a\r\nb\r\nc\r\nd\r\nxy\x1b[?25l\x1b[?1h\x1b[").unwrap();
+
+        let state = terminal.debug_state();
+        assert_eq!((2, 2), state.cursor);
+        assert_eq!(ModeState { cursor_visible: false, application_cursor_keys: true, ..ModeState::default() }, state.modes);
+        assert_eq!((5, 2, 0), (state.total_lines, state.scrollback_lines, state.evicted_lines));
+        assert_eq!(("escape", 2), (state.driver.parser_state, state.driver.pending_bytes));
+        assert!(!state.session_finished);
+    }
+
+    #[test]
+    fn rings_the_margin_bell_where_typing_would_reach() {
+        let mut harness = TestHarness::new(Settings { margin_bell_distance: 3, bell_rate_limit: Duration::from_secs(0), ..settings() });
+        harness.child_emits(vec![Event::SetMode { mode: Mode::MarginBell, enabled: true }]);
+        harness.step();
+
+        // Carriage returns go back to the start of the line.
+        harness.terminal_mut().write_text("abcde\rfgh").unwrap();
+        assert!(!harness.terminal().is_bell_pending());
+
+        harness.terminal_mut().write_text("abcdefgh").unwrap();
+        assert!(harness.terminal().is_bell_pending());
+        harness.terminal_mut().acknowledge_bell();
+
+        // The bell would give away how long a password is.
+        harness.set_echo_disabled(true);
+        harness.terminal_mut().write_text("abcdefgh").unwrap();
+        assert!(!harness.terminal().is_bell_pending());
+    }
+
+    #[test]
+    fn palette_changes_recolor_later_output() {
+        let mut terminal = Terminal::headless(settings());
+        terminal.feed(b"\x1b]4;1;#00ff00\x07\x1b[31ma\x1b]104;1\x07\x1b[31mb\x1b]10;#0000ff\x07\x1b[0mc").unwrap();
+        terminal.feed(b"\x1b]4;200;#00ff00\x07\x1b[38;5;200md\x1b]104\x07\x1b[38;5;200me").unwrap();
+
+        let colors: Vec<_> = terminal.visible_slices().into_iter().take(5).map(|slice| slice.style.color).collect();
+        assert_eq!(vec![Color::GREEN, Palette::default().ansi[1], Color::BLUE, Color::GREEN, Color::from_ansi_256(200)], colors);
+    }
+
+    #[test]
+    fn discards_control_strings_over_the_limit() {
+        let mut terminal = Terminal::headless(Settings {
+            string_limits: StringLimits { max_payload_length: 8, ..StringLimits::default() },
+            ..settings()
+        });
+        let events = terminal.feed(b"\x1b]4;1;#00ff00\x07\x1b[31ma").unwrap();
+
+        assert!(events.contains(&Event::ControlStringDiscarded { kind: ControlStringKind::Osc, length: 11 }));
+        assert_eq!(Palette::default().ansi[1], terminal.visible_slices()[0].style.color);
+    }
+
+    #[test]
+    #[cfg(feature = "scripting")]
+    fn scripts_respond_to_output() {
+        let mut harness = TestHarness::new(settings());
+        harness.set_echo_disabled(true);
+        harness.terminal_mut().load_script(r#"
+            fn on_line(text) {
+                if text == "login:" { send("root\n"); mark_line(); }
+            }
+        "#).unwrap();
+
+        harness.child_writes("hi\nlogin:\n");
+        harness.step();
+        harness.assert_input(b"root\n");
+
+        let marked: String = harness.terminal().visible_slices().into_iter()
+            .filter(|slice| slice.classes.contains(crate::CellClass::Marker))
+            .map(|slice| slice.text)
+            .collect();
+        assert_eq!("login:    ", marked);
+    }
+
+    #[test]
+    #[cfg(feature = "scripting")]
+    fn scripts_see_prompts_and_whole_lines() {
+        let mut harness = TestHarness::new(settings());
+        harness.set_echo_disabled(true);
+        harness.terminal_mut().load_script(r#"
+            fn on_prompt(text) {
+                if text == "password:" { send("hunter2\n"); }
+            }
+            fn on_line(text) {
+                if text == "a long line" { send("seen\n"); }
+            }
+        "#).unwrap();
+
+        harness.child_writes("password:");
+        harness.step();
+        harness.step();
+        harness.assert_input(b"hunter2
+");
+
+        harness.child_writes("
+a long line
+");
+        harness.step();
+        harness.assert_input(b"hunter2
+seen
+");
+    }
+
+    #[test]
+    fn sends_encoded_keys() {
+        let mut harness = TestHarness::new(settings());
+        harness.set_echo_disabled(true);
+
+        harness.terminal_mut().send_key(Key::Char('d'), Modifiers { ctrl: true, ..Modifiers::NONE }).unwrap();
+        harness.terminal_mut().send_key(Key::Left, Modifiers { alt: true, ..Modifiers::NONE }).unwrap();
+        harness.child_emits(vec![Event::SetMode { mode: Mode::BackarrowKey, enabled: true }]);
+        harness.step();
+        harness.terminal_mut().send_key(Key::Backspace, Modifiers::NONE).unwrap();
+        Action::Key(KeyEvent::from(Key::Function(13))).apply(harness.terminal_mut()).unwrap();
+
+        harness.assert_input(b"\x04\x1b[1;3D\x08\x1b[1;2P");
+    }
+
+    #[test]
+    fn sends_application_cursor_keys_once_enabled() {
+        let mut harness = TestHarness::new(settings());
+
+        harness.terminal_mut().cursor_up().unwrap();
+        harness.child_emits(vec![Event::SetMode { mode: Mode::ApplicationCursorKeys, enabled: true }]);
+        harness.step();
+        harness.terminal_mut().cursor_up().unwrap();
+        Action::CursorLeft.apply(harness.terminal_mut()).unwrap();
+
+        harness.assert_input(b"\x1b[A\x1bOA\x1bOD");
+    }
+
+    #[test]
+    fn respawning_resets_what_the_old_child_set() {
+        let mut harness = TestHarness::new(settings());
+        harness.child_emits(vec![
+            Event::SetAlternateScreen { enabled: true },
+            Event::SetMode { mode: Mode::ApplicationCursorKeys, enabled: true },
+            Event::SetMode { mode: Mode::MouseClick, enabled: true },
+            Event::SetCursorStyle { shape: CursorShape::Bar, blinking: true },
+            Event::PaletteChanged { index: 1, color: Some(Color::GREEN) },
+            Event::DynamicColorChanged { target: DynamicColor::Background, color: Some(Color::RED) },
+            Event::SetTitle("vim".to_owned()),
+            Event::SetWarningBellVolume(BellVolume::Off),
+        ]);
+        harness.exit(ExitStatus { code: Some(1), signal: None });
+        harness.step();
+        assert!(harness.terminal().is_session_finished());
+
+        harness.terminal_mut().respawn().unwrap();
+        assert!(!harness.terminal().is_session_finished());
+        assert!(!harness.terminal().is_alternate_screen_active());
+        assert_eq!(&ModeState::default(), harness.terminal().modes());
+        assert_eq!(&settings().palette, harness.terminal().palette());
+        assert_eq!(Theme::default().background, harness.terminal().dynamic_color(DynamicColor::Background));
+        assert_eq!(None, harness.terminal().title());
+
+        harness.child_emits(vec![Event::Bell]);
+        assert!(harness.step().events.contains(&Event::Bell));
+
+        harness.terminal_mut().cursor_up().unwrap();
+        assert!(!harness.terminal_mut().mouse_press(0, 0, MouseButton::Left, Modifiers::NONE).unwrap());
+        harness.assert_input(b"\x1b[A");
+    }
+
+    #[test]
+    fn injected_events_are_handled_like_driver_events() {
+        let mut harness = TestHarness::new(settings());
+        let terminal = harness.terminal_mut();
+
+        assert_eq!(vec![Event::Bell], terminal.inject_event(Event::Bell));
+        // Too soon after the last bell.
+        assert_eq!(Vec::<Event>::new(), terminal.inject_event(Event::Bell));
+
+        let finished = Event::ShellIntegration(ShellMark::CommandFinished { exit_code: Some(0) });
+        let events = terminal.inject_event(finished.clone());
+        assert_eq!(finished, events[0]);
+        assert!(matches!(events[1], Event::CommandFinished { exit_code: Some(0), .. }));
+        assert_eq!(1, terminal.session_stats().commands_run);
+    }
+
+    #[test]
+    fn parses_what_the_child_writes() {
+        let mut harness = TestHarness::new(settings());
+        harness.child_writes("\x1b]0;vim\x07\x1b[31mab\x1b[0m\tc");
+        harness.step();
+
+        harness.assert_visible_text("ab      c");
+        assert_eq!(Some("vim"), harness.terminal().title());
+        assert_eq!(Palette::default().ansi[1], harness.terminal().visible_slices()[0].style.color);
+    }
+
+    #[test]
+    fn fills_rectangles_with_the_pen() {
+        let mut harness = TestHarness::new(settings());
+        harness.child_writes("\x1b[31;44m\x1b[42;1;1;1;2$x");
+        harness.step();
+
+        harness.assert_visible_text("**");
+        let style = &harness.terminal().visible_slices()[1].style;
+        assert_eq!((Palette::default().ansi[1], Palette::default().ansi[4]), (style.color, style.background));
+    }
+
+    #[test]
+    fn switching_screens_does_not_scroll_the_viewport() {
+        let mut harness = TestHarness::new(settings());
+        harness.terminal_mut().set_autoscroll(Autoscroll::Never);
+        harness.child_writes(&"line\n".repeat(30));
+        harness.step();
+        harness.terminal_mut().scroll_to_bottom();
+        let lines_scrolled = harness.terminal().session_stats().lines_scrolled;
+
+        for enabled in [true, false] {
+            harness.child_emits(vec![Event::SetAlternateScreen { enabled }]);
+            let step = harness.step();
+            assert!(!step.events.iter().any(|event| matches!(event, Event::AutoscrollSuppressed { .. })));
+        }
+
+        assert_eq!(0, harness.terminal().scroll_offset());
+        assert_eq!(lines_scrolled, harness.terminal().session_stats().lines_scrolled);
+    }
+
+    #[test]
+    fn output_on_the_alternate_screen_is_not_sent_back_as_arrows() {
+        let mut harness = TestHarness::new(settings());
+        harness.terminal_mut().set_autoscroll(Autoscroll::Never);
+        harness.child_emits(vec![
+            Event::SetAlternateScreen { enabled: true },
+            Event::SetMode { mode: Mode::AlternateScroll, enabled: true },
+        ]);
+        harness.step();
+
+        harness.child_writes("a\nb\nc\nd\ne\n");
+        harness.step();
+
+        harness.assert_input(b"");
+    }
+
+    #[test]
+    fn writing_after_the_child_exits_fails() {
+        let mut harness = TestHarness::new(settings());
+        harness.terminal_mut().write_text("a").unwrap();
+        harness.finish();
+
+        let error = harness.terminal_mut().write_text("b").unwrap_err();
+        assert_eq!(io::ErrorKind::BrokenPipe, error.kind());
+        assert!(Action::ControlCode('c').apply(harness.terminal_mut()).is_err());
+        assert!(harness.terminal_mut().update().unwrap().is_empty());
+        harness.assert_input(b"a");
+    }
+
+    #[test]
+    fn reports_how_the_child_exited() {
+        let mut harness = TestHarness::new(settings());
+        harness.child_writes("bye");
+        harness.exit(ExitStatus { code: Some(1), signal: None });
+
+        assert_eq!(None, harness.terminal().exit_status());
+        let step = harness.step();
+        assert_eq!(Some(&Event::SessionExited { code: Some(1), signal: None }), step.events.last());
+        assert!(step.visible_text.starts_with("bye"));
+
+        assert!(harness.terminal().is_session_finished());
+        assert_eq!(Some(ExitStatus { code: Some(1), signal: None }), harness.terminal().exit_status());
+        assert!(harness.step().events.is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_child_to_exit() {
+        let mut harness = TestHarness::new(settings());
+        assert_eq!(io::ErrorKind::TimedOut,
+                   harness.terminal_mut().wait_timeout(Duration::from_millis(1)).unwrap_err().kind());
+
+        harness.child_writes("done");
+        harness.exit(ExitStatus { code: Some(0), signal: None });
+
+        assert_eq!(Some(ExitStatus { code: Some(0), signal: None }), harness.terminal_mut().wait().unwrap());
+        assert!(harness.terminal().visible_text().starts_with("done"));
+    }
+
+    #[test]
+    fn counts_what_happens_in_the_session() {
+        let mut harness = TestHarness::new(settings());
+        harness.set_echo_disabled(true);
+        let started = harness.terminal().session_stats();
+
+        harness.terminal_mut().write_text("ls\r").unwrap();
+        harness.child_writes("a\nb\nc\nd\n");
+        harness.child_emits(vec![Event::ShellIntegration(ShellMark::CommandFinished { exit_code: Some(0) })]);
+        harness.step();
+
+        let stats = harness.terminal().session_stats();
+        assert_eq!((3, 8, 2, 1), (stats.bytes_in, stats.bytes_out, stats.lines_scrolled, stats.commands_run));
+        assert_eq!(started.started_at, stats.started_at);
+        assert!(stats.last_activity > started.last_activity);
+
+        harness.step();
+        assert_eq!(stats, harness.terminal().session_stats());
+    }
+
+    #[test]
+    fn reports_the_mouse_once_tracking_is_enabled() {
+        let mut harness = TestHarness::new(settings());
+
+        assert!(!harness.terminal_mut().mouse_press(1, 2, MouseButton::Left, Modifiers::NONE).unwrap());
+        harness.child_emits(vec![
+            Event::SetMode { mode: Mode::MouseClick, enabled: true },
+            Event::SetMode { mode: Mode::MouseSgr, enabled: true },
+        ]);
+        harness.step();
+
+        assert!(harness.terminal_mut().mouse_press(1, 2, MouseButton::Left, Modifiers::NONE).unwrap());
+        assert!(!harness.terminal_mut().mouse_move(2, 2, Some(MouseButton::Left), Modifiers::NONE).unwrap());
+        assert!(harness.terminal_mut().mouse_release(2, 2, MouseButton::Left, Modifiers::NONE).unwrap());
+        assert!(harness.terminal_mut().mouse_scroll(0, 0, MouseButton::WheelDown, Modifiers::NONE).unwrap());
+        harness.assert_input(b"\x1b[<0;2;3M\x1b[<0;3;3m\x1b[<65;1;1M");
+    }
+
+    #[test]
+    fn times_commands_marked_by_the_shell() {
+        let mut harness = TestHarness::new(settings());
+        harness.child_emits(vec![Event::ShellIntegration(ShellMark::CommandFinished { exit_code: Some(1) })]);
+        harness.step();
+        harness.assert_emitted(&Event::CommandFinished { exit_code: Some(1), duration: None });
+
+        harness.child_emits(vec![Event::ShellIntegration(ShellMark::OutputStart)]);
+        harness.step();
+        std::thread::sleep(Duration::from_millis(10));
+        harness.child_emits(vec![Event::ShellIntegration(ShellMark::CommandFinished { exit_code: Some(0) })]);
+
+        let duration = harness.step().events.iter().find_map(|event| match *event {
+            Event::CommandFinished { duration, .. } => duration,
+            _ => None,
+        });
+        assert!(duration.unwrap() >= Duration::from_millis(10));
+        assert_eq!(duration, harness.terminal().commands()[1].duration);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn huge_parameters_do_not_grow_the_grid(parameters in proptest::collection::vec(proptest::prelude::any::<u64>(), 7)) {
+            let mut terminal = Terminal::headless(settings());
+            let p = &parameters;
+
+            let sequences = format!("\x1b[{};{}Hx\x1b[{}@\x1b[{}L\x1b[{}X\x1b[{};{};{};{};1;{};{}$v\x1b[88;{};{};{};{}$x\x1b[{};{};{};{}$z",
+                                    p[0], p[1], p[2], p[3], p[4], p[0], p[1], p[2], p[3], p[5], p[6],
+                                    p[0], p[1], p[2], p[3], p[4], p[5], p[6], p[0]);
+            terminal.feed(sequences.as_bytes()).unwrap();
+
+            proptest::prop_assert_eq!(3, terminal.visible_text().lines().count());
+            proptest::prop_assert!(terminal.memory_footprint().total() < 64 * 1024);
+        }
+    }
+
+    #[test]
+    fn terminals_can_be_read_from_other_threads() {
+        use std::sync::{Arc, RwLock};
+
+        let terminal = Arc::new(RwLock::new(Terminal::headless(settings())));
+        terminal.write().unwrap().feed(b"hello").unwrap();
+
+        let reader = terminal.clone();
+        let text = std::thread::spawn(move || reader.read().unwrap().visible_text()).join().unwrap();
+        assert!(text.starts_with("hello"));
+    }
+}
@@ -0,0 +1,212 @@
+//! Instrumentation for measuring end-to-end input echo latency.
+
+use crate::event::Event;
+use std::time::{Duration, Instant};
+
+/// How long a probe waits for its echo before it is dropped, such as when
+/// the running program does not echo it.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A completed measurement of how long a probe took to be echoed.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LatencySample {
+    /// The probe string that was written.
+    pub probe: String,
+    /// The time from writing the probe to its echo being received from the driver.
+    ///
+    /// This covers the PTY, the running program, and the driver.
+    pub round_trip: Duration,
+    /// The time readterm spent handling the events that contained the echo.
+    pub processing: Duration,
+}
+
+/// Tracks probes that are waiting for their echo.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyProbes {
+    pending: Vec<PendingProbe>,
+    samples: Vec<LatencySample>,
+}
+
+#[derive(Clone, Debug)]
+struct PendingProbe {
+    probe: String,
+    /// The characters of the probe that are echoed, without control codes
+    /// such as the enter that submits it, which do not come back as text.
+    echo: String,
+    sent_at: Instant,
+    /// The most recently received characters, at most as long as the echo.
+    recent: String,
+    /// When the echo was received, once it has been.
+    received_at: Option<Instant>,
+}
+
+impl LatencyProbes {
+    /// Checks if a probe has any characters that would be echoed as text.
+    pub fn is_measurable(probe: &str) -> bool {
+        probe.chars().any(|character| !character.is_control())
+    }
+
+    /// Starts waiting for the echo of a probe that was written at `sent_at`.
+    ///
+    /// Probes that are not measurable are ignored, as any output would match them.
+    pub fn start(&mut self, probe: &str, sent_at: Instant) {
+        if !LatencyProbes::is_measurable(probe) {
+            return;
+        }
+
+        self.pending.push(PendingProbe {
+            probe: probe.to_owned(),
+            echo: probe.chars().filter(|character| !character.is_control()).collect(),
+            sent_at,
+            recent: String::new(),
+            received_at: None,
+        });
+    }
+
+    /// Looks for probe echoes in a batch of events received from the driver.
+    pub fn observe(&mut self, events: &[Event], received_at: Instant) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        for event in events {
+            if let Event::PutCharacter { character, .. } = *event {
+                for pending in self.pending.iter_mut().filter(|p| p.received_at.is_none()) {
+                    pending.recent.push(character);
+
+                    if pending.recent.chars().count() > pending.echo.chars().count() {
+                        pending.recent.remove(0);
+                    }
+
+                    if pending.recent == pending.echo {
+                        pending.received_at = Some(received_at);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Completes every probe whose echo was received, once its events are
+    /// handled, and drops the ones that have waited too long.
+    pub fn finish_batch(&mut self, processed_at: Instant) {
+        let (received, pending): (Vec<_>, Vec<_>) = self.pending.drain(..)
+            .partition(|p| p.received_at.is_some());
+        self.pending = pending;
+        self.pending.retain(|p| {
+            let stale = processed_at.saturating_duration_since(p.sent_at) > PROBE_TIMEOUT;
+            if stale {
+                info!("dropping the latency probe {:?}, as it was not echoed", p.probe);
+            }
+            !stale
+        });
+
+        for probe in received {
+            let PendingProbe { probe, sent_at, received_at, .. } = probe;
+            let received_at = received_at.unwrap();
+
+            self.samples.push(LatencySample {
+                probe,
+                round_trip: received_at - sent_at,
+                processing: processed_at - received_at,
+            });
+        }
+    }
+
+    /// Takes all completed samples.
+    pub fn take_samples(&mut self) -> Vec<LatencySample> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Gets the number of probes still waiting for their echo.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Color;
+
+    fn echo(text: &str) -> Vec<Event> {
+        text.chars().enumerate().map(|(x, character)| Event::PutCharacter {
+            x,
+            y: 0,
+            character,
+            bold: false,
+            italic: false,
+            underlined: false,
+            strikethrough: false,
+            overline: false,
+            color: Color::WHITE,
+            background: Color::TRANSPARENT,
+        }).collect()
+    }
+
+    #[test]
+    fn measures_the_round_trip_and_processing() {
+        let sent_at = Instant::now();
+        let mut probes = LatencyProbes::default();
+        probes.start("ls", sent_at);
+
+        probes.observe(&echo("$ ls"), sent_at + Duration::from_millis(5));
+        probes.finish_batch(sent_at + Duration::from_millis(7));
+
+        assert_eq!(vec![LatencySample {
+            probe: "ls".to_owned(),
+            round_trip: Duration::from_millis(5),
+            processing: Duration::from_millis(2),
+        }], probes.take_samples());
+        assert_eq!(0, probes.pending_count());
+    }
+
+    #[test]
+    fn waits_for_the_whole_echo() {
+        let sent_at = Instant::now();
+        let mut probes = LatencyProbes::default();
+        probes.start("hello", sent_at);
+
+        probes.observe(&echo("hel"), sent_at);
+        probes.finish_batch(sent_at);
+        assert_eq!(1, probes.pending_count());
+        assert!(probes.take_samples().is_empty());
+
+        // The rest of the echo arrives in the next batch.
+        probes.observe(&echo("lo"), sent_at + Duration::from_millis(3));
+        probes.finish_batch(sent_at + Duration::from_millis(3));
+        assert_eq!(Duration::from_millis(3), probes.take_samples()[0].round_trip);
+    }
+
+    #[test]
+    fn matches_probes_without_their_control_codes() {
+        let sent_at = Instant::now();
+        let mut probes = LatencyProbes::default();
+        probes.start("ls\r", sent_at);
+
+        probes.observe(&echo("ls"), sent_at);
+        probes.finish_batch(sent_at);
+        assert_eq!("ls\r", probes.take_samples()[0].probe);
+    }
+
+    #[test]
+    fn ignores_probes_that_cannot_be_echoed() {
+        let mut probes = LatencyProbes::default();
+        probes.start("", Instant::now());
+        probes.start("\r\n", Instant::now());
+
+        assert_eq!(0, probes.pending_count());
+        assert!(!LatencyProbes::is_measurable("\x1b"));
+    }
+
+    #[test]
+    fn drops_probes_that_are_never_echoed() {
+        let sent_at = Instant::now();
+        let mut probes = LatencyProbes::default();
+        probes.start("ls", sent_at);
+
+        probes.finish_batch(sent_at + PROBE_TIMEOUT);
+        assert_eq!(1, probes.pending_count());
+        probes.finish_batch(sent_at + PROBE_TIMEOUT + Duration::from_millis(1));
+        assert_eq!(0, probes.pending_count());
+    }
+}
@@ -2,25 +2,215 @@
 // FIXME: process may not stop after Drop.
 // read Child docs.
 
+// Without the `std` feature, only the grid model is available.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate log;
 
-pub use self::color::{Color, Style};
-pub use self::core::{Terminal, Settings, Action};
-pub use self::event::Event;
+#[cfg(feature = "std")]
+pub use self::automation::{CommandOutput, RunOptions};
+pub use self::color::{Color, Palette, Style};
+#[cfg(feature = "std")]
+pub use self::convert::render_ansi;
+#[cfg(feature = "std")]
+pub use self::core::{Terminal, Settings, Action, Autoscroll, BackspaceKey, ErrorClassifier, FinishedCommand, LinkConfirmation, SessionStats, StyleOverride};
+pub use self::cursor::{CursorPresence, CursorShape, CursorState};
+#[cfg(feature = "std")]
+pub use self::debug::{DebugState, DriverDebugState};
+pub use self::event::{BellVolume, DynamicColor, EraseMode, Event, ExitStatus, ParserResetReason, ShellMark, UploadProgress};
+#[cfg(feature = "std")]
+pub use self::harness::{TestHarness, TestStep};
+#[cfg(feature = "std")]
+pub use self::latency::LatencySample;
+#[cfg(feature = "std")]
+pub use self::manager::{ScrollbackQuota, TerminalId, TerminalManager};
+pub use self::modes::{Mode, ModeState};
+pub use self::parser::{ControlStringKind, StringLimits};
+pub use self::search::{Search, SearchMatch};
+#[cfg(feature = "std")]
+pub use self::os::{BinaryOutputPolicy, C1Controls, FlowControl, LineEndings};
+#[cfg(all(feature = "std", unix))]
+pub use self::os::{PtyOwnership, Signal};
+#[cfg(feature = "std")]
+pub use self::profile::{Profile, ProfileError, Theme};
+#[cfg(feature = "scripting")]
+pub use self::script::{Script, ScriptError};
+#[cfg(feature = "std")]
+pub use self::session::Session;
 
+#[cfg(feature = "std")]
+mod automation;
 mod color;
+#[cfg(feature = "std")]
+mod convert;
+#[cfg(feature = "std")]
 mod core;
+mod cursor;
+#[cfg(feature = "std")]
+mod debug;
+pub mod encode;
 mod event;
+#[cfg(feature = "std")]
+mod harness;
+#[cfg(feature = "std")]
+mod latency;
+#[cfg(feature = "std")]
+mod manager;
+mod modes;
+#[cfg(feature = "std")]
 pub mod os;
+pub mod parser;
+#[cfg(feature = "std")]
+mod profile;
+#[cfg(feature = "scripting")]
+mod script;
 pub mod scroll_buffer;
+mod search;
+#[cfg(feature = "std")]
+mod session;
+#[cfg(feature = "std")]
+mod upload;
+
+/// The types most programs need, to glob import with `use readterm::prelude::*`.
+pub mod prelude {
+    pub use crate::{Color, Event, Mode, Style, TextSlice};
+    pub use crate::encode::{Key, KeyEvent, Modifiers, MouseButton};
+    #[cfg(feature = "std")]
+    pub use crate::{Action, Session, Settings, Terminal};
+}
+
+/// The `alloc` types that are in the `std` prelude, for `no_std` builds.
+mod std_prelude {
+    #[cfg(not(feature = "std"))]
+    pub use alloc::{
+        borrow::ToOwned,
+        string::{String, ToString},
+        vec::Vec,
+    };
+}
 
 
+/// A rectangular area of the grid.
+///
+/// Coordinates are zero-based and relative to the top-left of the
+/// visible area. The right and bottom edges are exclusive.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rect {
+    /// The leftmost column.
+    pub left: usize,
+    /// The topmost line.
+    pub top: usize,
+    /// The column after the rightmost column.
+    pub right: usize,
+    /// The line after the bottommost line.
+    pub bottom: usize,
+}
+
+/// A semantic hint about what a cell represents.
+///
+/// Frontends can map classes to styles instead of consulting the
+/// subsystem responsible for each one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CellClass {
+    /// Part of a shell prompt.
+    Prompt,
+    /// Part of the user's selection.
+    Selection,
+    /// Part of a search match.
+    SearchMatch,
+    /// Part of the current search match.
+    CurrentSearchMatch,
+    /// Part of a hyperlink.
+    Link,
+    /// The cell under the cursor.
+    Cursor,
+    /// Output that was classified as an error.
+    ErrorStream,
+    /// A line marked by an automation script.
+    Marker,
+}
+
+/// A set of cell classes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CellClasses {
+    bits: u8,
+}
+
+#[allow(unused_imports)]
+use crate::std_prelude::*;
+
 /// A styled set of characters.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct TextSlice {
     /// The text within the slice.
     pub text: String,
     pub style: Style,
+    /// The semantic classes shared by every cell in the slice.
+    pub classes: CellClasses,
+}
+
+impl CellClass {
+    /// Every class.
+    pub const ALL: &'static [CellClass] = &[
+        CellClass::Prompt,
+        CellClass::Selection,
+        CellClass::SearchMatch,
+        CellClass::CurrentSearchMatch,
+        CellClass::Link,
+        CellClass::Cursor,
+        CellClass::ErrorStream,
+        CellClass::Marker,
+    ];
+}
+
+impl CellClasses {
+    /// Creates an empty set.
+    pub fn empty() -> Self {
+        CellClasses::default()
+    }
+
+    /// Checks if the set contains a class.
+    pub fn contains(&self, class: CellClass) -> bool {
+        self.bits & CellClasses::bit(class) != 0
+    }
+
+    /// Adds a class to the set.
+    pub fn insert(&mut self, class: CellClass) {
+        self.bits |= CellClasses::bit(class);
+    }
+
+    /// Removes a class from the set.
+    pub fn remove(&mut self, class: CellClass) {
+        self.bits &= !CellClasses::bit(class);
+    }
+
+    /// Checks if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Creates a set of every class.
+    pub fn all() -> Self {
+        let mut classes = CellClasses::empty();
+        for &class in CellClass::ALL {
+            classes.insert(class);
+        }
+        classes
+    }
+
+    /// Gets the classes in both sets.
+    pub fn intersection(&self, other: CellClasses) -> Self {
+        CellClasses { bits: self.bits & other.bits }
+    }
+
+    fn bit(class: CellClass) -> u8 {
+        1 << class as u8
+    }
 }
 
@@ -0,0 +1,168 @@
+//! Managing many terminals within a shared scrollback memory budget.
+
+use crate::{core::Terminal, event::Event};
+
+/// A memory budget for the scrollback of every terminal in a manager.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScrollbackQuota {
+    /// The most memory all terminals together may use, in bytes.
+    pub max_bytes: usize,
+    /// The number of scrollback lines each terminal keeps, even over budget.
+    pub min_lines_per_terminal: usize,
+}
+
+/// Identifies a terminal in a `TerminalManager`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TerminalId(u64);
+
+/// Owns many terminals, such as the tabs of a frontend.
+///
+/// With a quota, scrollback is evicted from the least recently viewed
+/// terminals first whenever they use too much memory together.
+pub struct TerminalManager {
+    sessions: Vec<Session>,
+    quota: Option<ScrollbackQuota>,
+    next_id: u64,
+    /// Incremented whenever a terminal is viewed.
+    view_clock: u64,
+}
+
+struct Session {
+    id: TerminalId,
+    terminal: Terminal,
+    /// The view clock when the terminal was last viewed.
+    last_viewed: u64,
+}
+
+impl TerminalManager {
+    /// Creates a manager with no terminals and no quota.
+    pub fn new() -> Self {
+        TerminalManager {
+            sessions: Vec::new(),
+            quota: None,
+            next_id: 0,
+            view_clock: 0,
+        }
+    }
+
+    /// Adds a terminal, counting it as just viewed.
+    pub fn add(&mut self, terminal: Terminal) -> TerminalId {
+        let id = TerminalId(self.next_id);
+        self.next_id += 1;
+        self.view_clock += 1;
+
+        self.sessions.push(Session { id, terminal, last_viewed: self.view_clock });
+        id
+    }
+
+    /// Removes a terminal, handing it back.
+    pub fn remove(&mut self, id: TerminalId) -> Option<Terminal> {
+        let index = self.sessions.iter().position(|session| session.id == id)?;
+        Some(self.sessions.remove(index).terminal)
+    }
+
+    /// Gets a terminal.
+    pub fn get(&self, id: TerminalId) -> Option<&Terminal> {
+        self.sessions.iter().find(|session| session.id == id).map(|session| &session.terminal)
+    }
+
+    /// Gets a terminal mutably.
+    pub fn get_mut(&mut self, id: TerminalId) -> Option<&mut Terminal> {
+        self.sessions.iter_mut().find(|session| session.id == id).map(|session| &mut session.terminal)
+    }
+
+    /// Gets the ids of every terminal, in the order they were added.
+    pub fn ids(&self) -> Vec<TerminalId> {
+        self.sessions.iter().map(|session| session.id).collect()
+    }
+
+    /// Records that a terminal is being viewed, so its scrollback is evicted last.
+    pub fn mark_viewed(&mut self, id: TerminalId) {
+        self.view_clock += 1;
+        let view_clock = self.view_clock;
+
+        if let Some(session) = self.sessions.iter_mut().find(|session| session.id == id) {
+            session.last_viewed = view_clock;
+        }
+    }
+
+    /// Gets the shared scrollback quota.
+    pub fn quota(&self) -> Option<ScrollbackQuota> {
+        self.quota
+    }
+
+    /// Sets the shared scrollback quota, or removes it.
+    ///
+    /// The quota is enforced on the next update.
+    pub fn set_quota(&mut self, quota: Option<ScrollbackQuota>) {
+        self.quota = quota;
+    }
+
+    /// Gets the approximate memory used by every terminal together, in bytes.
+    pub fn memory_used(&self) -> usize {
+        self.sessions.iter().map(|session| session.terminal.memory_footprint().total()).sum()
+    }
+
+    /// Updates every terminal, then enforces the quota.
+    ///
+    /// A terminal whose program can't be reached is skipped, so that one
+    /// failing terminal doesn't stop the others from updating.
+    pub fn update(&mut self) -> Vec<(TerminalId, Event)> {
+        let mut events = Vec::new();
+
+        for session in self.sessions.iter_mut() {
+            let id = session.id;
+            match session.terminal.update() {
+                Ok(updated) => events.extend(updated.into_iter().map(|event| (id, event))),
+                Err(e) => warn!("failed to update terminal {:?}: {}", id, e),
+            }
+        }
+
+        events.extend(self.enforce_quota());
+        events
+    }
+
+    /// Evicts scrollback from the least recently viewed terminals until the
+    /// quota is met, or every terminal is down to its minimum.
+    ///
+    /// Each terminal that lost scrollback gets an `Event::ScrollbackEvicted`.
+    pub fn enforce_quota(&mut self) -> Vec<(TerminalId, Event)> {
+        let quota = match self.quota {
+            Some(quota) => quota,
+            None => return Vec::new(),
+        };
+
+        let mut used = self.memory_used();
+        let mut events = Vec::new();
+        self.sessions.sort_by_key(|session| session.last_viewed);
+
+        for session in self.sessions.iter_mut() {
+            if used <= quota.max_bytes {
+                break;
+            }
+
+            let terminal = &mut session.terminal;
+            let footprint = terminal.memory_footprint().total();
+            let line_footprint = terminal.line_footprint().max(1);
+            let wanted = (used - quota.max_bytes).div_ceil(line_footprint);
+            let evictable = terminal.retained_scrollback_line_count().saturating_sub(quota.min_lines_per_terminal);
+
+            let lines = terminal.evict_scrollback(wanted.min(evictable));
+            if lines > 0 {
+                used = used - footprint + terminal.memory_footprint().total();
+                events.push((session.id, Event::ScrollbackEvicted { lines }));
+            }
+        }
+
+        // Keep the terminals in the order they were added.
+        self.sessions.sort_by_key(|session| session.id);
+        events
+    }
+}
+
+impl Default for TerminalManager {
+    fn default() -> Self {
+        TerminalManager::new()
+    }
+}
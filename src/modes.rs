@@ -0,0 +1,193 @@
+//! Terminal modes that the running program can toggle.
+
+/// A mode that can be set or reset by the running program.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Mode {
+    /// Cursor keys send application sequences, as in `ESC O A` (DECCKM, DECSET 1).
+    ApplicationCursorKeys,
+    /// ANSI mode, as opposed to VT52 compatibility mode (DECANM).
+    Ansi,
+    /// Blink the cursor (DECSET 12).
+    CursorBlink,
+    /// Show the cursor (DECTCEM, DECSET 25).
+    CursorVisible,
+    /// Ring the bell when typing nears the right margin (DECSET 44).
+    MarginBell,
+    /// The backarrow key sends backspace rather than delete (DECBKM, DECSET 67).
+    BackarrowKey,
+    /// Backspacing at the start of a line wraps to the previous line (DECSET 45).
+    ReverseWrap,
+    /// The keypad sends application sequences rather than digits (DECKPAM
+    /// and DECKPNM, or DECNKM, DECSET 66).
+    ApplicationKeypad,
+    /// Report focus changes to the program (DECSET 1004).
+    FocusReporting,
+    /// Scrolling on the alternate screen sends arrow keys instead (DECSET 1007).
+    AlternateScroll,
+    /// Report mouse button presses only (X10 mouse, DECSET 9).
+    MousePress,
+    /// Report mouse button presses and releases (DECSET 1000).
+    MouseClick,
+    /// Also report the mouse moving while a button is held (DECSET 1002).
+    MouseDrag,
+    /// Also report the mouse moving with no button held (DECSET 1003).
+    MouseMotion,
+    /// Report the mouse with SGR sequences rather than bytes (DECSET 1006).
+    MouseSgr,
+}
+
+/// The state of every mode tracked by the terminal.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ModeState {
+    /// Whether cursor keys send application sequences.
+    pub application_cursor_keys: bool,
+    /// Whether ANSI mode is enabled. When disabled, the terminal is in VT52 mode.
+    pub ansi: bool,
+    /// Whether the cursor blinks.
+    pub cursor_blink: bool,
+    /// Whether the cursor is shown.
+    pub cursor_visible: bool,
+    /// Whether the margin bell is enabled.
+    pub margin_bell: bool,
+    /// Whether the backarrow key sends backspace (0x08) rather than delete (0x7f).
+    pub backarrow_key: bool,
+    /// Whether backspace wraps to the previous line.
+    pub reverse_wrap: bool,
+    /// Whether the keypad sends application sequences.
+    pub application_keypad: bool,
+    /// Whether focus changes are reported to the program.
+    pub focus_reporting: bool,
+    /// Whether scrolling on the alternate screen sends arrow keys.
+    pub alternate_scroll: bool,
+    /// Whether mouse button presses are reported, in X10 style.
+    pub mouse_press: bool,
+    /// Whether mouse button presses and releases are reported.
+    pub mouse_click: bool,
+    /// Whether the mouse moving with a button held is reported.
+    pub mouse_drag: bool,
+    /// Whether all mouse movement is reported.
+    pub mouse_motion: bool,
+    /// Whether the mouse is reported with SGR sequences.
+    pub mouse_sgr: bool,
+}
+
+impl Mode {
+    /// Every supported mode.
+    pub const ALL: &'static [Mode] = &[
+        Mode::ApplicationCursorKeys,
+        Mode::Ansi,
+        Mode::CursorBlink,
+        Mode::CursorVisible,
+        Mode::MarginBell,
+        Mode::BackarrowKey,
+        Mode::ReverseWrap,
+        Mode::ApplicationKeypad,
+        Mode::FocusReporting,
+        Mode::AlternateScroll,
+        Mode::MousePress,
+        Mode::MouseClick,
+        Mode::MouseDrag,
+        Mode::MouseMotion,
+        Mode::MouseSgr,
+    ];
+
+    /// Looks up a mode from its DEC private mode number.
+    pub fn from_dec_private(number: usize) -> Option<Self> {
+        Mode::ALL.iter().cloned().find(|mode| mode.dec_private_number() == number)
+    }
+
+    /// Gets the DEC private mode number, as used in `CSI ? Pm h`.
+    pub fn dec_private_number(self) -> usize {
+        match self {
+            Mode::ApplicationCursorKeys => 1,
+            Mode::Ansi => 2,
+            Mode::CursorBlink => 12,
+            Mode::CursorVisible => 25,
+            Mode::MarginBell => 44,
+            Mode::BackarrowKey => 67,
+            Mode::ReverseWrap => 45,
+            Mode::ApplicationKeypad => 66,
+            Mode::FocusReporting => 1004,
+            Mode::AlternateScroll => 1007,
+            Mode::MousePress => 9,
+            Mode::MouseClick => 1000,
+            Mode::MouseDrag => 1002,
+            Mode::MouseMotion => 1003,
+            Mode::MouseSgr => 1006,
+        }
+    }
+}
+
+impl ModeState {
+    /// Checks whether a mode is enabled.
+    pub fn get(&self, mode: Mode) -> bool {
+        match mode {
+            Mode::ApplicationCursorKeys => self.application_cursor_keys,
+            Mode::Ansi => self.ansi,
+            Mode::CursorBlink => self.cursor_blink,
+            Mode::CursorVisible => self.cursor_visible,
+            Mode::MarginBell => self.margin_bell,
+            Mode::BackarrowKey => self.backarrow_key,
+            Mode::ReverseWrap => self.reverse_wrap,
+            Mode::ApplicationKeypad => self.application_keypad,
+            Mode::FocusReporting => self.focus_reporting,
+            Mode::AlternateScroll => self.alternate_scroll,
+            Mode::MousePress => self.mouse_press,
+            Mode::MouseClick => self.mouse_click,
+            Mode::MouseDrag => self.mouse_drag,
+            Mode::MouseMotion => self.mouse_motion,
+            Mode::MouseSgr => self.mouse_sgr,
+        }
+    }
+
+    /// Checks whether the running program asked for any mouse reports.
+    pub fn is_mouse_tracking(&self) -> bool {
+        self.mouse_press || self.mouse_click || self.mouse_drag || self.mouse_motion
+    }
+
+    /// Enables or disables a mode.
+    pub fn set(&mut self, mode: Mode, enabled: bool) {
+        match mode {
+            Mode::ApplicationCursorKeys => self.application_cursor_keys = enabled,
+            Mode::Ansi => self.ansi = enabled,
+            Mode::CursorBlink => self.cursor_blink = enabled,
+            Mode::CursorVisible => self.cursor_visible = enabled,
+            Mode::MarginBell => self.margin_bell = enabled,
+            Mode::BackarrowKey => self.backarrow_key = enabled,
+            Mode::ReverseWrap => self.reverse_wrap = enabled,
+            Mode::ApplicationKeypad => self.application_keypad = enabled,
+            Mode::FocusReporting => self.focus_reporting = enabled,
+            Mode::AlternateScroll => self.alternate_scroll = enabled,
+            Mode::MousePress => self.mouse_press = enabled,
+            Mode::MouseClick => self.mouse_click = enabled,
+            Mode::MouseDrag => self.mouse_drag = enabled,
+            Mode::MouseMotion => self.mouse_motion = enabled,
+            Mode::MouseSgr => self.mouse_sgr = enabled,
+        }
+    }
+}
+
+/// The modes a newly spawned program starts with, which
+/// `Terminal::respawn` goes back to.
+impl Default for ModeState {
+    fn default() -> Self {
+        ModeState {
+            application_cursor_keys: false,
+            ansi: true,
+            cursor_blink: false,
+            cursor_visible: true,
+            margin_bell: false,
+            backarrow_key: false,
+            reverse_wrap: false,
+            application_keypad: false,
+            focus_reporting: false,
+            alternate_scroll: false,
+            mouse_press: false,
+            mouse_click: false,
+            mouse_drag: false,
+            mouse_motion: false,
+            mouse_sgr: false,
+        }
+    }
+}
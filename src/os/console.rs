@@ -0,0 +1,730 @@
+//! Turns the output of the running program into events, on top of the
+//! escape sequence parser.
+//!
+//! The console tracks where each character goes, as the scroll buffer will
+//! place it, and the graphic rendition it is drawn with. Sequences that
+//! the escape interceptor already handles, such as OSC strings and most
+//! modes, are ignored here.
+
+use crate::{
+    event::{EraseMode, Event, ParserResetReason},
+    parser::{ControlSequence, ControlStringKind, EscapeSequence, Parser, StringLimits, Visitor},
+    Color, Palette, Rect, Style,
+};
+use std::{convert::TryFrom, ops::Range, str};
+
+/// The columns between tab stops.
+const TAB_STOP_WIDTH: usize = 8;
+/// The most sequences in a row that may go unrecognized before the output
+/// is taken to be garbled.
+const MAX_UNKNOWN_SEQUENCES: usize = 64;
+
+/// A virtual console for the output of one running program.
+#[derive(Clone, Debug)]
+pub struct Console {
+    parser: Parser,
+    state: State,
+}
+
+/// Everything the parser's visitor updates.
+#[derive(Clone, Debug)]
+struct State {
+    columns: usize,
+    lines: usize,
+    /// The cursor. The column may equal `columns` when the line is full and
+    /// the next character wraps.
+    x: usize,
+    y: usize,
+    /// The lines that scroll, set by DECSTBM.
+    scroll_region: Range<usize>,
+    rendition: Rendition,
+    /// The colors that SGR selects by number, as changed by OSC 4, 10 and 11.
+    palette: Palette,
+    /// The colors from the settings, which OSC 104, 110 and 111 go back to.
+    default_palette: Palette,
+    /// The cursor saved by DECSC or `CSI s`.
+    saved_cursor: Option<(usize, usize, Rendition)>,
+    /// The sequences in a row that were not recognized.
+    unknown_sequences: usize,
+    events: Vec<Event>,
+}
+
+/// The graphic rendition set by SGR.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Rendition {
+    bold: bool,
+    italic: bool,
+    underlined: bool,
+    strikethrough: bool,
+    overline: bool,
+    reverse: bool,
+    /// The foreground color, or `None` for the default.
+    foreground: Option<Color>,
+    /// The background color, or `None` for the default.
+    background: Option<Color>,
+}
+
+impl Console {
+    /// Creates a console of the given size, drawing with the given colors
+    /// and reading control strings up to the given limits.
+    pub fn new(columns: usize, lines: usize, palette: Palette, limits: StringLimits) -> Self {
+        Console {
+            parser: Parser::with_limits(limits),
+            state: State {
+                columns: columns.max(1),
+                lines: lines.max(1),
+                x: 0,
+                y: 0,
+                scroll_region: 0..lines.max(1),
+                rendition: Rendition::default(),
+                palette: palette.clone(),
+                default_palette: palette,
+                saved_cursor: None,
+                unknown_sequences: 0,
+                events: Vec::new(),
+            },
+        }
+    }
+
+    /// Handles output from the running program, which may end partway
+    /// through a sequence or character.
+    pub fn write(&mut self, bytes: &[u8]) -> Vec<Event> {
+        self.parser.advance(&mut self.state, bytes);
+        std::mem::take(&mut self.state.events)
+    }
+
+    /// Resizes the console, keeping the cursor within it.
+    pub fn resize(&mut self, columns: usize, lines: usize) {
+        self.state.columns = columns.max(1);
+        self.state.lines = lines.max(1);
+        self.state.x = self.state.x.min(self.state.columns);
+        self.state.y = self.state.y.min(self.state.lines - 1);
+        self.state.scroll_region = 0..self.state.lines;
+    }
+}
+
+impl State {
+    /// Emits a character at the cursor.
+    fn put(&mut self, character: char) {
+        let (color, background) = self.rendition.colors(&self.palette);
+
+        self.events.push(Event::PutCharacter {
+            x: self.x,
+            y: self.y,
+            character,
+            bold: self.rendition.bold,
+            italic: self.rendition.italic,
+            underlined: self.rendition.underlined,
+            strikethrough: self.rendition.strikethrough,
+            overline: self.rendition.overline,
+            color,
+            background,
+        });
+    }
+
+    fn print_character(&mut self, character: char) {
+        self.put(character);
+
+        // The scroll buffer wraps a full line before the next character.
+        if self.x >= self.columns {
+            self.x = 0;
+            self.y = self.line_below();
+        }
+        self.x += 1;
+    }
+
+    /// Moves down a line, scrolling at the bottom.
+    ///
+    /// The scroll buffer scrolls when it is given a line feed, which also
+    /// returns its cursor to the left, but the next character is placed
+    /// by its own position anyway.
+    fn line_feed(&mut self) {
+        self.put('\n');
+        self.y = self.line_below();
+    }
+
+    /// Gets the line that a line feed moves to, which is the same line at
+    /// the bottom of the scroll region or the grid.
+    fn line_below(&self) -> usize {
+        match self.y + 1 == self.scroll_region.end {
+            true => self.y,
+            false => (self.y + 1).min(self.lines - 1),
+        }
+    }
+
+    /// Sets the lines that scroll, from DECSTBM, ignoring invalid regions.
+    fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        let bottom = bottom.min(self.lines);
+        if top + 1 >= bottom {
+            return;
+        }
+
+        self.scroll_region = top..bottom;
+        self.events.push(Event::SetScrollRegion { top, bottom });
+        self.move_to(0, 0);
+    }
+
+    /// Moves the cursor, keeping it within the grid.
+    fn move_to(&mut self, x: usize, y: usize) {
+        self.x = x.min(self.columns - 1);
+        self.y = y.min(self.lines - 1);
+    }
+
+    /// Erases an area of the grid with the pen's background, as xterm does.
+    /// Fills an area with a character, drawn with the pen.
+    fn fill(&mut self, area: Rect, character: char) {
+        let (color, background) = self.rendition.colors(&self.palette);
+        let style = Style {
+            color,
+            background,
+            bold: self.rendition.bold,
+            italic: self.rendition.italic,
+            underlined: self.rendition.underlined,
+            strikethrough: self.rendition.strikethrough,
+            overline: self.rendition.overline,
+            concealed: false,
+        };
+        self.events.push(Event::FillRect { area, character, style });
+    }
+
+    fn erase(&mut self, left: usize, top: usize, right: usize, bottom: usize) {
+        if left < right && top < bottom {
+            let background = self.rendition.background.unwrap_or(self.palette.background);
+            self.events.push(Event::EraseRect { area: Rect { left, top, right, bottom }, background });
+        }
+    }
+
+    /// Handles ED.
+    fn erase_in_display(&mut self, parameter: usize) {
+        if let Some(mode) = EraseMode::from_parameter(parameter) {
+            let background = self.rendition.background.unwrap_or(self.palette.background);
+            self.events.push(Event::EraseInDisplay { x: self.x, y: self.y, mode, background });
+        }
+    }
+
+    /// Handles EL.
+    fn erase_in_line(&mut self, parameter: usize) {
+        match EraseMode::from_parameter(parameter) {
+            Some(EraseMode::Scrollback) | None => (),
+            Some(mode) => {
+                let background = self.rendition.background.unwrap_or(self.palette.background);
+                self.events.push(Event::EraseInLine { x: self.x, y: self.y, mode, background });
+            },
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some((self.x, self.y, self.rendition));
+        self.events.push(Event::SaveCursor);
+    }
+
+    fn restore_cursor(&mut self) {
+        let (x, y, rendition) = self.saved_cursor.unwrap_or((0, 0, Rendition::default()));
+        self.move_to(x, y);
+        self.rendition = rendition;
+        self.events.push(Event::RestoreCursor);
+    }
+
+    /// Counts a sequence that was not recognized, following `previous` others.
+    ///
+    /// The parser is back in its ground state after every sequence, so what
+    /// a run of garbled sequences may have left behind is the rendition.
+    fn unknown_sequence(&mut self, previous: usize) {
+        self.unknown_sequences = previous + 1;
+
+        if self.unknown_sequences >= MAX_UNKNOWN_SEQUENCES {
+            self.unknown_sequences = 0;
+            self.rendition = Rendition::default();
+            self.events.push(Event::ParserReset { reason: ParserResetReason::UnknownSequences });
+        }
+    }
+
+    /// Handles DECSET and DECRST for the alternate screen modes, which
+    /// the escape interceptor leaves to the console.
+    fn set_private_mode(&mut self, number: usize, enabled: bool) {
+        match number {
+            47 => self.events.push(Event::SetAlternateScreen { enabled }),
+            1047 => {
+                self.events.push(Event::SetAlternateScreen { enabled });
+                if enabled {
+                    self.events.push(Event::ClearScreen);
+                }
+            },
+            1049 => {
+                if enabled {
+                    self.save_cursor();
+                    self.events.push(Event::SetAlternateScreen { enabled });
+                    self.events.push(Event::ClearScreen);
+                } else {
+                    self.events.push(Event::SetAlternateScreen { enabled });
+                    self.restore_cursor();
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /// Handles SGR.
+    fn select_graphic_rendition(&mut self, parameters: &[usize]) {
+        if parameters.is_empty() {
+            self.rendition = Rendition::default();
+            return;
+        }
+
+        let mut parameters = parameters.iter().cloned();
+        while let Some(parameter) = parameters.next() {
+            let (rendition, palette) = (&mut self.rendition, &self.palette);
+
+            match parameter {
+                0 => *rendition = Rendition::default(),
+                1 => rendition.bold = true,
+                3 => rendition.italic = true,
+                4 | 21 => rendition.underlined = true,
+                7 => rendition.reverse = true,
+                9 => rendition.strikethrough = true,
+                22 => rendition.bold = false,
+                23 => rendition.italic = false,
+                24 => rendition.underlined = false,
+                27 => rendition.reverse = false,
+                29 => rendition.strikethrough = false,
+                // Framed and encircled text is drawn plainly.
+                51 | 52 | 54 => (),
+                53 => rendition.overline = true,
+                55 => rendition.overline = false,
+                30..=37 => rendition.foreground = Some(palette.indexed(parameter as u8 - 30)),
+                38 => rendition.foreground = extended_color(&mut parameters, palette),
+                39 => rendition.foreground = None,
+                40..=47 => rendition.background = Some(palette.indexed(parameter as u8 - 40)),
+                48 => rendition.background = extended_color(&mut parameters, palette),
+                49 => rendition.background = None,
+                90..=97 => rendition.foreground = Some(palette.indexed(parameter as u8 - 90 + 8)),
+                100..=107 => rendition.background = Some(palette.indexed(parameter as u8 - 100 + 8)),
+                // Skip the arguments of underline colors.
+                58 => { extended_color(&mut parameters, palette); },
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Visitor for State {
+    fn print(&mut self, character: char) {
+        self.unknown_sequences = 0;
+        self.print_character(character);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        self.unknown_sequences = 0;
+        match byte {
+            0x07 => self.events.push(Event::Bell),
+            0x08 => self.x = self.x.min(self.columns - 1).saturating_sub(1),
+            b'\t' => {
+                let next_stop = (self.x / TAB_STOP_WIDTH + 1) * TAB_STOP_WIDTH;
+                self.x = next_stop.min(self.columns - 1).max(self.x);
+            },
+            b'\n' | 0x0b | 0x0c => self.line_feed(),
+            b'\r' => {
+                self.put('\r');
+                self.x = 0;
+            },
+            _ => (),
+        }
+    }
+
+    fn esc(&mut self, sequence: &EscapeSequence) {
+        let unknown_sequences = std::mem::take(&mut self.unknown_sequences);
+        if !sequence.intermediates.is_empty() {
+            return;
+        }
+
+        match sequence.final_byte {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            // Index.
+            b'D' => self.line_feed(),
+            // Next line.
+            b'E' => {
+                self.line_feed();
+                self.x = 0;
+            },
+            // Reverse index, without scrolling back.
+            b'M' => self.y = self.y.saturating_sub(1),
+            // Full reset.
+            b'c' => {
+                self.rendition = Rendition::default();
+                self.saved_cursor = None;
+                self.set_scroll_region(0, self.lines);
+                self.move_to(0, 0);
+                self.events.push(Event::ClearScreen);
+            },
+            _ => self.unknown_sequence(unknown_sequences),
+        }
+    }
+
+    fn csi(&mut self, sequence: &ControlSequence) {
+        let unknown_sequences = std::mem::take(&mut self.unknown_sequences);
+        let n = sequence.parameter(0, 1);
+
+        match (sequence.private_marker, sequence.intermediates, sequence.final_byte) {
+            (Some(b'?'), b"", b'h') | (Some(b'?'), b"", b'l') => {
+                for &number in sequence.parameters {
+                    self.set_private_mode(number, sequence.final_byte == b'h');
+                }
+            },
+            // DECFRA - Fill Rectangular Area.
+            (None, b"$", b'x') => {
+                if let Some(character) = std::char::from_u32(sequence.parameter(0, b' ' as usize) as u32) {
+                    self.fill(rect(sequence, 1), character);
+                }
+            },
+            // DECERA - Erase Rectangular Area.
+            (None, b"$", b'z') => {
+                let Rect { left, top, right, bottom } = rect(sequence, 0);
+                self.erase(left, top, right, bottom);
+            },
+            (Some(_), _, _) | (_, [_, ..], _) => (),
+            (None, _, b'A') => self.move_to(self.x, self.y.saturating_sub(n)),
+            (None, _, b'B') | (None, _, b'e') => self.move_to(self.x, self.y.saturating_add(n)),
+            (None, _, b'C') | (None, _, b'a') => self.move_to(self.x.saturating_add(n), self.y),
+            (None, _, b'D') => self.move_to(self.x.min(self.columns - 1).saturating_sub(n), self.y),
+            (None, _, b'E') => self.move_to(0, self.y.saturating_add(n)),
+            (None, _, b'F') => self.move_to(0, self.y.saturating_sub(n)),
+            (None, _, b'G') | (None, _, b'`') => self.move_to(n - 1, self.y),
+            (None, _, b'H') | (None, _, b'f') => {
+                self.move_to(sequence.parameter(1, 1) - 1, sequence.parameter(0, 1) - 1);
+            },
+            (None, _, b'd') => self.move_to(self.x, n - 1),
+            (None, _, b'J') => self.erase_in_display(sequence.parameter(0, 0)),
+            (None, _, b'K') => self.erase_in_line(sequence.parameter(0, 0)),
+            // Erase characters.
+            (None, _, b'X') => {
+                let (x, y) = (self.x.min(self.columns - 1), self.y);
+                self.erase(x, y, x.saturating_add(n).min(self.columns), y + 1);
+            },
+            // Counts are clamped to the grid, as more would do nothing more.
+            (None, _, b'@') => {
+                let (x, y) = (self.x.min(self.columns - 1), self.y);
+                self.events.push(Event::InsertCharacters { x, y, count: n.min(self.columns) });
+            },
+            (None, _, b'P') => {
+                let (x, y) = (self.x.min(self.columns - 1), self.y);
+                self.events.push(Event::DeleteCharacters { x, y, count: n.min(self.columns) });
+            },
+            (None, _, b'L') | (None, _, b'M') if self.scroll_region.contains(&self.y) => {
+                let (y, count) = (self.y, n.min(self.lines));
+                self.events.push(match sequence.final_byte {
+                    b'L' => Event::InsertLines { y, count },
+                    _ => Event::DeleteLines { y, count },
+                });
+                self.x = 0;
+            },
+            (None, _, b'm') => self.select_graphic_rendition(sequence.parameters),
+            (None, _, b'r') => self.set_scroll_region(n - 1, sequence.parameter(1, self.lines)),
+            (None, _, b's') => self.save_cursor(),
+            (None, _, b'u') => self.restore_cursor(),
+            // Window manipulation: resize the text area.
+            (None, _, b't') if sequence.parameter(0, 0) == 8 => {
+                let rows = sequence.parameter(1, self.lines);
+                let cols = sequence.parameter(2, self.columns);
+                self.events.push(Event::ResizeRequested { cols, rows });
+            },
+            _ => self.unknown_sequence(unknown_sequences),
+        }
+    }
+
+    fn osc(&mut self, parameters: &[&[u8]]) {
+        let arguments = parameters.get(1..).unwrap_or(&[]);
+
+        match parameters.first().copied() {
+            Some(b"4") => {
+                for pair in arguments.chunks_exact(2) {
+                    if let (Some(index), Some(color)) = (palette_index(pair[0]), parse_color(pair[1])) {
+                        self.palette.set_indexed(index, color);
+                    }
+                }
+            },
+            Some(b"104") if arguments.is_empty() => {
+                self.palette.ansi = self.default_palette.ansi;
+                self.palette.extended = self.default_palette.extended.clone();
+            },
+            Some(b"104") => {
+                for index in arguments.iter().filter_map(|&argument| palette_index(argument)) {
+                    self.palette.set_indexed(index, self.default_palette.indexed(index));
+                }
+            },
+            // The foreground, then the background, unless the list starts at the background.
+            Some(b"10") | Some(b"11") => {
+                let skip = if parameters[0] == b"10" { 0 } else { 1 };
+                let colors = [&mut self.palette.foreground, &mut self.palette.background];
+
+                for (color, argument) in IntoIterator::into_iter(colors).skip(skip).zip(arguments) {
+                    if let Some(parsed) = parse_color(argument) {
+                        *color = parsed;
+                    }
+                }
+            },
+            Some(b"110") => self.palette.foreground = self.default_palette.foreground,
+            Some(b"111") => self.palette.background = self.default_palette.background,
+            _ => (),
+        }
+    }
+
+    fn string_abandoned(&mut self) {
+        self.events.push(Event::ParserReset { reason: ParserResetReason::UnterminatedString });
+    }
+
+    fn string_discarded(&mut self, kind: ControlStringKind, length: usize) {
+        self.events.push(Event::ControlStringDiscarded { kind, length });
+    }
+}
+
+impl Rendition {
+    /// Gets the foreground and background colors to draw with.
+    fn colors(&self, palette: &Palette) -> (Color, Color) {
+        let foreground = self.foreground.unwrap_or(palette.foreground);
+        let background = self.background.unwrap_or(palette.background);
+
+        match self.reverse {
+            // Text cannot be drawn in the frontend's background.
+            true if background == Color::TRANSPARENT => (Color::BLACK, foreground),
+            true => (background, foreground),
+            false => (foreground, background),
+        }
+    }
+}
+
+/// Gets the rectangle given by four parameters, as top, left, bottom and
+/// right, each counting from one and inclusive.
+fn rect(sequence: &ControlSequence, index: usize) -> Rect {
+    Rect {
+        top: sequence.parameter(index, 1) - 1,
+        left: sequence.parameter(index + 1, 1) - 1,
+        bottom: sequence.parameter(index + 2, usize::MAX),
+        right: sequence.parameter(index + 3, usize::MAX),
+    }
+}
+
+/// Parses the index of one of the 256 colors in the palette.
+fn palette_index(parameter: &[u8]) -> Option<u8> {
+    str::from_utf8(parameter).ok()?.parse().ok()
+}
+
+/// Parses a color specification from an OSC string, such as `#ff0000`.
+fn parse_color(parameter: &[u8]) -> Option<Color> {
+    Color::parse_x11(str::from_utf8(parameter).ok()?)
+}
+
+/// Reads the arguments of an extended color, `5;n` or `2;r;g;b`.
+fn extended_color<I>(parameters: &mut I, palette: &Palette) -> Option<Color> where I: Iterator<Item = usize> {
+    match parameters.next() {
+        Some(5) => parameters.next().and_then(|index| u8::try_from(index).ok()).map(|index| palette.indexed(index)),
+        Some(2) => {
+            let mut channel = || parameters.next().map(|value| value.min(255) as u8);
+            match (channel(), channel(), channel()) {
+                (Some(red), Some(green), Some(blue)) => Some(Color::from_rgb8(red, green, blue)),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn characters(events: &[Event]) -> Vec<(usize, usize, char)> {
+        events.iter().filter_map(|event| match *event {
+            Event::PutCharacter { x, y, character, .. } => Some((x, y, character)),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn places_characters_and_moves_the_cursor() {
+        let mut console = Console::new(4, 2, Palette::default(), StringLimits::default());
+        let events = console.write(b"ab\r\nc\x1b[1;3Hd\x1b[2Ge");
+
+        assert_eq!(vec![(0, 0, 'a'), (1, 0, 'b'), (2, 0, '\r'), (0, 0, '\n'), (0, 1, 'c'), (2, 0, 'd'), (1, 0, 'e')],
+                   characters(&events));
+    }
+
+    #[test]
+    fn wraps_and_scrolls_like_the_scroll_buffer() {
+        let mut console = Console::new(2, 2, Palette::default(), StringLimits::default());
+        let events = console.write(b"abc\r\nd");
+
+        assert_eq!(vec![(0, 0, 'a'), (1, 0, 'b'), (2, 0, 'c'), (1, 1, '\r'), (0, 1, '\n'), (0, 1, 'd')],
+                   characters(&events));
+    }
+
+    #[test]
+    fn applies_graphic_rendition() {
+        let mut console = Console::new(10, 2, Palette::default(), StringLimits::default());
+        let events = console.write(b"\x1b[1;31;48;5;21ma\x1b[7mb\x1b[0mc");
+
+        let styles: Vec<_> = events.iter().map(|event| match *event {
+            Event::PutCharacter { bold, color, background, .. } => (bold, color, background),
+            _ => unreachable!(),
+        }).collect();
+        let red = Color::from_rgb8(205, 0, 0);
+        let blue = Color::from_rgb8(0, 0, 255);
+
+        assert_eq!(vec![
+            (true, red, blue),
+            (true, blue, red),
+            (false, Color::WHITE, Color::TRANSPARENT),
+        ], styles);
+    }
+
+    #[test]
+    fn overlines_and_ignores_frames() {
+        let mut console = Console::new(10, 2, Palette::default(), StringLimits::default());
+        let events = console.write(b"\x1b[1;53;51;52;54ma\x1b[55mb\x1b[53m\x1b[0mc");
+
+        let styles: Vec<_> = events.iter().map(|event| match *event {
+            Event::PutCharacter { bold, overline, .. } => (bold, overline),
+            _ => unreachable!(),
+        }).collect();
+        assert_eq!(vec![(true, true), (true, false), (false, false)], styles);
+    }
+
+    #[test]
+    fn draws_with_the_palette() {
+        let mut palette = Palette::default();
+        palette.ansi[1] = Color::from_rgb8(0xdc, 0x32, 0x2f);
+        palette.foreground = Color::from_rgb8(0x83, 0x94, 0x96);
+        palette.background = Color::from_rgb8(0x00, 0x2b, 0x36);
+        let mut console = Console::new(10, 2, palette.clone(), StringLimits::default());
+
+        assert_eq!(vec![
+            Event::PutCharacter {
+                x: 0, y: 0, character: 'a', bold: false, italic: false, underlined: false, strikethrough: false, overline: false,
+                color: palette.ansi[1], background: palette.background,
+            },
+            Event::PutCharacter {
+                x: 1, y: 0, character: 'b', bold: false, italic: false, underlined: false, strikethrough: false, overline: false,
+                color: palette.background, background: palette.foreground,
+            },
+            Event::EraseInLine { x: 2, y: 0, mode: EraseMode::All, background: palette.background },
+        ], console.write(b"\x1b[31ma\x1b[0;7mb\x1b[0m\x1b[2K"));
+        assert_eq!(Color::from_ansi_256(100), palette.indexed(100));
+    }
+
+    #[test]
+    fn erases_and_switches_screens() {
+        let mut console = Console::new(4, 3, Palette::default(), StringLimits::default());
+        console.write(b"\x1b[2;2H");
+
+        assert_eq!(vec![
+            Event::EraseInDisplay { x: 1, y: 1, mode: EraseMode::ToEnd, background: Color::TRANSPARENT },
+        ], console.write(b"\x1b[J"));
+        assert!(console.write(b"\x1b[3K").is_empty());
+        assert_eq!(vec![Event::SaveCursor, Event::SetAlternateScreen { enabled: true }, Event::ClearScreen],
+                   console.write(b"\x1b[?1049h"));
+        assert_eq!(vec![Event::ResizeRequested { cols: 80, rows: 24 }], console.write(b"\x1b[8;24;80t"));
+    }
+
+    #[test]
+    fn line_feeds_stay_within_the_scroll_region() {
+        let mut console = Console::new(4, 4, Palette::default(), StringLimits::default());
+        let events = console.write(b"\x1b[2;3r\x1b[3Ha\nb");
+
+        assert_eq!(Event::SetScrollRegion { top: 1, bottom: 3 }, events[0]);
+        assert_eq!(vec![(0, 2, 'a'), (1, 2, '\n'), (1, 2, 'b')], characters(&events));
+        assert_eq!(vec![(1, 3, 'c')], characters(&console.write(b"\x1b[4;2Hc")));
+        assert!(console.write(b"\x1b[3;3r").is_empty());
+    }
+
+    #[test]
+    fn inserts_and_deletes_characters_and_lines() {
+        let mut console = Console::new(4, 4, Palette::default(), StringLimits::default());
+
+        assert_eq!(vec![
+            Event::InsertCharacters { x: 1, y: 2, count: 1 },
+            Event::DeleteCharacters { x: 1, y: 2, count: 2 },
+            Event::InsertLines { y: 2, count: 3 },
+        ], console.write(b"\x1b[3;2H\x1b[@\x1b[2P\x1b[3L"));
+        assert_eq!(vec![(0, 2, 'a')], characters(&console.write(b"a")));
+
+        console.write(b"\x1b[1;2r");
+        assert!(console.write(b"\x1b[4H\x1b[M").is_empty());
+    }
+
+    #[test]
+    fn saves_and_restores_the_cursor() {
+        let mut console = Console::new(4, 3, Palette::default(), StringLimits::default());
+        let events = console.write(b"\x1b[2;2H\x1b7\x1b[3;1Hs\x1b8r");
+
+        assert_eq!(Event::SaveCursor, events[0]);
+        assert_eq!(Event::RestoreCursor, events[2]);
+        assert_eq!(vec![(0, 2, 's'), (1, 1, 'r')], characters(&events));
+    }
+
+    #[test]
+    fn erases_with_the_pen_background() {
+        let mut console = Console::new(4, 2, Palette::default(), StringLimits::default());
+        let blue = Color::from_ansi_256(4);
+
+        assert_eq!(vec![
+            Event::EraseInLine { x: 0, y: 0, mode: EraseMode::All, background: blue },
+        ], console.write(b"\x1b[44m\x1b[2K"));
+        assert_eq!(vec![
+            Event::EraseRect { area: Rect { left: 0, top: 0, right: 2, bottom: 1 }, background: blue },
+        ], console.write(b"\x1b[2X"));
+        assert_eq!(vec![
+            Event::EraseInDisplay { x: 0, y: 0, mode: EraseMode::All, background: Color::TRANSPARENT },
+        ], console.write(b"\x1b[0m\x1b[2J"));
+    }
+
+    #[test]
+    fn fills_and_erases_rectangles_with_the_pen() {
+        let mut console = Console::new(4, 2, Palette::default(), StringLimits::default());
+        let red = Color::from_ansi_256(1);
+        let blue = Color::from_ansi_256(4);
+
+        assert_eq!(vec![Event::FillRect {
+            area: Rect { top: 1, left: 0, bottom: usize::MAX, right: 5 },
+            character: 'E',
+            style: Style { color: red, background: blue, bold: true, ..Style::default() },
+        }], console.write(b"\x1b[1;31;44m\x1b[69;2;;;5$x"));
+        assert_eq!(vec![Event::EraseRect {
+            area: Rect { top: 0, left: 0, bottom: usize::MAX, right: usize::MAX },
+            background: blue,
+        }], console.write(b"\x1b[$z"));
+    }
+
+    #[test]
+    fn resets_after_many_unknown_sequences() {
+        let mut console = Console::new(4, 2, Palette::default(), StringLimits::default());
+        let garbage = "\x1b[9y".repeat(MAX_UNKNOWN_SEQUENCES - 1);
+        let reset = Event::ParserReset { reason: ParserResetReason::UnknownSequences };
+
+        // Text breaks the run.
+        assert!(!console.write(format!("\x1b[1m{}a{}", garbage, garbage).as_bytes()).contains(&reset));
+
+        let events = console.write(b"\x1b[9yb");
+        assert_eq!(Some(&reset), events.first());
+        assert!(matches!(events[1], Event::PutCharacter { character: 'b', bold: false, .. }));
+    }
+
+    proptest! {
+        #[test]
+        fn huge_parameters_stay_on_the_grid(first in any::<u64>(), second in any::<u64>(),
+                                            final_byte in proptest::sample::select(b"@ABCDEFGHLMPXadefr`".to_vec())) {
+            let mut console = Console::new(10, 5, Palette::default(), StringLimits::default());
+            let sequence = format!("\x1b[{};{}{}x\x1b[2;4r\x1b[3H\x1b[{}L", first, second, final_byte as char, first);
+
+            for event in console.write(sequence.as_bytes()) {
+                match event {
+                    Event::PutCharacter { x, y, .. } => prop_assert!(x <= 10 && y < 5),
+                    Event::InsertCharacters { count, .. } | Event::DeleteCharacters { count, .. } => prop_assert!(count <= 10),
+                    Event::InsertLines { count, .. } | Event::DeleteLines { count, .. } => prop_assert!(count <= 5),
+                    Event::SetScrollRegion { top, bottom } => prop_assert!(top < bottom && bottom <= 5),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
@@ -2,18 +2,32 @@
 
 use crate::{
     core::Settings,
-    os,
-    Color, Event,
+    debug::DriverDebugState,
+    encode::control_byte,
+    event::ExitStatus,
+    os::{self, console::Console, escape, queue, flow::FlowState, input::InputQueue, output::OutputFilter, BinaryOutputPolicy, ByteCounts},
+    Event,
 };
 
 use std::{
     io, mem,
     io::prelude::*,
-    process::{Child, ChildStdin, Command, ExitStatus, Stdio},
-    sync::mpsc,
+    process::{self, Child, ChildStdin, Command, Stdio},
+    sync::{Arc, atomic::{AtomicBool, Ordering}},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
-
-const TEXT_COLOR: Color = Color::WHITE;
+use self::queue::RecvTimeoutError;
+
+/// The number of output chunks that may be buffered before the reader blocks.
+const CHANNEL_CAPACITY: usize = 64;
+/// The maximum number of bytes read from the shell at once.
+const READ_CHUNK_SIZE: usize = 4096;
+/// How often the manager thread checks whether the shell has exited.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long output is waited for after the shell exits. Background jobs
+/// may keep its stdout open for much longer.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
 
 mod default_shell {
     #[cfg(unix)]
@@ -38,109 +52,280 @@ mod default_shell {
 ///
 /// *NOTE:* This driver does not support many features.
 ///
-/// Features that are not supported include colors, styling, etc.
+/// Features that are not supported include resizing the running program,
+/// echo detection, etc.
 ///
 /// This driver operates on the standard out/err/in text streams only.
 pub struct Driver {
-    events: std::sync::mpsc::Receiver<manager_thread::Event>,
+    manager: manager_thread::Handle,
     shell_stdin: ChildStdin,
+    /// Detects binary output and normalizes line endings.
+    output_filter: OutputFilter,
+    /// Handles XON/XOFF in the input.
+    flow: FlowState,
+    /// Bulk input waiting to be written.
+    input: InputQueue,
+    /// Turns output into events.
+    console: Console,
+    /// Handles the escape sequences that the parser does not support.
+    interceptor: escape::Interceptor,
     is_session_finished: bool,
+    /// How the shell exited, once it has.
+    exit_status: Option<ExitStatus>,
+    /// The bytes written to and read from the shell.
+    byte_counts: ByteCounts,
 }
 
-impl os::Driver for Driver {
-    fn new(_: &Settings) -> Result<Self, io::Error> {
+impl Driver {
+    /// Starts the default shell with piped standard streams.
+    pub fn new(settings: &Settings) -> Result<Self, io::Error> {
         let mut child_shell = Command::new(default_shell::EXECUTABLE)
             .args(default_shell::ARGS)
+            .envs(settings.environment.iter().cloned())
             .stdout(Stdio::piped())
             .stdin(Stdio::piped())
             .stderr(Stdio::piped()) // ideally stdout/stderr will be interleaved, completely on stdout.
             .spawn()?;
 
-        let shell_stdin = mem::replace(&mut child_shell.stdin, None).unwrap();
+        let shell_stdin = child_shell.stdin.take().unwrap();
 
-        let rx = manager_thread::create(child_shell);
+        let manager = manager_thread::create(child_shell);
 
         Ok(Driver {
-            events: rx,
+            manager,
             shell_stdin,
+            output_filter: OutputFilter::new(settings.binary_output, settings.line_endings),
+            flow: FlowState::new(settings.flow_control),
+            input: InputQueue::new(),
+            console: Console::new(settings.column_count, settings.line_count, settings.palette.clone(), settings.string_limits),
+            interceptor: escape::Interceptor::new(settings.c1_controls, settings.string_limits),
             is_session_finished: false,
+            exit_status: None,
+            byte_counts: ByteCounts::default(),
         })
     }
+}
+
+impl os::Driver for Driver {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        // Keep the order of input written behind a paste.
+        if !self.input.is_empty() {
+            self.input.push(bytes);
+            return Ok(());
+        }
 
-    fn write_text(&mut self, s: &str) {
-        self.shell_stdin.write(s.as_bytes()).unwrap();
+        self.send_input(bytes)
     }
 
-    fn backspace(&mut self) {
+    fn backspace(&mut self) -> Result<(), io::Error> {
         unimplemented("backspace");
+        Ok(())
     }
 
-    fn escape(&mut self) {
+    fn escape(&mut self) -> Result<(), io::Error> {
         unimplemented("escape key");
+        Ok(())
     }
 
-    fn cursor_left(&mut self) {
+    fn cursor_left(&mut self) -> Result<(), io::Error> {
         unimplemented("cursor left");
+        Ok(())
     }
 
-    fn cursor_right(&mut self) {
+    fn cursor_right(&mut self) -> Result<(), io::Error> {
         unimplemented("cursor right");
+        Ok(())
     }
 
-    fn cursor_up(&mut self) {
+    fn cursor_up(&mut self) -> Result<(), io::Error> {
         unimplemented("cursor up");
+        Ok(())
     }
 
-    fn cursor_down(&mut self) {
+    fn cursor_down(&mut self) -> Result<(), io::Error> {
         unimplemented("cursor down");
+        Ok(())
+    }
+
+    fn control_code(&mut self, c: char) -> Result<(), io::Error> {
+        if !self.flow.filter_control_code(c) {
+            return Ok(());
+        }
+
+        match control_byte(c) {
+            Some(byte) => self.send_input(&[byte]),
+            None => {
+                warn!("cannot send {:?} as a control code", c);
+                Ok(())
+            },
+        }
     }
 
-    fn control_code(&mut self, c: char) {
-        unimplemented(format!("control code: {:?}", c));
+    fn respawn(&mut self, settings: &Settings) -> Result<(), io::Error> {
+        *self = Driver::new(settings)?;
+        Ok(())
     }
 
-    fn signal_interrupt(&mut self) {
+    fn signal_interrupt(&mut self) -> Result<(), io::Error> {
         unimplemented("signal interrupt");
+        Ok(())
+    }
+
+    fn write_bulk(&mut self, s: &str) {
+        self.input.push(s.as_bytes());
+    }
+
+    fn write_bulk_bytes(&mut self, bytes: &[u8]) {
+        self.input.push(bytes);
     }
 
-    /// Sends raw data to the underlying terminal.
-    fn send_raw<S>(&mut self, s: S) where S: ToString {
-        self.write_text(&s.to_string());
+    fn pending_input_byte_count(&self) -> usize { self.input.len() }
+
+    fn cancel_pending_input(&mut self) -> usize {
+        self.input.clear()
+    }
+
+    fn resize(&mut self, columns: usize, lines: usize) {
+        // Without a PTY there is no way to tell the shell.
+        self.console.resize(columns, lines);
     }
 
     /// Updates the terminal.
-    fn update(&mut self) -> Vec<Event> {
+    fn update(&mut self) -> Result<Vec<Event>, io::Error> {
         let mut events = Vec::new();
 
-        while let Ok(event) = self.events.try_recv() {
-            match event {
-                manager_thread::Event::WriteText { ref text } => {
-                    for character in text.chars() {
-                        events.push(Event::PutCharacter {
-                            x: 0, // FIXME: implement
-                            y: 0, // FIXME: implement
-                            character,
-                            bold: false,
-                            italic: false,
-                            underlined: false,
-                            strikethrough: false,
-                            color: TEXT_COLOR,
-                        });
-                    }
-                },
-                manager_thread::Event::ShellExited(exit_status) => {
-                    self.is_session_finished = true;
+        if let Some(chunk) = self.input.take_chunk() {
+            self.send_input(&chunk)?;
+        }
 
-                    println!("shell exited: {:?}", exit_status);
-                },
-            }
+        // Release any output held back by a pause that has since been resumed.
+        self.handle_output(&[], &mut events);
+
+        // Leave the output queued while paused, so the shell blocks once it fills.
+        while self.is_reading() {
+            let event = match self.manager.events.try_recv() {
+                Ok(event) => event,
+                Err(_) => break,
+            };
+
+            self.handle_manager_event(event, &mut events);
+        }
+
+        Ok(events)
+    }
+
+    /// Waits on the queue, which the reader threads signal as output arrives.
+    fn update_timeout(&mut self, timeout: Duration) -> Result<Vec<Event>, io::Error> {
+        let mut events = self.update()?;
+
+        // Keep writing any paste rather than waiting.
+        if !events.is_empty() || self.is_session_finished || !self.input.is_empty() {
+            return Ok(events);
+        }
+
+        if !self.is_reading() {
+            // Only the host can resume output, so there is nothing to wait for.
+            thread::sleep(timeout);
+            return Ok(events);
+        }
+
+        match self.manager.events.recv_timeout(timeout) {
+            Ok(event) => {
+                self.handle_manager_event(event, &mut events);
+                events.extend(self.update()?);
+            },
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => (),
         }
 
-        events
+        Ok(events)
     }
 
     /// Checks if the underlying shell session has finished.
     fn is_session_finished(&self) -> bool { self.is_session_finished }
+
+    fn is_echo_disabled(&self) -> bool {
+        // Without a PTY there is no way to know.
+        false
+    }
+
+    fn is_output_paused(&self) -> bool { self.output_filter.is_paused() }
+
+    fn is_flow_stopped(&self) -> bool { self.flow.is_stopped() }
+
+    fn resume_output(&mut self, policy: BinaryOutputPolicy) {
+        self.output_filter.resume(policy);
+    }
+
+    fn debug_state(&self) -> DriverDebugState {
+        DriverDebugState {
+            parser_state: self.interceptor.state_name(),
+            pending_bytes: self.interceptor.pending_byte_count() + self.output_filter.held_byte_count(),
+        }
+    }
+
+    fn exit_status(&self) -> Option<ExitStatus> { self.exit_status }
+
+    fn byte_counts(&self) -> ByteCounts { self.byte_counts }
+}
+
+impl Driver {
+    /// Writes input to the shell straight away.
+    fn send_input(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        let bytes = self.flow.filter_input(bytes);
+        self.shell_stdin.write_all(&bytes)?;
+        self.byte_counts.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Checks if output should be read from the shell.
+    fn is_reading(&self) -> bool {
+        !self.output_filter.is_paused() && !self.flow.is_stopped()
+    }
+
+    fn handle_manager_event(&mut self, event: manager_thread::Event, events: &mut Vec<Event>) {
+        match event {
+            manager_thread::Event::Output(bytes) => {
+                self.handle_output(&bytes, events);
+            },
+            manager_thread::Event::ShellExited(exit_status) => {
+                let status = ExitStatus { code: exit_status.code(), signal: exit_signal(&exit_status) };
+                self.is_session_finished = true;
+                self.exit_status = Some(status);
+
+                events.push(Event::SessionExited { code: status.code, signal: status.signal });
+            },
+        }
+    }
+
+    /// Converts output from the shell into events.
+    fn handle_output(&mut self, bytes: &[u8], events: &mut Vec<Event>) {
+        self.byte_counts.read += bytes.len() as u64;
+        let filtered = self.output_filter.filter(bytes);
+        events.extend(filtered.events);
+
+        let output = self.interceptor.write(&filtered.bytes, &mut self.console);
+        events.extend(output.events);
+
+        // There is no one left to answer once the shell has exited.
+        if !self.is_session_finished && !output.response.is_empty() {
+            match self.shell_stdin.write_all(&output.response) {
+                Ok(()) => self.byte_counts.written += output.response.len() as u64,
+                Err(e) => warn!("failed to answer the shell: {}", e),
+            }
+        }
+    }
+}
+
+/// Gets the signal that killed a process, where the platform has signals.
+#[cfg(unix)]
+fn exit_signal(status: &process::ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_: &process::ExitStatus) -> Option<i32> {
+    None
 }
 
 mod manager_thread {
@@ -148,36 +333,105 @@ mod manager_thread {
 
     #[derive(Clone, Debug)]
     pub enum Event {
-        WriteText {
-            text: String,
-        },
-        ShellExited(ExitStatus),
+        /// Raw bytes written by the shell.
+        Output(Vec<u8>),
+        /// The shell exited. Sent only after all of its output.
+        ShellExited(process::ExitStatus),
+    }
+
+    /// A handle to the manager thread.
+    ///
+    /// Dropping the handle kills the shell and joins the manager thread.
+    pub struct Handle {
+        pub events: queue::Receiver<Event>,
+        shutdown: Arc<AtomicBool>,
+        manager_thread: Option<JoinHandle<()>>,
     }
 
     /// Creates a new manager thread.
-    pub fn create(mut child: Child)
-        -> std::sync::mpsc::Receiver<Event> {
-        let (tx, rx) = mpsc::channel();
+    pub fn create(mut child: Child) -> Handle {
+        let (tx, rx) = queue::channel(CHANNEL_CAPACITY);
+        let shutdown = Arc::new(AtomicBool::new(false));
 
-        let shell_stdout = mem::replace(&mut child.stdout, None).unwrap();
+        let mut shell_stdout = child.stdout.take().unwrap();
 
-        let _stdout_thread = {
+        let stdout_thread = {
             let tx = tx.clone();
 
-            std::thread::spawn(move || {
-                for byte in shell_stdout.bytes() {
-                    let byte = byte.unwrap();
-                    tx.send(Event::WriteText { text: String::from_utf8_lossy(&[byte]).to_string() }).ok();
+            thread::spawn(move || {
+                let mut buffer = [0; READ_CHUNK_SIZE];
+
+                loop {
+                    match shell_stdout.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(byte_count) => {
+                            // Blocks while the queue is full, fails once the driver is gone.
+                            if tx.send(Event::Output(buffer[..byte_count].to_vec())).is_err() {
+                                break;
+                            }
+                        },
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(_) => break,
+                    }
                 }
-            });
+            })
         };
 
-        let _manager_thread = std::thread::spawn(move || {
-            let exit_status = child.wait().unwrap();
-            tx.send(Event::ShellExited(exit_status)).ok();
-        });
+        let manager_thread = {
+            let shutdown = shutdown.clone();
 
-        rx
+            thread::spawn(move || {
+                loop {
+                    if shutdown.load(Ordering::SeqCst) {
+                        child.kill().ok();
+                        child.wait().ok();
+                        return;
+                    }
+
+                    match child.try_wait() {
+                        Ok(Some(exit_status)) => {
+                            // Deliver the output written before the exit, without
+                            // waiting on background jobs still holding the pipe.
+                            let deadline = Instant::now() + OUTPUT_DRAIN_TIMEOUT;
+                            while !stdout_thread.is_finished() && Instant::now() < deadline
+                                && !shutdown.load(Ordering::SeqCst) {
+                                thread::sleep(EXIT_POLL_INTERVAL);
+                            }
+
+                            // A reader still running is detached, and stops once the
+                            // pipe closes or the driver is gone.
+                            tx.send(Event::ShellExited(exit_status)).ok();
+                            return;
+                        },
+                        Ok(None) => thread::sleep(EXIT_POLL_INTERVAL),
+                        Err(e) => {
+                            warn!("failed to query shell status: {}", e);
+                            return;
+                        },
+                    }
+                }
+            })
+        };
+
+        Handle {
+            events: rx,
+            shutdown,
+            manager_thread: Some(manager_thread),
+        }
+    }
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            self.shutdown.store(true, Ordering::SeqCst);
+
+            // Close the queue so that blocked senders give up.
+            let (_, closed) = queue::channel(1);
+            drop(mem::replace(&mut self.events, closed));
+
+            if let Some(manager_thread) = self.manager_thread.take() {
+                manager_thread.join().ok();
+            }
+        }
     }
 }
 
@@ -0,0 +1,930 @@
+//! Recognition of escape sequences that the underlying parser does not support.
+//!
+//! Bytes are fed through an `Interceptor` before they reach the parser. Any
+//! sequence it understands is converted into events directly, everything else
+//! is passed through untouched.
+//!
+//! In VT52 mode, sequences are translated into their ANSI equivalents so
+//! that the parser keeps track of the cursor. When 8-bit controls are
+//! recognized, they are translated into their 7-bit equivalents.
+
+use crate::{
+    cursor::CursorShape,
+    event::{BellVolume, DynamicColor, Event, ParserResetReason, ShellMark},
+    modes::Mode,
+    os::{console::Console, C1Controls},
+    parser::{StringLimits, MAX_PARAMETER},
+    Color, Rect,
+};
+
+/// The longest sequence we will buffer before giving up and passing it through.
+const MAX_SEQUENCE_LENGTH: usize = 64;
+
+const BEL: u8 = 0x07;
+const CAN: u8 = 0x18;
+const ESC: u8 = 0x1b;
+/// The 8-bit string terminator, `ESC \`.
+const ST: u8 = 0x9c;
+
+/// The result of feeding a byte into the interceptor.
+///
+/// If both fields are empty, the byte is part of a sequence that is not yet complete.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Output {
+    /// Events converted from a recognized sequence.
+    pub events: Vec<Event>,
+    /// Bytes that should be passed through to the parser.
+    pub pass_through: Vec<u8>,
+    /// Bytes that should be sent back to the running program.
+    pub response: Vec<u8>,
+}
+
+/// Intercepts escape sequences from a byte stream.
+#[derive(Clone, Debug)]
+pub struct Interceptor {
+    /// The bytes of the sequence currently being read.
+    buffer: Vec<u8>,
+    /// The control string (OSC, DCS, etc) we are inside, if any.
+    control_string: Option<ControlString>,
+    /// Whether we are in VT52 compatibility mode.
+    vt52: bool,
+    /// How bytes 0x80–0x9F are treated.
+    c1_controls: C1Controls,
+    /// The number of continuation bytes left in the current UTF-8 character.
+    utf8_continuations: usize,
+    /// Whether text is concealed by SGR 8, which the parser does not track.
+    concealed: bool,
+    /// The caps on control strings, which match the parser's. Longer
+    /// payloads are still passed through, for the parser to discard.
+    limits: StringLimits,
+}
+
+/// A control string that is being read.
+#[derive(Clone, Debug)]
+struct ControlString {
+    /// The byte after ESC that introduced the string, such as `]` for OSC.
+    introducer: u8,
+    /// The captured payload, for the kinds of string we understand.
+    payload: Vec<u8>,
+    /// Whether the payload grew too long to capture.
+    overflowed: bool,
+    /// The bytes read so far, including those not captured.
+    length: usize,
+    /// Whether the previous byte was ESC, which may begin the string terminator.
+    previous_was_escape: bool,
+}
+
+/// A parsed control sequence (CSI).
+struct ControlSequence<'a> {
+    /// The private marker (`<`, `=`, `>` or `?`), if any.
+    private_marker: Option<u8>,
+    parameters: Vec<usize>,
+    intermediates: &'a [u8],
+    final_byte: u8,
+}
+
+impl Interceptor {
+    /// Creates a new interceptor.
+    pub fn new(c1_controls: C1Controls, limits: StringLimits) -> Self {
+        Interceptor {
+            buffer: Vec::new(),
+            control_string: None,
+            vt52: false,
+            c1_controls,
+            utf8_continuations: 0,
+            concealed: false,
+            limits,
+        }
+    }
+
+    /// Feeds bytes through the interceptor, writing whatever passes through
+    /// to the console.
+    ///
+    /// The events of both are returned in the order they happened, with
+    /// every response, and nothing left to pass through.
+    pub fn write(&mut self, bytes: &[u8], console: &mut Console) -> Output {
+        let mut output = Output::default();
+
+        for &byte in bytes {
+            let byte_output = self.feed(byte);
+            output.events.extend(byte_output.events);
+            output.response.extend(byte_output.response);
+
+            if !byte_output.pass_through.is_empty() {
+                output.events.extend(console.write(&byte_output.pass_through));
+            }
+        }
+
+        output
+    }
+
+    /// Feeds a byte into the interceptor.
+    pub fn feed(&mut self, byte: u8) -> Output {
+        match self.c1_control(byte) {
+            Some(escaped) => {
+                let mut output = self.feed_byte(ESC);
+                output.extend(self.feed_byte(escaped));
+                output
+            },
+            None => self.feed_byte(byte),
+        }
+    }
+
+    /// Gets the 7-bit equivalent of a byte, such as `[` for CSI, if it is
+    /// an 8-bit control that should be recognized.
+    fn c1_control(&mut self, byte: u8) -> Option<u8> {
+        let in_character = self.utf8_continuations > 0;
+
+        self.utf8_continuations = match byte {
+            0x80..=0xbf => self.utf8_continuations.saturating_sub(1),
+            0xc0..=0xdf => 1,
+            0xe0..=0xef => 2,
+            0xf0..=0xf7 => 3,
+            _ => 0,
+        };
+
+        if self.c1_controls != C1Controls::Recognize || self.vt52 || in_character || !(0x80..=0x9f).contains(&byte) {
+            return None;
+        }
+
+        if self.control_string.is_some() {
+            // Only the string terminator means anything inside a control string.
+            return if byte == ST { Some(b'\\') } else { None };
+        }
+
+        if self.buffer.is_empty() {
+            Some(byte - 0x40)
+        } else {
+            None
+        }
+    }
+
+    /// Feeds a 7-bit byte, or a byte of text, into the interceptor.
+    fn feed_byte(&mut self, byte: u8) -> Output {
+        if self.control_string.is_some() {
+            return self.feed_control_string(byte);
+        }
+
+        if self.buffer.is_empty() {
+            return match byte {
+                ESC => {
+                    self.buffer.push(byte);
+                    Output::default()
+                },
+                BEL => Output::events(vec![Event::Bell]),
+                _ => Output::pass_through(vec![byte]),
+            };
+        }
+
+        self.buffer.push(byte);
+
+        if self.vt52 {
+            return self.feed_vt52();
+        }
+
+        if self.buffer.len() == 2 {
+            return match byte {
+                b'[' | b' ' => Output::default(),
+                // DECKPAM and DECKPNM.
+                b'=' | b'>' => self.set_application_keypad(byte == b'='),
+                // OSC, DCS, SOS, PM and APC strings.
+                b']' | b'P' | b'X' | b'^' | b'_' => {
+                    self.control_string = Some(ControlString {
+                        introducer: byte,
+                        payload: Vec::new(),
+                        overflowed: false,
+                        length: 0,
+                        previous_was_escape: false,
+                    });
+                    self.take_pass_through()
+                },
+                _ => self.take_pass_through(),
+            };
+        }
+
+        if self.buffer[1] == b' ' {
+            return match byte {
+                // S7C1T and S8C1T.
+                b'F' | b'G' => {
+                    self.buffer.clear();
+                    Output::events(vec![Event::SetEightBitControls { enabled: byte == b'G' }])
+                },
+                _ => self.take_pass_through(),
+            };
+        }
+
+        match byte {
+            // Parameter and intermediate bytes.
+            0x20..=0x3f if self.buffer.len() < MAX_SEQUENCE_LENGTH => Output::default(),
+            // The final byte.
+            0x40..=0x7e => {
+                let sequence_bytes = std::mem::take(&mut self.buffer);
+
+                let sequence = ControlSequence::parse(&sequence_bytes[2..]);
+                if let Some(concealed) = sequence.as_ref().and_then(ControlSequence::concealment) {
+                    return self.set_concealed(concealed, sequence_bytes);
+                }
+
+                let output = sequence
+                    .and_then(|sequence| sequence.convert(&sequence_bytes))
+                    .unwrap_or_else(|| Output::pass_through(sequence_bytes.clone()));
+
+                if output.events.contains(&Event::SetMode { mode: Mode::Ansi, enabled: false }) {
+                    self.vt52 = true;
+                }
+
+                output
+            },
+            _ => self.take_pass_through(),
+        }
+    }
+
+    /// Gets the name of the current state, for diagnostics.
+    pub fn state_name(&self) -> &'static str {
+        match (self.vt52, self.control_string.is_some(), self.buffer.is_empty()) {
+            (_, true, _) => "control string",
+            (true, _, true) => "vt52 ground",
+            (true, _, false) => "vt52 escape",
+            (false, _, true) => "ground",
+            (false, _, false) => "escape",
+        }
+    }
+
+    /// Gets the number of bytes buffered in an incomplete sequence.
+    pub fn pending_byte_count(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Handles the buffered bytes of a VT52 escape sequence.
+    fn feed_vt52(&mut self) -> Output {
+        let translated: &[u8] = match self.buffer[1..] {
+            // Cursor up, down, right and left.
+            [b'A'] => b"\x1b[A",
+            [b'B'] => b"\x1b[B",
+            [b'C'] => b"\x1b[C",
+            [b'D'] => b"\x1b[D",
+            // Cursor home.
+            [b'H'] => b"\x1b[H",
+            // Reverse line feed.
+            [b'I'] => b"\x1bM",
+            // Erase to end of screen and end of line.
+            [b'J'] => b"\x1b[J",
+            [b'K'] => b"\x1b[K",
+            // Enter and exit graphics mode.
+            [b'F'] => b"\x1b(0",
+            [b'G'] => b"\x1b(B",
+            // Alternate and numeric keypad modes are the same as in ANSI.
+            [b'='] => return self.set_application_keypad(true),
+            [b'>'] => return self.set_application_keypad(false),
+            // Direct cursor addressing takes a line and a column, each offset by 32.
+            [b'Y'] | [b'Y', _] => return Output::default(),
+            [b'Y', line, column] => {
+                self.buffer.clear();
+                let line = line.saturating_sub(0x1f).max(1);
+                let column = column.saturating_sub(0x1f).max(1);
+                return Output::pass_through(format!("\x1b[{};{}H", line, column).into_bytes());
+            },
+            // Identify.
+            [b'Z'] => {
+                self.buffer.clear();
+                return Output { response: b"\x1b/Z".to_vec(), ..Output::default() };
+            },
+            // Exit VT52 mode.
+            [b'<'] => {
+                self.buffer.clear();
+                self.vt52 = false;
+                return Output::events(vec![Event::SetMode { mode: Mode::Ansi, enabled: true }]);
+            },
+            _ => return self.take_pass_through(),
+        };
+
+        self.buffer.clear();
+        Output::pass_through(translated.to_vec())
+    }
+
+    /// Feeds a byte from inside a control string, watching for its terminator.
+    fn feed_control_string(&mut self, byte: u8) -> Output {
+        let mut output = Output::pass_through(vec![byte]);
+        let string = self.control_string.as_mut().unwrap();
+
+        string.length += 1;
+        if string.length > self.limits.max_unterminated_length {
+            return self.abandon_control_string(byte);
+        }
+        // A doubled ESC, as in tmux passthrough, does not begin the terminator.
+        let begins_terminator = byte == ESC && !string.previous_was_escape;
+        let previous_was_escape = std::mem::replace(&mut string.previous_was_escape, begins_terminator);
+
+        let terminated = match byte {
+            BEL => true,
+            b'\\' if previous_was_escape => {
+                // Drop the ESC that began the terminator.
+                string.payload.pop();
+                true
+            },
+            _ => {
+                if string.payload.len() < self.limits.max_payload_length {
+                    string.payload.push(byte);
+                } else {
+                    string.overflowed = true;
+                }
+                false
+            },
+        };
+
+        if terminated {
+            let string = self.control_string.take().unwrap();
+
+            output.events = match (string.introducer, soft_font_number(&string.payload)) {
+                // Soft fonts are large, so they are noticed even if too long to capture.
+                (b'P', Some(font_number)) => vec![Event::SoftFontIgnored { font_number }],
+                _ if string.overflowed => Vec::new(),
+                (b']', _) => convert_osc(&string.payload),
+                (b'P', None) => vec![Event::DcsPassthrough(unwrap_tmux_passthrough(string.payload))],
+                _ => Vec::new(),
+            };
+        }
+
+        output
+    }
+
+    /// Gives up on a control string whose terminator seems lost, and feeds
+    /// the byte that was too many as if the string had ended before it.
+    fn abandon_control_string(&mut self, byte: u8) -> Output {
+        self.control_string = None;
+
+        // CAN puts the parser back in its ground state too.
+        let mut output = Output {
+            events: vec![Event::ParserReset { reason: ParserResetReason::UnterminatedString }],
+            pass_through: vec![CAN],
+            ..Output::default()
+        };
+        output.extend(self.feed_byte(byte));
+        output
+    }
+
+    /// Switches the keypad between application and numeric mode.
+    fn set_application_keypad(&mut self, enabled: bool) -> Output {
+        self.buffer.clear();
+        Output::events(vec![Event::SetMode { mode: Mode::ApplicationKeypad, enabled }])
+    }
+
+    /// Tracks concealment from an SGR sequence, which the parser also needs to see.
+    fn set_concealed(&mut self, concealed: bool, sequence_bytes: Vec<u8>) -> Output {
+        let mut output = Output::pass_through(sequence_bytes);
+
+        if concealed != self.concealed {
+            self.concealed = concealed;
+            output.events.push(Event::SetConcealed { enabled: concealed });
+        }
+
+        output
+    }
+
+    /// Passes the buffered bytes through to the parser.
+    fn take_pass_through(&mut self) -> Output {
+        Output::pass_through(std::mem::take(&mut self.buffer))
+    }
+}
+
+/// Converts an operating system command into events.
+fn convert_osc(payload: &[u8]) -> Vec<Event> {
+    let payload = String::from_utf8_lossy(payload);
+    let mut parts = payload.splitn(2, ';');
+    let command = parts.next().unwrap_or("");
+    let argument = parts.next();
+
+    match (command, argument) {
+        // Set the icon name and window title, or just the window title.
+        ("0", Some(title)) | ("2", Some(title)) => vec![Event::SetTitle(title.to_owned())],
+        // Set or query the foreground, background and cursor colors.
+        ("10", Some(specs)) | ("11", Some(specs)) | ("12", Some(specs)) => {
+            convert_dynamic_colors(command.parse().unwrap(), specs)
+        },
+        // Reset the foreground, background and cursor colors.
+        ("110", _) | ("111", _) | ("112", _) => {
+            let target = DynamicColor::from_osc_number(command.parse::<usize>().unwrap() - 100).unwrap();
+            vec![Event::DynamicColorChanged { target, color: None }]
+        },
+        // Set or query colors of the palette, as `index;spec` pairs.
+        ("4", Some(pairs)) => convert_palette_colors(pairs),
+        // Reset the given colors of the palette, or all of them.
+        ("104", indices) => {
+            let indices: Vec<u8> = match indices {
+                Some(indices) => indices.split(';').filter_map(|index| index.parse().ok()).collect(),
+                None => (0..16).collect(),
+            };
+            indices.into_iter().map(|index| Event::PaletteChanged { index, color: None }).collect()
+        },
+        // Hyperlinks, as `8;params;uri`. An empty URI ends the link.
+        ("8", Some(link)) => {
+            let uri = link.split_once(';').map_or("", |(_, uri)| uri);
+            let uri = if uri.is_empty() { None } else { Some(uri.to_owned()) };
+            vec![Event::SetHyperlink { uri }]
+        },
+        // Shell integration marks.
+        ("133", Some(mark)) => convert_shell_mark(mark).map(Event::ShellIntegration).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Gets the font number of a DCS payload that downloads a soft font
+/// (DECDLD), as in `DCS Pfn ; Pcn ; ... { Dscs Sxbp1 ; ... ST`.
+fn soft_font_number(payload: &[u8]) -> Option<usize> {
+    let parameters_length = payload.iter().position(|byte| !matches!(byte, b'0'..=b'9' | b';'))?;
+    if payload[parameters_length] != b'{' {
+        return None;
+    }
+
+    let first_parameter = payload[..parameters_length].split(|&byte| byte == b';').next().unwrap_or(b"");
+    Some(std::str::from_utf8(first_parameter).ok()?.parse().unwrap_or(0))
+}
+
+/// Unwraps a tmux passthrough payload, `tmux;` followed by a sequence with
+/// each ESC doubled. Other payloads are returned unchanged.
+fn unwrap_tmux_passthrough(payload: Vec<u8>) -> Vec<u8> {
+    let wrapped = match payload.strip_prefix(b"tmux;") {
+        Some(wrapped) => wrapped,
+        None => return payload,
+    };
+
+    let mut unwrapped = Vec::with_capacity(wrapped.len());
+    let mut previous_was_escape = false;
+
+    for &byte in wrapped {
+        if byte == ESC && previous_was_escape {
+            previous_was_escape = false;
+            continue;
+        }
+
+        previous_was_escape = byte == ESC;
+        unwrapped.push(byte);
+    }
+
+    unwrapped
+}
+
+/// Converts the color specifications of OSC 10, 11 or 12.
+///
+/// Each extra specification applies to the next color, so `10;fg;bg`
+/// sets both the foreground and the background. `?` queries the color.
+fn convert_dynamic_colors(first_number: usize, specs: &str) -> Vec<Event> {
+    specs.split(';').enumerate().filter_map(|(i, spec)| {
+        let target = DynamicColor::from_osc_number(first_number + i)?;
+
+        match spec {
+            "?" => Some(Event::DynamicColorQueried(target)),
+            _ => Color::parse_x11(spec).map(|color| Event::DynamicColorChanged { target, color: Some(color) }),
+        }
+    }).collect()
+}
+
+/// Converts the `index;spec` pairs of OSC 4.
+fn convert_palette_colors(pairs: &str) -> Vec<Event> {
+    let parts: Vec<&str> = pairs.split(';').collect();
+
+    parts.chunks_exact(2).filter_map(|pair| {
+        let index = pair[0].parse().ok()?;
+
+        match pair[1] {
+            "?" => Some(Event::PaletteQueried(index)),
+            spec => Color::parse_x11(spec).map(|color| Event::PaletteChanged { index, color: Some(color) }),
+        }
+    }).collect()
+}
+
+/// Converts the argument of a shell integration sequence, such as `D;1`.
+fn convert_shell_mark(argument: &str) -> Option<ShellMark> {
+    let mut parts = argument.split(';');
+
+    match parts.next()? {
+        "A" => Some(ShellMark::PromptStart),
+        "B" => Some(ShellMark::CommandStart),
+        "C" => Some(ShellMark::OutputStart),
+        "D" => Some(ShellMark::CommandFinished {
+            exit_code: parts.next().and_then(|code| code.parse().ok()),
+        }),
+        _ => None,
+    }
+}
+
+impl Output {
+    fn events(events: Vec<Event>) -> Self {
+        Output { events, ..Output::default() }
+    }
+
+    fn pass_through(bytes: Vec<u8>) -> Self {
+        Output { pass_through: bytes, ..Output::default() }
+    }
+
+    /// Appends the results of another byte.
+    fn extend(&mut self, other: Output) {
+        self.events.extend(other.events);
+        self.pass_through.extend(other.pass_through);
+        self.response.extend(other.response);
+    }
+}
+
+impl<'a> ControlSequence<'a> {
+    /// Parses the bytes following `ESC [`.
+    fn parse(bytes: &'a [u8]) -> Option<Self> {
+        let (&final_byte, rest) = bytes.split_last()?;
+
+        let (private_marker, rest) = match rest.first() {
+            Some(&marker @ b'<'..=b'?') => (Some(marker), &rest[1..]),
+            _ => (None, rest),
+        };
+
+        let parameter_count = rest.iter().take_while(|&&b| (0x30..=0x3f).contains(&b)).count();
+        let (parameter_bytes, intermediates) = rest.split_at(parameter_count);
+
+        let mut sequence = ControlSequence { private_marker, parameters: Vec::new(), intermediates, final_byte };
+
+        if parameter_bytes.is_empty() {
+            return Some(sequence);
+        }
+
+        sequence.parameters = parameter_bytes.split(|&b| b == b';').map(|parameter| {
+            parameter.iter().try_fold(0, |value: usize, &byte| match byte {
+                b'0'..=b'9' => Some((value * 10 + (byte - b'0') as usize).min(MAX_PARAMETER)),
+                _ => None,
+            })
+        }).collect::<Option<Vec<usize>>>()?;
+
+        Some(sequence)
+    }
+
+    /// Gets a parameter, substituting a default if it is missing or zero.
+    fn parameter(&self, index: usize, default: usize) -> usize {
+        match self.parameters.get(index) {
+            Some(&0) | None => default,
+            Some(&value) => value,
+        }
+    }
+
+    /// Parses a rectangle from four parameters starting at `index`.
+    ///
+    /// Parameters are one-based and inclusive, as in `Pt;Pl;Pb;Pr`.
+    fn rect(&self, index: usize) -> Rect {
+        Rect {
+            top: self.parameter(index, 1) - 1,
+            left: self.parameter(index + 1, 1) - 1,
+            bottom: self.parameter(index + 2, usize::MAX),
+            right: self.parameter(index + 3, usize::MAX),
+        }
+    }
+
+    /// Gets whether text is concealed after this sequence, if it is an SGR
+    /// sequence that conceals, reveals or resets.
+    fn concealment(&self) -> Option<bool> {
+        if self.private_marker.is_some() || !self.intermediates.is_empty() || self.final_byte != b'm' {
+            return None;
+        }
+
+        if self.parameters.is_empty() {
+            return Some(false);
+        }
+
+        let mut concealed = None;
+        let mut parameters = self.parameters.iter();
+
+        while let Some(&parameter) = parameters.next() {
+            match parameter {
+                0 | 28 => concealed = Some(false),
+                8 => concealed = Some(true),
+                // Skip the arguments of extended colors, such as `38;5;8`.
+                38 | 48 | 58 => match parameters.next() {
+                    Some(5) => { parameters.nth(0); },
+                    Some(2) => { parameters.nth(2); },
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+
+        concealed
+    }
+
+    /// Converts the sequence into events, passing through the original bytes
+    /// if the parser needs to see them too.
+    ///
+    /// Returns `None` if the sequence is not supported at all.
+    fn convert(&self, original: &[u8]) -> Option<Output> {
+        match (self.private_marker, self.intermediates, self.final_byte) {
+            // DECSET and DECRST.
+            (Some(b'?'), b"", b'h') | (Some(b'?'), b"", b'l') => {
+                let enabled = self.final_byte == b'h';
+                let modes: Vec<_> = self.parameters.iter().map(|&n| Mode::from_dec_private(n)).collect();
+
+                let events: Vec<_> = modes.iter().filter_map(|&mode| mode)
+                    .map(|mode| Event::SetMode { mode, enabled }).collect();
+
+                // Let the parser see any modes we do not handle ourselves.
+                let pass_through = if modes.iter().any(Option::is_none) {
+                    original.to_vec()
+                } else {
+                    Vec::new()
+                };
+
+                Some(Output { events, pass_through, ..Output::default() })
+            },
+            // DECRQM - Request Mode, for DEC private or ANSI modes.
+            (Some(b'?'), b"$", b'p') | (None, b"$", b'p') => Some(Output::events(vec![Event::ModeQueried {
+                number: self.parameters.first().cloned().unwrap_or(0),
+                dec_private: self.private_marker.is_some(),
+            }])),
+            (None, b"", _) | (Some(_), _, _) => None,
+            (None, _, _) => self.convert_with_intermediates().map(Output::events),
+        }
+    }
+
+    /// Converts a sequence that has intermediate bytes.
+    fn convert_with_intermediates(&self) -> Option<Vec<Event>> {
+        match (self.intermediates, self.final_byte) {
+            // DECCRA - Copy Rectangular Area.
+            (b"$", b'v') => Some(vec![Event::CopyRect {
+                source: self.rect(0),
+                y: self.parameter(5, 1) - 1,
+                x: self.parameter(6, 1) - 1,
+            }]),
+            // DECSWBV - Set Warning Bell Volume.
+            (b" ", b't') => {
+                let volume = BellVolume::from_parameter(self.parameters.first().cloned().unwrap_or(0))?;
+                Some(vec![Event::SetWarningBellVolume(volume)])
+            },
+            // DECSMBV - Set Margin Bell Volume.
+            (b" ", b'u') => {
+                let volume = BellVolume::from_parameter(self.parameters.first().cloned().unwrap_or(0))?;
+                Some(vec![Event::SetMarginBellVolume(volume)])
+            },
+            // DECSCUSR - Set Cursor Style.
+            (b" ", b'q') => {
+                let (shape, blinking) = CursorShape::from_decscusr(self.parameters.first().cloned().unwrap_or(0))?;
+                Some(vec![Event::SetCursorStyle { shape, blinking }])
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{MAX_STRING_LENGTH, MAX_UNTERMINATED_STRING_LENGTH};
+
+    fn feed(s: &str) -> Vec<Output> {
+        feed_bytes(s.as_bytes(), C1Controls::Text)
+    }
+
+    fn feed_bytes(bytes: &[u8], c1_controls: C1Controls) -> Vec<Output> {
+        let mut interceptor = Interceptor::new(c1_controls, StringLimits::default());
+        bytes.iter().map(|&b| interceptor.feed(b)).filter(|o| *o != Output::default()).collect()
+    }
+
+    fn events(events: Vec<Event>) -> Vec<Output> {
+        vec![Output::events(events)]
+    }
+
+    #[test]
+    fn passes_through_plain_text() {
+        assert_eq!(vec![Output::pass_through(vec![b'a']), Output::pass_through(vec![b'b'])], feed("ab"));
+    }
+
+    #[test]
+    fn passes_through_unsupported_sequences() {
+        assert_eq!(vec![Output::pass_through(b"\x1b[1;2H".to_vec())], feed("\x1b[1;2H"));
+    }
+
+    #[test]
+    fn recognizes_deccra() {
+        assert_eq!(events(vec![Event::CopyRect {
+            source: Rect { top: 0, left: 1, bottom: 3, right: 4 },
+            y: 4, x: 9,
+        }]), feed("\x1b[1;2;3;4;1;5;10;1$v"));
+    }
+
+    #[test]
+    fn leaves_rectangle_fills_to_the_console() {
+        // The console knows the pen they are drawn with.
+        assert_eq!(vec![Output::pass_through(b"\x1b[69;2;;;5$x".to_vec())], feed("\x1b[69;2;;;5$x"));
+        assert_eq!(vec![Output::pass_through(b"\x1b[$z".to_vec())], feed("\x1b[$z"));
+    }
+
+    #[test]
+    fn recognizes_bell_volumes() {
+        assert_eq!(events(vec![Event::SetWarningBellVolume(BellVolume::Off)]), feed("\x1b[1 t"));
+        assert_eq!(events(vec![Event::SetWarningBellVolume(BellVolume::High)]), feed("\x1b[ t"));
+        assert_eq!(events(vec![Event::SetMarginBellVolume(BellVolume::Low)]), feed("\x1b[3 u"));
+    }
+
+    #[test]
+    fn recognizes_bell_outside_of_control_strings() {
+        assert_eq!(events(vec![Event::Bell]), feed("\x07"));
+        assert!(!feed("\x1b]0;title\x07").iter().any(|output| output.events.contains(&Event::Bell)));
+    }
+
+    #[test]
+    fn recognizes_window_titles() {
+        let titles = |s: &str| -> Vec<Event> {
+            feed(s).into_iter().flat_map(|output| output.events).collect()
+        };
+
+        assert_eq!(vec![Event::SetTitle("vim main.rs".to_owned())], titles("\x1b]2;vim main.rs\x1b\\"));
+        assert_eq!(vec![Event::SetTitle(String::new())], titles("\x1b]0;\x07"));
+        assert!(titles("\x1b]1;icon\x07").is_empty());
+    }
+
+    #[test]
+    fn passes_through_unsupported_modes_alongside_supported_ones() {
+        assert_eq!(events(vec![Event::SetMode { mode: Mode::MarginBell, enabled: true }]), feed("\x1b[?44h"));
+        assert_eq!(vec![Output {
+            events: vec![Event::SetMode { mode: Mode::MarginBell, enabled: false }],
+            pass_through: b"\x1b[?1049;44l".to_vec(),
+            ..Output::default()
+        }], feed("\x1b[?1049;44l"));
+    }
+
+    #[test]
+    fn recognizes_backarrow_key_mode() {
+        assert_eq!(events(vec![Event::SetMode { mode: Mode::BackarrowKey, enabled: true }]), feed("\x1b[?67h"));
+    }
+
+    #[test]
+    fn translates_vt52_sequences() {
+        let outputs = feed("\x1b[?2l\x1bA\x1bY%(\x1bKx\x1bZ\x1b<\x1bA");
+
+        assert_eq!(vec![
+            Output::events(vec![Event::SetMode { mode: Mode::Ansi, enabled: false }]),
+            Output::pass_through(b"\x1b[A".to_vec()),
+            Output::pass_through(b"\x1b[6;9H".to_vec()),
+            Output::pass_through(b"\x1b[K".to_vec()),
+            Output::pass_through(b"x".to_vec()),
+            Output { response: b"\x1b/Z".to_vec(), ..Output::default() },
+            Output::events(vec![Event::SetMode { mode: Mode::Ansi, enabled: true }]),
+            // Back in ANSI mode, this is no longer a sequence on its own.
+            Output::pass_through(b"\x1bA".to_vec()),
+        ], outputs);
+    }
+
+    #[test]
+    fn recognizes_cursor_style_and_color() {
+        assert_eq!(events(vec![Event::SetCursorStyle { shape: CursorShape::Bar, blinking: false }]),
+                   feed("\x1b[6 q"));
+
+        let outputs = feed("\x1b]12;#ff0000\x1b\\");
+        assert_eq!(vec![Event::DynamicColorChanged { target: DynamicColor::Cursor, color: Some(Color::RED) }],
+                   outputs.last().unwrap().events);
+
+        let outputs = feed("\x1b]112\x07");
+        assert_eq!(vec![Event::DynamicColorChanged { target: DynamicColor::Cursor, color: None }],
+                   outputs.last().unwrap().events);
+    }
+
+    #[test]
+    fn recognizes_dynamic_color_lists_and_queries() {
+        let outputs = feed("\x1b]10;#00ff00;?\x07");
+
+        assert_eq!(vec![
+            Event::DynamicColorChanged { target: DynamicColor::Foreground, color: Some(Color::GREEN) },
+            Event::DynamicColorQueried(DynamicColor::Background),
+        ], outputs.last().unwrap().events);
+    }
+
+    #[test]
+    fn recognizes_palette_colors_and_queries() {
+        let outputs = feed("\x1b]4;1;#00ff00;200;?\x07");
+        assert_eq!(vec![
+            Event::PaletteChanged { index: 1, color: Some(Color::GREEN) },
+            Event::PaletteQueried(200),
+        ], outputs.last().unwrap().events);
+
+        let outputs = feed("\x1b]104;3;4\x1b\\");
+        assert_eq!(vec![
+            Event::PaletteChanged { index: 3, color: None },
+            Event::PaletteChanged { index: 4, color: None },
+        ], outputs.last().unwrap().events);
+        assert_eq!(16, feed("\x1b]104\x07").last().unwrap().events.len());
+    }
+
+    #[test]
+    fn recognizes_shell_integration_marks() {
+        let marks = |s: &str| -> Vec<Event> {
+            feed(s).into_iter().flat_map(|output| output.events).collect()
+        };
+
+        assert_eq!(vec![Event::ShellIntegration(ShellMark::OutputStart)], marks("\x1b]133;C\x07"));
+        assert_eq!(vec![Event::ShellIntegration(ShellMark::CommandFinished { exit_code: Some(2) })],
+                   marks("\x1b]133;D;2\x1b\\"));
+        assert_eq!(vec![Event::ShellIntegration(ShellMark::CommandFinished { exit_code: None })],
+                   marks("\x1b]133;D\x07"));
+        assert!(marks("\x1b]133;Z\x07").is_empty());
+    }
+
+    #[test]
+    fn recognizes_eight_bit_controls() {
+        let outputs = feed_bytes(b"\x9b$z\x9d133;C\x9c", C1Controls::Recognize);
+        let pass_through: Vec<_> = outputs.iter().flat_map(|output| output.pass_through.clone()).collect();
+        let events: Vec<_> = outputs.into_iter().flat_map(|output| output.events).collect();
+
+        assert!(pass_through.starts_with(b"\x1b[$z"));
+        assert_eq!(vec![Event::ShellIntegration(ShellMark::OutputStart)], events);
+
+        // Unsupported sequences reach the parser in their 7-bit form.
+        assert_eq!(vec![Output::pass_through(b"\x1b[2J".to_vec())], feed_bytes(b"\x9b2J", C1Controls::Recognize));
+    }
+
+    #[test]
+    fn leaves_eight_bit_controls_as_text_inside_utf8_or_when_disabled() {
+        // 'Ŝ' is C5 9C, and 'ś' is C5 9B.
+        let text = "Ŝś".as_bytes();
+        let passed: Vec<u8> = feed_bytes(text, C1Controls::Recognize).into_iter()
+            .flat_map(|output| output.pass_through).collect();
+        assert_eq!(text.to_vec(), passed);
+
+        assert_eq!(vec![Output::pass_through(vec![0x9b]), Output::pass_through(vec![b'A'])],
+                   feed_bytes(b"\x9bA", C1Controls::Text));
+    }
+
+    #[test]
+    fn tracks_concealment_from_sgr() {
+        assert_eq!(vec![Output {
+            events: vec![Event::SetConcealed { enabled: true }],
+            pass_through: b"\x1b[1;8m".to_vec(),
+            ..Output::default()
+        }], feed("\x1b[1;8m"));
+
+        // Only changes are reported, and color arguments are not attributes.
+        let events: Vec<_> = feed("\x1b[8m\x1b[8mx\x1b[38;5;8m\x1b[m\x1b[28m").into_iter()
+            .flat_map(|output| output.events).collect();
+        assert_eq!(vec![Event::SetConcealed { enabled: true }, Event::SetConcealed { enabled: false }], events);
+    }
+
+    #[test]
+    fn recognizes_hyperlinks() {
+        let links = |s: &str| -> Vec<Event> {
+            feed(s).into_iter().flat_map(|output| output.events).collect()
+        };
+
+        assert_eq!(vec![Event::SetHyperlink { uri: Some("https://a.example/?q=1;2".to_owned()) }],
+                   links("\x1b]8;id=x;https://a.example/?q=1;2\x1b\\"));
+        assert_eq!(vec![Event::SetHyperlink { uri: None }], links("\x1b]8;;\x07"));
+    }
+
+    #[test]
+    fn recognizes_dcs_passthrough() {
+        let passthrough = |s: &str| -> Vec<Event> {
+            feed(s).into_iter().flat_map(|output| output.events).collect()
+        };
+
+        assert_eq!(vec![Event::DcsPassthrough(b"$qm".to_vec())], passthrough("\x1bP$qm\x1b\\"));
+        assert_eq!(vec![Event::DcsPassthrough(b"\x1b]52;c;aGk=\x1b\\".to_vec())],
+                   passthrough("\x1bPtmux;\x1b\x1b]52;c;aGk=\x1b\x1b\\\x1b\\"));
+    }
+
+    #[test]
+    fn ignores_soft_fonts() {
+        let font = |s: &str| -> Vec<Event> {
+            feed(s).into_iter().flat_map(|output| output.events).collect()
+        };
+
+        assert_eq!(vec![Event::SoftFontIgnored { font_number: 1 }], font("\x1bP1;1;1;10;0;2;20;0{ @???~~~/???~~~\x1b\\"));
+        assert_eq!(vec![Event::SoftFontIgnored { font_number: 0 }], font("\x1bP;1{ @???\x1b\\"));
+
+        let long_font = format!("\x1bP2;1{{ @{}\x1b\\", "?~".repeat(MAX_STRING_LENGTH));
+        assert_eq!(vec![Event::SoftFontIgnored { font_number: 2 }], font(&long_font));
+    }
+
+    #[test]
+    fn abandons_unterminated_strings() {
+        let outputs = feed(&format!("\x1b]0;{}b\x07", "a".repeat(MAX_UNTERMINATED_STRING_LENGTH - 2)));
+
+        assert_eq!(&[
+            Output {
+                events: vec![Event::ParserReset { reason: ParserResetReason::UnterminatedString }],
+                pass_through: vec![CAN, b'b'],
+                ..Output::default()
+            },
+            Output::events(vec![Event::Bell]),
+        ], &outputs[outputs.len() - 2..]);
+    }
+
+    #[test]
+    fn recognizes_keypad_modes() {
+        assert_eq!(events(vec![Event::SetMode { mode: Mode::ApplicationKeypad, enabled: true }]), feed("\x1b="));
+        assert_eq!(events(vec![Event::SetMode { mode: Mode::ApplicationKeypad, enabled: false }]), feed("\x1b>"));
+        assert_eq!(events(vec![Event::SetMode { mode: Mode::ApplicationCursorKeys, enabled: true }]), feed("\x1b[?1h"));
+    }
+
+    #[test]
+    fn recognizes_decrqm() {
+        assert_eq!(events(vec![Event::ModeQueried { number: 1004, dec_private: true }]), feed("\x1b[?1004$p"));
+        assert_eq!(events(vec![Event::ModeQueried { number: 4, dec_private: false }]), feed("\x1b[4$p"));
+    }
+
+    #[test]
+    fn recognizes_s7c1t_and_s8c1t() {
+        assert_eq!(events(vec![Event::SetEightBitControls { enabled: true }]), feed("\x1b G"));
+        assert_eq!(events(vec![Event::SetEightBitControls { enabled: false }]), feed("\x1b F"));
+        assert_eq!(vec![Output::pass_through(b"\x1b L".to_vec())], feed("\x1b L"));
+    }
+}
@@ -0,0 +1,103 @@
+//! XON/XOFF software flow control on the input path.
+
+use std::borrow::Cow;
+
+/// Stops output (DC3, ^S).
+const XOFF: char = '\x13';
+/// Resumes output (DC1, ^Q).
+const XON: char = '\x11';
+
+/// What the driver does with XON/XOFF characters written by the host.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FlowControl {
+    /// Send them on to the running program, or the PTY's line discipline.
+    Forward,
+    /// Stop reading output on XOFF until XON, without sending either.
+    Interpret,
+    /// Drop them.
+    Filter,
+}
+
+/// Applies the flow control setting to input.
+#[derive(Clone, Debug)]
+pub struct FlowState {
+    mode: FlowControl,
+    stopped: bool,
+}
+
+impl FlowState {
+    pub fn new(mode: FlowControl) -> Self {
+        FlowState { mode, stopped: false }
+    }
+
+    /// Handles flow control characters in input, returning the bytes to send.
+    ///
+    /// Neither character can be part of a multibyte UTF-8 sequence, so
+    /// the input is filtered a byte at a time.
+    pub fn filter_input<'a>(&mut self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        let is_flow_control = |byte: u8| byte == XON as u8 || byte == XOFF as u8;
+
+        if self.mode == FlowControl::Forward || !bytes.iter().any(|&byte| is_flow_control(byte)) {
+            return Cow::Borrowed(bytes);
+        }
+
+        if self.mode == FlowControl::Interpret {
+            for &byte in bytes {
+                match byte as char {
+                    XOFF => self.stopped = true,
+                    XON => self.stopped = false,
+                    _ => (),
+                }
+            }
+        }
+
+        Cow::Owned(bytes.iter().cloned().filter(|&byte| !is_flow_control(byte)).collect())
+    }
+
+    /// Handles a control code such as `'s'` for ^S, returning whether to send it.
+    pub fn filter_control_code(&mut self, c: char) -> bool {
+        let character = match c.to_ascii_lowercase() {
+            's' => XOFF,
+            'q' => XON,
+            _ => return true,
+        };
+
+        !self.filter_input(&[character as u8]).is_empty()
+    }
+
+    /// Checks if output is stopped by XOFF.
+    pub fn is_stopped(&self) -> bool { self.stopped }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn forwards_by_default() {
+        let mut state = FlowState::new(FlowControl::Forward);
+
+        assert_eq!(&b"a\x13b"[..], &*state.filter_input(b"a\x13b"));
+        assert!(!state.is_stopped());
+    }
+
+    #[test]
+    fn interprets_xoff_and_xon() {
+        let mut state = FlowState::new(FlowControl::Interpret);
+
+        assert_eq!(&b"ab"[..], &*state.filter_input(b"a\x13b"));
+        assert!(state.is_stopped());
+        assert!(!state.filter_control_code('q'));
+        assert!(!state.is_stopped());
+        assert!(state.filter_control_code('c'));
+    }
+
+    #[test]
+    fn filters_without_stopping() {
+        let mut state = FlowState::new(FlowControl::Filter);
+
+        assert!(state.filter_input(b"\x13").is_empty());
+        assert!(!state.is_stopped());
+    }
+}
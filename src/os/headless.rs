@@ -0,0 +1,115 @@
+//! A driver with no running program, whose output is fed in by the embedder.
+//!
+//! This turns output captured elsewhere, such as a build log, into the
+//! same grid a shell's output would produce. Queries are not answered, as
+//! there is no program to read the responses.
+
+use crate::{
+    core::Settings,
+    debug::DriverDebugState,
+    event::Event,
+    os::{self, console::Console, escape, BinaryOutputPolicy, ByteCounts},
+};
+use std::io;
+
+/// A driver whose output is fed in with `Terminal::feed`.
+///
+/// Input is discarded, as there is no program to receive it.
+pub struct Driver {
+    /// Output fed in since the last update.
+    pending_output: Vec<u8>,
+    /// Turns output into events.
+    console: Console,
+    /// Handles the escape sequences that the parser does not support.
+    interceptor: escape::Interceptor,
+    /// The output fed in so far. Input is not counted, as it is discarded.
+    byte_counts: ByteCounts,
+}
+
+impl Driver {
+    /// Creates a driver with nothing to output yet.
+    pub fn new(settings: &Settings) -> Self {
+        Driver {
+            pending_output: Vec::new(),
+            console: Console::new(settings.column_count, settings.line_count, settings.palette.clone(), settings.string_limits),
+            interceptor: escape::Interceptor::new(settings.c1_controls, settings.string_limits),
+            byte_counts: ByteCounts::default(),
+        }
+    }
+}
+
+impl os::Driver for Driver {
+    fn write_bytes(&mut self, _: &[u8]) -> Result<(), io::Error> { Ok(()) }
+
+    fn backspace(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn escape(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn cursor_left(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn cursor_right(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn cursor_up(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn cursor_down(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn control_code(&mut self, _: char) -> Result<(), io::Error> { Ok(()) }
+
+    fn signal_interrupt(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn write_bulk(&mut self, _: &str) { }
+
+    fn pending_input_byte_count(&self) -> usize { 0 }
+
+    fn cancel_pending_input(&mut self) -> usize { 0 }
+
+    fn feed_output(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        self.pending_output.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn resize(&mut self, columns: usize, lines: usize) {
+        self.console.resize(columns, lines);
+    }
+
+    fn update(&mut self) -> Result<Vec<Event>, io::Error> {
+        let bytes = std::mem::take(&mut self.pending_output);
+        self.byte_counts.read += bytes.len() as u64;
+        Ok(self.interceptor.write(&bytes, &mut self.console).events)
+    }
+
+    fn is_session_finished(&self) -> bool { false }
+
+    fn is_echo_disabled(&self) -> bool { false }
+
+    fn is_output_paused(&self) -> bool { false }
+
+    fn resume_output(&mut self, _: BinaryOutputPolicy) { }
+
+    fn is_flow_stopped(&self) -> bool { false }
+
+    fn debug_state(&self) -> DriverDebugState {
+        DriverDebugState {
+            parser_state: self.interceptor.state_name(),
+            pending_bytes: self.pending_output.len() + self.interceptor.pending_byte_count(),
+        }
+    }
+
+    fn byte_counts(&self) -> ByteCounts { self.byte_counts }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{modes::Mode, os::Driver as _};
+
+    #[test]
+    fn handles_the_sequences_a_shell_driver_would() {
+        let mut driver = Driver::new(&Settings::default());
+        driver.feed_output(b"\x1b]0;hello\x07\x1b[?25l").unwrap();
+        let events = driver.update().unwrap();
+
+        assert!(events.contains(&Event::SetTitle("hello".to_owned())));
+        assert!(events.contains(&Event::SetMode { mode: Mode::CursorVisible, enabled: false }));
+    }
+}
@@ -0,0 +1,95 @@
+//! The bulk lane of the input path, so that large writes such as pastes
+//! never hold up control codes.
+
+use std::collections::VecDeque;
+
+/// The most bulk input written to the running program per update.
+pub const CHUNK_SIZE: usize = 1024;
+
+/// Bulk input waiting to be written, a chunk at a time.
+#[derive(Clone, Debug, Default)]
+pub struct InputQueue {
+    /// UTF-8 bytes, only ever split on character boundaries.
+    pending: VecDeque<u8>,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        InputQueue::default()
+    }
+
+    /// Queues input behind any input already waiting.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend(bytes);
+    }
+
+    /// Takes the next chunk of at most `CHUNK_SIZE` bytes.
+    ///
+    /// Chunks end on UTF-8 character boundaries where there are any.
+    pub fn take_chunk(&mut self) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        // Back up to the start of a character.
+        let limit = CHUNK_SIZE.min(self.pending.len());
+        let mut end = limit;
+        while end > 0 && end < self.pending.len() && self.pending[end] & 0xc0 == 0x80 {
+            end -= 1;
+        }
+
+        // Input that is not UTF-8 may have no boundary to back up to.
+        if end == 0 {
+            end = limit;
+        }
+
+        Some(self.pending.drain(..end).collect())
+    }
+
+    /// Discards everything waiting, returning the number of bytes dropped.
+    pub fn clear(&mut self) -> usize {
+        let count = self.pending.len();
+        self.pending.clear();
+        count
+    }
+
+    /// Gets the number of bytes waiting.
+    pub fn len(&self) -> usize { self.pending.len() }
+
+    /// Checks if nothing is waiting.
+    pub fn is_empty(&self) -> bool { self.pending.is_empty() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn takes_chunks_on_character_boundaries() {
+        let mut queue = InputQueue::new();
+        queue.push("a".repeat(CHUNK_SIZE - 1).as_bytes());
+        queue.push("éb".as_bytes());
+
+        assert_eq!(CHUNK_SIZE - 1, queue.take_chunk().unwrap().len());
+        assert_eq!(Some("éb".as_bytes().to_vec()), queue.take_chunk());
+        assert_eq!(None, queue.take_chunk());
+    }
+
+    #[test]
+    fn takes_whole_chunks_of_continuation_bytes() {
+        let mut queue = InputQueue::new();
+        queue.push(&[0x80; CHUNK_SIZE + 1]);
+
+        assert_eq!(CHUNK_SIZE, queue.take_chunk().unwrap().len());
+        assert_eq!(Some(vec![0x80]), queue.take_chunk());
+    }
+
+    #[test]
+    fn clearing_drops_pending_input() {
+        let mut queue = InputQueue::new();
+        queue.push(b"abc");
+
+        assert_eq!(3, queue.clear());
+        assert!(queue.is_empty());
+    }
+}
@@ -4,60 +4,183 @@
 #[cfg(not(unix))] pub use self::default as current;
 
 pub mod default;
+pub mod headless;
+pub mod recording;
 
 #[cfg(unix)] pub mod unix;
-
-use crate::{core::Settings, event::Event};
-use std::io;
+pub(crate) mod console;
+pub(crate) mod escape;
+mod flow;
+mod input;
+mod output;
+mod queue;
+
+pub use self::flow::FlowControl;
+pub use self::output::{BinaryOutputPolicy, C1Controls, LineEndings};
+#[cfg(unix)] pub use self::unix::{PtyOwnership, Signal};
+
+use crate::{core::Settings, debug::DriverDebugState, event::{Event, ExitStatus}};
+use std::{io, time::Duration};
+
+/// How long `update_blocking` waits for output at a time.
+const BLOCKING_WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Creates the driver for the current operating system.
+pub fn create_driver(settings: &Settings) -> Result<Box<dyn Driver>, io::Error> {
+    Ok(Box::new(current::Driver::new(settings)?))
+}
 
 /// An operating system specific terminal driver.
-pub trait Driver : Sized {
-    /// Creates a new operating-system specific driver.
-    fn new(settings: &Settings) -> Result<Self, io::Error>;
+///
+/// Drivers are object safe, so that the backend can be chosen at runtime.
+/// Each driver has its own constructor; `create_driver` creates the one
+/// for the current operating system. Drivers must be `Send`, so that
+/// terminals can move between threads.
+///
+/// Writing and updating fail if the running program can no longer be
+/// reached, such as when it exits partway through a write.
+pub trait Driver: Send {
+    /// Writes bytes to the terminal, such as an encoded key sequence.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error>;
 
     /// Writes text to the terminal.
-    fn write_text(&mut self, s: &str);
+    fn write_text(&mut self, s: &str) -> Result<(), io::Error> {
+        self.write_bytes(s.as_bytes())
+    }
 
-    /// Backspaces the last character.
-    fn backspace(&mut self);
+    /// Backspaces the last character, sending delete (0x7f).
+    fn backspace(&mut self) -> Result<(), io::Error>;
 
     /// Sends the ESC character code.
-    fn escape(&mut self);
+    fn escape(&mut self) -> Result<(), io::Error>;
 
     /// Moves the cursor left.
-    fn cursor_left(&mut self);
+    fn cursor_left(&mut self) -> Result<(), io::Error>;
 
     /// Moves the cursor right.
-    fn cursor_right(&mut self);
+    fn cursor_right(&mut self) -> Result<(), io::Error>;
 
     /// Moves the cursor up.
-    fn cursor_up(&mut self);
+    fn cursor_up(&mut self) -> Result<(), io::Error>;
 
     /// Moves the cursor down.
-    fn cursor_down(&mut self);
+    fn cursor_down(&mut self) -> Result<(), io::Error>;
 
     /// Sends a control code to the running process.
-    fn control_code(&mut self, c: char);
+    fn control_code(&mut self, c: char) -> Result<(), io::Error>;
 
     /// Sends an interrupt signal to the running program.
-    fn signal_interrupt(&mut self);
+    fn signal_interrupt(&mut self) -> Result<(), io::Error>;
+
+    /// Sends a signal to the running program's foreground process group.
+    ///
+    /// Drivers without a local process return an error.
+    #[cfg(unix)]
+    fn signal(&mut self, signal: Signal) -> Result<(), io::Error> {
+        let _ = signal;
+        Err(io::Error::other("the driver has no process to signal"))
+    }
 
     /// Sends raw data to the underlying terminal.
-    fn send_raw<S>(&mut self, s: S) where S: ToString;
+    fn send_raw(&mut self, s: &str) -> Result<(), io::Error> {
+        self.write_bytes(s.as_bytes())
+    }
+
+    /// Queues text to be written a chunk at a time as the terminal updates,
+    /// such as a large paste.
+    ///
+    /// Text written later waits behind it, but control codes and
+    /// interrupts are sent straight away.
+    fn write_bulk(&mut self, s: &str);
+
+    /// Queues bytes to be written like `write_bulk`, such as the contents
+    /// of a file, which need not be UTF-8.
+    fn write_bulk_bytes(&mut self, bytes: &[u8]) {
+        self.write_bulk(&String::from_utf8_lossy(bytes));
+    }
+
+    /// Gets the number of bytes of bulk input not yet written.
+    fn pending_input_byte_count(&self) -> usize;
+
+    /// Discards bulk input not yet written, returning the number of bytes dropped.
+    fn cancel_pending_input(&mut self) -> usize;
+
+    /// Re-establishes a lost transport, after `Event::Disconnected`.
+    ///
+    /// Drivers for local processes have no transport, and return an error.
+    fn reconnect(&mut self) -> Result<(), io::Error> {
+        Err(io::Error::other("the driver has no transport to reconnect"))
+    }
+
+    /// Starts the program again, replacing the one running if it hasn't
+    /// exited, with a fresh parser.
+    ///
+    /// Drivers that did not start their program return an error.
+    fn respawn(&mut self, settings: &Settings) -> Result<(), io::Error> {
+        let _ = settings;
+        Err(io::Error::other("the driver did not start the running program"))
+    }
+
+    /// Feeds output to the terminal as if the running program wrote it.
+    ///
+    /// Only headless drivers, which have no running program, support this.
+    fn feed_output(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        let _ = bytes;
+        Err(io::Error::other("the driver has a running program to read output from"))
+    }
+
+    /// Resizes the terminal, telling the running program about its new size.
+    fn resize(&mut self, columns: usize, lines: usize);
 
     /// Updates the terminal.
-    fn update(&mut self) -> Vec<Event>;
+    fn update(&mut self) -> Result<Vec<Event>, io::Error>;
+
+    /// Updates the terminal, waiting up to `timeout` for output if there is none yet.
+    ///
+    /// This lets a frontend sleep until output arrives or its next frame is
+    /// due, rather than polling. Drivers that cannot wait return immediately.
+    fn update_timeout(&mut self, timeout: Duration) -> Result<Vec<Event>, io::Error> {
+        let _ = timeout;
+        self.update()
+    }
 
     /// Checks if the underlying shell session has finished.
     fn is_session_finished(&self) -> bool;
 
+    /// Checks if the running program has disabled echo, as password prompts do.
+    fn is_echo_disabled(&self) -> bool;
+
+    /// Checks if output is paused because it looks like binary data.
+    fn is_output_paused(&self) -> bool;
+
+    /// Resumes paused output, handling the rest of the binary data with the given policy.
+    fn resume_output(&mut self, policy: BinaryOutputPolicy);
+
+    /// Checks if output is stopped by XOFF, when flow control is interpreted.
+    fn is_flow_stopped(&self) -> bool;
+
+    /// Gets a snapshot of the driver's internal state, for diagnostics.
+    fn debug_state(&self) -> DriverDebugState;
+
+    /// Gets how the running program exited, once it has and if the driver
+    /// can tell.
+    fn exit_status(&self) -> Option<ExitStatus> {
+        None
+    }
+
+    /// Gets how many bytes have passed between the terminal and the
+    /// running program so far. Drivers that don't count report none.
+    fn byte_counts(&self) -> ByteCounts {
+        ByteCounts::default()
+    }
+
     /// Update in a loop, blocking until events are received.
-    fn update_blocking(&mut self) -> Vec<Event> {
+    fn update_blocking(&mut self) -> Result<Vec<Event>, io::Error> {
         let mut events = Vec::new();
 
         // wait until we receive the first event.
         loop {
-            let new_events = self.update();
+            let new_events = self.update_timeout(BLOCKING_WAIT_INTERVAL)?;
 
             if !new_events.is_empty() {
                 events.extend(new_events);
@@ -69,7 +192,7 @@ pub trait Driver : Sized {
 
         // keep reading until the events stop.
         loop {
-            let new_events = self.update();
+            let new_events = self.update()?;
 
             if new_events.is_empty() {
                 break;
@@ -80,6 +203,15 @@ pub trait Driver : Sized {
             std::thread::yield_now();
         }
 
-        events
+        Ok(events)
     }
 }
+
+/// How many bytes a driver has passed between the terminal and the running program.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteCounts {
+    /// The input written to the running program, including responses to queries.
+    pub written: u64,
+    /// The output read from the running program.
+    pub read: u64,
+}
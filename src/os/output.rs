@@ -0,0 +1,374 @@
+//! Filtering of raw output before it reaches the escape sequence parser.
+//!
+//! This detects binary data, such as an accidentally `cat`ed executable,
+//! and optionally normalizes line endings.
+
+use crate::event::Event;
+use std::{mem, str};
+
+/// Output is judged in blocks of at most this many bytes.
+const BLOCK_SIZE: usize = 512;
+/// Blocks shorter than this are too small to judge by ratio, and are only
+/// binary if they continue a binary run with a suspicious byte.
+const MIN_SAMPLE_SIZE: usize = 16;
+/// A block is binary once more than one in this many of its bytes are suspicious.
+const SUSPICIOUS_RATIO: usize = 10;
+
+/// The glyph that replaces unprintable bytes in binary output.
+const REPLACEMENT: char = '\u{fffd}';
+
+/// What to do with output that looks like binary data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinaryOutputPolicy {
+    /// Pass it to the parser unchanged.
+    Ignore,
+    /// Replace control characters and invalid UTF-8 with a replacement glyph.
+    Replace,
+    /// Swallow it, writing a one-line summary once textual output resumes.
+    Summarize,
+    /// Stop reading output until the host decides, with
+    /// [`Terminal::resume_output`](crate::Terminal::resume_output).
+    Pause,
+}
+
+/// How line endings in the output are treated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LineEndings {
+    /// Pass them through unchanged, so a lone CR returns to the start of the line.
+    Preserve,
+    /// Treat CR LF, LF CR, and lone CR or LF each as a single new line.
+    Normalize,
+}
+
+/// How bytes 0x80–0x9F in the output are treated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum C1Controls {
+    /// Leave them to the parser as Latin-1 text, so only ESC begins a sequence.
+    Text,
+    /// Recognize them as 8-bit controls, so 0x9B is CSI (`ESC [`).
+    ///
+    /// Bytes inside a UTF-8 character are still text. Once the running
+    /// program asks for 8-bit controls (S8C1T), responses use them too.
+    Recognize,
+}
+
+/// The result of filtering some output.
+#[derive(Clone, Debug, Default)]
+pub struct Filtered {
+    /// The bytes to pass to the parser.
+    pub bytes: Vec<u8>,
+    /// Events to report to the host.
+    pub events: Vec<Event>,
+}
+
+/// Filters raw output on its way to the parser.
+#[derive(Clone, Debug)]
+pub struct OutputFilter {
+    policy: BinaryOutputPolicy,
+    line_endings: LineEndings,
+    /// Whether the most recent block was binary.
+    in_binary_run: bool,
+    /// The policy the host chose for the current binary run, after a pause.
+    run_policy: Option<BinaryOutputPolicy>,
+    /// The number of bytes swallowed so far in the current binary run.
+    suppressed_byte_count: usize,
+    /// Whether output is paused until the host decides what to do with it.
+    paused: bool,
+    /// Output that has not been filtered yet, because of a pause.
+    held: Vec<u8>,
+    /// The line ending that the previous byte ended, if any.
+    previous_line_ending: Option<u8>,
+}
+
+impl OutputFilter {
+    /// Creates a new output filter.
+    pub fn new(policy: BinaryOutputPolicy, line_endings: LineEndings) -> Self {
+        OutputFilter {
+            policy,
+            line_endings,
+            in_binary_run: false,
+            run_policy: None,
+            suppressed_byte_count: 0,
+            paused: false,
+            held: Vec::new(),
+            previous_line_ending: None,
+        }
+    }
+
+    /// Filters a chunk of output.
+    ///
+    /// While paused, the output is held back, and no bytes are returned.
+    pub fn filter(&mut self, bytes: &[u8]) -> Filtered {
+        self.held.extend_from_slice(bytes);
+
+        let mut filtered = Filtered::default();
+        if self.paused {
+            return filtered;
+        }
+
+        let input = mem::take(&mut self.held);
+        let mut text = Vec::new();
+
+        for (index, block) in input.chunks(BLOCK_SIZE).enumerate() {
+            if !self.is_binary(block) {
+                self.end_binary_run(&mut text);
+                text.extend_from_slice(block);
+                continue;
+            }
+
+            if !self.in_binary_run {
+                self.in_binary_run = true;
+                filtered.events.push(Event::BinaryOutputDetected);
+            }
+
+            match self.run_policy.unwrap_or(self.policy) {
+                BinaryOutputPolicy::Ignore => text.extend_from_slice(block),
+                BinaryOutputPolicy::Replace => replace_unprintable(block, &mut text),
+                BinaryOutputPolicy::Summarize => self.suppressed_byte_count += block.len(),
+                BinaryOutputPolicy::Pause => {
+                    // Hold this block and everything after it.
+                    self.paused = true;
+                    self.held = input[index * BLOCK_SIZE..].to_vec();
+                    break;
+                },
+            }
+        }
+
+        filtered.bytes = self.normalize_line_endings(&text);
+        filtered
+    }
+
+    /// Checks if output is paused until the host decides what to do.
+    pub fn is_paused(&self) -> bool { self.paused }
+
+    /// Resumes paused output, handling the rest of the binary run with
+    /// the given policy.
+    ///
+    /// The held output is released by the next call to `filter`.
+    /// Resuming with `Pause` passes the output through unchanged.
+    pub fn resume(&mut self, policy: BinaryOutputPolicy) {
+        if !self.paused {
+            return;
+        }
+
+        self.paused = false;
+        self.run_policy = Some(match policy {
+            BinaryOutputPolicy::Pause => BinaryOutputPolicy::Ignore,
+            policy => policy,
+        });
+    }
+
+    /// Gets the number of bytes held back, for diagnostics.
+    pub fn held_byte_count(&self) -> usize {
+        self.held.len()
+    }
+
+    /// Judges whether a block of output is binary.
+    fn is_binary(&self, block: &[u8]) -> bool {
+        if block.contains(&0) {
+            return true;
+        }
+
+        let suspicious_count = suspicious_count(block);
+
+        if block.len() < MIN_SAMPLE_SIZE {
+            return self.in_binary_run && suspicious_count > 0;
+        }
+
+        suspicious_count * SUSPICIOUS_RATIO > block.len()
+    }
+
+    /// Finishes the current binary run, if any, summarizing what was swallowed.
+    fn end_binary_run(&mut self, text: &mut Vec<u8>) {
+        if !self.in_binary_run {
+            return;
+        }
+
+        if self.suppressed_byte_count > 0 {
+            let summary = format!("[{} bytes of binary output suppressed]\r\n", self.suppressed_byte_count);
+            text.extend_from_slice(summary.as_bytes());
+        }
+
+        self.in_binary_run = false;
+        self.run_policy = None;
+        self.suppressed_byte_count = 0;
+    }
+
+    fn normalize_line_endings(&mut self, text: &[u8]) -> Vec<u8> {
+        if self.line_endings == LineEndings::Preserve {
+            return text.to_vec();
+        }
+
+        let mut normalized = Vec::with_capacity(text.len());
+
+        for &byte in text {
+            match (self.previous_line_ending, byte) {
+                // The second half of a CR LF or LF CR pair.
+                (Some(b'\r'), b'\n') | (Some(b'\n'), b'\r') => {
+                    self.previous_line_ending = None;
+                    continue;
+                },
+                (_, b'\r') | (_, b'\n') => {
+                    normalized.extend_from_slice(b"\r\n");
+                    self.previous_line_ending = Some(byte);
+                },
+                _ => {
+                    normalized.push(byte);
+                    self.previous_line_ending = None;
+                },
+            }
+        }
+
+        normalized
+    }
+}
+
+/// Checks whether a character is a control character that is
+/// expected in textual terminal output.
+fn is_expected_control(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' | '\x07' | '\x08' | '\x0c' | '\x1b' => true,
+        // Shift out and shift in, for switching character sets.
+        '\x0e' | '\x0f' => true,
+        _ => false,
+    }
+}
+
+/// Counts the unexpected control characters and invalid UTF-8 sequences in a block.
+///
+/// A partial character at the end of the block is not counted.
+fn suspicious_count(block: &[u8]) -> usize {
+    let mut count = 0;
+
+    for_each_utf8_chunk(block, |text, invalid| {
+        count += text.chars().filter(|&c| c.is_control() && !is_expected_control(c)).count();
+
+        if invalid {
+            count += 1;
+        }
+    }, false);
+
+    count
+}
+
+/// Writes a block, replacing every control character except whitespace,
+/// and every invalid UTF-8 sequence.
+///
+/// Escape sequences are replaced too, as those in binary data are garbage.
+fn replace_unprintable(block: &[u8], output: &mut Vec<u8>) {
+    let mut buffer = [0; 4];
+
+    for_each_utf8_chunk(block, |text, invalid| {
+        for c in text.chars() {
+            let c = match c {
+                '\t' | '\n' | '\r' => c,
+                c if c.is_control() => REPLACEMENT,
+                c => c,
+            };
+
+            output.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+        }
+
+        if invalid {
+            output.extend_from_slice(REPLACEMENT.encode_utf8(&mut buffer).as_bytes());
+        }
+    }, true);
+}
+
+/// Splits bytes into runs of valid UTF-8, each followed by
+/// whether an invalid sequence came after it.
+///
+/// A partial character at the end only counts as invalid if `partial_is_invalid` is set.
+fn for_each_utf8_chunk<F>(mut bytes: &[u8], mut f: F, partial_is_invalid: bool)
+    where F: FnMut(&str, bool) {
+    loop {
+        match str::from_utf8(bytes) {
+            Ok(text) => {
+                f(text, false);
+                return;
+            },
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                let text = str::from_utf8(valid).unwrap();
+
+                match e.error_len() {
+                    Some(len) => {
+                        f(text, true);
+                        bytes = &rest[len..];
+                    },
+                    None => {
+                        f(text, partial_is_invalid);
+                        return;
+                    },
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BINARY: &[u8] = &[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                            3, 0, 0x3e, 0, 1, 0, 0, 0, 0x50, 0x10, 0, 0, 0, 0, 0, 0];
+
+    fn filter(policy: BinaryOutputPolicy, line_endings: LineEndings, bytes: &[u8]) -> Filtered {
+        OutputFilter::new(policy, line_endings).filter(bytes)
+    }
+
+    #[test]
+    fn text_passes_through() {
+        let text = "hello \x1b[1mworld\x1b[0m\r\n\tnaïve\x07\r\n".as_bytes();
+        let filtered = filter(BinaryOutputPolicy::Replace, LineEndings::Preserve, text);
+
+        assert_eq!(text, &filtered.bytes[..]);
+        assert!(filtered.events.is_empty());
+    }
+
+    #[test]
+    fn replaces_binary() {
+        let filtered = filter(BinaryOutputPolicy::Replace, LineEndings::Preserve, BINARY);
+        let text = String::from_utf8(filtered.bytes).unwrap();
+
+        assert!(text.starts_with("\u{fffd}ELF\u{fffd}\u{fffd}"));
+        assert_eq!(vec![Event::BinaryOutputDetected], filtered.events);
+    }
+
+    #[test]
+    fn summarizes_binary_once_text_resumes() {
+        let mut filter = OutputFilter::new(BinaryOutputPolicy::Summarize, LineEndings::Preserve);
+
+        assert!(filter.filter(BINARY).bytes.is_empty());
+        assert_eq!(b"[32 bytes of binary output suppressed]\r\n$ ",
+                   &filter.filter(b"$ ").bytes[..]);
+    }
+
+    #[test]
+    fn pauses_until_resumed() {
+        let mut filter = OutputFilter::new(BinaryOutputPolicy::Pause, LineEndings::Preserve);
+
+        let filtered = filter.filter(BINARY);
+        assert!(filtered.bytes.is_empty());
+        assert_eq!(vec![Event::BinaryOutputDetected], filtered.events);
+        assert!(filter.is_paused());
+        assert!(filter.filter(b"more").bytes.is_empty());
+
+        filter.resume(BinaryOutputPolicy::Summarize);
+        assert!(filter.filter(&[]).bytes.is_empty());
+        assert_eq!(b"[36 bytes of binary output suppressed]\r\n$ ",
+                   &filter.filter(b"$ ").bytes[..]);
+    }
+
+    #[test]
+    fn normalizes_line_endings() {
+        let mut filter = OutputFilter::new(BinaryOutputPolicy::Replace, LineEndings::Normalize);
+
+        assert_eq!(b"a\r\nb\r\nc\r\nd\r\n", &filter.filter(b"a\r\nb\rc\n\rd\r").bytes[..]);
+        // The LF completes the CR from the previous chunk.
+        assert_eq!(b"e", &filter.filter(b"\ne").bytes[..]);
+    }
+}
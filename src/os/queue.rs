@@ -0,0 +1,271 @@
+//! A bounded, closeable channel shared between driver threads.
+//!
+//! Unlike `std::sync::mpsc::sync_channel`, this is built only on
+//! `Mutex`/`Condvar`, which lets the shutdown paths be model-checked with loom.
+
+#[cfg(loom)]
+use loom::sync::{Arc, Condvar, Mutex, MutexGuard};
+#[cfg(not(loom))]
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+/// The sending half of a queue.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The receiving half of a queue.
+///
+/// Dropping the receiver closes the queue, waking any blocked senders.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// An error returned when the receiver has been dropped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// An error returned when receiving from a queue.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// There are no items waiting, but senders still exist.
+    Empty,
+    /// There are no items waiting, and all senders are gone.
+    Disconnected,
+}
+
+/// An error returned when receiving from a queue with a timeout.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// No items arrived in time, but senders still exist.
+    Timeout,
+    /// There are no items waiting, and all senders are gone.
+    Disconnected,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Signalled when an item is pushed or the last sender is dropped.
+    not_empty: Condvar,
+    /// Signalled when an item is popped or the receiver is dropped.
+    not_full: Condvar,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    sender_count: usize,
+    receiver_alive: bool,
+}
+
+/// Creates a new queue that holds at most `capacity` items.
+///
+/// Senders block while the queue is full.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "queue capacity must be nonzero");
+
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+            sender_count: 1,
+            receiver_alive: true,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+    });
+
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> Sender<T> {
+    /// Sends an item, blocking while the queue is full.
+    ///
+    /// Fails if the receiver has been dropped.
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.lock();
+
+        while state.receiver_alive && state.items.len() >= state.capacity {
+            state = self.shared.not_full.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+
+        if !state.receiver_alive {
+            return Err(SendError(item));
+        }
+
+        state.items.push_back(item);
+        self.shared.not_empty.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives an item without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.lock();
+
+        match state.items.pop_front() {
+            Some(item) => {
+                self.shared.not_full.notify_one();
+                Ok(item)
+            },
+            None if state.sender_count == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Receives an item, blocking until one arrives or the timeout passes.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.shared.not_full.notify_one();
+                return Ok(item);
+            }
+
+            if state.sender_count == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            state = self.shared.not_empty.wait_timeout(state, deadline - now)
+                .map(|(state, _)| state)
+                .unwrap_or_else(|e| e.into_inner().0);
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().sender_count += 1;
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.sender_count -= 1;
+
+        if state.sender_count == 0 {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_alive = false;
+        state.items.clear();
+        self.shared.not_full.notify_all();
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn receives_items_in_order_then_disconnects() {
+        let (tx, rx) = channel(2);
+
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        drop(tx);
+
+        assert_eq!(Ok(1), rx.try_recv());
+        assert_eq!(Ok(2), rx.try_recv());
+        assert_eq!(Err(TryRecvError::Disconnected), rx.try_recv());
+    }
+
+    #[test]
+    fn send_fails_after_receiver_dropped() {
+        let (tx, rx) = channel(1);
+
+        drop(rx);
+        assert_eq!(Err(SendError(1)), tx.send(1));
+    }
+
+    #[test]
+    fn recv_timeout_wakes_on_send() {
+        let (tx, rx) = channel(1);
+
+        assert_eq!(Err(RecvTimeoutError::Timeout), rx.recv_timeout(Duration::from_millis(1)));
+
+        let sender = std::thread::spawn(move || tx.send(1).unwrap());
+        assert_eq!(Ok(1), rx.recv_timeout(Duration::from_secs(10)));
+        sender.join().unwrap();
+
+        assert_eq!(Err(RecvTimeoutError::Disconnected), rx.recv_timeout(Duration::from_secs(10)));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use super::*;
+    use loom::thread;
+
+    #[test]
+    fn dropping_receiver_unblocks_full_sender() {
+        loom::model(|| {
+            let (tx, rx) = channel(1);
+            tx.send(1).unwrap();
+
+            let sender = thread::spawn(move || tx.send(2));
+            drop(rx);
+
+            assert_eq!(Err(SendError(2)), sender.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn dropping_sender_delivers_everything_then_disconnects() {
+        loom::model(|| {
+            let (tx, rx) = channel(1);
+
+            let sender = thread::spawn(move || {
+                tx.send(1).unwrap();
+                tx.send(2).unwrap();
+            });
+
+            let mut received = Vec::new();
+            loop {
+                match rx.try_recv() {
+                    Ok(item) => received.push(item),
+                    Err(TryRecvError::Empty) => thread::yield_now(),
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            }
+
+            sender.join().unwrap();
+            assert_eq!(vec![1, 2], received);
+        });
+    }
+
+    #[test]
+    fn recv_timeout_is_woken_by_last_sender() {
+        loom::model(|| {
+            let (tx, rx) = channel::<i32>(1);
+
+            let sender = thread::spawn(move || drop(tx));
+
+            // Loom does not model time, so this only returns once woken.
+            assert_eq!(Err(RecvTimeoutError::Disconnected), rx.recv_timeout(Duration::from_secs(1)));
+            sender.join().unwrap();
+        });
+    }
+}
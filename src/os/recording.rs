@@ -0,0 +1,486 @@
+//! Recording a session, and replaying it later without a running program.
+//!
+//! Input and output are kept in separate tracks, each with its own
+//! retention and redaction policy, so that a recording shared for a demo
+//! need not carry what was typed. Output is recorded as the events the
+//! driver emitted, which replay through the same path as a live session.
+
+use crate::{
+    core::Settings,
+    debug::DriverDebugState,
+    encode,
+    event::{Event, ExitStatus},
+    os::{self, BinaryOutputPolicy, ByteCounts},
+};
+use std::{
+    io,
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use crate::os::Signal;
+
+/// What is hidden from a track before it is recorded.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Redaction {
+    /// Record everything.
+    #[default]
+    None,
+    /// Mask input typed while the running program has echo disabled, as
+    /// password prompts do. Control codes such as enter are kept, so that
+    /// the replay still lines up. Output is recorded as it is, as such
+    /// input is never echoed.
+    Secrets,
+    /// Record nothing on the track.
+    All,
+}
+
+/// How one track of a recording is kept.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TrackPolicy {
+    /// How long entries are kept, or `None` to keep the whole session.
+    pub retention: Option<Duration>,
+    /// What is hidden before entries are recorded.
+    pub redaction: Redaction,
+}
+
+/// Input written to the running program.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InputEntry {
+    /// How long after the recording started the input was written.
+    pub at: Duration,
+    /// The bytes written, after redaction.
+    pub bytes: Vec<u8>,
+}
+
+/// The events one update of the driver emitted.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct OutputEntry {
+    /// How long after the recording started the events were emitted.
+    pub at: Duration,
+    /// The events, in the order they were emitted.
+    pub events: Vec<Event>,
+}
+
+/// A recorded session, with input and output in separate tracks.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct Recording {
+    /// The input track, oldest first.
+    pub input: Vec<InputEntry>,
+    /// The output track, oldest first.
+    pub output: Vec<OutputEntry>,
+}
+
+/// A handle to a recording that is still being made, kept by the embedder
+/// after the `Recorder` is given to a terminal.
+#[derive(Clone, Debug)]
+pub struct RecordingHandle(Arc<Mutex<Recording>>);
+
+/// A driver that records the input and output of another driver.
+pub struct Recorder {
+    /// The driver being recorded.
+    inner: Box<dyn os::Driver>,
+    /// When recording started, which entries are timed from.
+    started_at: Instant,
+    input_policy: TrackPolicy,
+    output_policy: TrackPolicy,
+    /// The recording, shared with the handles.
+    recording: Arc<Mutex<Recording>>,
+}
+
+/// Which tracks a `Replay` plays back.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReplayMode {
+    /// Only the output, as a demo would show it.
+    Output,
+    /// The output, with the input reported as `Event::InputReplayed` at
+    /// the point it was typed, for exact reproduction.
+    InputAndOutput,
+}
+
+/// A driver that plays back a recording at the pace it was recorded.
+///
+/// Input is discarded, as there is no program to receive it. The session
+/// finishes once everything has been played.
+pub struct Replay {
+    recording: Recording,
+    mode: ReplayMode,
+    /// When playback started, which entries are timed from.
+    started_at: Instant,
+    /// The index of the next input entry to play.
+    next_input: usize,
+    /// The index of the next output entry to play.
+    next_output: usize,
+}
+
+impl RecordingHandle {
+    /// Gets a copy of everything recorded so far.
+    pub fn snapshot(&self) -> Recording {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Takes everything recorded so far, leaving the recording empty.
+    pub fn take(&self) -> Recording {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl Recorder {
+    /// Starts recording a driver, with a policy for each track.
+    pub fn new(inner: Box<dyn os::Driver>, input_policy: TrackPolicy, output_policy: TrackPolicy) -> Self {
+        Recorder {
+            inner,
+            started_at: Instant::now(),
+            input_policy,
+            output_policy,
+            recording: Arc::new(Mutex::new(Recording::default())),
+        }
+    }
+
+    /// Gets a handle to the recording, which stays valid after the recorder
+    /// is given to a terminal.
+    pub fn handle(&self) -> RecordingHandle {
+        RecordingHandle(self.recording.clone())
+    }
+
+    /// Records input that was written to the driver.
+    fn record_input(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() || self.input_policy.redaction == Redaction::All {
+            return;
+        }
+
+        let echo_disabled = self.input_policy.redaction == Redaction::Secrets && self.inner.is_echo_disabled();
+        let at = self.started_at.elapsed();
+        self.record_input_at(at, bytes, echo_disabled);
+    }
+
+    fn record_input_at(&mut self, at: Duration, bytes: &[u8], echo_disabled: bool) {
+        let bytes = match echo_disabled {
+            true => bytes.iter().map(|&byte| if byte.is_ascii_control() { byte } else { b'*' }).collect(),
+            false => bytes.to_owned(),
+        };
+
+        let mut recording = self.recording.lock().unwrap_or_else(PoisonError::into_inner);
+        recording.input.push(InputEntry { at, bytes });
+        if let Some(cutoff) = self.input_policy.retention.and_then(|retention| at.checked_sub(retention)) {
+            recording.input.retain(|entry| entry.at >= cutoff);
+        }
+    }
+
+    /// Records the events of an update, passing them on.
+    fn record_output(&mut self, events: Vec<Event>) -> Vec<Event> {
+        if !events.is_empty() && self.output_policy.redaction != Redaction::All {
+            let at = self.started_at.elapsed();
+            self.record_output_at(at, events.clone());
+        }
+        events
+    }
+
+    fn record_output_at(&mut self, at: Duration, events: Vec<Event>) {
+        let mut recording = self.recording.lock().unwrap_or_else(PoisonError::into_inner);
+        recording.output.push(OutputEntry { at, events });
+        if let Some(cutoff) = self.output_policy.retention.and_then(|retention| at.checked_sub(retention)) {
+            recording.output.retain(|entry| entry.at >= cutoff);
+        }
+    }
+}
+
+impl os::Driver for Recorder {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        self.record_input(bytes);
+        self.inner.write_bytes(bytes)
+    }
+
+    fn write_text(&mut self, s: &str) -> Result<(), io::Error> {
+        self.record_input(s.as_bytes());
+        self.inner.write_text(s)
+    }
+
+    fn backspace(&mut self) -> Result<(), io::Error> {
+        self.record_input(b"\x7f");
+        self.inner.backspace()
+    }
+
+    fn escape(&mut self) -> Result<(), io::Error> {
+        self.record_input(b"\x1b");
+        self.inner.escape()
+    }
+
+    fn cursor_left(&mut self) -> Result<(), io::Error> {
+        self.record_input(b"\x1b[D");
+        self.inner.cursor_left()
+    }
+
+    fn cursor_right(&mut self) -> Result<(), io::Error> {
+        self.record_input(b"\x1b[C");
+        self.inner.cursor_right()
+    }
+
+    fn cursor_up(&mut self) -> Result<(), io::Error> {
+        self.record_input(b"\x1b[A");
+        self.inner.cursor_up()
+    }
+
+    fn cursor_down(&mut self) -> Result<(), io::Error> {
+        self.record_input(b"\x1b[B");
+        self.inner.cursor_down()
+    }
+
+    fn control_code(&mut self, c: char) -> Result<(), io::Error> {
+        if let Some(byte) = encode::control_byte(c) {
+            self.record_input(&[byte]);
+        }
+        self.inner.control_code(c)
+    }
+
+    fn signal_interrupt(&mut self) -> Result<(), io::Error> {
+        self.record_input(b"\x03");
+        self.inner.signal_interrupt()
+    }
+
+    #[cfg(unix)]
+    fn signal(&mut self, signal: Signal) -> Result<(), io::Error> {
+        self.inner.signal(signal)
+    }
+
+    fn send_raw(&mut self, s: &str) -> Result<(), io::Error> {
+        self.record_input(s.as_bytes());
+        self.inner.send_raw(s)
+    }
+
+    fn write_bulk(&mut self, s: &str) {
+        self.record_input(s.as_bytes());
+        self.inner.write_bulk(s)
+    }
+
+    fn write_bulk_bytes(&mut self, bytes: &[u8]) {
+        self.record_input(bytes);
+        self.inner.write_bulk_bytes(bytes)
+    }
+
+    fn pending_input_byte_count(&self) -> usize { self.inner.pending_input_byte_count() }
+
+    fn cancel_pending_input(&mut self) -> usize { self.inner.cancel_pending_input() }
+
+    fn reconnect(&mut self) -> Result<(), io::Error> { self.inner.reconnect() }
+
+    fn respawn(&mut self, settings: &Settings) -> Result<(), io::Error> { self.inner.respawn(settings) }
+
+    fn feed_output(&mut self, bytes: &[u8]) -> Result<(), io::Error> { self.inner.feed_output(bytes) }
+
+    fn resize(&mut self, columns: usize, lines: usize) { self.inner.resize(columns, lines) }
+
+    fn update(&mut self) -> Result<Vec<Event>, io::Error> {
+        let events = self.inner.update()?;
+        Ok(self.record_output(events))
+    }
+
+    fn update_timeout(&mut self, timeout: Duration) -> Result<Vec<Event>, io::Error> {
+        let events = self.inner.update_timeout(timeout)?;
+        Ok(self.record_output(events))
+    }
+
+    fn is_session_finished(&self) -> bool { self.inner.is_session_finished() }
+
+    fn is_echo_disabled(&self) -> bool { self.inner.is_echo_disabled() }
+
+    fn is_output_paused(&self) -> bool { self.inner.is_output_paused() }
+
+    fn resume_output(&mut self, policy: BinaryOutputPolicy) { self.inner.resume_output(policy) }
+
+    fn is_flow_stopped(&self) -> bool { self.inner.is_flow_stopped() }
+
+    fn debug_state(&self) -> DriverDebugState { self.inner.debug_state() }
+
+    fn exit_status(&self) -> Option<ExitStatus> { self.inner.exit_status() }
+
+    fn byte_counts(&self) -> ByteCounts { self.inner.byte_counts() }
+}
+
+impl Replay {
+    /// Starts playing a recording back.
+    pub fn new(recording: Recording, mode: ReplayMode) -> Self {
+        Replay {
+            recording,
+            mode,
+            started_at: Instant::now(),
+            next_input: 0,
+            next_output: 0,
+        }
+    }
+
+    /// Gets when the next entry to play is due, if any are left.
+    fn next_entry_at(&self) -> Option<Duration> {
+        let input = match self.mode {
+            ReplayMode::Output => None,
+            ReplayMode::InputAndOutput => self.recording.input.get(self.next_input).map(|entry| entry.at),
+        };
+        let output = self.recording.output.get(self.next_output).map(|entry| entry.at);
+
+        match (input, output) {
+            (Some(input), Some(output)) => Some(input.min(output)),
+            (input, output) => input.or(output),
+        }
+    }
+}
+
+impl os::Driver for Replay {
+    fn write_bytes(&mut self, _: &[u8]) -> Result<(), io::Error> { Ok(()) }
+
+    fn backspace(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn escape(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn cursor_left(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn cursor_right(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn cursor_up(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn cursor_down(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn control_code(&mut self, _: char) -> Result<(), io::Error> { Ok(()) }
+
+    fn signal_interrupt(&mut self) -> Result<(), io::Error> { Ok(()) }
+
+    fn write_bulk(&mut self, _: &str) { }
+
+    fn pending_input_byte_count(&self) -> usize { 0 }
+
+    fn cancel_pending_input(&mut self) -> usize { 0 }
+
+    fn resize(&mut self, _: usize, _: usize) { }
+
+    fn update(&mut self) -> Result<Vec<Event>, io::Error> {
+        let elapsed = self.started_at.elapsed();
+        let mut events = Vec::new();
+
+        // Play the entries that are due in the order they were recorded,
+        // input first where the two tracks were recorded at the same time.
+        while let Some(at) = self.next_entry_at().filter(|&at| at <= elapsed) {
+            let input = match self.mode {
+                ReplayMode::Output => None,
+                ReplayMode::InputAndOutput => self.recording.input.get(self.next_input).filter(|entry| entry.at == at),
+            };
+
+            match input {
+                Some(entry) => {
+                    events.push(Event::InputReplayed(entry.bytes.clone()));
+                    self.next_input += 1;
+                },
+                None => {
+                    events.extend(self.recording.output[self.next_output].events.iter().cloned());
+                    self.next_output += 1;
+                },
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn update_timeout(&mut self, timeout: Duration) -> Result<Vec<Event>, io::Error> {
+        if let Some(at) = self.next_entry_at() {
+            thread::sleep(at.saturating_sub(self.started_at.elapsed()).min(timeout));
+        }
+        self.update()
+    }
+
+    fn is_session_finished(&self) -> bool { self.next_entry_at().is_none() }
+
+    fn is_echo_disabled(&self) -> bool { false }
+
+    fn is_output_paused(&self) -> bool { false }
+
+    fn resume_output(&mut self, _: BinaryOutputPolicy) { }
+
+    fn is_flow_stopped(&self) -> bool { false }
+
+    fn debug_state(&self) -> DriverDebugState {
+        DriverDebugState {
+            parser_state: "ground",
+            pending_bytes: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{core::Terminal, os::{headless, Driver as _}};
+
+    fn recorder(input_policy: TrackPolicy, output_policy: TrackPolicy) -> Recorder {
+        let inner = headless::Driver::new(&Settings::default());
+        Recorder::new(Box::new(inner), input_policy, output_policy)
+    }
+
+    #[test]
+    fn records_input_and_output_in_separate_tracks() {
+        let mut recorder = recorder(TrackPolicy::default(), TrackPolicy::default());
+        let handle = recorder.handle();
+
+        recorder.write_text("ls\r").unwrap();
+        recorder.feed_output(b"hi").unwrap();
+        let events = recorder.update().unwrap();
+
+        let recording = handle.take();
+        assert_eq!(recording.input.iter().map(|entry| &entry.bytes[..]).collect::<Vec<_>>(), vec![&b"ls\r"[..]]);
+        assert_eq!(recording.output.len(), 1);
+        assert!(!events.is_empty());
+        assert_eq!(recording.output[0].events, events);
+        assert_eq!(handle.snapshot(), Recording::default());
+    }
+
+    #[test]
+    fn applies_each_tracks_policy() {
+        let secrets = TrackPolicy { redaction: Redaction::Secrets, retention: Some(Duration::from_secs(10)) };
+        let mut recorder = recorder(secrets, TrackPolicy { redaction: Redaction::All, ..TrackPolicy::default() });
+        let handle = recorder.handle();
+
+        recorder.record_input_at(Duration::from_secs(1), b"user\r", false);
+        recorder.record_input_at(Duration::from_secs(5), b"hunter2\r", true);
+        recorder.record_input_at(Duration::from_secs(12), b"exit\r", false);
+        recorder.feed_output(b"hello").unwrap();
+        recorder.update().unwrap();
+
+        let recording = handle.snapshot();
+        let input: Vec<_> = recording.input.iter().map(|entry| &entry.bytes[..]).collect();
+        assert_eq!(input, vec![&b"*******\r"[..], &b"exit\r"[..]]);
+        assert!(recording.output.is_empty());
+    }
+
+    #[test]
+    fn replays_output_alone_or_with_input() {
+        let recording = Recording {
+            input: vec![InputEntry { at: Duration::from_secs(0), bytes: b"echo hi\r".to_vec() }],
+            output: vec![OutputEntry {
+                at: Duration::from_secs(0),
+                events: vec![Event::SetTitle("demo".to_owned())],
+            }],
+        };
+
+        let mut terminal = Terminal::with_driver(Settings::default(), Box::new(Replay::new(recording.clone(), ReplayMode::Output)));
+        assert_eq!(terminal.update().unwrap(), vec![Event::SetTitle("demo".to_owned())]);
+        assert_eq!(terminal.title(), Some("demo"));
+        assert!(terminal.is_session_finished());
+
+        let mut terminal = Terminal::with_driver(Settings::default(), Box::new(Replay::new(recording, ReplayMode::InputAndOutput)));
+        assert_eq!(terminal.update().unwrap(), vec![
+            Event::InputReplayed(b"echo hi\r".to_vec()),
+            Event::SetTitle("demo".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn replays_at_the_recorded_pace() {
+        let recording = Recording {
+            input: Vec::new(),
+            output: vec![OutputEntry { at: Duration::from_secs(60), events: vec![Event::Bell] }],
+        };
+        let mut replay = Replay::new(recording, ReplayMode::Output);
+
+        assert_eq!(replay.update().unwrap(), vec![]);
+        assert!(!replay.is_session_finished());
+    }
+}
@@ -1,143 +1,587 @@
 use crate::{
     core::Settings,
-    event,
-    os,
-    Color,
+    debug::DriverDebugState,
+    encode::control_byte,
+    event::{self, ExitStatus},
+    os::{self, console::Console, escape, flow::FlowState, input::InputQueue, output::OutputFilter, BinaryOutputPolicy, ByteCounts},
 };
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::process::Command;
-use std::{env, io, mem};
+use std::{env, fs::File, io::{self, Read, Write}, mem::ManuallyDrop};
+
+/// The most output read from an adopted PTY at a time.
+const READ_CHUNK_SIZE: usize = 4096;
+/// The status of a program that is gone, when how it exited is not known.
+const UNKNOWN_EXIT: ExitStatus = ExitStatus { code: None, signal: None };
 
 /// A Unix terminal driver.
 pub struct Driver {
     /// The settings.
     settings: Settings,
-    /// The underlying shell background process.
-    session: rexpect::session::PtySession,
+    /// The PTY of the underlying shell background process.
+    pty: Pty,
     /// Whether the underlying shell process is finished.
     session_finished: bool,
-    /// The ANSI escape parser.
-    parser: ransid::Console,
+    /// How the underlying shell process exited, once it has.
+    exit_status: Option<ExitStatus>,
+    /// Turns output into events.
+    console: Console,
+    /// Handles the escape sequences that the parser does not support.
+    interceptor: escape::Interceptor,
+    /// Detects binary output and normalizes line endings.
+    output_filter: OutputFilter,
+    /// Handles XON/XOFF in the input.
+    flow: FlowState,
+    /// Bulk input waiting to be written.
+    input: InputQueue,
+    /// The bytes written to and read from the PTY.
+    byte_counts: ByteCounts,
 }
 
-impl os::Driver for Driver {
-    fn new(settings: &Settings) -> Result<Self, io::Error> {
+/// What a driver does with an adopted PTY when it is dropped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PtyOwnership {
+    /// Close the PTY and kill the child, as with a shell the driver spawned.
+    Owned,
+    /// Leave the PTY open and the child running, for the embedder to clean up.
+    Borrowed,
+}
+
+/// A signal that can be sent to the running program.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Signal {
+    /// `SIGHUP`, as when the terminal is closed.
+    Hangup,
+    /// `SIGINT`, as with ^C.
+    Interrupt,
+    /// `SIGQUIT`, as with ^\.
+    Quit,
+    /// `SIGTERM`.
+    Terminate,
+    /// `SIGKILL`.
+    Kill,
+    /// `SIGUSR1`.
+    User1,
+    /// `SIGUSR2`.
+    User2,
+    /// `SIGWINCH`, as when the terminal is resized.
+    WindowChanged,
+    /// `SIGTSTP`, as with ^Z.
+    TerminalStop,
+    /// `SIGSTOP`.
+    Stop,
+    /// `SIGCONT`.
+    Continue,
+}
+
+impl Signal {
+    /// Gets the signal's number.
+    pub fn number(self) -> libc::c_int {
+        match self {
+            Signal::Hangup => libc::SIGHUP,
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Quit => libc::SIGQUIT,
+            Signal::Terminate => libc::SIGTERM,
+            Signal::Kill => libc::SIGKILL,
+            Signal::User1 => libc::SIGUSR1,
+            Signal::User2 => libc::SIGUSR2,
+            Signal::WindowChanged => libc::SIGWINCH,
+            Signal::TerminalStop => libc::SIGTSTP,
+            Signal::Stop => libc::SIGSTOP,
+            Signal::Continue => libc::SIGCONT,
+        }
+    }
+}
+
+/// The PTY that the driver talks to.
+enum Pty {
+    /// A shell that the driver spawned.
+    Spawned(rexpect::session::PtySession),
+    /// A PTY that the embedder created, such as with its own `forkpty`.
+    Adopted {
+        /// Only closed on drop when owned.
+        file: ManuallyDrop<File>,
+        /// The process on the other end, if it is a child of this process.
+        child_pid: Option<libc::pid_t>,
+        ownership: PtyOwnership,
+        /// Whether the other end of the PTY has been closed.
+        hung_up: bool,
+    },
+}
+
+impl Driver {
+    /// Starts the shell in a new PTY.
+    pub fn new(settings: &Settings) -> Result<Self, io::Error> {
+        if settings.reap_orphaned_descendants {
+            become_child_subreaper();
+        }
+
         let session = spawn_shell(&settings);
+        Ok(Driver::with_pty(settings, Pty::Spawned(session)))
+    }
+
+    /// Adopts a PTY that the embedder already has, such as one from its own
+    /// `forkpty` or one received over a socket, instead of spawning a shell.
+    ///
+    /// `child_pid` is the process on the other end, if it is a child of
+    /// this process. Without it, the session finishes once the PTY hangs up.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be the master side of a PTY. When it is owned, the driver
+    /// closes it on drop, so nothing else may close it.
+    pub unsafe fn adopt(settings: &Settings,
+                        fd: RawFd,
+                        child_pid: Option<libc::pid_t>,
+                        ownership: PtyOwnership) -> Result<Self, io::Error> {
+        if libc::fcntl(fd, libc::F_GETFD) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let pty = Pty::Adopted {
+            file: ManuallyDrop::new(File::from_raw_fd(fd)),
+            child_pid,
+            ownership,
+            hung_up: false,
+        };
+        Ok(Driver::with_pty(settings, pty))
+    }
 
-        Ok(Driver {
-            parser: create_parser(settings),
-            session,
+    fn with_pty(settings: &Settings, pty: Pty) -> Self {
+        let mut driver = Driver {
+            console: Console::new(settings.column_count, settings.line_count, settings.palette.clone(), settings.string_limits),
+            interceptor: escape::Interceptor::new(settings.c1_controls, settings.string_limits),
+            output_filter: OutputFilter::new(settings.binary_output, settings.line_endings),
+            flow: FlowState::new(settings.flow_control),
+            input: InputQueue::new(),
+            pty,
             settings: settings.clone(),
             session_finished: false,
-        })
+            exit_status: None,
+            byte_counts: ByteCounts::default(),
+        };
+
+        // Make sure the program starts with the same size as the grid.
+        os::Driver::resize(&mut driver, settings.column_count, settings.line_count);
+        driver
+    }
+}
+
+impl os::Driver for Driver {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        // Keep the order of input written behind a paste.
+        if !self.input.is_empty() {
+            self.input.push(bytes);
+            return Ok(());
+        }
+
+        self.send_input(bytes)
+    }
+
+    fn backspace(&mut self) -> Result<(), io::Error> {
+        self.write_bytes(b"\x7f") // send delete, as the backarrow key does by default.
     }
 
-    fn write_text(&mut self, s: &str) {
-        self.session.send(s).unwrap();
+    fn escape(&mut self) -> Result<(), io::Error> {
+        self.write_bytes(b"\x1b") // send ESC character code.
     }
 
-    fn backspace(&mut self) {
-        self.session.send("\x08").unwrap(); // send backspace character code.
+    fn cursor_left(&mut self) -> Result<(), io::Error> {
+        self.send_raw(&ansi_escapes::CursorMove::X(-1).to_string())
     }
 
-    fn escape(&mut self) {
-        self.session.send("\x1b").unwrap(); // send ESC character code.
+    fn cursor_right(&mut self) -> Result<(), io::Error> {
+        self.send_raw(&ansi_escapes::CursorMove::X(1).to_string())
     }
 
-    fn cursor_left(&mut self) {
-        self.send_raw(ansi_escapes::CursorMove::X(-1));
+    fn cursor_up(&mut self) -> Result<(), io::Error> {
+        self.send_raw(&ansi_escapes::CursorMove::Y(-1).to_string())
     }
 
-    fn cursor_right(&mut self) {
-        self.send_raw(ansi_escapes::CursorMove::X(1));
+    fn cursor_down(&mut self) -> Result<(), io::Error> {
+        self.send_raw(&ansi_escapes::CursorMove::Y(1).to_string())
     }
 
-    fn cursor_up(&mut self) {
-        self.send_raw(ansi_escapes::CursorMove::Y(-1));
+    fn control_code(&mut self, c: char) -> Result<(), io::Error> {
+        if !self.flow.filter_control_code(c) {
+            return Ok(());
+        }
+
+        match control_byte(c) {
+            Some(byte) => {
+                self.pty.write_all(&[byte])?;
+                self.byte_counts.written += 1;
+                Ok(())
+            },
+            None => {
+                warn!("cannot send {:?} as a control code", c);
+                Ok(())
+            },
+        }
     }
 
-    fn cursor_down(&mut self) {
-        self.send_raw(ansi_escapes::CursorMove::Y(1));
+    fn signal_interrupt(&mut self) -> Result<(), io::Error> {
+        self.control_code('c')
     }
 
-    fn control_code(&mut self, c: char) {
-        self.session.send_control(c).expect("failed to send control code to pty");
+    fn signal(&mut self, signal: Signal) -> Result<(), io::Error> {
+        self.pty.signal(signal)
     }
 
-    fn signal_interrupt(&mut self) {
-        self.control_code('c');
+    fn respawn(&mut self, settings: &Settings) -> Result<(), io::Error> {
+        match self.pty {
+            // Dropping the old driver kills the shell if it is still running.
+            Pty::Spawned(..) => *self = Driver::new(settings)?,
+            Pty::Adopted { .. } => return Err(io::Error::other("an adopted PTY cannot be respawned")),
+        }
+        Ok(())
     }
 
-    /// Sends raw data to the underlying terminal.
-    fn send_raw<S>(&mut self, s: S) where S: ToString {
-        self.session.send(&s.to_string()).unwrap();
+    fn write_bulk(&mut self, s: &str) {
+        self.input.push(s.as_bytes());
     }
 
-    /// Updates the terminal.
-    fn update(&mut self) -> Vec<event::Event> {
-        use rexpect::process::wait::WaitStatus::*;
+    fn write_bulk_bytes(&mut self, bytes: &[u8]) {
+        self.input.push(bytes);
+    }
+
+    fn pending_input_byte_count(&self) -> usize { self.input.len() }
+
+    fn cancel_pending_input(&mut self) -> usize {
+        self.input.clear()
+    }
+
+    fn resize(&mut self, columns: usize, lines: usize) {
+        self.settings.column_count = columns;
+        self.settings.line_count = lines;
+        self.console.resize(columns, lines);
+
+        let size = libc::winsize {
+            ws_row: lines.min(u16::MAX as usize) as u16,
+            ws_col: columns.min(u16::MAX as usize) as u16,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+
+        // The kernel sends SIGWINCH to the foreground process group.
+        let fd = self.pty.as_raw_fd();
+        if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } != 0 {
+            warn!("failed to set the PTY window size: {}", io::Error::last_os_error());
+        }
+    }
 
+    /// Updates the terminal.
+    fn update(&mut self) -> Result<Vec<event::Event>, io::Error> {
         let mut events = Vec::new();
 
         if self.is_session_finished() {
-            return events;
+            return Ok(events);
         }
 
-        match self.session.process.status() {
-            Some(Exited(_, _)) | None => {
-                self.session_finished = true;
-            },
-            Some(_) => {
-                while let Some(byte) = self.session.try_read_raw() {
-                    // anything to appease the borrow checker.
-                    let mut parser = mem::replace(&mut self.parser, create_parser(&self.settings));
-                    parser.write(&[byte], |event| {
-                        events.extend(self::convert_ransid_event(event))
-                    });
-                    self.parser = parser;
-                }
+        // The output the program wrote before exiting is still read.
+        let exit_status = self.pty.poll_exit();
+        let running = exit_status.is_none();
+
+        if running {
+            if self.settings.reap_orphaned_descendants {
+                events.extend(self.reap_descendants());
+            }
+
+            if let Some(chunk) = self.input.take_chunk() {
+                self.send_input(&chunk)?;
             }
         }
 
-        events
+        // Leave the output in the PTY while paused or stopped, so the program
+        // blocks once it fills.
+        let mut bytes = Vec::new();
+        if !self.output_filter.is_paused() && !self.flow.is_stopped() {
+            self.pty.read_available(&mut bytes);
+        }
+        self.byte_counts.read += bytes.len() as u64;
+
+        let filtered = self.output_filter.filter(&bytes);
+        events.extend(filtered.events);
+
+        let output = self.interceptor.write(&filtered.bytes, &mut self.console);
+        events.extend(output.events);
+
+        // There is no one left to answer once the program has exited.
+        if running && !output.response.is_empty() {
+            self.pty.write_all(&output.response)?;
+            self.byte_counts.written += output.response.len() as u64;
+        }
+
+        if let Some(status) = exit_status {
+            events.push(self.finish_session(status));
+        }
+
+        Ok(events)
     }
 
     /// Checks if the underlying shell session has finished.
     fn is_session_finished(&self) -> bool { self.session_finished }
+
+    fn is_echo_disabled(&self) -> bool {
+        let fd = self.pty.as_raw_fd();
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+
+        // The master side of the PTY reflects the terminal attributes of the slave.
+        if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+            return false;
+        }
+
+        termios.c_lflag & libc::ECHO == 0
+    }
+
+    fn is_output_paused(&self) -> bool { self.output_filter.is_paused() }
+
+    fn is_flow_stopped(&self) -> bool { self.flow.is_stopped() }
+
+    fn resume_output(&mut self, policy: BinaryOutputPolicy) {
+        self.output_filter.resume(policy);
+    }
+
+    fn debug_state(&self) -> DriverDebugState {
+        DriverDebugState {
+            parser_state: self.interceptor.state_name(),
+            pending_bytes: self.interceptor.pending_byte_count() + self.output_filter.held_byte_count(),
+        }
+    }
+
+    fn exit_status(&self) -> Option<ExitStatus> { self.exit_status }
+
+    fn byte_counts(&self) -> ByteCounts { self.byte_counts }
 }
 
-/// Handles a terminal event.
-fn convert_ransid_event<'a>(event: ransid::Event<'a>)
-    -> Vec<event::Event> {
-    use ransid::Event::*;
-
-    match event {
-        // FIXME: we should take into account position.
-        // there are x,y values in Char
-        Char { x, y, c, color, bold, italic, underlined, strikethrough } => {
-            vec![
-                event::Event::PutCharacter {
-                    x, y, bold, italic, underlined, strikethrough,
-                    character: c,
-                    color: Color::from_packed_argb8(color.as_rgb())
-                }
-            ]
-        },
-        ScreenBuffer { clear, .. } => {
-            let mut events = Vec::new();
+impl Driver {
+    /// Writes input to the PTY straight away.
+    fn send_input(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        let bytes = self.flow.filter_input(bytes);
+        self.pty.write_all(&bytes)?;
+        self.byte_counts.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Reaps any exited orphans from the shell's session, reporting the
+    /// ones that died unexpectedly. The shell itself is left to `poll_exit`.
+    ///
+    /// Job control gives each job its own process group, so the session is
+    /// what they have in common. Each child is waited for by its PID, so
+    /// that children from elsewhere in this process, such as other
+    /// terminals or the embedder's own commands, are left for their owners.
+    fn reap_descendants(&mut self) -> Vec<event::Event> {
+        let shell_pid = match self.pty.child_pid() {
+            Some(pid) => pid,
+            None => return Vec::new(),
+        };
+        let mut events = Vec::new();
+
+        // The shell is a session leader, so its session ID is its PID.
+        let orphans = child_pids().into_iter()
+            .filter(|&pid| pid != shell_pid && unsafe { libc::getsid(pid) } == shell_pid);
+
+        for pid in orphans {
+            let mut status = 0;
+            if unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } != pid {
+                continue;
+            }
+
+            let ExitStatus { code, signal } = match exit_status(status) {
+                Some(status) => status,
+                None => continue,
+            };
 
-            if clear {
-                events.push(event::Event::ClearScreen);
+            if code != Some(0) {
+                events.push(event::Event::DescendantExited { pid, code, signal });
             }
+        }
+
+        events
+    }
+
+    /// Notes that the shell has exited.
+    fn finish_session(&mut self, status: ExitStatus) -> event::Event {
+        self.session_finished = true;
+        self.exit_status = Some(status);
+        event::Event::SessionExited { code: status.code, signal: status.signal }
+    }
+}
 
-            events
+/// Gets how a process exited from its `waitpid` status, or `None` if it
+/// has only stopped or continued.
+fn exit_status(status: libc::c_int) -> Option<ExitStatus> {
+    if libc::WIFEXITED(status) {
+        Some(ExitStatus { code: Some(libc::WEXITSTATUS(status)), signal: None })
+    } else if libc::WIFSIGNALED(status) {
+        Some(ExitStatus { code: None, signal: Some(libc::WTERMSIG(status)) })
+    } else {
+        None
+    }
+}
+
+/// Makes orphaned descendants get reparented to this process instead of init.
+#[cfg(target_os = "linux")]
+fn become_child_subreaper() {
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+        warn!("failed to become a child subreaper: {}", io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn become_child_subreaper() {
+    info!("child subreaping is only supported on Linux");
+}
+
+/// Lists the children of this process, from the `children` file of each
+/// of its threads. Exited children are listed until they are reaped.
+#[cfg(target_os = "linux")]
+fn child_pids() -> Vec<libc::pid_t> {
+    let tasks = match std::fs::read_dir("/proc/self/task") {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            warn!("failed to list the threads of this process: {}", e);
+            return Vec::new();
         },
-        _ => vec![], // unimplemented event
+    };
+
+    let mut pids = Vec::new();
+    for task in tasks.flatten() {
+        if let Ok(children) = std::fs::read_to_string(task.path().join("children")) {
+            pids.extend(children.split_whitespace().filter_map(|pid| pid.parse::<libc::pid_t>().ok()));
+        }
     }
+    pids
+}
+
+/// Without subreaping, no orphans are reparented to this process.
+#[cfg(not(target_os = "linux"))]
+fn child_pids() -> Vec<libc::pid_t> {
+    Vec::new()
 }
 
+impl Pty {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Pty::Spawned(ref session) => session.process.pty.as_raw_fd(),
+            Pty::Adopted { ref file, .. } => file.as_raw_fd(),
+        }
+    }
 
-fn create_parser(settings: &Settings) -> ransid::Console {
-    ransid::Console::new(settings.column_count, settings.line_count)
+    /// Gets the process on the other end, if it is a child of this process.
+    fn child_pid(&self) -> Option<libc::pid_t> {
+        match *self {
+            Pty::Spawned(ref session) => Some(session.process.child_pid.as_raw()),
+            Pty::Adopted { child_pid, .. } => child_pid,
+        }
+    }
+
+    /// Checks if the process on the other end has exited, and how.
+    fn poll_exit(&mut self) -> Option<ExitStatus> {
+        use rexpect::process::wait::WaitStatus::*;
+
+        match *self {
+            Pty::Spawned(ref session) => match session.process.status() {
+                Some(Exited(_, code)) => Some(ExitStatus { code: Some(code), signal: None }),
+                Some(Signaled(_, signal, _)) => Some(ExitStatus { code: None, signal: Some(signal as i32) }),
+                None => Some(UNKNOWN_EXIT),
+                Some(_) => None,
+            },
+            Pty::Adopted { hung_up: true, .. } => Some(UNKNOWN_EXIT),
+            Pty::Adopted { child_pid: Some(pid), .. } => {
+                let mut status = 0;
+                // Processes that are not our children can only be seen to hang up.
+                match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } == pid {
+                    true => Some(exit_status(status).unwrap_or(UNKNOWN_EXIT)),
+                    false => None,
+                }
+            },
+            Pty::Adopted { child_pid: None, .. } => None,
+        }
+    }
+
+    /// Reads all of the output available without blocking.
+    fn read_available(&mut self, bytes: &mut Vec<u8>) {
+        match *self {
+            Pty::Spawned(ref mut session) => {
+                while let Some(byte) = session.try_read_raw() {
+                    bytes.push(byte);
+                }
+            },
+            Pty::Adopted { ref file, ref mut hung_up, .. } => {
+                let mut buffer = [0; READ_CHUNK_SIZE];
+
+                loop {
+                    let mut poll_fd = libc::pollfd { fd: file.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+                    if unsafe { libc::poll(&mut poll_fd, 1, 0) } <= 0 {
+                        break;
+                    }
+
+                    // Linux fails with EIO rather than reading nothing once the other end closes.
+                    match (&**file).read(&mut buffer) {
+                        Ok(0) | Err(_) => {
+                            *hung_up = true;
+                            break;
+                        },
+                        Ok(count) => bytes.extend_from_slice(&buffer[..count]),
+                    }
+                }
+            },
+        }
+    }
+
+    /// Writes bytes to the PTY, bypassing the session's line buffered writer
+    /// and the string it requires.
+    fn write_all(&self, bytes: &[u8]) -> io::Result<()> {
+        // The PTY owns the descriptor, so it must not be closed here.
+        let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(self.as_raw_fd()) });
+        file.write_all(bytes)
+    }
+
+    /// Kills the process on the other end, if the driver owns it.
+    fn kill(&mut self) {
+        match *self {
+            Pty::Spawned(ref mut session) => {
+                // We should probably do something more graceful.
+                if let Err(e) = session.process.signal(rexpect::process::signal::Signal::SIGKILL) {
+                    info!("failed to kill terminal process with pid {:?}: {}",
+                          session.process.child_pid, e);
+                }
+            },
+            Pty::Adopted { child_pid: Some(pid), ownership: PtyOwnership::Owned, .. } => {
+                if unsafe { libc::kill(pid, libc::SIGKILL) } != 0 {
+                    info!("failed to kill terminal process with pid {}: {}", pid, io::Error::last_os_error());
+                }
+            },
+            Pty::Adopted { .. } => (),
+        }
+    }
+
+    /// Sends a signal to the foreground process group of the PTY, as the
+    /// line discipline does for ^C, falling back to the child's own group.
+    fn signal(&self, signal: Signal) -> Result<(), io::Error> {
+        let group = match unsafe { libc::tcgetpgrp(self.as_raw_fd()) } {
+            group if group > 0 => group,
+            // A spawned shell is a session leader, so its process group ID is its PID.
+            _ => match self.child_pid() {
+                Some(pid) => pid,
+                None => return Err(io::Error::other("the PTY has no process group to signal")),
+            },
+        };
+
+        if unsafe { libc::kill(-group, signal.number()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        if let Pty::Adopted { ref mut file, ownership: PtyOwnership::Owned, .. } = *self {
+            unsafe { ManuallyDrop::drop(file) };
+        }
+    }
 }
 
 fn spawn_shell(settings: &Settings)
@@ -148,6 +592,7 @@ fn spawn_shell(settings: &Settings)
     // FIXME: this won't exist if binaries are redistributed.
     let dir = format!("{}/../", env!("CARGO_MANIFEST_DIR"));
     cmd.current_dir(dir);
+    cmd.envs(settings.environment.iter().cloned());
 
     rexpect::session::spawn_command(cmd, None)
         .expect("failed to spawn shell")
@@ -156,11 +601,7 @@ fn spawn_shell(settings: &Settings)
 impl Drop for Driver {
     fn drop(&mut self) {
         if !self.session_finished {
-            // We should probably do something more graceful.
-            if let Err(e) = self.session.process.signal(rexpect::process::signal::Signal::SIGKILL) {
-                info!("failed to kill terminal process with pid {:?}: {}",
-                      self.session.process.child_pid, e);
-            }
+            self.pty.kill();
         }
     }
 }
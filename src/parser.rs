@@ -0,0 +1,565 @@
+//! A streaming parser for the escape sequences in terminal output.
+//!
+//! The parser is a state machine after the DEC ANSI parser described by
+//! Paul Williams. It knows nothing about what sequences mean; each parsed
+//! item is handed to a `Visitor`, so that filters and recorders can be
+//! built without a `Terminal`.
+//!
+//! Output is expected to be UTF-8. Invalid bytes are printed as U+FFFD.
+
+#[allow(unused_imports)]
+use crate::std_prelude::*;
+use core::{mem, str};
+
+/// The largest parameter value. Larger values are clamped to it, as xterm
+/// does, so that no sequence can ask for more than a grid could hold.
+pub const MAX_PARAMETER: usize = 65535;
+/// The most parameters kept for a sequence. Sequences with more are ignored.
+const MAX_PARAMETERS: usize = 32;
+/// The most intermediate bytes kept for a sequence. Sequences with more are ignored.
+const MAX_INTERMEDIATES: usize = 2;
+/// The longest OSC or DCS payload kept by default.
+pub const MAX_STRING_LENGTH: usize = 4096;
+/// The longest a control string may run without a terminator by default.
+pub const MAX_UNTERMINATED_STRING_LENGTH: usize = 1 << 20;
+
+const BEL: u8 = 0x07;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+/// Printed in place of invalid UTF-8.
+const REPLACEMENT: char = '\u{fffd}';
+
+/// Receives the items parsed from a byte stream.
+///
+/// Every method does nothing by default.
+pub trait Visitor {
+    /// Prints a character.
+    fn print(&mut self, _character: char) { }
+
+    /// Executes a C0 control, such as a line feed.
+    fn execute(&mut self, _byte: u8) { }
+
+    /// Handles an escape sequence, such as `ESC 7`.
+    fn esc(&mut self, _sequence: &EscapeSequence) { }
+
+    /// Handles a control sequence, such as `CSI 1 ; 2 H`.
+    fn csi(&mut self, _sequence: &ControlSequence) { }
+
+    /// Handles an operating system command, such as `OSC 0 ; title BEL`,
+    /// split into its `;`-separated parameters.
+    fn osc(&mut self, _parameters: &[&[u8]]) { }
+
+    /// Handles a device control string, such as a DECRQSS request.
+    fn dcs(&mut self, _string: &DeviceControlString) { }
+
+    /// Notes that a control string was abandoned for running on too long,
+    /// leaving the parser in its ground state.
+    fn string_abandoned(&mut self) { }
+
+    /// Notes that an OSC or DCS string was discarded for having a payload
+    /// longer than the limit, which is `length` bytes long.
+    fn string_discarded(&mut self, _kind: ControlStringKind, _length: usize) { }
+}
+
+/// Caps on the control strings (OSC, DCS, SOS, PM and APC) that a parser
+/// reads, so that a buggy or hostile program cannot make it hold on to
+/// unbounded amounts of memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StringLimits {
+    /// The longest OSC or DCS payload kept. Strings with longer payloads
+    /// are discarded whole, as a cut-down payload could mean something else.
+    pub max_payload_length: usize,
+    /// The longest a control string may run without a terminator. Past this,
+    /// the terminator is taken to be lost, and the string is abandoned so that
+    /// the output after it is not hidden for good.
+    pub max_unterminated_length: usize,
+}
+
+/// A kind of control string whose payload is kept.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ControlStringKind {
+    /// An operating system command, such as a window title.
+    Osc,
+    /// A device control string, such as a DECRQSS request.
+    Dcs,
+}
+
+/// An escape sequence.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EscapeSequence<'a> {
+    /// The bytes between ESC and the final byte, such as `(` in `ESC ( B`.
+    pub intermediates: &'a [u8],
+    pub final_byte: u8,
+}
+
+/// A control sequence (CSI).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ControlSequence<'a> {
+    /// The private marker (`<`, `=`, `>` or `?`), if any.
+    pub private_marker: Option<u8>,
+    /// The parameters, where missing parameters are zero.
+    pub parameters: &'a [usize],
+    pub intermediates: &'a [u8],
+    pub final_byte: u8,
+}
+
+/// A device control string (DCS).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DeviceControlString<'a> {
+    /// The private marker (`<`, `=`, `>` or `?`), if any.
+    pub private_marker: Option<u8>,
+    /// The parameters, where missing parameters are zero.
+    pub parameters: &'a [usize],
+    pub intermediates: &'a [u8],
+    pub final_byte: u8,
+    /// The payload between the final byte and the string terminator.
+    pub data: &'a [u8],
+}
+
+/// A streaming escape sequence parser.
+///
+/// Sequences may be split across calls to `advance`.
+#[derive(Clone, Debug)]
+pub struct Parser {
+    state: State,
+    private_marker: Option<u8>,
+    parameters: Vec<usize>,
+    intermediates: Vec<u8>,
+    /// Whether the current sequence has too many parameters or
+    /// intermediates, or is malformed, and will not be dispatched.
+    ignoring: bool,
+    /// The payload of the current OSC or DCS string.
+    string: Vec<u8>,
+    /// The bytes read in the current control string, including those not kept.
+    string_length: usize,
+    /// The payload bytes of the current OSC or DCS string, including those not kept.
+    payload_length: usize,
+    limits: StringLimits,
+    /// The final byte of the current DCS string.
+    dcs_final_byte: u8,
+    /// The bytes of a UTF-8 character that is not complete yet.
+    partial_character: Vec<u8>,
+}
+
+/// The state of the parser.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    Osc,
+    /// Reading the parameters of a DCS string.
+    Dcs,
+    /// Reading the payload of a DCS string.
+    DcsData,
+    /// Skipping an SOS, PM or APC string.
+    IgnoredString,
+}
+
+impl<'a> ControlSequence<'a> {
+    /// Gets a parameter, substituting a default if it is missing or zero.
+    pub fn parameter(&self, index: usize, default: usize) -> usize {
+        match self.parameters.get(index) {
+            Some(&0) | None => default,
+            Some(&value) => value,
+        }
+    }
+}
+
+impl Default for StringLimits {
+    fn default() -> Self {
+        StringLimits {
+            max_payload_length: MAX_STRING_LENGTH,
+            max_unterminated_length: MAX_UNTERMINATED_STRING_LENGTH,
+        }
+    }
+}
+
+impl Parser {
+    /// Creates a new parser in the ground state, with the default limits.
+    pub fn new() -> Self {
+        Parser::with_limits(StringLimits::default())
+    }
+
+    /// Creates a new parser in the ground state, with the given limits.
+    pub fn with_limits(limits: StringLimits) -> Self {
+        Parser {
+            state: State::Ground,
+            private_marker: None,
+            parameters: Vec::new(),
+            intermediates: Vec::new(),
+            ignoring: false,
+            string: Vec::new(),
+            string_length: 0,
+            payload_length: 0,
+            limits,
+            dcs_final_byte: 0,
+            partial_character: Vec::new(),
+        }
+    }
+
+    /// Parses some bytes, handing each complete item to the visitor.
+    pub fn advance<V>(&mut self, visitor: &mut V, bytes: &[u8]) where V: Visitor + ?Sized {
+        for &byte in bytes {
+            self.advance_byte(visitor, byte);
+        }
+    }
+
+    /// Checks whether the parser is between sequences and characters.
+    pub fn is_ground(&self) -> bool {
+        self.state == State::Ground && self.partial_character.is_empty()
+    }
+
+    fn advance_byte<V>(&mut self, visitor: &mut V, byte: u8) where V: Visitor + ?Sized {
+        if !self.partial_character.is_empty() && !is_continuation(byte) {
+            // The character was cut short.
+            self.partial_character.clear();
+            visitor.print(REPLACEMENT);
+        }
+
+        // These interrupt any sequence.
+        match byte {
+            CAN | SUB => {
+                self.state = State::Ground;
+                visitor.execute(byte);
+                return;
+            },
+            ESC => {
+                self.finish_string(visitor);
+                self.enter(State::Escape);
+                return;
+            },
+            _ => (),
+        }
+
+        if let State::Osc | State::DcsData | State::IgnoredString = self.state {
+            self.string_length += 1;
+            if self.string_length > self.limits.max_unterminated_length {
+                self.state = State::Ground;
+                visitor.string_abandoned();
+            }
+        }
+
+        match self.state {
+            State::Ground => match byte {
+                0x00..=0x1f => visitor.execute(byte),
+                DEL => (),
+                _ => self.print_byte(visitor, byte),
+            },
+            State::Escape => match byte {
+                0x00..=0x1f => visitor.execute(byte),
+                0x20..=0x2f => self.collect_intermediate(byte),
+                b'[' if self.intermediates.is_empty() => self.enter(State::Csi),
+                b']' if self.intermediates.is_empty() => self.enter(State::Osc),
+                b'P' if self.intermediates.is_empty() => self.enter(State::Dcs),
+                b'X' | b'^' | b'_' if self.intermediates.is_empty() => self.enter(State::IgnoredString),
+                // The string terminator on its own has already done its job.
+                b'\\' if self.intermediates.is_empty() => self.state = State::Ground,
+                0x30..=0x7e => {
+                    self.state = State::Ground;
+                    if !self.ignoring {
+                        visitor.esc(&EscapeSequence { intermediates: &self.intermediates, final_byte: byte });
+                    }
+                },
+                _ => (),
+            },
+            State::Csi | State::Dcs => match byte {
+                0x00..=0x1f => visitor.execute(byte),
+                0x40..=0x7e => self.finish_parameters(visitor, byte),
+                _ => self.collect_parameter(byte),
+            },
+            State::Osc => match byte {
+                BEL => {
+                    self.finish_string(visitor);
+                    self.state = State::Ground;
+                },
+                0x00..=0x1f => (),
+                _ => self.collect_string(byte),
+            },
+            State::DcsData => self.collect_string(byte),
+            State::IgnoredString => (),
+        }
+    }
+
+    /// Starts a new sequence in the given state.
+    fn enter(&mut self, state: State) {
+        self.state = state;
+        self.private_marker = None;
+        self.parameters.clear();
+        self.intermediates.clear();
+        self.ignoring = false;
+        self.string.clear();
+        self.string_length = 0;
+        self.payload_length = 0;
+    }
+
+    fn collect_intermediate(&mut self, byte: u8) {
+        if self.intermediates.len() < MAX_INTERMEDIATES {
+            self.intermediates.push(byte);
+        } else {
+            self.ignoring = true;
+        }
+    }
+
+    /// Collects a parameter or intermediate byte of a CSI or DCS sequence.
+    fn collect_parameter(&mut self, byte: u8) {
+        match byte {
+            // Parameters may not follow intermediates.
+            b'0'..=b';' if !self.intermediates.is_empty() => self.ignoring = true,
+            b'0'..=b'9' => {
+                if self.parameters.is_empty() {
+                    self.parameters.push(0);
+                }
+
+                let parameter = self.parameters.last_mut().unwrap();
+                *parameter = (*parameter * 10 + (byte - b'0') as usize).min(MAX_PARAMETER);
+            },
+            // Subparameters are treated as parameters.
+            b':' | b';' => {
+                if self.parameters.is_empty() {
+                    self.parameters.push(0);
+                }
+
+                if self.parameters.len() < MAX_PARAMETERS {
+                    self.parameters.push(0);
+                } else {
+                    self.ignoring = true;
+                }
+            },
+            b'<'..=b'?' => {
+                if self.private_marker.is_none() && self.parameters.is_empty() && self.intermediates.is_empty() {
+                    self.private_marker = Some(byte);
+                } else {
+                    self.ignoring = true;
+                }
+            },
+            0x20..=0x2f => self.collect_intermediate(byte),
+            _ => (),
+        }
+    }
+
+    /// Dispatches a CSI sequence, or starts the payload of a DCS string.
+    fn finish_parameters<V>(&mut self, visitor: &mut V, final_byte: u8) where V: Visitor + ?Sized {
+        if self.state == State::Dcs {
+            self.dcs_final_byte = final_byte;
+            self.state = State::DcsData;
+            return;
+        }
+
+        self.state = State::Ground;
+        if !self.ignoring {
+            visitor.csi(&ControlSequence {
+                private_marker: self.private_marker,
+                parameters: &self.parameters,
+                intermediates: &self.intermediates,
+                final_byte,
+            });
+        }
+    }
+
+    fn collect_string(&mut self, byte: u8) {
+        self.payload_length += 1;
+        if self.payload_length <= self.limits.max_payload_length {
+            self.string.push(byte);
+        }
+    }
+
+    /// Dispatches the OSC or DCS string being read, if any.
+    fn finish_string<V>(&mut self, visitor: &mut V) where V: Visitor + ?Sized {
+        let state = mem::replace(&mut self.state, State::Ground);
+        if self.ignoring {
+            return;
+        }
+
+        let kind = match state {
+            State::Osc => ControlStringKind::Osc,
+            State::DcsData => ControlStringKind::Dcs,
+            _ => return,
+        };
+        if self.payload_length > self.limits.max_payload_length {
+            return visitor.string_discarded(kind, self.payload_length);
+        }
+
+        match kind {
+            ControlStringKind::Osc => {
+                let parameters: Vec<&[u8]> = self.string.split(|&b| b == b';').collect();
+                visitor.osc(&parameters);
+            },
+            ControlStringKind::Dcs => visitor.dcs(&DeviceControlString {
+                private_marker: self.private_marker,
+                parameters: &self.parameters,
+                intermediates: &self.intermediates,
+                final_byte: self.dcs_final_byte,
+                data: &self.string,
+            }),
+        }
+    }
+
+    /// Prints a byte of UTF-8 text, once its character is complete.
+    fn print_byte<V>(&mut self, visitor: &mut V, byte: u8) where V: Visitor + ?Sized {
+        let length = match self.partial_character.first() {
+            Some(&first) => utf8_length(first),
+            None if byte < 0x80 => return visitor.print(byte as char),
+            None => utf8_length(byte),
+        };
+
+        if length == 0 {
+            return visitor.print(REPLACEMENT);
+        }
+
+        self.partial_character.push(byte);
+        if self.partial_character.len() < length {
+            return;
+        }
+
+        let character = str::from_utf8(&self.partial_character).ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(REPLACEMENT);
+        self.partial_character.clear();
+        visitor.print(character);
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+/// Gets the length of a UTF-8 character from its first byte, or zero if
+/// the byte cannot start one.
+fn utf8_length(first_byte: u8) -> usize {
+    match first_byte {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => 0,
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Records every item as a string, for easy comparison.
+    #[derive(Default)]
+    struct Recorder {
+        items: Vec<String>,
+    }
+
+    impl Visitor for Recorder {
+        fn print(&mut self, character: char) {
+            match self.items.last_mut() {
+                Some(item) if item.starts_with("print ") => item.push(character),
+                _ => self.items.push(format!("print {}", character)),
+            }
+        }
+
+        fn execute(&mut self, byte: u8) {
+            self.items.push(format!("execute {:#04x}", byte));
+        }
+
+        fn esc(&mut self, sequence: &EscapeSequence) {
+            self.items.push(format!("esc {:?} {}", sequence.intermediates, sequence.final_byte as char));
+        }
+
+        fn csi(&mut self, sequence: &ControlSequence) {
+            self.items.push(format!("csi {:?} {:?} {:?} {}", sequence.private_marker.map(|b| b as char),
+                                    sequence.parameters, sequence.intermediates, sequence.final_byte as char));
+        }
+
+        fn osc(&mut self, parameters: &[&[u8]]) {
+            let parameters: Vec<_> = parameters.iter().map(|p| String::from_utf8_lossy(p).into_owned()).collect();
+            self.items.push(format!("osc {:?}", parameters));
+        }
+
+        fn dcs(&mut self, string: &DeviceControlString) {
+            self.items.push(format!("dcs {:?} {} {}", string.intermediates, string.final_byte as char,
+                                    String::from_utf8_lossy(string.data)));
+        }
+
+        fn string_abandoned(&mut self) {
+            self.items.push("abandoned".to_owned());
+        }
+
+        fn string_discarded(&mut self, kind: ControlStringKind, length: usize) {
+            self.items.push(format!("discarded {:?} {}", kind, length));
+        }
+    }
+
+    fn parse(chunks: &[&[u8]]) -> Vec<String> {
+        parse_with_limits(chunks, StringLimits::default())
+    }
+
+    fn parse_with_limits(chunks: &[&[u8]], limits: StringLimits) -> Vec<String> {
+        let mut parser = Parser::with_limits(limits);
+        let mut recorder = Recorder::default();
+
+        for chunk in chunks {
+            parser.advance(&mut recorder, chunk);
+        }
+        recorder.items
+    }
+
+    #[test]
+    fn parses_text_and_controls() {
+        assert_eq!(vec!["print ab", "execute 0x0a", "print ü€"], parse(&["ab\nü€".as_bytes()]));
+    }
+
+    #[test]
+    fn parses_sequences_split_across_chunks() {
+        assert_eq!(vec![
+            "csi Some('?') [1049] [] h",
+            "csi None [0, 2] [] H",
+            "esc [40] B",
+            "csi None [2] [32] q",
+        ], parse(&[b"\x1b[?10", b"49h\x1b[;2", b"H\x1b(", b"B\x1b[2 q"]));
+    }
+
+    #[test]
+    fn parses_control_strings() {
+        assert_eq!(vec!["osc [\"133\", \"A\"]", "dcs [36] q m", "print x"],
+                   parse(&[b"\x1b]133;A\x07\x1bP$qm\x1b\\\x1b_ignored\x1b\\x"]));
+        assert_eq!(vec!["osc [\"0\", \"title\"]"], parse(&[b"\x1b]0;title\x1b\\"]));
+    }
+
+    #[test]
+    fn replaces_invalid_utf8() {
+        assert_eq!(vec!["print \u{fffd}a\u{fffd}", "execute 0x0a"], parse(&[b"\xffa\xe2\x82\n"]));
+    }
+
+    #[test]
+    fn clamps_huge_parameters() {
+        assert_eq!(vec!["csi None [65535, 12] [] H"], parse(&[b"\x1b[99999999999999999999999;12H"]));
+    }
+
+    #[test]
+    fn ignores_malformed_sequences() {
+        assert_eq!(vec!["execute 0x18", "print x"], parse(&[b"\x1b[1\x18x"]));
+        assert_eq!(vec!["print y"], parse(&[b"\x1b[1$2Hy"]));
+    }
+
+    #[test]
+    fn abandons_unterminated_strings() {
+        let string = vec![b'a'; MAX_UNTERMINATED_STRING_LENGTH - 2];
+        assert_eq!(vec!["abandoned", "print bc"], parse(&[b"\x1b]0;", &string, b"bc"]));
+        assert_eq!(vec!["abandoned", "print bc"], parse(&[b"\x1b_", &string, b"aabc"]));
+    }
+
+    #[test]
+    fn discards_strings_over_the_limits() {
+        let limits = StringLimits { max_payload_length: 4, max_unterminated_length: 8 };
+
+        assert_eq!(vec!["osc [\"0\", \"ab\"]", "discarded Osc 5", "dcs [] q abcd"],
+                   parse_with_limits(&[b"\x1b]0;ab\x07\x1b]0;abc\x07\x1bPqabcd\x1b\\"], limits));
+        assert_eq!(vec!["abandoned", "print z"], parse_with_limits(&[b"\x1b_abcdefghz"], limits));
+    }
+}
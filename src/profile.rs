@@ -0,0 +1,163 @@
+//! User-configurable session profiles, such as "Python venv" or "Prod SSH".
+
+use crate::{Color, Settings};
+use std::{collections::BTreeMap, fmt};
+
+/// A named bundle of everything needed to start a session.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Profile {
+    /// The name shown to the user.
+    pub name: String,
+    /// The terminal settings.
+    pub settings: Settings,
+    /// The colors used by the frontend.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub theme: Theme,
+    /// Commands written to the shell once it starts, one per line.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub startup_commands: Vec<String>,
+    /// Environment variables for the shell, on top of `Settings::environment`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub environment: BTreeMap<String, String>,
+}
+
+/// The colors a frontend should draw with.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Theme {
+    /// The default text color.
+    pub foreground: Color,
+    /// The default background color.
+    pub background: Color,
+    /// The cursor color, unless the running program sets one.
+    /// `None` uses the foreground color.
+    pub cursor: Option<Color>,
+    /// The text color of output classified as an error, with
+    /// `CellClass::ErrorStream`. `None` keeps the output's own colors.
+    pub error: Option<Color>,
+}
+
+/// An error reading or writing a profile.
+#[derive(Debug)]
+pub enum ProfileError {
+    /// The TOML could not be parsed into a profile.
+    #[cfg(feature = "toml")]
+    TomlDeserialize(toml::de::Error),
+    /// The profile could not be written as TOML.
+    #[cfg(feature = "toml")]
+    TomlSerialize(toml::ser::Error),
+    /// The JSON could not be parsed into a profile, or written.
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+}
+
+impl Profile {
+    /// Creates a profile with the given name and settings, and nothing else.
+    pub fn new<S>(name: S, settings: Settings) -> Self where S: Into<String> {
+        Profile {
+            name: name.into(),
+            settings,
+            theme: Theme::default(),
+            startup_commands: Vec::new(),
+            environment: BTreeMap::new(),
+        }
+    }
+
+    /// Gets the settings with the profile's environment variables merged in.
+    pub fn effective_settings(&self) -> Settings {
+        let mut settings = self.settings.clone();
+        settings.environment.extend(self.environment.iter().map(|(key, value)| (key.clone(), value.clone())));
+        settings
+    }
+
+    /// Parses a profile from TOML.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> Result<Self, ProfileError> {
+        toml::from_str(s).map_err(ProfileError::TomlDeserialize)
+    }
+
+    /// Writes the profile as TOML.
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> Result<String, ProfileError> {
+        toml::to_string_pretty(self).map_err(ProfileError::TomlSerialize)
+    }
+
+    /// Parses a profile from JSON.
+    #[cfg(feature = "json")]
+    pub fn from_json(s: &str) -> Result<Self, ProfileError> {
+        serde_json::from_str(s).map_err(ProfileError::Json)
+    }
+
+    /// Writes the profile as JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Result<String, ProfileError> {
+        serde_json::to_string_pretty(self).map_err(ProfileError::Json)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            foreground: Color::WHITE,
+            background: Color::BLACK,
+            cursor: None,
+            error: None,
+        }
+    }
+}
+
+impl fmt::Display for ProfileError {
+    // Without any formats enabled, there are no errors to format.
+    #[allow(unused_variables)]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            #[cfg(feature = "toml")]
+            ProfileError::TomlDeserialize(ref e) => write!(fmt, "invalid TOML profile: {}", e),
+            #[cfg(feature = "toml")]
+            ProfileError::TomlSerialize(ref e) => write!(fmt, "failed to write TOML profile: {}", e),
+            #[cfg(feature = "json")]
+            ProfileError::Json(ref e) => write!(fmt, "invalid JSON profile: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError { }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn profile() -> Profile {
+        let mut profile = Profile::new("Python venv", Settings {
+            shell: "bash".to_owned(),
+            environment: vec![("LANG".to_owned(), "C".to_owned())],
+            ..Settings::default()
+        });
+        profile.startup_commands.push("source venv/bin/activate".to_owned());
+        profile.environment.insert("VIRTUAL_ENV_DISABLE_PROMPT".to_owned(), "1".to_owned());
+        profile.theme.cursor = Some(Color::GREEN);
+        profile
+    }
+
+    #[test]
+    fn profile_environment_is_merged_into_settings() {
+        assert_eq!(vec![("LANG".to_owned(), "C".to_owned()),
+                        ("VIRTUAL_ENV_DISABLE_PROMPT".to_owned(), "1".to_owned())],
+                   profile().effective_settings().environment);
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn toml_round_trip() {
+        let toml = profile().to_toml().unwrap();
+        assert_eq!(profile(), Profile::from_toml(&toml).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn json_round_trip() {
+        let json = profile().to_json().unwrap();
+        assert_eq!(profile(), Profile::from_json(&json).unwrap());
+    }
+}
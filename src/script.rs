@@ -0,0 +1,161 @@
+//! Automating the terminal with small Rhai scripts, loaded at runtime.
+//!
+//! A script defines functions that are called as output arrives:
+//!
+//! * `on_line(text)`, when the running program finishes a line. Lines
+//!   that wrapped are given whole.
+//! * `on_prompt(text)`, when output stops partway through a line, as at a
+//!   prompt such as `password:`. Each partial line is given once, but it
+//!   may be the start of a line still being written.
+//! * `on_command_finished(exit_code)`, when shell integration reports
+//!   that a command finished. The exit code is `()` if it was not given.
+//!
+//! From these, a script can call `send(text)` to write to the running
+//! program, and `mark_line()` to tag the line with `CellClass::Marker`.
+
+use rhai::{Dynamic, Engine, Scope, AST};
+use std::{fmt, mem, sync::{Arc, Mutex}};
+
+/// The most operations a script may run at once, so that a script stuck
+/// in a loop fails rather than hanging the terminal.
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// The deepest a script's function calls may nest.
+const MAX_CALL_LEVELS: usize = 32;
+
+/// A compiled script, with the state its top-level statements set up.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    /// What the script asked for during the current hook.
+    actions: Arc<Mutex<Vec<ScriptAction>>>,
+}
+
+/// Something a script asked the terminal to do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ScriptAction {
+    /// Write text to the running program.
+    Send(String),
+    /// Tag the line the hook is about.
+    MarkLine,
+}
+
+/// An error loading or running a script.
+#[derive(Debug)]
+pub enum ScriptError {
+    /// The script could not be parsed.
+    Compile(String),
+    /// The script failed while running.
+    Runtime(String),
+}
+
+impl Script {
+    /// Compiles a script and runs its top-level statements.
+    pub fn new(source: &str) -> Result<Self, ScriptError> {
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+
+        let send_actions = actions.clone();
+        engine.register_fn("send", move |text: &str| {
+            send_actions.lock().unwrap().push(ScriptAction::Send(text.to_owned()));
+        });
+        let mark_actions = actions.clone();
+        engine.register_fn("mark_line", move || {
+            mark_actions.lock().unwrap().push(ScriptAction::MarkLine);
+        });
+
+        let ast = engine.compile(source).map_err(|e| ScriptError::Compile(e.to_string()))?;
+        let mut scope = Scope::new();
+        engine.run_ast_with_scope(&mut scope, &ast).map_err(|e| ScriptError::Runtime(e.to_string()))?;
+
+        Ok(Script { engine, ast, scope, actions })
+    }
+
+    /// Calls `on_line`, if the script defines it.
+    pub(crate) fn on_line(&mut self, text: &str) -> Result<Vec<ScriptAction>, ScriptError> {
+        self.call("on_line", Dynamic::from(text.to_owned()))
+    }
+
+    /// Calls `on_prompt`, if the script defines it.
+    pub(crate) fn on_prompt(&mut self, text: &str) -> Result<Vec<ScriptAction>, ScriptError> {
+        self.call("on_prompt", Dynamic::from(text.to_owned()))
+    }
+
+    /// Calls `on_command_finished`, if the script defines it.
+    pub(crate) fn on_command_finished(&mut self, exit_code: Option<i32>) -> Result<Vec<ScriptAction>, ScriptError> {
+        let exit_code = exit_code.map_or(Dynamic::UNIT, |code| Dynamic::from(code as i64));
+        self.call("on_command_finished", exit_code)
+    }
+
+    /// Calls a hook taking one argument, returning what the script asked for.
+    fn call(&mut self, name: &str, argument: Dynamic) -> Result<Vec<ScriptAction>, ScriptError> {
+        let defined = self.ast.iter_functions().any(|f| f.name == name && f.params.len() == 1);
+        if !defined {
+            return Ok(Vec::new());
+        }
+
+        let result = self.engine.call_fn::<Dynamic>(&mut self.scope, &self.ast, name, (argument,));
+        let actions = mem::take(&mut *self.actions.lock().unwrap());
+
+        result.map(|_| actions).map_err(|e| ScriptError::Runtime(e.to_string()))
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Script").finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScriptError::Compile(ref e) => write!(fmt, "invalid script: {}", e),
+            ScriptError::Runtime(ref e) => write!(fmt, "script failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError { }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hooks_return_the_actions_asked_for() {
+        let mut script = Script::new(r#"
+            fn on_prompt(text) {
+                if text.contains("password:") {
+                    send("hunter2\n");
+                    mark_line();
+                }
+            }
+        "#).unwrap();
+
+        assert_eq!(Vec::<ScriptAction>::new(), script.on_prompt("hello").unwrap());
+        assert_eq!(vec![ScriptAction::Send("hunter2\n".to_owned()), ScriptAction::MarkLine],
+                   script.on_prompt("password:").unwrap());
+        assert_eq!(Vec::<ScriptAction>::new(), script.on_line("password:").unwrap());
+        assert_eq!(Vec::<ScriptAction>::new(), script.on_command_finished(Some(0)).unwrap());
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert!(matches!(Script::new("fn on_line("), Err(ScriptError::Compile(_))));
+
+        let mut script = Script::new("fn on_command_finished(code) { throw \"oops\"; }").unwrap();
+        assert!(matches!(script.on_command_finished(None), Err(ScriptError::Runtime(_))));
+    }
+
+    #[test]
+    fn stops_scripts_that_run_too_long() {
+        let mut script = Script::new("fn on_line(text) { loop { } }").unwrap();
+        assert!(matches!(script.on_line("hi"), Err(ScriptError::Runtime(_))));
+
+        let mut script = Script::new("fn deeper(n) { deeper(n + 1) } fn on_line(text) { deeper(0) }").unwrap();
+        assert!(matches!(script.on_line("hi"), Err(ScriptError::Runtime(_))));
+    }
+}
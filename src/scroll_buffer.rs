@@ -1,5 +1,28 @@
-use crate::{Color, TextSlice, Style};
-use std::{fmt, io};
+use crate::{CellClass, CellClasses, Color, Rect, TextSlice, Style};
+use crate::event::{EraseMode, ShellMark};
+use crate::search::{Search, SearchMatch, SearchState};
+#[allow(unused_imports)]
+use crate::std_prelude::*;
+use core::{fmt, mem, ops::Range, time::Duration};
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::time::Instant;
+// With std, the cache is thread safe, so the buffer can be read from several threads at once.
+#[cfg(not(feature = "std"))]
+use core::cell::OnceCell as TextCache;
+#[cfg(feature = "std")]
+use std::sync::OnceLock as TextCache;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Marks host lines in the text exports.
+const HOST_LINE_MARKER: &str = "[host] ";
+/// Replaced by the number of folded lines in fold placeholders.
+const FOLD_COUNT_PLACEHOLDER: &str = "{count}";
+/// How many lines past the scrollback limit a long write may add before
+/// they are evicted, so that evicting does not shift the lines for every row.
+const EVICTION_BATCH_LINES: usize = 256;
 
 /// A scrollable terminal.
 pub struct ScrollBuffer {
@@ -10,15 +33,335 @@ pub struct ScrollBuffer {
 
     /// The cursor location.
     cursor: Location,
+
+    /// The style of the last character written.
+    style: Style,
+
+    /// The cursor and style saved by DECSC.
+    saved_cursor: Option<(Location, Style)>,
+
+    /// When the buffer was created.
+    #[cfg(feature = "std")]
+    created_at: SystemTime,
+
+    /// Whether backspacing at the start of a line wraps to the previous line.
+    reverse_wrap: bool,
+
+    /// The number of lines that have been evicted from the top of the scrollback.
+    /// Used to give each line a stable id.
+    evicted_line_count: usize,
+
+    /// Ranges of cells tagged with semantic classes.
+    class_ranges: Vec<ClassRange>,
+
+    /// The interactive search.
+    pub(crate) search: SearchState,
+
+    /// Folds over ranges of lines, ordered by their first line.
+    folds: Vec<Fold>,
+
+    /// The text of fold placeholder lines, with `{count}` standing for the
+    /// number of folded lines.
+    fold_placeholder: String,
+
+    /// The number of lines at the top of the buffer that have not been
+    /// rewrapped to the current width yet.
+    pending_reflow_lines: usize,
+
+    /// The number of lines that needed rewrapping after the last resize.
+    reflow_line_count: usize,
+
+    /// The visible lines that scroll, set by DECSTBM, or `None` for the whole grid.
+    scroll_region: Option<Range<usize>>,
+
+    /// Evicted lines kept so that new lines can reuse their cells rather than
+    /// allocating, which would fragment the heap over a long session.
+    spare_lines: Vec<Line>,
+
+    /// The commands marked by shell integration, oldest first.
+    command_marks: Vec<CommandMarks>,
+}
+
+/// Where shell integration marked the parts of a command.
+#[derive(Clone, Debug, Default)]
+struct CommandMarks {
+    prompt: Option<Mark>,
+    command: Option<Mark>,
+    output: Option<Mark>,
+    end: Option<Mark>,
+    exit_code: Option<i32>,
+    /// When the command started running.
+    #[cfg(feature = "std")]
+    started_at: Option<Instant>,
+    /// How long the command ran, once it finished.
+    duration: Option<Duration>,
+}
+
+/// A cell in the buffer that shell integration marked.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Mark {
+    line_id: usize,
+    column: usize,
+}
+
+/// How far rewrapping the scrollback has got after a resize.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ReflowProgress {
+    /// The number of old lines still to be rewrapped.
+    pub remaining_lines: usize,
+    /// The number of old lines that needed rewrapping.
+    pub total_lines: usize,
+}
+
+/// Where the lines of a batch ended up after rewrapping.
+struct Rewrap {
+    /// The indices of the old lines.
+    old: Range<usize>,
+    /// The number of new lines they became.
+    new_len: usize,
+    /// Where each old line went, in order.
+    lines: Vec<RewrappedLine>,
+}
+
+/// Where an old line went after rewrapping.
+#[derive(Copy, Clone, Debug)]
+struct RewrappedLine {
+    /// The new index of the first line of its logical line.
+    first_index: usize,
+    /// The number of cells before it in its logical line.
+    offset: usize,
+    /// The new index of the last line of its logical line.
+    last_index: usize,
+}
+
+/// A range of cells on a single line tagged with a class.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ClassRange {
+    class: CellClass,
+    line_id: usize,
+    columns: Range<usize>,
+    /// The target of a hyperlink set by the running program.
+    uri: Option<String>,
+}
+
+/// A hyperlink in the buffer.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Link {
+    /// The target of the link.
+    pub uri: String,
+    /// Where the link came from.
+    pub source: LinkSource,
+    /// The id of the line the link is on.
+    pub line_id: usize,
+    /// The columns of the link on its line.
+    pub columns: Range<usize>,
+}
+
+/// Where a hyperlink came from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LinkSource {
+    /// The running program marked it with OSC 8, so the text shown may
+    /// not match the target.
+    Program,
+    /// It was detected as a URL in the text.
+    Detected,
+}
+
+/// A range of lines that can be collapsed into a single placeholder line,
+/// such as the output of a long command.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fold {
+    /// The ids of the folded lines, as given by `ScrollBuffer::visible_line_id`.
+    pub lines: Range<usize>,
+    /// Whether the lines are hidden behind the placeholder.
+    pub collapsed: bool,
+    /// The line shown in place of the folded lines.
+    placeholder: Line,
+}
+
+/// A line as it is shown: either a line in the buffer, or the placeholder
+/// of a collapsed fold.
+#[derive(Copy, Clone, Debug)]
+struct Row<'a> {
+    /// The id of the line, or of the first folded line.
+    id: usize,
+    line: &'a Line,
+    folded: bool,
 }
 
 /// A constant-width line in the buffer.
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug)]
 struct Line {
     /// The cells in the line.
     /// All lines within a buffer will be the same length. Unused
     /// cells should be space-padded.
     pub cells: Vec<Cell>,
+    /// When the first character arrived on the line.
+    /// Only recorded if timestamps are enabled in the settings.
+    #[cfg(feature = "std")]
+    pub timestamp: Option<SystemTime>,
+    /// Whether the text on this line continues onto the next line
+    /// because it reached the right margin.
+    pub wrapped: bool,
+    /// Where the line came from.
+    pub origin: LineOrigin,
+    /// Whether the line is included in searches.
+    pub searchable: bool,
+    /// Whether a shell prompt starts on the line.
+    pub prompt_start: bool,
+    /// The characters of the line, built the first time they are needed.
+    /// Cleared whenever the cells are mutated through `cells_mut`.
+    text: TextCache<String>,
+}
+
+/// Metadata about a line in the buffer.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LineMetadata {
+    /// When the first character arrived on the line, if recorded.
+    #[cfg(feature = "std")]
+    pub timestamp: Option<SystemTime>,
+    /// Whether the line continues onto the next line.
+    pub wrapped: bool,
+    /// Where the line came from.
+    pub origin: LineOrigin,
+    /// Whether the line is included in searches.
+    ///
+    /// Frontends should also leave unsearchable lines out of selections.
+    pub searchable: bool,
+    /// Whether a shell prompt starts on the line, as marked by shell integration.
+    pub prompt_start: bool,
+}
+
+/// A line in the buffer, as yielded by `ScrollBuffer::iter_lines`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineView<'a> {
+    /// The id of the line, as given by `ScrollBuffer::visible_line_id`.
+    pub id: usize,
+    /// The cells of the line.
+    ///
+    /// Lines still waiting to be rewrapped may be wider than the grid.
+    pub cells: &'a [Cell],
+    /// The metadata of the line.
+    pub metadata: LineMetadata,
+}
+
+/// A command run at a shell prompt, as marked by shell integration (OSC 133).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShellCommand {
+    /// The id of the line the prompt starts on, if the shell marked it.
+    pub prompt_line_id: Option<usize>,
+    /// The command the user entered, without trailing whitespace.
+    pub command: String,
+    /// Everything the command wrote, so far if it is still running.
+    pub output: String,
+    /// The ids of the lines the output is on.
+    pub output_line_ids: Range<usize>,
+    /// Whether the shell reported that the command finished.
+    pub finished: bool,
+    /// The exit code, if the shell reported one.
+    pub exit_code: Option<i32>,
+    /// How long the command ran, from when its output started until the
+    /// shell reported it finished.
+    ///
+    /// This is only measured with the `std` feature.
+    pub duration: Option<Duration>,
+}
+
+/// A line encoded compactly, as its text and the runs of cells that share
+/// a style, such as to send to a remote client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncodedLine {
+    /// The id of the line.
+    pub id: usize,
+    /// The characters of the line, one per cell. Concealed characters are blanked.
+    pub text: String,
+    /// The styles of the cells, in order.
+    pub runs: Vec<StyleRun>,
+    /// Whether the line continues onto the next line.
+    pub wrapped: bool,
+}
+
+/// A run of cells with the same style.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StyleRun {
+    /// The number of cells in the run.
+    pub length: usize,
+    pub style: Style,
+}
+
+/// The selected text, as copied by `ScrollBuffer::copy_selection`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CopyResult {
+    /// The text, with a newline between lines that did not wrap.
+    pub text: String,
+    /// The same text split wherever the style changes, for copying with formatting.
+    pub styled: Vec<TextSlice>,
+    /// The ids of the lines the text was copied from.
+    pub source_line_ids: Range<usize>,
+    /// When the first character arrived on the first line, if recorded.
+    #[cfg(feature = "std")]
+    pub timestamp: Option<SystemTime>,
+}
+
+/// A position in the text that does not depend on how it is wrapped: a
+/// column in the logical line whose first line is `line_id`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LogicalPosition {
+    pub line_id: usize,
+    pub column: usize,
+}
+
+/// A position in the text as it is shown when wrapped to some width,
+/// counting rows from the oldest line in the buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VisualPosition {
+    pub row: usize,
+    pub column: usize,
+}
+
+/// Where a cell is, as given to the hook of `ScrollBuffer::visible_slices_styled`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CellContext {
+    /// The id of the line, as given by `ScrollBuffer::visible_line_id`.
+    pub line_id: usize,
+    /// The column of the cell.
+    pub column: usize,
+    /// The semantic classes of the cell.
+    pub classes: CellClasses,
+}
+
+/// Where a line in the buffer came from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LineOrigin {
+    /// Output from the running program, or local echo.
+    Program,
+    /// A message added by the host with `ScrollBuffer::append_external_line`.
+    Host,
+    /// The placeholder of a collapsed fold.
+    Fold,
+}
+
+/// Options for `ScrollBuffer::visible_slices_with`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SliceOptions {
+    /// The classes whose boundaries split slices. Other classes are left
+    /// out of the slices, so that runs of the same style stay together.
+    pub split_classes: CellClasses,
+    /// Whether concealed cells show their real characters rather than
+    /// blanks, such as for debugging.
+    pub reveal_concealed: bool,
+}
+
+/// How line timestamps are rendered in front of each line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimestampGutter {
+    /// Do not render a timestamp gutter.
+    Hidden,
+    /// Render the UTC wall-clock time, `[HH:MM:SS] `.
+    Clock,
+    /// Render the time since the buffer was created, `[+HH:MM:SS] `.
+    Elapsed,
 }
 
 /// A cell in the grid.
@@ -30,6 +373,57 @@ pub struct Cell {
     pub style: Style,
 }
 
+/// An occurrence of a query on the visible grid.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextMatch {
+    /// The zero-based line number relative to the top of the visible area.
+    pub line_number: usize,
+    /// The range of matched cells within the line.
+    pub columns: Range<usize>,
+}
+
+/// The approximate memory used by a buffer, in bytes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MemoryFootprint {
+    /// Memory used by the cells of every line.
+    pub cells: usize,
+    /// Memory used by per-line bookkeeping, such as timestamps.
+    pub metadata: usize,
+    /// Memory held by evicted lines that are kept for reuse.
+    pub spare: usize,
+}
+
+/// The grid shrunk to a few blocks, for tab previews and minimaps.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thumbnail {
+    /// The number of blocks across.
+    pub columns: usize,
+    /// The number of blocks down.
+    pub rows: usize,
+    /// The blocks, a row at a time from the top left.
+    pub blocks: Vec<ThumbnailBlock>,
+}
+
+/// A rectangle of cells in a `Thumbnail`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ThumbnailBlock {
+    /// The most common text color among the block's non-blank cells, or
+    /// `None` if they are all blank.
+    pub color: Option<Color>,
+    /// The most common background color among the block's cells.
+    pub background: Color,
+    /// The fraction of the block's cells that are not blank, from 0 to 1.
+    pub density: f32,
+}
+
+/// An error from a grid operation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GridError {
+    /// The coordinates are outside of the visible grid.
+    OutOfBounds { x: usize, y: usize },
+}
+
 /// Scroll buffer settings.
 pub struct Settings {
     /// The maximum number of columns that can be displayed at once.
@@ -40,6 +434,11 @@ pub struct Settings {
     pub tab_width: usize,
     /// The number of lines to keep in the history.
     pub lines_to_remember: usize,
+    /// Whether to record the arrival time of each line.
+    /// Ignored without the `std` feature, as there is no clock.
+    pub record_timestamps: bool,
+    /// How to render timestamps in the slice and text export paths.
+    pub timestamp_gutter: TimestampGutter,
 }
 
 /// A location relative to the top-left of the terminal.
@@ -58,21 +457,110 @@ impl ScrollBuffer {
             // Fill the buffer with a full viewport of space-only lines.
             lines: (0..settings.max_lines).into_iter().map(|_| Line::new(&settings)).collect(),
             cursor: Location::top_left(),
+            style: Style::default(),
+            saved_cursor: None,
+            #[cfg(feature = "std")]
+            created_at: SystemTime::now(),
+            reverse_wrap: false,
+            evicted_line_count: 0,
+            class_ranges: Vec::new(),
+            search: SearchState::default(),
+            folds: Vec::new(),
+            fold_placeholder: format!("[{} lines folded]", FOLD_COUNT_PLACEHOLDER),
+            pending_reflow_lines: 0,
+            reflow_line_count: 0,
+            scroll_region: None,
+            spare_lines: Vec::new(),
+            command_marks: Vec::new(),
             settings,
         }
     }
 
     /// Writes a string.
     pub fn put_str(&mut self, s: &str) {
-        for c in s.chars() {
-            self.put_character(c);
+        self.put_str_styled(s, Style::default())
+    }
+
+    /// Writes a string in a single style.
+    ///
+    /// Runs of printable characters are written a row at a time, so that
+    /// even a single very long line takes linear time.
+    pub fn put_str_styled(&mut self, s: &str, style: Style) {
+        self.style = style.clone();
+        let mut rest = s;
+
+        while !rest.is_empty() {
+            let run_length = rest.find(['\n', '\r', '\t']).unwrap_or(rest.len());
+            let (run, remainder) = rest.split_at(run_length);
+            self.put_run(run, &style);
+
+            let mut chars = remainder.chars();
+            if let Some(character) = chars.next() {
+                self.put_character_styled(character, style.clone());
+            }
+            rest = chars.as_str();
+        }
+
+        self.evict_excess_lines();
+    }
+
+    /// Writes a run of printable characters, filling a row at a time.
+    fn put_run(&mut self, run: &str, style: &Style) {
+        let record_timestamps = self.settings.record_timestamps;
+        let mut chars = run.chars().peekable();
+
+        while chars.peek().is_some() {
+            if self.lines_over_limit() >= EVICTION_BATCH_LINES {
+                self.evict_excess_lines();
+            }
+            self.wrap_full_line();
+
+            let Location { line_number, column_number } = self.cursor;
+            let mut written = 0;
+
+            if let Some(line) = self.line_at_mut(line_number) {
+                let cells = line.cells_mut().iter_mut().skip(column_number);
+
+                for (cell, character) in cells.zip(&mut chars) {
+                    *cell = Cell { character, style: style.clone() };
+                    written += 1;
+                }
+
+                if record_timestamps {
+                    line.record_timestamp();
+                }
+            }
+
+            // Like a single character, one with no cell to go in is dropped.
+            if written == 0 {
+                chars.next();
+                written = 1;
+            }
+            self.cursor.column_number += written;
         }
     }
 
     /// Backspaces the last character.
+    ///
+    /// At the start of a line, the cursor only moves back onto the previous
+    /// line if reverse wrapping is enabled and that line was soft-wrapped.
     pub fn backspace(&mut self) {
         match self.cursor.column_number {
-            0 => (),
+            0 => {
+                let line_number = self.cursor.line_number;
+
+                let previous_line_wrapped = line_number > 0 &&
+                    self.line_at(line_number - 1).map(|line| line.wrapped).unwrap_or(false);
+
+                if self.reverse_wrap && previous_line_wrapped {
+                    self.set_wrapped(line_number - 1, false);
+                    self.cursor = Location {
+                        line_number: line_number - 1,
+                        column_number: self.settings.max_columns,
+                    };
+                    self.backspace();
+                }
+            },
             _ => {
                 self.cursor.column_number -= 1;
                 self.put_character(' ');
@@ -83,7 +571,16 @@ impl ScrollBuffer {
 
     /// Clears the entire buffer, including scrollback.
     pub fn clear_everything(&mut self) {
-        self.lines.clear();
+        self.evicted_line_count += self.lines.len();
+        let old_lines = mem::take(&mut self.lines);
+        self.recycle_lines(old_lines);
+        while self.lines.len() < self.settings.max_lines {
+            self.add_new_whitespace_line();
+        }
+        self.class_ranges.clear();
+        self.folds.clear();
+        self.command_marks.clear();
+        self.pending_reflow_lines = 0;
         self.reset_cursor();
     }
 
@@ -92,24 +589,417 @@ impl ScrollBuffer {
         let visible_lines = self.first_visible_line_index_no_scroll()..;
 
         for line in self.lines[visible_lines].iter_mut() {
-            *line = Line::new(&self.settings);
+            line.reset(&self.settings);
+        }
+    }
+
+    /// Changes the size of the visible grid.
+    ///
+    /// Changing the width rewraps lines, joining lines that were wrapped and
+    /// wrapping lines that no longer fit. Only the lines at the bottom, which
+    /// fill the grid, are rewrapped straight away; the rest of the scrollback
+    /// is rewrapped, newest first, by `continue_reflow`.
+    ///
+    /// Growing pulls lines back out of the scrollback; shrinking first drops
+    /// blank lines below the cursor, and then pushes lines from the top into
+    /// the scrollback.
+    pub fn resize(&mut self, columns: usize, lines: usize) {
+        let cursor_index = self.first_visible_line_index_no_scroll() + self.cursor.line_number;
+
+        // Drop blank lines below the cursor rather than scrolling.
+        let mut excess_lines = self.settings.max_lines.saturating_sub(lines);
+        while excess_lines > 0 && self.lines.len() > cursor_index + 1 &&
+            self.lines.last().is_some_and(Line::is_blank) {
+            self.lines.pop();
+            excess_lines -= 1;
+        }
+
+        if columns != self.settings.max_columns {
+            self.pending_reflow_lines = self.lines.len();
+            self.reflow_line_count = self.lines.len();
+        }
+        self.settings.max_columns = columns;
+        self.settings.max_lines = lines;
+        self.scroll_region = None;
+
+        self.pending_reflow_lines = self.pending_reflow_lines.min(self.lines.len());
+        let cursor = (cursor_index, self.cursor.column_number);
+        let (cursor_index, cursor_column) = self.reflow_bottom_lines(lines, cursor);
+
+        while self.lines.len() < lines {
+            self.add_new_whitespace_line();
+        }
+
+        self.cursor = Location {
+            line_number: cursor_index.saturating_sub(self.first_visible_line_index_no_scroll()),
+            column_number: cursor_column.min(columns),
+        };
+
+        let end_line_id = self.evicted_line_count + self.lines.len();
+        self.class_ranges.retain(|range| range.line_id < end_line_id);
+        for fold in self.folds.iter_mut() {
+            fold.lines.end = fold.lines.end.min(end_line_id);
+        }
+        self.folds.retain(|fold| !fold.lines.is_empty());
+        self.evict_excess_lines();
+        self.update_fold_placeholders();
+    }
+
+    /// Rewraps the next batch of scrollback lines after a resize, newest
+    /// first, so that the lines the user is likely to scroll to are ready first.
+    ///
+    /// At least `max_lines` old lines are rewrapped, unless fewer remain, and
+    /// always whole logical lines at a time. Returns `None` once there is
+    /// nothing left to rewrap.
+    pub fn continue_reflow(&mut self, max_lines: usize) -> Option<ReflowProgress> {
+        if self.pending_reflow_lines == 0 {
+            return None;
+        }
+
+        let end = self.pending_reflow_lines;
+        let mut start = end;
+        while start > 0 && end - start < max_lines {
+            start = self.logical_line_start(start - 1);
+        }
+
+        self.rewrap_lines(start..end, None);
+        self.pending_reflow_lines = start;
+        self.evict_excess_lines();
+
+        Some(self.reflow_progress())
+    }
+
+    /// Gets how far rewrapping the scrollback has got after a resize.
+    pub fn reflow_progress(&self) -> ReflowProgress {
+        ReflowProgress {
+            remaining_lines: self.pending_reflow_lines,
+            total_lines: self.reflow_line_count,
+        }
+    }
+
+    /// Rewraps enough of the newest lines to fill the given number of lines,
+    /// returning where the cursor ends up.
+    fn reflow_bottom_lines(&mut self, line_count: usize, cursor: (usize, usize)) -> (usize, usize) {
+        let end = self.pending_reflow_lines;
+        let mut start = end;
+        let mut new_line_count = self.lines.len() - end;
+
+        while start > 0 && new_line_count < line_count {
+            let group_start = self.logical_line_start(start - 1);
+            new_line_count += self.rewrapped_line_count(group_start..start, Some(cursor));
+            start = group_start;
+        }
+
+        if start == end {
+            return cursor;
+        }
+
+        let rewrap = self.rewrap_lines(start..end, Some(cursor));
+        self.pending_reflow_lines = start;
+        rewrap.map(cursor.0, cursor.1, self.settings.max_columns)
+    }
+
+    /// Gets the index of the first line of the logical line that a line is part of.
+    fn logical_line_start(&self, index: usize) -> usize {
+        let mut start = index;
+        while start > 0 && self.lines[start - 1].wrapped {
+            start -= 1;
+        }
+        start
+    }
+
+    /// Gets the number of cells in the last line of a logical line that
+    /// are kept when rewrapping: everything up to the last non-blank cell,
+    /// or the cursor if it is further along.
+    fn kept_cell_count(&self, index: usize, cursor: Option<(usize, usize)>) -> usize {
+        let cells = &self.lines[index].cells;
+        let content = cells.iter().rposition(|cell| *cell != Cell::default()).map_or(0, |i| i + 1);
+
+        match cursor {
+            Some((cursor_index, cursor_column)) if cursor_index == index => content.max(cursor_column.min(cells.len())),
+            _ => content,
+        }
+    }
+
+    /// Gets the number of lines a single logical line becomes at the current width.
+    fn rewrapped_line_count(&self, group: Range<usize>, cursor: Option<(usize, usize)>) -> usize {
+        wrapped_row_count(self.logical_cell_count(group, cursor), self.settings.max_columns)
+    }
+
+    /// Gets the number of cells in a logical line that are kept when rewrapping.
+    fn logical_cell_count(&self, group: Range<usize>, cursor: Option<(usize, usize)>) -> usize {
+        self.lines[group.start..group.end - 1].iter().map(|line| line.cells.len()).sum::<usize>()
+            + self.kept_cell_count(group.end - 1, cursor)
+    }
+
+    /// Iterates over the logical lines, oldest first, with the indices of
+    /// their lines and their lengths in cells.
+    fn logical_lines(&self) -> impl Iterator<Item = (Range<usize>, usize)> + '_ {
+        let mut start = 0;
+
+        core::iter::from_fn(move || {
+            if start >= self.lines.len() {
+                return None;
+            }
+
+            let mut end = start + 1;
+            while end < self.lines.len() && self.lines[end - 1].wrapped {
+                end += 1;
+            }
+
+            let group = start..end;
+            start = end;
+            Some((group.clone(), self.logical_cell_count(group, None)))
+        })
+    }
+
+    /// Rewraps a range of whole logical lines to the current width, and
+    /// updates everything that refers to lines by id.
+    fn rewrap_lines(&mut self, range: Range<usize>, cursor: Option<(usize, usize)>) -> Rewrap {
+        let columns = self.settings.max_columns.max(1);
+        let mut new_lines = Vec::with_capacity(range.len());
+        let mut rewrapped = Vec::with_capacity(range.len());
+        let mut start = range.start;
+
+        while start < range.end {
+            let mut end = start + 1;
+            while end < range.end && self.lines[end - 1].wrapped {
+                end += 1;
+            }
+
+            let group = &self.lines[start..end];
+            let kept_cell_count = self.kept_cell_count(end - 1, cursor);
+            let cells: Vec<Cell> = group[..group.len() - 1].iter()
+                .flat_map(|line| line.cells.iter().cloned())
+                .chain(group[group.len() - 1].cells[..kept_cell_count].iter().cloned())
+                .collect();
+
+            let first_index = range.start + new_lines.len();
+            let line_count = self.rewrapped_line_count(start..end, cursor);
+            let last_index = first_index + line_count - 1;
+
+            let mut offset = 0;
+            for line in group {
+                rewrapped.push(RewrappedLine { first_index, offset, last_index });
+                offset += line.cells.len();
+            }
+
+            for line_offset in 0..line_count {
+                let mut line = Line::new(&self.settings);
+                line.origin = group[0].origin;
+                line.searchable = group[0].searchable;
+                line.prompt_start = group[0].prompt_start && line_offset == 0;
+                line.wrapped = line_offset + 1 < line_count;
+                #[cfg(feature = "std")]
+                { line.timestamp = group[0].timestamp; }
+
+                let line_cells = cells.iter().skip(line_offset * columns).take(columns);
+                for (cell, new_cell) in line.cells_mut().iter_mut().zip(line_cells) {
+                    *cell = new_cell.clone();
+                }
+                new_lines.push(line);
+            }
+
+            start = end;
+        }
+
+        let rewrap = Rewrap { old: range.clone(), new_len: new_lines.len(), lines: rewrapped };
+        self.lines.splice(range, new_lines);
+        self.apply_rewrap(&rewrap);
+        rewrap
+    }
+
+    /// Moves class ranges, folds and search matches to where their lines
+    /// ended up after rewrapping.
+    fn apply_rewrap(&mut self, rewrap: &Rewrap) {
+        let columns = self.settings.max_columns;
+        let evicted_line_count = self.evicted_line_count;
+        let ranges = mem::take(&mut self.class_ranges);
+
+        for range in ranges {
+            let index = match range.line_id.checked_sub(evicted_line_count) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            // A range that now crosses a wrap is split across the lines.
+            let (start_index, start_column) = rewrap.map(index, range.columns.start, columns);
+            let (end_index, end_column) = rewrap.map(index, range.columns.end, columns);
+            for new_index in start_index..=end_index {
+                let start = if new_index == start_index { start_column } else { 0 };
+                let end = if new_index == end_index { end_column } else { columns };
+
+                if start < end {
+                    self.class_ranges.push(ClassRange {
+                        class: range.class,
+                        line_id: evicted_line_count + new_index,
+                        columns: start..end,
+                        uri: range.uri.clone(),
+                    });
+                }
+            }
+        }
+
+        for marks in self.command_marks.iter_mut() {
+            for mark in marks.iter_mut() {
+                let index = match mark.line_id.checked_sub(evicted_line_count) {
+                    Some(index) => index,
+                    None => continue,
+                };
+                let (new_index, column) = rewrap.map(index, mark.column, columns);
+                *mark = Mark { line_id: evicted_line_count + new_index, column };
+            }
+        }
+
+        for fold in self.folds.iter_mut() {
+            let start = rewrap.map(fold.lines.start - evicted_line_count, 0, columns).0;
+            let end = rewrap.map_end(fold.lines.end - evicted_line_count);
+            fold.lines = evicted_line_count + start..evicted_line_count + end;
+        }
+        self.update_fold_placeholders();
+
+        if self.search.is_active() {
+            self.search().refresh();
+        }
+    }
+
+    /// Copies a rectangular area of the visible grid so that its top-left
+    /// corner lands at `(x, y)`. Overlapping areas are handled correctly.
+    pub fn copy_rect(&mut self, source: Rect, x: usize, y: usize) {
+        let source = self.clip_rect(source);
+        let copied: Vec<Vec<Cell>> = (source.top..source.bottom).map(|line_number| {
+            let index = self.first_visible_line_index_no_scroll() + line_number;
+            self.lines[index].cells[source.left..source.right].to_vec()
+        }).collect();
+
+        for (line_offset, cells) in copied.into_iter().enumerate() {
+            let line_number = y + line_offset;
+            if line_number >= self.settings.max_lines { break; }
+
+            for (column_offset, cell) in cells.into_iter().enumerate() {
+                if let Some(destination) = self.cell_mut(x + column_offset, line_number) {
+                    *destination = cell;
+                }
+            }
+        }
+    }
+
+    /// Fills a rectangular area of the visible grid with a character.
+    pub fn fill_rect(&mut self, area: Rect, character: char, style: Style) {
+        let area = self.clip_rect(area);
+
+        for line_number in area.top..area.bottom {
+            for column_number in area.left..area.right {
+                if let Some(cell) = self.cell_mut(column_number, line_number) {
+                    *cell = Cell { character, style: style.clone() };
+                }
+            }
+        }
+    }
+
+    /// Erases a rectangular area of the visible grid.
+    pub fn erase_rect(&mut self, area: Rect) {
+        self.erase_rect_with_background(area, Color::TRANSPARENT);
+    }
+
+    /// Erases a rectangular area, filling it with a background color.
+    pub fn erase_rect_with_background(&mut self, area: Rect, background: Color) {
+        self.fill_rect(area, ' ', Style { background, ..Style::default() });
+    }
+
+    /// Erases part of the cursor line, as with EL, filling it with a background color.
+    ///
+    /// `EraseMode::Scrollback` does nothing here.
+    pub fn erase_in_line(&mut self, mode: EraseMode, background: Color) {
+        let Location { line_number, column_number } = self.cursor;
+        let columns = match mode {
+            EraseMode::ToEnd => column_number..usize::MAX,
+            EraseMode::ToStart => 0..column_number + 1,
+            EraseMode::All => 0..usize::MAX,
+            EraseMode::Scrollback => return,
+        };
+
+        let area = Rect { left: columns.start, top: line_number, right: columns.end, bottom: line_number + 1 };
+        self.erase_rect_with_background(area, background);
+    }
+
+    /// Erases part of the display, as with ED, filling it with a background color.
+    pub fn erase_in_display(&mut self, mode: EraseMode, background: Color) {
+        let line_number = self.cursor.line_number;
+
+        match mode {
+            EraseMode::ToEnd => {
+                self.erase_in_line(mode, background);
+                self.erase_rect_with_background(Rect { left: 0, top: line_number + 1, right: usize::MAX, bottom: usize::MAX }, background);
+            },
+            EraseMode::ToStart => {
+                self.erase_rect_with_background(Rect { left: 0, top: 0, right: usize::MAX, bottom: line_number }, background);
+                self.erase_in_line(mode, background);
+            },
+            EraseMode::All => {
+                self.clear_visible();
+                if background != Color::TRANSPARENT {
+                    self.erase_rect_with_background(Rect { left: 0, top: 0, right: usize::MAX, bottom: usize::MAX }, background);
+                }
+            },
+            EraseMode::Scrollback => {
+                self.evict_scrollback(self.lines_in_scroll_buffer());
+            },
         }
     }
 
+    /// Sets whether backspacing at the start of a line wraps to the previous line.
+    pub fn set_reverse_wrap(&mut self, reverse_wrap: bool) {
+        self.reverse_wrap = reverse_wrap;
+    }
+
     /// Resets the cursor back to (0,0).
     pub fn reset_cursor(&mut self) {
         self.cursor = Location::top_left();
     }
 
     /// Sets the cursor from xy coordinates relative to the top-left corner.
+    ///
+    /// Coordinates outside of the grid are clamped to its edges.
     pub fn set_cursor_xy(&mut self, x: usize, y: usize) {
-        self.cursor = Location { line_number: y, column_number: x };
+        self.cursor = Location {
+            line_number: y.min(self.settings.max_lines.saturating_sub(1)),
+            column_number: x.min(self.settings.max_columns),
+        };
     }
 
     pub fn cursor_xy(&self) -> (usize, usize) {
         (self.cursor.column_number, self.cursor.line_number)
     }
 
+    /// Gets the style of the last character written.
+    pub fn current_style(&self) -> &Style {
+        &self.style
+    }
+
+    /// Saves the cursor and the current style, as with DECSC.
+    pub fn save_cursor(&mut self) {
+        self.saved_cursor = Some((self.cursor, self.style.clone()));
+    }
+
+    /// Restores the cursor and style saved by `save_cursor`, as with DECRC.
+    ///
+    /// Without a saved cursor, the cursor goes to the top left with the
+    /// default style.
+    pub fn restore_cursor(&mut self) {
+        let (cursor, style) = self.saved_cursor.clone().unwrap_or((Location::top_left(), Style::default()));
+        self.set_cursor_xy(cursor.column_number, cursor.line_number);
+        self.style = style;
+    }
+
+    /// Forgets the style, the saved cursor and the scroll region, as with a
+    /// soft reset (DECSTR). The cursor and the text stay where they are.
+    pub fn soft_reset(&mut self) {
+        self.style = Style::default();
+        self.saved_cursor = None;
+        self.scroll_region = None;
+    }
+
     /// Places a character into the bufer at the cursor.
     pub fn put_character(&mut self, c: char) {
         self.put_character_styled(c, Style::default())
@@ -117,22 +1007,13 @@ impl ScrollBuffer {
 
     /// Places a character into the bufer at the cursor.
     pub fn put_character_styled(&mut self, character: char, style: Style) {
-        // Remove the oldest line if we've hit the scrollback limit.
-        if self.lines_in_scroll_buffer() > self.settings.lines_to_remember {
-            self.lines.remove(0);
-        }
+        self.evict_excess_lines();
+        self.style = style.clone();
 
         match character {
             '\n' => {
                 self.cursor.carriage_return();
-
-                // Add a new line if we're reached the end of our buffer.
-                if self.cursor.line_number == Location::eof(&self.settings).line_number {
-                    self.add_new_whitespace_line();
-                } else {
-                    // Only advance the cursor line if we aren't already at the end.
-                    self.cursor.line_feed();
-                }
+                self.advance_line();
             },
             '\r' => {
                 self.cursor.carriage_return();
@@ -143,174 +1024,1631 @@ impl ScrollBuffer {
                 }
             },
             _ => {
-                // Attempt to advance the cursor.
-                // An error occurs if the end was reached.
-                // In this case, add a new line and set the column back to zero.
-                // No need to increment line number because the location is always relative
-                // to the top left, and the cursor is already on the last line.
-                if self.cursor.is_eof(&self.settings) {
-                    self.add_new_whitespace_line();
-                    self.cursor.carriage_return();
-                } else if self.cursor.column_number >= self.settings.max_columns {
-                    self.cursor.carriage_return().line_feed();
-                }
+                self.wrap_full_line();
 
                 let Location { line_number, column_number } = self.cursor;
+                let record_timestamps = self.settings.record_timestamps;
 
                 // Replace the old character.
-                self.line_at(line_number).cells[column_number] = Cell {
-                    character, style,
-                };
+                if let Some(line) = self.line_at_mut(line_number) {
+                    if let Some(cell) = line.cells_mut().get_mut(column_number) {
+                        *cell = Cell { character, style };
+                    }
+
+                    if record_timestamps {
+                        line.record_timestamp();
+                    }
+                }
                 self.cursor.column_number += 1;
             },
         }
     }
 
-    /// The line will always be at same size as the buffer width,
-    fn line_at(&mut self, line_number: usize) -> &mut Line {
-        let index = self.first_visible_line_index_no_scroll() + line_number;
+    /// Gets the column that putting a character at `column` leaves the
+    /// cursor at, as `put_character` moves it.
+    pub fn column_after(&self, column: usize, character: char) -> usize {
+        match character {
+            '\n' | '\r' => 0,
+            '\t' => (0..self.settings.tab_width).fold(column, |column, _| self.column_after(column, ' ')),
+            // Wraps onto the next line first.
+            _ if column >= self.settings.max_columns => 1,
+            _ => column + 1,
+        }
+    }
 
-        let line = self.lines.get_mut(index).unwrap();
-        assert_eq!(line.cells.len(), self.settings.max_columns, "line too big");
-        line
+    /// Moves the cursor onto the next line if the current one is full,
+    /// soft-wrapping it.
+    fn wrap_full_line(&mut self) {
+        if self.cursor.column_number >= self.settings.max_columns {
+            self.set_wrapped(self.cursor.line_number, true);
+            self.cursor.carriage_return();
+            self.advance_line();
+        }
     }
 
-    /// Gets the text visible at a specified scrollback.
-    fn visible_lines(&self, scrollback_line_count: usize) -> &[Line] {
-        let first_index = self.first_visible_line_index(scrollback_line_count);
+    /// Moves the cursor down a line.
+    ///
+    /// At the bottom of the scroll region, the region scrolls up instead.
+    /// Without a region, a new line is added at the bottom of the grid, so
+    /// the top line goes into the scrollback. The cursor line number is
+    /// always relative to the top left, so it stays the same when scrolling.
+    fn advance_line(&mut self) {
+        let line_number = self.cursor.line_number;
+        let region_end = self.scroll_region.as_ref().map(|region| region.end);
+
+        match region_end {
+            Some(end) if line_number + 1 == end => self.scroll_region_up(1),
+            _ if line_number + 1 < self.settings.max_lines => {
+                self.cursor.line_feed();
+            },
+            // Below the scroll region, the bottom line does not scroll.
+            Some(_) => (),
+            None => self.add_new_whitespace_line(),
+        }
+    }
 
-        &self.lines[first_index..first_index + self.settings.max_lines]
+    /// Inserts blank characters at the cursor, shifting the rest of the line
+    /// right. Characters shifted past the end of the line are lost.
+    pub fn insert_chars(&mut self, count: usize) {
+        let Location { line_number, column_number } = self.cursor;
+
+        if let Some(line) = self.line_at_mut(line_number) {
+            let start = column_number.min(line.cells.len());
+            let cells = &mut line.cells_mut()[start..];
+            let count = count.min(cells.len());
+            cells.rotate_right(count);
+            for cell in cells[..count].iter_mut() {
+                *cell = Cell::default();
+            }
+        }
     }
 
-    /// Gets the text visible at a specified scrollback.
-    pub fn visible_cells(&self, scrollback_line_count: usize) -> Vec<Vec<Cell>> {
-        self.visible_lines(scrollback_line_count).iter().map(|line| line.cells.clone()).collect()
+    /// Deletes characters at the cursor, shifting the rest of the line left
+    /// and adding blanks at its end.
+    pub fn delete_chars(&mut self, count: usize) {
+        let Location { line_number, column_number } = self.cursor;
+
+        if let Some(line) = self.line_at_mut(line_number) {
+            let start = column_number.min(line.cells.len());
+            let cells = &mut line.cells_mut()[start..];
+            let count = count.min(cells.len());
+            cells.rotate_left(count);
+            let first_blank = cells.len() - count;
+            for cell in cells[first_blank..].iter_mut() {
+                *cell = Cell::default();
+            }
+        }
     }
 
-    /// Gets the visible slices.
-    pub fn visible_slices(&self, scrollback_line_count: usize) -> Vec<TextSlice> {
-        let mut slices = Vec::new();
+    /// Inserts blank lines at the cursor line, shifting the lines below it
+    /// down within the scroll region. Nothing happens outside of the region.
+    pub fn insert_lines(&mut self, count: usize) {
+        if let Some(lines) = self.lines_below_cursor_in_region() {
+            self.scroll_lines_down(lines, count);
+            self.cursor.carriage_return();
+        }
+    }
 
-        for line_cells in self.visible_cells(scrollback_line_count) {
-            let mut remaining_cells = &line_cells[..];
+    /// Deletes lines at the cursor line, shifting the lines below it up
+    /// within the scroll region. Nothing happens outside of the region.
+    pub fn delete_lines(&mut self, count: usize) {
+        if let Some(lines) = self.lines_below_cursor_in_region() {
+            self.scroll_lines_up(lines, count);
+            self.cursor.carriage_return();
+        }
+    }
 
-            while !remaining_cells.is_empty() {
-                let next_style = remaining_cells[0].style.clone();
-                let same_style_count = remaining_cells.iter().take_while(|c| c.style == next_style).count();
+    /// Gets the lines from the cursor to the bottom of the scroll region,
+    /// if the cursor is in it.
+    fn lines_below_cursor_in_region(&self) -> Option<Range<usize>> {
+        let region = self.scroll_region();
+        let line_number = self.cursor.line_number;
 
-                let slice_text = remaining_cells[0..same_style_count].iter().map(|c| c.character).collect();
-                remaining_cells = &remaining_cells[same_style_count..];
+        if region.contains(&line_number) { Some(line_number..region.end) } else { None }
+    }
 
-                slices.push(TextSlice {
-                    text: slice_text,
-                    style: next_style,
-                });
-            }
+    /// Sets the visible lines that scroll, as with DECSTBM.
+    ///
+    /// A region covering the whole grid, or with fewer than two lines,
+    /// resets scrolling to the whole grid.
+    pub fn set_scroll_region(&mut self, lines: Range<usize>) {
+        let end = lines.end.min(self.settings.max_lines);
+        let is_partial = lines.start + 1 < end && (lines.start, end) != (0, self.settings.max_lines);
 
-            slices.push(TextSlice {
-                text: "\n".to_owned(),
-                style: line_cells.last().unwrap().style.clone(),
-            });
-        }
-        slices
+        self.scroll_region = if is_partial { Some(lines.start..end) } else { None };
     }
 
-    /// Gets the text visible at a specified scrollback.
-    pub fn visible_text(&self, scrollback_line_count: usize) -> String {
-        let lines: Vec<_> = self.visible_lines(scrollback_line_count)
-            .iter().map(ToString::to_string).collect();
-        lines.join("\n")
+    /// Gets the visible lines that scroll.
+    pub fn scroll_region(&self) -> Range<usize> {
+        self.scroll_region.clone().unwrap_or(0..self.settings.max_lines)
     }
 
-    /// Gets the entire text, including scrollback.
-    pub fn entire_text(&self) -> String {
-        let lines: Vec<_> = self.lines.iter().map(ToString::to_string).collect();
-        lines.join("\n")
+    /// Scrolls the lines in the scroll region up, adding blank lines at its bottom.
+    ///
+    /// Lines scrolled off the top of a region are discarded; without a
+    /// region, they go into the scrollback.
+    pub fn scroll_region_up(&mut self, line_count: usize) {
+        let region = match self.scroll_region.clone() {
+            Some(region) => region,
+            None => {
+                for _ in 0..line_count {
+                    self.add_new_whitespace_line();
+                }
+                self.evict_excess_lines();
+                return;
+            },
+        };
+
+        self.scroll_lines_up(region, line_count);
     }
 
-    /// Gets the cursor index relative to the top-left corner.
-    pub fn cursor_index(&self) -> usize {
-        (self.cursor.line_number * self.settings.max_columns) + self.cursor.column_number
+    /// Scrolls the lines in the scroll region down, adding blank lines at
+    /// its top and discarding lines scrolled off its bottom.
+    pub fn scroll_region_down(&mut self, line_count: usize) {
+        self.scroll_lines_down(self.scroll_region(), line_count);
     }
 
-    fn add_new_whitespace_line(&mut self) {
-        self.lines.push(Line::new(&self.settings));
+    /// Scrolls a range of visible lines up, adding blank lines at its bottom.
+    fn scroll_lines_up(&mut self, line_numbers: Range<usize>, line_count: usize) {
+        let first = self.first_visible_line_index_no_scroll();
+        let lines = &mut self.lines[first + line_numbers.start..first + line_numbers.end];
+        let line_count = line_count.min(lines.len());
+        lines.rotate_left(line_count);
+
+        let first_blank = lines.len() - line_count;
+        for line in lines[first_blank..].iter_mut() {
+            line.reset(&self.settings);
+        }
     }
 
-    fn first_visible_line_index(&self, scrollback_line_count: usize) -> usize {
-        if scrollback_line_count >= self.lines_in_scroll_buffer() {
+    /// Scrolls a range of visible lines down, adding blank lines at its top.
+    fn scroll_lines_down(&mut self, line_numbers: Range<usize>, line_count: usize) {
+        let first = self.first_visible_line_index_no_scroll();
+        let lines = &mut self.lines[first + line_numbers.start..first + line_numbers.end];
+        let line_count = line_count.min(lines.len());
+        lines.rotate_right(line_count);
+
+        for line in lines[..line_count].iter_mut() {
+            line.reset(&self.settings);
+        }
+    }
+
+    /// Adds a line written by the host, such as a status message, above the cursor line.
+    ///
+    /// The line is truncated to the width of the buffer, and its slice classes
+    /// are kept. The program's output continues on the cursor line as before.
+    /// The ids of the cursor line and the lines after it increase by one.
+    pub fn append_external_line(&mut self, slices: &[TextSlice], searchable: bool) {
+        let mut line = self.new_line();
+        line.origin = LineOrigin::Host;
+        line.searchable = searchable;
+        if self.settings.record_timestamps {
+            line.record_timestamp();
+        }
+
+        let cursor_index = self.first_visible_line_index_no_scroll() + self.cursor.line_number;
+        let line_id = self.evicted_line_count + cursor_index;
+        let mut new_ranges = Vec::new();
+        let mut column = 0;
+
+        for slice in slices {
+            let start = column;
+
+            for (cell, character) in line.cells_mut()[column..].iter_mut().zip(slice.text.chars()) {
+                *cell = Cell { character, style: slice.style.clone() };
+                column += 1;
+            }
+
+            for class in CellClass::ALL.iter().cloned().filter(|&class| slice.classes.contains(class)) {
+                new_ranges.push(ClassRange { class, line_id, columns: start..column, uri: None });
+            }
+        }
+
+        for range in self.class_ranges.iter_mut().filter(|range| range.line_id >= line_id) {
+            range.line_id += 1;
+        }
+        for marks in self.command_marks.iter_mut() {
+            for mark in marks.iter_mut().filter(|mark| mark.line_id >= line_id) {
+                mark.line_id += 1;
+            }
+        }
+        for fold in self.folds.iter_mut().filter(|fold| fold.lines.end > line_id) {
+            // A line inserted inside a fold joins it.
+            if fold.lines.start >= line_id {
+                fold.lines.start += 1;
+            }
+            fold.lines.end += 1;
+        }
+        self.search.shift_lines(line_id);
+        self.class_ranges.extend(new_ranges);
+        self.lines.insert(cursor_index, line);
+
+        if self.cursor.line_number + 1 < self.settings.max_lines {
+            // Keep the viewport where it is by dropping a blank line from the bottom.
+            self.lines.pop();
+            self.cursor.line_feed();
+        }
+
+        self.evict_excess_lines();
+    }
+
+    /// Gets a cell on the visible grid, relative to the top-left corner.
+    pub fn cell(&self, x: usize, y: usize) -> Option<&Cell> {
+        self.line_at(y)?.cells.get(x)
+    }
+
+    /// Replaces a cell on the visible grid, relative to the top-left corner.
+    pub fn set_cell(&mut self, x: usize, y: usize, cell: Cell) -> Result<(), GridError> {
+        match self.cell_mut(x, y) {
+            Some(destination) => {
+                *destination = cell;
+                Ok(())
+            },
+            None => Err(GridError::OutOfBounds { x, y }),
+        }
+    }
+
+    fn cell_mut(&mut self, x: usize, y: usize) -> Option<&mut Cell> {
+        self.line_at_mut(y)?.cells_mut().get_mut(x)
+    }
+
+    /// Gets a visible line, if it is within the grid.
+    /// The line will always be at same size as the buffer width.
+    fn line_at(&self, line_number: usize) -> Option<&Line> {
+        if line_number >= self.settings.max_lines {
+            return None;
+        }
+
+        self.lines.get(self.first_visible_line_index_no_scroll() + line_number)
+    }
+
+    fn line_at_mut(&mut self, line_number: usize) -> Option<&mut Line> {
+        if line_number >= self.settings.max_lines {
+            return None;
+        }
+
+        let index = self.first_visible_line_index_no_scroll() + line_number;
+        self.lines.get_mut(index)
+    }
+
+    fn set_wrapped(&mut self, line_number: usize, wrapped: bool) {
+        if let Some(line) = self.line_at_mut(line_number) {
+            line.wrapped = wrapped;
+        }
+    }
+
+    /// Gets every line as it is shown, with collapsed folds replaced by
+    /// their placeholders.
+    ///
+    /// Folds are only collapsed once they are entirely in the scrollback, so
+    /// the visible grid that the running program draws on is never hidden.
+    fn rows(&self) -> Vec<Row<'_>> {
+        let first_grid_line_id = self.evicted_line_count + self.first_visible_line_index_no_scroll();
+        let mut folds = self.folds.iter()
+            .filter(|fold| fold.collapsed && fold.lines.end <= first_grid_line_id)
+            .peekable();
+        let mut rows = Vec::with_capacity(self.lines.len());
+        let mut index = 0;
+
+        while index < self.lines.len() {
+            let id = self.evicted_line_count + index;
+
+            match folds.peek() {
+                Some(fold) if fold.lines.start == id => {
+                    rows.push(Row { id, line: &fold.placeholder, folded: true });
+                    index += fold.lines.len();
+                    folds.next();
+                },
+                _ => {
+                    rows.push(Row { id, line: &self.lines[index], folded: false });
+                    index += 1;
+                },
+            }
+        }
+
+        rows
+    }
+
+    /// Gets the lines visible at a specified scrollback.
+    fn visible_rows(&self, scrollback_line_count: usize) -> Vec<Row<'_>> {
+        let mut rows = self.rows();
+        let scrollback_rows = rows.len() - self.settings.max_lines;
+        let first_index = scrollback_rows - scrollback_line_count.min(scrollback_rows);
+
+        rows.truncate(first_index + self.settings.max_lines);
+        rows.drain(..first_index);
+        rows
+    }
+
+    /// Gets the text visible at a specified scrollback.
+    ///
+    /// Lines that are still waiting to be rewrapped are cut or padded to the width.
+    pub fn visible_cells(&self, scrollback_line_count: usize) -> Vec<Vec<Cell>> {
+        let columns = self.settings.max_columns;
+
+        self.visible_rows(scrollback_line_count).iter().map(|row| {
+            let mut cells: Vec<Cell> = row.line.cells.iter().take(columns).cloned().collect();
+            cells.resize(columns, Cell::default());
+            cells
+        }).collect()
+    }
+
+    /// Shrinks the grid to `columns` by `rows` blocks, for tab previews and
+    /// minimaps.
+    ///
+    /// The size is capped at the size of the grid, so that every block
+    /// covers at least one cell. Concealed cells count as blank.
+    pub fn thumbnail(&self, columns: usize, rows: usize) -> Thumbnail {
+        let grid = self.visible_cells(0);
+        let columns = columns.min(self.settings.max_columns);
+        let rows = rows.min(grid.len());
+
+        let mut blocks = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            let lines = &grid[block_span(row, rows, grid.len())];
+
+            for column in 0..columns {
+                let span = block_span(column, columns, self.settings.max_columns);
+                blocks.push(ThumbnailBlock::new(lines.iter().flat_map(|line| &line[span.clone()])));
+            }
+        }
+
+        Thumbnail { columns, rows, blocks }
+    }
+
+    /// Gets the visible slices.
+    ///
+    /// Slices are split wherever the style or the semantic classes change.
+    pub fn visible_slices(&self, scrollback_line_count: usize) -> Vec<TextSlice> {
+        self.visible_slices_with(scrollback_line_count, &SliceOptions::default())
+    }
+
+    /// Gets the visible slices, split wherever the style or one of the
+    /// given classes changes.
+    pub fn visible_slices_with(&self, scrollback_line_count: usize, options: &SliceOptions) -> Vec<TextSlice> {
+        self.visible_slices_styled(scrollback_line_count, options, &mut |style, _| style.clone())
+    }
+
+    /// Gets the visible slices, restyling each cell with a hook first.
+    ///
+    /// The hook can apply accessibility transforms, such as a palette that
+    /// is safe for color blindness or forced high contrast. Slices are split
+    /// wherever the restyled cells differ.
+    pub fn visible_slices_styled(&self,
+                                 scrollback_line_count: usize,
+                                 options: &SliceOptions,
+                                 restyle: &mut dyn FnMut(&Style, CellContext) -> Style)
+        -> Vec<TextSlice> {
+        self.row_slices(self.visible_rows(scrollback_line_count), options, restyle)
+    }
+
+    /// Gets the slices of a range of lines by id, including the scrollback.
+    ///
+    /// Evicted lines are skipped. Collapsed folds are not applied.
+    pub fn line_slices(&self, line_ids: Range<usize>, options: &SliceOptions) -> Vec<TextSlice> {
+        let start = line_ids.start.saturating_sub(self.evicted_line_count).min(self.lines.len());
+        let end = line_ids.end.saturating_sub(self.evicted_line_count).min(self.lines.len());
+        let first_id = self.evicted_line_count + start;
+
+        let rows = self.lines[start..end.max(start)].iter().enumerate().map(|(offset, line)| Row {
+            id: first_id + offset,
+            line,
+            folded: false,
+        });
+        self.row_slices(rows, options, &mut |style, _| style.clone())
+    }
+
+    /// Gets the slices of rows, each ending with a newline.
+    fn row_slices<'a>(&'a self,
+                      rows: impl IntoIterator<Item = Row<'a>>,
+                      options: &SliceOptions,
+                      restyle: &mut dyn FnMut(&Style, CellContext) -> Style)
+        -> Vec<TextSlice> {
+        let mut slices = Vec::new();
+
+        for row in rows {
+            let line = row.line;
+            let cell_classes = match row.folded {
+                true => vec![CellClasses::empty(); line.cells.len()],
+                false => self.cell_classes(row.id),
+            };
+            let classes: Vec<_> = cell_classes.iter()
+                .map(|classes| classes.intersection(options.split_classes))
+                .collect();
+            // Lines still waiting to be rewrapped may be wider than the grid.
+            let line_cells = &line.cells[..line.cells.len().min(classes.len())];
+            let styles: Vec<Style> = line_cells.iter().enumerate().map(|(column, cell)| {
+                restyle(&cell.style, CellContext { line_id: row.id, column, classes: cell_classes[column] })
+            }).collect();
+            let mut column_number = 0;
+
+            if let Some(gutter) = self.gutter_text(line) {
+                slices.push(TextSlice {
+                    text: gutter,
+                    style: Style::default(),
+                    classes: CellClasses::empty(),
+                });
+            }
+
+            while column_number < line_cells.len() {
+                let next_style = &styles[column_number];
+                let next_classes = classes[column_number];
+                let run_length = (column_number..line_cells.len()).take_while(|&i| {
+                    styles[i] == *next_style && classes[i] == next_classes
+                }).count();
+
+                let run = column_number..column_number + run_length;
+                let text = match next_style.concealed && !options.reveal_concealed {
+                    true => " ".repeat(run_length),
+                    false => line_cells[run.clone()].iter().map(|c| c.character).collect(),
+                };
+                slices.push(TextSlice {
+                    text,
+                    style: next_style.clone(),
+                    classes: next_classes,
+                });
+                column_number = run.end;
+            }
+
+            slices.push(TextSlice {
+                text: "\n".to_owned(),
+                style: styles.last().unwrap().clone(),
+                classes: CellClasses::empty(),
+            });
+        }
+        slices
+    }
+
+    /// Gets the id of a line visible at a specified scrollback.
+    ///
+    /// Line ids are stable; they do not change as lines scroll or are evicted.
+    ///
+    /// The placeholder of a collapsed fold has the id of the first folded line.
+    pub fn visible_line_id(&self, scrollback_line_count: usize, line_number: usize) -> usize {
+        match self.visible_rows(scrollback_line_count).get(line_number) {
+            Some(row) => row.id,
+            None => self.evicted_line_count + self.first_visible_line_index(scrollback_line_count) + line_number,
+        }
+    }
+
+    /// Adds a fold over a range of line ids, initially expanded.
+    ///
+    /// The range is clipped to the lines in the buffer, and any folds it
+    /// overlaps are removed.
+    pub fn add_fold(&mut self, lines: Range<usize>) {
+        let end = lines.end.min(self.evicted_line_count + self.lines.len());
+        let lines = lines.start.max(self.evicted_line_count)..end;
+        if lines.start >= lines.end {
+            return;
+        }
+
+        self.folds.retain(|fold| fold.lines.end <= lines.start || fold.lines.start >= lines.end);
+
+        let placeholder = self.fold_placeholder_line(lines.len());
+        let index = self.folds.iter().position(|fold| fold.lines.start > lines.start).unwrap_or(self.folds.len());
+        self.folds.insert(index, Fold { lines, collapsed: false, placeholder });
+    }
+
+    /// Collapses or expands the fold starting at a line.
+    ///
+    /// Returns `false` if no fold starts at the line.
+    pub fn set_fold_collapsed(&mut self, first_line_id: usize, collapsed: bool) -> bool {
+        match self.folds.iter_mut().find(|fold| fold.lines.start == first_line_id) {
+            Some(fold) => {
+                fold.collapsed = collapsed;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Removes the fold starting at a line, returning whether there was one.
+    pub fn remove_fold(&mut self, first_line_id: usize) -> bool {
+        let count = self.folds.len();
+        self.folds.retain(|fold| fold.lines.start != first_line_id);
+        self.folds.len() != count
+    }
+
+    /// Gets every fold, ordered by their first line.
+    pub fn folds(&self) -> &[Fold] {
+        &self.folds
+    }
+
+    /// Sets the text shown in place of collapsed folds.
+    ///
+    /// Any `{count}` in the text is replaced by the number of folded lines.
+    pub fn set_fold_placeholder(&mut self, text: &str) {
+        self.fold_placeholder = text.to_owned();
+        self.update_fold_placeholders();
+    }
+
+    fn fold_placeholder_line(&self, line_count: usize) -> Line {
+        let text = self.fold_placeholder.replace(FOLD_COUNT_PLACEHOLDER, &line_count.to_string());
+        let mut line = Line::new(&self.settings);
+        line.origin = LineOrigin::Fold;
+        line.searchable = false;
+
+        for (cell, character) in line.cells_mut().iter_mut().zip(text.chars()) {
+            cell.character = character;
+        }
+        line
+    }
+
+    /// Rebuilds the fold placeholders after their text, sizes, or the width changed.
+    fn update_fold_placeholders(&mut self) {
+        let placeholders: Vec<_> = self.folds.iter()
+            .map(|fold| self.fold_placeholder_line(fold.lines.len()))
+            .collect();
+
+        for (fold, placeholder) in self.folds.iter_mut().zip(placeholders) {
+            fold.placeholder = placeholder;
+        }
+    }
+
+    /// Tags a range of cells on a line with a semantic class.
+    pub fn add_class_range(&mut self, class: CellClass, line_id: usize, columns: Range<usize>) {
+        self.class_ranges.push(ClassRange { class, line_id, columns, uri: None });
+    }
+
+    /// Marks a cell on the visible grid as part of a hyperlink set by the
+    /// running program, relative to the top-left corner.
+    pub fn add_link(&mut self, x: usize, y: usize, uri: &str) {
+        let line_id = self.evicted_line_count + self.first_visible_line_index_no_scroll() + y;
+
+        // Extend the link written just before, as links arrive a character at a time.
+        if let Some(range) = self.class_ranges.last_mut() {
+            if range.line_id == line_id && range.columns.end == x && range.uri.as_deref() == Some(uri) {
+                range.columns.end += 1;
+                return;
+            }
+        }
+
+        self.class_ranges.push(ClassRange {
+            class: CellClass::Link,
+            line_id,
+            columns: x..x + 1,
+            uri: Some(uri.to_owned()),
+        });
+    }
+
+    /// Gets the hyperlink at a cell visible at a specified scrollback.
+    ///
+    /// Links set by the running program take priority over URLs detected
+    /// in the text.
+    pub fn link_at(&self, scrollback_line_count: usize, x: usize, y: usize) -> Option<Link> {
+        let rows = self.visible_rows(scrollback_line_count);
+        let row = rows.get(y).filter(|row| !row.folded)?;
+
+        let program_link = self.class_ranges.iter().rev()
+            .find(|range| range.line_id == row.id && range.columns.contains(&x) && range.uri.is_some());
+        if let Some(range) = program_link {
+            return Some(Link {
+                uri: range.uri.clone().unwrap(),
+                source: LinkSource::Program,
+                line_id: row.id,
+                columns: range.columns.clone(),
+            });
+        }
+
+        let (columns, uri) = detect_url(row.line, x)?;
+        Some(Link { uri, source: LinkSource::Detected, line_id: row.id, columns })
+    }
+
+    /// Tags every cell on a range of lines with a semantic class.
+    pub fn add_line_class(&mut self, class: CellClass, line_ids: Range<usize>) {
+        let columns = 0..self.settings.max_columns;
+
+        for line_id in line_ids {
+            self.add_class_range(class, line_id, columns.clone());
+        }
+    }
+
+    /// Marks a visible line as the start of a shell prompt.
+    pub fn mark_prompt_start(&mut self, line_number: usize) {
+        if let Some(line) = self.line_at_mut(line_number) {
+            line.prompt_start = true;
+        }
+    }
+
+    /// Records a shell integration mark at the cursor.
+    ///
+    /// A prompt starts a new command; the other marks fill in the parts of
+    /// the latest one.
+    pub fn record_shell_mark(&mut self, mark: ShellMark) {
+        let position = Mark { line_id: self.cursor_line_id(), column: self.cursor.column_number };
+
+        if mark == ShellMark::PromptStart {
+            self.mark_prompt_start(self.cursor.line_number);
+            self.command_marks.push(CommandMarks { prompt: Some(position), ..CommandMarks::default() });
+            return;
+        }
+
+        // Marks after a finished command, without a prompt in between, start another.
+        if self.command_marks.last().is_none_or(|marks| marks.end.is_some()) {
+            self.command_marks.push(CommandMarks::default());
+        }
+        let marks = self.command_marks.last_mut().unwrap();
+
+        match mark {
+            ShellMark::PromptStart => unreachable!(),
+            ShellMark::CommandStart => marks.command = Some(position),
+            ShellMark::OutputStart => {
+                marks.output = Some(position);
+                #[cfg(feature = "std")] {
+                    marks.started_at = Some(Instant::now());
+                }
+            },
+            ShellMark::CommandFinished { exit_code } => {
+                marks.end = Some(position);
+                marks.exit_code = exit_code;
+                #[cfg(feature = "std")] {
+                    marks.duration = marks.started_at.map(|started_at| started_at.elapsed());
+                }
+            },
+        }
+    }
+
+    /// Gets how long the last command ran, if it finished and was timed.
+    pub fn last_command_duration(&self) -> Option<Duration> {
+        self.command_marks.last().and_then(|marks| marks.duration)
+    }
+
+    /// Gets every command marked by shell integration that is still in the
+    /// buffer, oldest first, with its output and exit code.
+    ///
+    /// Text that has been evicted is left out.
+    pub fn commands(&self) -> Vec<ShellCommand> {
+        let cursor = Mark { line_id: self.cursor_line_id(), column: self.cursor.column_number };
+
+        self.command_marks.iter().map(|marks| {
+            let output_end = marks.end.unwrap_or(cursor);
+            let output_line_ids = match marks.output {
+                Some(output) => {
+                    // Output normally ends with a newline, leaving the cursor on an empty line.
+                    let end_line_id = if output_end.column > 0 { output_end.line_id + 1 } else { output_end.line_id };
+                    output.line_id..end_line_id.max(output.line_id)
+                },
+                None => output_end.line_id..output_end.line_id,
+            };
+
+            ShellCommand {
+                prompt_line_id: marks.prompt.map(|prompt| prompt.line_id),
+                command: match marks.command {
+                    Some(command) => self.text_between(command, marks.output.or(marks.end).unwrap_or(cursor)),
+                    None => String::new(),
+                },
+                output: match marks.output {
+                    Some(output) => self.text_between(output, output_end),
+                    None => String::new(),
+                },
+                output_line_ids,
+                finished: marks.end.is_some(),
+                exit_code: marks.exit_code,
+                duration: marks.duration,
+            }
+        }).collect()
+    }
+
+    /// Gets the text from one cell up to another, without trailing whitespace.
+    ///
+    /// Lines are separated by `\n` unless they wrapped. Evicted lines are skipped.
+    fn text_between(&self, start: Mark, end: Mark) -> String {
+        let mut text = String::new();
+
+        for line_id in start.line_id..=end.line_id {
+            let line = match self.line_with_id(line_id) {
+                Some(line) => line,
+                None => continue,
+            };
+            let first = if line_id == start.line_id { start.column } else { 0 };
+            let last = if line_id == end.line_id { end.column } else { line.cells.len() };
+            let cells = &line.cells[first.min(last).min(line.cells.len())..last.min(line.cells.len())];
+
+            let line_text: String = cells.iter().map(|cell| cell.character).collect();
+            match line.wrapped || line_id == end.line_id {
+                true => text.push_str(&line_text),
+                false => {
+                    text.push_str(line_text.trim_end());
+                    text.push('\n');
+                },
+            }
+        }
+
+        let trimmed_length = text.trim_end().len();
+        text.truncate(trimmed_length);
+        text
+    }
+
+    /// Gets the id of the line the cursor is on.
+    fn cursor_line_id(&self) -> usize {
+        self.evicted_line_count + self.first_visible_line_index_no_scroll() + self.cursor.line_number
+    }
+
+    /// Gets the id of the last line before `line_id` that a prompt starts on.
+    pub fn previous_prompt(&self, line_id: usize) -> Option<usize> {
+        let end = line_id.saturating_sub(self.evicted_line_count).min(self.lines.len());
+        let index = self.lines[..end].iter().rposition(|line| line.prompt_start)?;
+        Some(self.evicted_line_count + index)
+    }
+
+    /// Gets the id of the first line after `line_id` that a prompt starts on.
+    pub fn next_prompt(&self, line_id: usize) -> Option<usize> {
+        let start = (line_id + 1).saturating_sub(self.evicted_line_count).min(self.lines.len());
+        let offset = self.lines[start..].iter().position(|line| line.prompt_start)?;
+        Some(self.evicted_line_count + start + offset)
+    }
+
+    /// Removes a semantic class from every cell.
+    pub fn clear_class(&mut self, class: CellClass) {
+        self.class_ranges.retain(|range| range.class != class);
+    }
+
+    /// Computes the semantic classes of every cell on a line.
+    fn cell_classes(&self, line_id: usize) -> Vec<CellClasses> {
+        let mut classes = vec![CellClasses::empty(); self.settings.max_columns];
+
+        for range in self.class_ranges.iter().filter(|range| range.line_id == line_id) {
+            let end = range.columns.end.min(classes.len());
+
+            for cell_classes in classes[range.columns.start.min(end)..end].iter_mut() {
+                cell_classes.insert(range.class);
+            }
+        }
+
+        let (cursor_x, cursor_y) = self.cursor_xy();
+        let cursor_line_id = self.evicted_line_count + self.first_visible_line_index_no_scroll() + cursor_y;
+        if line_id == cursor_line_id {
+            if let Some(cell_classes) = classes.get_mut(cursor_x) {
+                cell_classes.insert(CellClass::Cursor);
+            }
+        }
+
+        classes
+    }
+
+    /// Gets the text visible at a specified scrollback.
+    pub fn visible_text(&self, scrollback_line_count: usize) -> String {
+        let lines: Vec<_> = self.visible_rows(scrollback_line_count)
+            .iter().map(|row| self.export_line(row.line)).collect();
+        lines.join("\n")
+    }
+
+    /// Gets the entire text, including scrollback.
+    pub fn entire_text(&self) -> String {
+        let lines: Vec<_> = self.lines.iter().map(|line| self.export_line(line)).collect();
+        lines.join("\n")
+    }
+
+    /// Gets the text of a range of lines by id, without trailing spaces.
+    ///
+    /// Lines are separated by `\n`. Evicted lines are skipped.
+    pub fn lines_text(&self, line_ids: Range<usize>) -> String {
+        let start = line_ids.start.saturating_sub(self.evicted_line_count).min(self.lines.len());
+        let end = line_ids.end.saturating_sub(self.evicted_line_count).min(self.lines.len());
+
+        let lines: Vec<_> = self.lines[start..end.max(start)].iter()
+            .map(|line| line.text().trim_end().to_owned())
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Gets the ids of the lines making up the logical line that a line is
+    /// part of, following soft wraps both ways, with its text.
+    ///
+    /// The text has no trailing spaces. Evicted lines are skipped.
+    pub fn logical_line_text(&self, line_id: usize) -> (Range<usize>, String) {
+        let wrapped = |line_id: usize| self.line_with_id(line_id).is_some_and(|line| line.wrapped);
+
+        let mut start = line_id;
+        while start > self.evicted_line_count && wrapped(start - 1) {
+            start -= 1;
+        }
+        let mut end = line_id + 1;
+        while wrapped(end - 1) && self.line_with_id(end).is_some() {
+            end += 1;
+        }
+
+        let text: String = (start..end).filter_map(|line_id| self.line_with_id(line_id)).map(Line::text).collect();
+        (start..end, text.trim_end().to_owned())
+    }
+
+    /// Copies the cells tagged with `CellClass::Selection`, with where they
+    /// came from.
+    ///
+    /// Spaces padding the end of a line are left out. Returns `None` if
+    /// nothing still in the buffer is selected.
+    pub fn copy_selection(&self) -> Option<CopyResult> {
+        let mut ranges: Vec<_> = self.class_ranges.iter()
+            .filter(|range| range.class == CellClass::Selection)
+            .filter_map(|range| Some((range, self.line_with_id(range.line_id)?)))
+            .collect();
+        ranges.sort_by_key(|(range, _)| (range.line_id, range.columns.start));
+
+        let source_line_ids = ranges.first()?.0.line_id..ranges.last()?.0.line_id + 1;
+        #[cfg(feature = "std")]
+        let timestamp = ranges[0].1.timestamp;
+        let mut text = String::new();
+        let mut styled: Vec<TextSlice> = Vec::new();
+        let mut previous: Option<(usize, bool)> = None;
+
+        for (range, line) in ranges {
+            if let Some((line_id, wrapped)) = previous {
+                if line_id != range.line_id && !wrapped {
+                    text.push('\n');
+                    styled.push(TextSlice { text: "\n".to_owned(), style: Style::default(), classes: CellClasses::empty() });
+                }
+            }
+            previous = Some((range.line_id, line.wrapped));
+
+            let content_end = match line.wrapped {
+                true => line.cells.len(),
+                false => line.cells.iter().rposition(|cell| cell.character != ' ').map_or(0, |i| i + 1),
+            };
+            let end = range.columns.end.min(content_end);
+            let cells = &line.cells[range.columns.start.min(end)..end];
+
+            for cell in cells {
+                text.push(cell.character);
+
+                match styled.last_mut() {
+                    Some(slice) if slice.style == cell.style && slice.text != "\n" => slice.text.push(cell.character),
+                    _ => styled.push(TextSlice {
+                        text: cell.character.to_string(),
+                        style: cell.style.clone(),
+                        classes: CellClasses::empty(),
+                    }),
+                }
+            }
+        }
+
+        Some(CopyResult {
+            text,
+            styled,
+            source_line_ids,
+            #[cfg(feature = "std")]
+            timestamp,
+        })
+    }
+
+    fn line_with_id(&self, line_id: usize) -> Option<&Line> {
+        self.lines.get(line_id.checked_sub(self.evicted_line_count)?)
+    }
+
+    /// Gets the ids of every line still in the buffer, from the oldest line
+    /// in the scrollback to the last line of the grid.
+    pub fn line_ids(&self) -> Range<usize> {
+        self.evicted_line_count..self.evicted_line_count + self.lines.len()
+    }
+
+    /// Iterates over a range of lines by id, such as to render one page of
+    /// a large scrollback without copying the rest.
+    ///
+    /// Evicted lines are skipped. Collapsed folds are not applied.
+    pub fn iter_lines(&self, line_ids: Range<usize>) -> impl Iterator<Item = LineView<'_>> {
+        let start = line_ids.start.saturating_sub(self.evicted_line_count).min(self.lines.len());
+        let end = line_ids.end.saturating_sub(self.evicted_line_count).min(self.lines.len());
+        let first_id = self.evicted_line_count + start;
+
+        self.lines[start..end.max(start)].iter().enumerate().map(move |(offset, line)| LineView {
+            id: first_id + offset,
+            cells: &line.cells,
+            metadata: line.metadata(),
+        })
+    }
+
+    /// Encodes a range of lines by id compactly, including the scrollback.
+    ///
+    /// See `LineView::encode`.
+    pub fn encode_lines(&self, line_ids: Range<usize>) -> Vec<EncodedLine> {
+        self.iter_lines(line_ids).map(|line| line.encode()).collect()
+    }
+
+    /// Converts a cell on a line into a position in its logical line.
+    ///
+    /// Returns `None` if the line is not in the buffer.
+    pub fn logical_position(&self, line_id: usize, column: usize) -> Option<LogicalPosition> {
+        let index = line_id.checked_sub(self.evicted_line_count).filter(|&index| index < self.lines.len())?;
+        let start = self.logical_line_start(index);
+        let offset: usize = self.lines[start..index].iter().map(|line| line.cells.len()).sum();
+
+        Some(LogicalPosition { line_id: self.evicted_line_count + start, column: offset + column })
+    }
+
+    /// Gets where a logical position is shown when the text is wrapped to
+    /// `width` columns, whatever the width of the grid.
+    ///
+    /// Columns past the end of the text are clamped to it. Converting
+    /// back with `visual_to_logical` at the same width gives the same
+    /// position. Returns `None` if no logical line starts at the line id.
+    pub fn logical_to_visual(&self, position: LogicalPosition, width: usize) -> Option<VisualPosition> {
+        let width = width.max(1);
+        let mut row = 0;
+
+        for (group, cell_count) in self.logical_lines() {
+            let row_count = wrapped_row_count(cell_count, width);
+
+            if self.evicted_line_count + group.start == position.line_id {
+                let column = position.column.min(cell_count);
+                let row_offset = (column / width).min(row_count - 1);
+                return Some(VisualPosition { row: row + row_offset, column: column - row_offset * width });
+            }
+            row += row_count;
+        }
+
+        None
+    }
+
+    /// Gets the logical position shown at a row and column when the text is
+    /// wrapped to `width` columns.
+    ///
+    /// Columns past the end of the text are clamped to it. Converting back
+    /// with `logical_to_visual` at the same width gives the same position
+    /// for any column before `width`. Returns `None` past the last row.
+    pub fn visual_to_logical(&self, position: VisualPosition, width: usize) -> Option<LogicalPosition> {
+        let width = width.max(1);
+        let mut row = 0;
+
+        for (group, cell_count) in self.logical_lines() {
+            let row_count = wrapped_row_count(cell_count, width);
+
+            if position.row < row + row_count {
+                let column = ((position.row - row) * width + position.column.min(width)).min(cell_count);
+                return Some(LogicalPosition { line_id: self.evicted_line_count + group.start, column });
+            }
+            row += row_count;
+        }
+
+        None
+    }
+
+    /// Gets the id of the top line of the page above a viewport starting at
+    /// `top_line_id`, stopping at the oldest line in the scrollback.
+    pub fn page_up(&self, top_line_id: usize) -> usize {
+        let top_line_id = self.clamp_top_line_id(top_line_id);
+        top_line_id.saturating_sub(self.settings.max_lines).max(self.evicted_line_count)
+    }
+
+    /// Gets the id of the top line of the page below a viewport starting at
+    /// `top_line_id`, stopping at the top of the grid.
+    pub fn page_down(&self, top_line_id: usize) -> usize {
+        let top_line_id = self.clamp_top_line_id(top_line_id);
+        (top_line_id + self.settings.max_lines).min(self.grid_top_line_id())
+    }
+
+    /// Moves the top line of a viewport back within the buffer, such as
+    /// after the lines it was showing were evicted.
+    fn clamp_top_line_id(&self, top_line_id: usize) -> usize {
+        top_line_id.max(self.evicted_line_count).min(self.grid_top_line_id())
+    }
+
+    /// Gets the id of the first line of the grid.
+    fn grid_top_line_id(&self) -> usize {
+        self.evicted_line_count + self.first_visible_line_index_no_scroll()
+    }
+
+    /// Gets the metadata of a line, indexed from the oldest line in the scrollback.
+    pub fn line_metadata(&self, line_index: usize) -> Option<LineMetadata> {
+        self.lines.get(line_index).map(Line::metadata)
+    }
+
+    /// Gets the metadata of every line visible at a specified scrollback.
+    pub fn visible_line_metadata(&self, scrollback_line_count: usize) -> Vec<LineMetadata> {
+        self.visible_rows(scrollback_line_count).iter().map(|row| row.line.metadata()).collect()
+    }
+
+    /// Renders a line for the text export paths, including the timestamp gutter
+    /// and the marker for host lines.
+    fn export_line(&self, line: &Line) -> String {
+        let marker = match line.origin {
+            LineOrigin::Program | LineOrigin::Fold => "",
+            LineOrigin::Host => HOST_LINE_MARKER,
+        };
+
+        match self.gutter_text(line) {
+            Some(gutter) => format!("{}{}{}", gutter, marker, line),
+            None => format!("{}{}", marker, line),
+        }
+    }
+
+    /// Gets the timestamp gutter text for a line, if the gutter is enabled.
+    #[cfg(not(feature = "std"))]
+    fn gutter_text(&self, _: &Line) -> Option<String> {
+        None
+    }
+
+    /// Gets the timestamp gutter text for a line, if the gutter is enabled.
+    #[cfg(feature = "std")]
+    fn gutter_text(&self, line: &Line) -> Option<String> {
+        let seconds = match (self.settings.timestamp_gutter, line.timestamp) {
+            (TimestampGutter::Hidden, _) => return None,
+            (TimestampGutter::Clock, None) => return Some(" ".repeat(11)),
+            (TimestampGutter::Elapsed, None) => return Some(" ".repeat(12)),
+            (TimestampGutter::Clock, Some(timestamp)) => {
+                timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % (24 * 60 * 60)
+            },
+            (TimestampGutter::Elapsed, Some(timestamp)) => {
+                timestamp.duration_since(self.created_at).unwrap_or_default().as_secs()
+            },
+        };
+
+        let (hours, minutes, seconds) = (seconds / 3600, (seconds / 60) % 60, seconds % 60);
+
+        Some(match self.settings.timestamp_gutter {
+            TimestampGutter::Elapsed => format!("[+{:02}:{:02}:{:02}] ", hours, minutes, seconds),
+            _ => format!("[{:02}:{:02}:{:02}] ", hours, minutes, seconds),
+        })
+    }
+
+    /// Finds every occurrence of a string on the visible grid where
+    /// the style of every matched cell satisfies the predicate.
+    ///
+    /// Matches do not span lines.
+    pub fn find_text<P>(&self, scrollback_line_count: usize, needle: &str, predicate: P) -> Vec<TextMatch>
+        where P: Fn(&Style) -> bool {
+        if needle.is_empty() {
+            return Vec::new();
+        }
+
+        self.find_matches(scrollback_line_count, predicate, |text| {
+            text.match_indices(needle).map(|(start, s)| start..start + s.len()).collect()
+        })
+    }
+
+    /// Finds every match of a regular expression on the visible grid where
+    /// the style of every matched cell satisfies the predicate.
+    ///
+    /// Matches do not span lines.
+    #[cfg(feature = "regex")]
+    pub fn find_regex<P>(&self, scrollback_line_count: usize, regex: &regex::Regex, predicate: P) -> Vec<TextMatch>
+        where P: Fn(&Style) -> bool {
+        self.find_matches(scrollback_line_count, predicate, |text| {
+            regex.find_iter(text).filter(|m| !m.range().is_empty()).map(|m| m.range()).collect()
+        })
+    }
+
+    /// Starts or resumes an interactive search over the whole buffer.
+    pub fn search(&mut self) -> Search<'_> {
+        Search::new(self)
+    }
+
+    /// Gets the smallest scrollback at which a line is visible, such as to
+    /// scroll to a search match.
+    ///
+    /// Returns `None` if the line has been evicted or does not exist yet.
+    /// Lines in a collapsed fold are shown by their placeholder.
+    pub fn scrollback_to_show(&self, line_id: usize) -> Option<usize> {
+        let index = line_id.checked_sub(self.evicted_line_count)?;
+        if index >= self.lines.len() {
+            return None;
+        }
+
+        let rows = self.rows();
+        let row_index = rows.iter().rposition(|row| row.id <= line_id)?;
+        Some((rows.len() - self.settings.max_lines).saturating_sub(row_index))
+    }
+
+    /// Finds every occurrence of a string in the buffer, including the scrollback.
+    pub(crate) fn find_search_matches(&self, needle: &str) -> Vec<SearchMatch> {
+        if needle.is_empty() {
+            return Vec::new();
+        }
+
+        let mut matches = Vec::new();
+
+        for (index, line) in self.lines.iter().enumerate().filter(|(_, line)| line.searchable) {
+            let text = line.text();
+            let column_of = |byte_index: usize| text[..byte_index].chars().count();
+
+            for (start, s) in text.match_indices(needle) {
+                matches.push(SearchMatch {
+                    line_id: self.evicted_line_count + index,
+                    columns: column_of(start)..column_of(start + s.len()),
+                });
+            }
+        }
+
+        matches
+    }
+
+    /// Finds matches on each visible line given a function returning byte ranges.
+    fn find_matches<P, F>(&self, scrollback_line_count: usize, predicate: P, find: F) -> Vec<TextMatch>
+        where P: Fn(&Style) -> bool, F: Fn(&str) -> Vec<Range<usize>> {
+        let mut matches = Vec::new();
+
+        for (line_number, row) in self.visible_rows(scrollback_line_count).iter().enumerate() {
+            let line = row.line;
+            if !line.searchable {
+                continue;
+            }
+
+            let text = line.text();
+            // Every cell holds exactly one character, so char indices are column numbers.
+            let column_of = |byte_index: usize| text[..byte_index].chars().count();
+
+            for byte_range in find(text) {
+                let columns = column_of(byte_range.start)..column_of(byte_range.end);
+
+                if line.cells[columns.clone()].iter().all(|cell| predicate(&cell.style)) {
+                    matches.push(TextMatch { line_number, columns });
+                }
+            }
+        }
+
+        matches
+    }
+
+    /// Gets the number of lines in the buffer, including scrollback.
+    pub fn total_line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Gets the number of lines in the scrollback, counting each collapsed
+    /// fold as one line.
+    pub fn scrollback_line_count(&self) -> usize {
+        self.rows().len() - self.settings.max_lines
+    }
+
+    /// Gets the number of lines evicted from the top of the scrollback so far.
+    pub fn evicted_line_count(&self) -> usize {
+        self.evicted_line_count
+    }
+
+    /// Evicts up to `line_count` of the oldest lines in the scrollback, such
+    /// as to stay within a memory budget, returning how many were evicted.
+    pub fn evict_scrollback(&mut self, line_count: usize) -> usize {
+        let line_count = line_count.min(self.lines_in_scroll_buffer());
+        self.evict_lines(line_count);
+        self.lines.shrink_to_fit();
+        // The caller wants the memory back, so don't hold on to it for reuse.
+        self.spare_lines = Vec::new();
+        line_count
+    }
+
+    /// Gets the approximate memory used by each line.
+    pub fn line_footprint(&self) -> usize {
+        self.settings.max_columns * mem::size_of::<Cell>() + mem::size_of::<Line>()
+    }
+
+    /// Gets the approximate memory used by the buffer.
+    ///
+    /// This is cheap to call, as all lines in the buffer have the same width.
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        let cells_per_line = self.settings.max_columns * mem::size_of::<Cell>();
+
+        MemoryFootprint {
+            cells: self.lines.len() * cells_per_line,
+            metadata: self.lines.capacity() * mem::size_of::<Line>() + mem::size_of::<Self>(),
+            spare: self.spare_lines.len() * cells_per_line + self.spare_lines.capacity() * mem::size_of::<Line>(),
+        }
+    }
+
+    /// Gets the cursor index relative to the top-left corner.
+    pub fn cursor_index(&self) -> usize {
+        (self.cursor.line_number * self.settings.max_columns) + self.cursor.column_number
+    }
+
+    /// Clips a rectangle to the visible grid.
+    fn clip_rect(&self, rect: Rect) -> Rect {
+        let right = rect.right.min(self.settings.max_columns);
+        let bottom = rect.bottom.min(self.settings.max_lines);
+
+        Rect {
+            left: rect.left.min(right),
+            top: rect.top.min(bottom),
+            right,
+            bottom,
+        }
+    }
+
+    /// Removes the oldest lines if we've hit the scrollback limit.
+    fn evict_excess_lines(&mut self) {
+        self.evict_lines(self.lines_over_limit());
+    }
+
+    /// Evicts lines from the top of the scrollback.
+    fn evict_lines(&mut self, excess_line_count: usize) {
+        if excess_line_count == 0 {
+            return;
+        }
+
+        let room = self.settings.max_lines.saturating_sub(self.spare_lines.len());
+        self.spare_lines.extend(self.lines.drain(..excess_line_count).take(room));
+        self.evicted_line_count += excess_line_count;
+        self.pending_reflow_lines = self.pending_reflow_lines.saturating_sub(excess_line_count);
+
+        let first_line_id = self.evicted_line_count;
+        self.class_ranges.retain(|range| range.line_id >= first_line_id);
+        self.command_marks.retain(|marks| marks.end.is_none_or(|end| end.line_id >= first_line_id));
+
+        if self.folds.first().is_some_and(|fold| fold.lines.start < first_line_id) {
+            for fold in self.folds.iter_mut() {
+                fold.lines.start = fold.lines.start.max(first_line_id);
+            }
+            self.folds.retain(|fold| !fold.lines.is_empty());
+            self.update_fold_placeholders();
+        }
+    }
+
+    /// Gets the number of lines in the scrollback past the number to remember.
+    fn lines_over_limit(&self) -> usize {
+        self.lines_in_scroll_buffer().saturating_sub(self.settings.lines_to_remember)
+    }
+
+    fn add_new_whitespace_line(&mut self) {
+        let line = self.new_line();
+        self.lines.push(line);
+    }
+
+    /// Creates a blank line, reusing the cells of an evicted line if there is one.
+    fn new_line(&mut self) -> Line {
+        match self.spare_lines.pop() {
+            Some(mut line) => {
+                line.reset(&self.settings);
+                line
+            },
+            None => Line::new(&self.settings),
+        }
+    }
+
+    /// Keeps removed lines for reuse, up to a screenful.
+    fn recycle_lines(&mut self, lines: impl IntoIterator<Item=Line>) {
+        let room = self.settings.max_lines.saturating_sub(self.spare_lines.len());
+        self.spare_lines.extend(lines.into_iter().take(room));
+    }
+
+    fn first_visible_line_index(&self, scrollback_line_count: usize) -> usize {
+        if scrollback_line_count >= self.lines_in_scroll_buffer() {
             0
         } else {
             self.first_visible_line_index_no_scroll() - scrollback_line_count
         }
     }
 
-    fn first_visible_line_index_no_scroll(&self) -> usize {
-        self.lines_in_scroll_buffer()
+    fn first_visible_line_index_no_scroll(&self) -> usize {
+        self.lines_in_scroll_buffer()
+    }
+
+    fn lines_in_scroll_buffer(&self) -> usize {
+        self.lines.len() - self.settings.max_lines
+    }
+}
+
+/// Gets the number of rows that a logical line of some length takes up
+/// when wrapped to a width. Empty lines still take up a row.
+fn wrapped_row_count(cell_count: usize, width: usize) -> usize {
+    let width = width.max(1);
+    cell_count.div_ceil(width).max(1)
+}
+
+/// Finds a URL, such as `https://example.com/a`, covering a column of a line.
+///
+/// URLs end at whitespace, and trailing punctuation is not part of them.
+fn detect_url(line: &Line, column: usize) -> Option<(Range<usize>, String)> {
+    let characters: Vec<char> = line.cells.iter().map(|cell| cell.character).collect();
+    if characters.get(column).is_none_or(|c| c.is_whitespace()) {
+        return None;
+    }
+
+    let word_start = characters[..column].iter().rposition(|c| c.is_whitespace()).map_or(0, |i| i + 1);
+    let word_end = characters[column..].iter().position(|c| c.is_whitespace()).map_or(characters.len(), |i| column + i);
+    let word = &characters[word_start..word_end];
+
+    let separator = word.windows(3).position(|w| w == [':', '/', '/'])?;
+    let scheme_length = word[..separator].iter().rev()
+        .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        .count();
+    let scheme_start = separator - scheme_length;
+    if !word.get(scheme_start).is_some_and(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let trailing = word.iter().rev()
+        .take_while(|c| matches!(c, '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' | ')' | ']' | '}' | '>'))
+        .count();
+    let columns = word_start + scheme_start..word_end - trailing;
+    if columns.len() <= scheme_length + 3 || !columns.contains(&column) {
+        return None;
+    }
+
+    let url = characters[columns.clone()].iter().collect();
+    Some((columns, url))
+}
+
+impl Rewrap {
+    /// Maps the position of a cell before rewrapping to where it is now.
+    ///
+    /// Positions past the end of a logical line map to the end of its last line.
+    fn map(&self, index: usize, column: usize, columns: usize) -> (usize, usize) {
+        if index < self.old.start {
+            return (index, column);
+        }
+        if index >= self.old.end {
+            return (index - self.old.len() + self.new_len, column);
+        }
+
+        let line = self.lines[index - self.old.start];
+        let offset = line.offset.saturating_add(column);
+        let new_index = line.first_index + offset / columns.max(1);
+
+        if new_index > line.last_index {
+            (line.last_index, columns)
+        } else {
+            (new_index, offset % columns.max(1))
+        }
+    }
+
+    /// Maps the exclusive end of a range of lines, so that a range of
+    /// whole logical lines still covers them.
+    fn map_end(&self, end: usize) -> usize {
+        if end <= self.old.start {
+            end
+        } else if end >= self.old.end {
+            end - self.old.len() + self.new_len
+        } else {
+            self.lines[end - 1 - self.old.start].last_index + 1
+        }
+    }
+}
+
+impl Location {
+    pub fn top_left() -> Self {
+        Location { line_number: 0, column_number: 0 }
+    }
+
+    pub fn carriage_return(&mut self) -> &mut Self {
+        self.column_number = 0;
+        self
+    }
+
+    pub fn line_feed(&mut self) -> &mut Self {
+        self.line_number += 1;
+        self
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::Write for ScrollBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let s = String::from_utf8_lossy(buf);
+        self.put_str(&s);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+impl Line {
+    /// Creates a new line.
+    pub fn new(settings: &Settings) -> Self {
+        Line {
+            cells: (0..settings.max_columns).map(|_| Cell::default()).collect(),
+            #[cfg(feature = "std")]
+            timestamp: None,
+            wrapped: false,
+            origin: LineOrigin::Program,
+            searchable: true,
+            prompt_start: false,
+            text: TextCache::new(),
+        }
+    }
+
+    /// Blanks the line, keeping the allocation of its cells.
+    fn reset(&mut self, settings: &Settings) {
+        self.cells.clear();
+        self.cells.resize(settings.max_columns, Cell::default());
+        #[cfg(feature = "std")]
+        { self.timestamp = None; }
+        self.wrapped = false;
+        self.origin = LineOrigin::Program;
+        self.searchable = true;
+        self.prompt_start = false;
+        self.text.take();
+    }
+
+    /// Gets the characters of the line, building them if the cells changed.
+    fn text(&self) -> &str {
+        self.text.get_or_init(|| self.cells.iter().map(|cell| cell.character).collect())
+    }
+
+    /// Gets the cells for mutation, invalidating the cached text.
+    fn cells_mut(&mut self) -> &mut [Cell] {
+        self.text.take();
+        &mut self.cells
+    }
+
+    /// Checks if every cell in the line is a space.
+    fn is_blank(&self) -> bool {
+        self.cells.iter().all(|cell| cell.character == ' ')
+    }
+
+    /// Records the current time as the arrival time, if there is none yet.
+    #[cfg(feature = "std")]
+    fn record_timestamp(&mut self) {
+        if self.timestamp.is_none() {
+            self.timestamp = Some(SystemTime::now());
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn record_timestamp(&mut self) { }
+
+    /// Gets the metadata of the line.
+    pub fn metadata(&self) -> LineMetadata {
+        LineMetadata {
+            #[cfg(feature = "std")]
+            timestamp: self.timestamp,
+            wrapped: self.wrapped,
+            origin: self.origin,
+            searchable: self.searchable,
+            prompt_start: self.prompt_start,
+        }
+    }
+}
+
+impl<'a> LineView<'a> {
+    /// Encodes the line as its text and runs of styles.
+    pub fn encode(&self) -> EncodedLine {
+        let mut runs: Vec<StyleRun> = Vec::new();
+
+        for cell in self.cells {
+            match runs.last_mut() {
+                Some(run) if run.style == cell.style => run.length += 1,
+                _ => runs.push(StyleRun { length: 1, style: cell.style.clone() }),
+            }
+        }
+
+        EncodedLine {
+            id: self.id,
+            text: self.cells.iter().map(|cell| if cell.style.concealed { ' ' } else { cell.character }).collect(),
+            runs,
+            wrapped: self.metadata.wrapped,
+        }
     }
+}
 
-    fn lines_in_scroll_buffer(&self) -> usize {
-        self.lines.len() - self.settings.max_lines
+impl EncodedLine {
+    /// Hashes the text, styles and wrapping of the line, but not its id.
+    ///
+    /// The hash is the same on every platform and version of Rust, so a
+    /// remote client can keep the hashes of the lines it has, and only ask
+    /// for lines whose hashes have changed.
+    pub fn content_hash(&self) -> u64 {
+        let mut hash = StableHash::new();
+
+        hash.write(self.text.as_bytes());
+        hash.write_u64(self.runs.len() as u64);
+        for run in self.runs.iter() {
+            let style = &run.style;
+
+            hash.write_u64(run.length as u64);
+            for color in [style.color, style.background].iter() {
+                for channel in [color.red, color.green, color.blue, color.alpha].iter() {
+                    hash.write(&channel.to_bits().to_le_bytes());
+                }
+            }
+            hash.write(&[style.bold as u8, style.italic as u8, style.underlined as u8,
+                         style.strikethrough as u8, style.overline as u8, style.concealed as u8]);
+        }
+        hash.write(&[self.wrapped as u8]);
+
+        hash.finish()
     }
 }
 
-impl Location {
-    pub fn top_left() -> Self {
-        Location { line_number: 0, column_number: 0 }
+/// A 64-bit FNV-1a hash, which unlike the standard hasher is specified
+/// and so stable between processes.
+struct StableHash(u64);
+
+impl StableHash {
+    fn new() -> Self {
+        StableHash(0xcbf2_9ce4_8422_2325)
     }
 
-    /// Gets the EOF cursor location.
-    pub fn eof(settings: &Settings) -> Self {
-        Location {
-            line_number: settings.max_lines - 1,
-            column_number: settings.max_columns,
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
         }
     }
 
-    pub fn carriage_return(&mut self) -> &mut Self {
-        self.column_number = 0;
-        self
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
     }
 
-    pub fn line_feed(&mut self) -> &mut Self {
-        self.line_number += 1;
-        self
+    fn finish(&self) -> u64 {
+        self.0
     }
+}
 
-    /// Checks if the cursor is at the very end.
-    pub fn is_eof(&self, settings: &Settings) -> bool {
-        *self == Location::eof(settings)
+impl CommandMarks {
+    /// Iterates over the marks that have been recorded.
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Mark> {
+        IntoIterator::into_iter([&mut self.prompt, &mut self.command, &mut self.output, &mut self.end]).flatten()
     }
 }
 
-impl io::Write for ScrollBuffer {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let s = String::from_utf8_lossy(buf);
-        self.put_str(&s);
-        Ok(buf.len())
+impl PartialEq for Line {
+    fn eq(&self, other: &Line) -> bool {
+        // The cached text is left out, as it follows from the cells.
+        #[cfg(feature = "std")]
+        { if self.timestamp != other.timestamp { return false; } }
+
+        self.cells == other.cells &&
+            self.wrapped == other.wrapped &&
+            self.origin == other.origin &&
+            self.searchable == other.searchable &&
+            self.prompt_start == other.prompt_start
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.text())
     }
+}
 
-    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+impl SliceOptions {
+    /// Splits slices only at style changes, the cursor, and selection edges.
+    ///
+    /// This suits renderers that shape ligatures across each slice, as
+    /// a ligature must be broken wherever the cursor or selection cuts it.
+    pub fn ligature_safe() -> Self {
+        let mut split_classes = CellClasses::empty();
+        split_classes.insert(CellClass::Cursor);
+        split_classes.insert(CellClass::Selection);
+
+        SliceOptions { split_classes, reveal_concealed: false }
+    }
 }
 
-impl Line {
-    /// Creates a new line.
-    pub fn new(settings: &Settings) -> Self {
-        Line {
-            cells: (0..settings.max_columns).into_iter().map(|_| Cell::default()).collect()
-        }
+impl Default for SliceOptions {
+    fn default() -> Self {
+        SliceOptions { split_classes: CellClasses::all(), reveal_concealed: false }
     }
 }
 
-impl fmt::Display for Line {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        for cell in self.cells.iter() {
-            cell.character.fmt(fmt)?
+impl ThumbnailBlock {
+    /// Summarizes the cells of a block.
+    fn new<'a>(cells: impl Iterator<Item = &'a Cell>) -> Self {
+        let mut colors = Vec::new();
+        let mut backgrounds = Vec::new();
+        let (mut filled, mut total) = (0, 0);
+
+        for cell in cells {
+            total += 1;
+            count_color(&mut backgrounds, cell.style.background);
+
+            if cell.character != ' ' && !cell.style.concealed {
+                filled += 1;
+                count_color(&mut colors, cell.style.color);
+            }
+        }
+
+        ThumbnailBlock {
+            color: most_common_color(&colors),
+            background: most_common_color(&backgrounds).unwrap_or(Color::TRANSPARENT),
+            density: if total == 0 { 0.0 } else { filled as f32 / total as f32 },
         }
-        Ok(())
     }
 }
 
+/// Gets the cells covered by one of `count` blocks spread over `length` cells.
+fn block_span(index: usize, count: usize, length: usize) -> Range<usize> {
+    index * length / count..(index + 1) * length / count
+}
+
+/// Counts a color towards a tally. Colors are not hashable, and blocks have few.
+fn count_color(tally: &mut Vec<(Color, usize)>, color: Color) {
+    match tally.iter_mut().find(|(counted, _)| *counted == color) {
+        Some((_, count)) => *count += 1,
+        None => tally.push((color, 1)),
+    }
+}
+
+/// Gets the color counted most, preferring the first seen on a tie.
+fn most_common_color(tally: &[(Color, usize)]) -> Option<Color> {
+    tally.iter().rev().max_by_key(|&&(_, count)| count).map(|&(color, _)| color)
+}
+
 impl Default for Cell {
     fn default() -> Self {
         Cell {
@@ -324,11 +2662,48 @@ impl Default for Style {
     fn default() -> Self {
         Style {
             color: Color::BLACK,
+            background: Color::TRANSPARENT,
+            bold: false,
+            italic: false,
+            underlined: false,
+            strikethrough: false,
+            overline: false,
+            concealed: false,
+        }
+    }
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GridError::OutOfBounds { x, y } => write!(fmt, "cell ({}, {}) is outside of the grid", x, y),
         }
     }
 }
 
-#[cfg(test)]
+#[cfg(feature = "std")]
+impl std::error::Error for GridError { }
+
+impl MemoryFootprint {
+    /// Gets the total number of bytes used.
+    pub fn total(&self) -> usize {
+        self.cells + self.metadata + self.spare
+    }
+}
+
+impl core::ops::Add for MemoryFootprint {
+    type Output = MemoryFootprint;
+
+    fn add(self, other: MemoryFootprint) -> MemoryFootprint {
+        MemoryFootprint {
+            cells: self.cells + other.cells,
+            metadata: self.metadata + other.metadata,
+            spare: self.spare + other.spare,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod test {
     use super::*;
     use std::io::Write;
@@ -338,8 +2713,218 @@ mod test {
         max_lines: 3,
         lines_to_remember: 2, // two lines of scrollback
         tab_width: 4,
+        record_timestamps: false,
+        timestamp_gutter: TimestampGutter::Hidden,
     };
 
+    #[test]
+    fn external_lines_are_inserted_above_the_cursor_line() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        buffer.put_str("$ ");
+        buffer.append_external_line(&[TextSlice {
+            text: "hi".to_owned(),
+            style: Style::default(),
+            classes: CellClasses::empty(),
+        }], false);
+
+        assert_eq!("[host] hi \n$  \n   ", buffer.entire_text());
+        assert_eq!((2, 1), buffer.cursor_xy());
+        assert_eq!(LineOrigin::Host, buffer.visible_line_metadata(0)[0].origin);
+
+        buffer.put_str("\nhi\n");
+        // The host line is not searchable.
+        assert_eq!(vec![TextMatch { line_number: 1, columns: 0..2 }], buffer.find_text(0, "hi", |_| true));
+    }
+
+    #[test]
+    fn external_lines_move_the_commands_after_them() {
+        let mut buffer = ScrollBuffer::new(Settings { max_columns: 16, ..SMALL_SETTINGS });
+        buffer.record_shell_mark(ShellMark::PromptStart);
+        buffer.put_str("$ ");
+        buffer.record_shell_mark(ShellMark::CommandStart);
+        buffer.put_str("ls");
+        buffer.append_external_line(&[TextSlice {
+            text: "host message".to_owned(),
+            style: Style::default(),
+            classes: CellClasses::empty(),
+        }], false);
+
+        let commands = buffer.commands();
+        assert_eq!(Some(1), commands[0].prompt_line_id);
+        assert_eq!("ls", commands[0].command);
+    }
+
+    #[test]
+    fn classifies_whole_lines() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        buffer.put_str("ab\nc\n");
+
+        assert_eq!("ab\nc", buffer.lines_text(0..2));
+
+        buffer.add_line_class(CellClass::ErrorStream, 1..2);
+        let classes: Vec<_> = buffer.visible_slices(0).iter()
+            .map(|slice| (slice.text.clone(), slice.classes.contains(CellClass::ErrorStream)))
+            .collect();
+        assert_eq!(("ab ".to_owned(), false), classes[0]);
+        assert_eq!(("c  ".to_owned(), true), classes[2]);
+    }
+
+    #[test]
+    fn collapsed_folds_show_a_placeholder() {
+        let mut buffer = ScrollBuffer::new(Settings {
+            max_columns: 4,
+            max_lines: 2,
+            lines_to_remember: 10,
+            ..SMALL_SETTINGS
+        });
+        buffer.put_str("a\nb\nc\nd");
+        buffer.set_fold_placeholder("+{count}");
+
+        buffer.add_fold(0..2);
+        assert!(buffer.set_fold_collapsed(0, true));
+        assert_eq!(1, buffer.scrollback_line_count());
+        assert_eq!("+2  \nc   ", buffer.visible_text(1));
+        assert_eq!(LineOrigin::Fold, buffer.visible_line_metadata(1)[0].origin);
+        assert_eq!(0, buffer.visible_line_id(1, 0));
+        assert_eq!(2, buffer.visible_line_id(1, 1));
+        assert_eq!(Some(1), buffer.scrollback_to_show(1));
+
+        // Folds reaching into the visible grid stay expanded.
+        buffer.add_fold(2..4);
+        buffer.set_fold_collapsed(2, true);
+        assert_eq!("c   \nd   ", buffer.visible_text(0));
+
+        buffer.set_fold_collapsed(0, false);
+        assert_eq!(2, buffer.scrollback_line_count());
+        assert_eq!("a   \nb   ", buffer.visible_text(2));
+    }
+
+    #[test]
+    fn resizing_keeps_the_cursor_line() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        buffer.put_str("a\nbcd");
+
+        // The blank line below the cursor is dropped instead of scrolling,
+        // and the cursor line is rewrapped.
+        buffer.resize(2, 2);
+        assert_eq!("bc\nd ", buffer.visible_text(0));
+        assert_eq!((1, 1), buffer.cursor_xy());
+        assert_eq!(1, buffer.scrollback_line_count());
+
+        // Widening joins the wrapped line back together.
+        buffer.resize(4, 3);
+        assert_eq!("a   \nbcd \n    ", buffer.visible_text(0));
+        assert_eq!((3, 1), buffer.cursor_xy());
+
+        buffer.put_str("\ne");
+        buffer.resize(4, 2);
+        assert_eq!("bcd \ne   ", buffer.visible_text(0));
+        assert_eq!(1, buffer.scrollback_line_count());
+
+        // Growing pulls the line back out of the scrollback.
+        buffer.resize(4, 3);
+        assert_eq!("a   \nbcd \ne   ", buffer.visible_text(0));
+        assert_eq!((1, 2), buffer.cursor_xy());
+    }
+
+    #[test]
+    fn long_runs_wrap_like_single_characters() {
+        let settings = || Settings { max_columns: 4, lines_to_remember: 5, ..SMALL_SETTINGS };
+        let text = format!("$ {}\tab\r{}\nx", "0123456789".repeat(3), "yz".repeat(5));
+
+        let mut by_run = ScrollBuffer::new(settings());
+        by_run.put_str(&text);
+
+        let mut by_character = ScrollBuffer::new(settings());
+        for c in text.chars() {
+            by_character.put_character(c);
+        }
+        by_character.evict_excess_lines();
+
+        assert_eq!(by_character.entire_text(), by_run.entire_text());
+        assert_eq!(by_character.cursor_xy(), by_run.cursor_xy());
+        assert_eq!(by_character.evicted_line_count(), by_run.evicted_line_count());
+    }
+
+    #[test]
+    fn rewraps_the_scrollback_newest_first() {
+        let mut buffer = ScrollBuffer::new(Settings {
+            max_columns: 4,
+            max_lines: 2,
+            lines_to_remember: 10,
+            ..SMALL_SETTINGS
+        });
+        buffer.put_str("abcdef\nghij\nkl");
+        buffer.add_class_range(CellClass::Link, 0, 2..4);
+
+        // Only the lines filling the grid are rewrapped straight away.
+        buffer.resize(2, 2);
+        assert_eq!("abcd\nef  \ngh\nij\nkl", buffer.entire_text());
+        assert_eq!(ReflowProgress { remaining_lines: 2, total_lines: 4 }, buffer.reflow_progress());
+
+        assert_eq!(Some(ReflowProgress { remaining_lines: 0, total_lines: 4 }), buffer.continue_reflow(1));
+        assert_eq!("ab\ncd\nef\ngh\nij\nkl", buffer.entire_text());
+        assert_eq!(None, buffer.continue_reflow(1));
+
+        // Classes move with their cells.
+        let slices = buffer.visible_slices(4);
+        assert_eq!("cd", slices[2].text);
+        assert!(slices[2].classes.contains(CellClass::Link));
+    }
+
+    #[test]
+    fn reflow_round_trips_and_keeps_hard_line_breaks() {
+        let mut buffer = ScrollBuffer::new(Settings {
+            max_columns: 6,
+            lines_to_remember: 10,
+            ..SMALL_SETTINGS
+        });
+        buffer.put_str("abcdefgh\nxy");
+        let original = buffer.entire_text();
+
+        buffer.resize(3, 3);
+        while buffer.continue_reflow(1).is_some() {}
+        assert_eq!("abc\ndef\ngh \nxy ", buffer.entire_text());
+
+        // Only soft wraps are joined back together, as in xterm.
+        buffer.resize(6, 3);
+        while buffer.continue_reflow(1).is_some() {}
+        assert_eq!(original, buffer.entire_text());
+        assert_eq!((2, 2), buffer.cursor_xy());
+    }
+
+    #[test]
+    fn evicting_scrollback_keeps_the_grid() {
+        let mut buffer = ScrollBuffer::new(Settings { lines_to_remember: 10, ..SMALL_SETTINGS });
+        buffer.put_str("a\nb\nc\nd\ne");
+        let footprint = buffer.memory_footprint().total();
+
+        assert_eq!(2, buffer.evict_scrollback(5));
+        assert_eq!("c  \nd  \ne  ", buffer.entire_text());
+        assert_eq!(2, buffer.evicted_line_count());
+        assert!(buffer.memory_footprint().total() < footprint);
+    }
+
+    #[test]
+    fn finds_program_and_detected_links() {
+        let mut buffer = ScrollBuffer::new(Settings { max_columns: 30, ..SMALL_SETTINGS });
+        buffer.put_str("see (https://a.example/x). ok");
+        for x in 27..29 {
+            buffer.add_link(x, 0, "https://b.example");
+        }
+
+        let detected = buffer.link_at(0, 10, 0).unwrap();
+        assert_eq!(("https://a.example/x", LinkSource::Detected, 5..24),
+                   (detected.uri.as_str(), detected.source, detected.columns));
+
+        let program = buffer.link_at(0, 28, 0).unwrap();
+        assert_eq!(("https://b.example", LinkSource::Program, 27..29),
+                   (program.uri.as_str(), program.source, program.columns));
+
+        assert_eq!(None, buffer.link_at(0, 0, 0));
+        assert_eq!(None, buffer.link_at(0, 25, 0));
+    }
+
     #[test]
     fn empty_buffer_is_full_of_spaces() {
         let buffer = ScrollBuffer::new(SMALL_SETTINGS);
@@ -404,5 +2989,476 @@ mod test {
         write!(buffer, "abcdefghijklmnopqr").unwrap();
         assert_eq!("def\nghi\njkl\nmno\npqr", buffer.entire_text());
     }
-}
 
+    #[test]
+    fn does_not_record_timestamps_by_default() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+
+        write!(buffer, "a").unwrap();
+        assert_eq!(None, buffer.line_metadata(0).unwrap().timestamp);
+    }
+
+    #[test]
+    fn records_timestamps_of_written_lines_only() {
+        let mut buffer = ScrollBuffer::new(Settings { record_timestamps: true, ..SMALL_SETTINGS });
+
+        write!(buffer, "a\nb").unwrap();
+        let metadata = buffer.visible_line_metadata(0);
+        assert!(metadata[0].timestamp.is_some());
+        assert!(metadata[1].timestamp.is_some());
+        assert_eq!(None, metadata[2].timestamp);
+    }
+
+    #[test]
+    fn renders_elapsed_timestamp_gutter() {
+        let mut buffer = ScrollBuffer::new(Settings {
+            record_timestamps: true,
+            timestamp_gutter: TimestampGutter::Elapsed,
+            ..SMALL_SETTINGS
+        });
+
+        write!(buffer, "a").unwrap();
+        assert_eq!("[+00:00:00] a  \n               \n               ", buffer.visible_text(0));
+    }
+
+    #[test]
+    fn can_copy_overlapping_rect() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+
+        write!(buffer, "abcdefghi").unwrap();
+        buffer.copy_rect(Rect { left: 0, top: 0, right: 2, bottom: 2 }, 1, 1);
+        assert_eq!("abc\ndab\ngde", buffer.visible_text(0));
+    }
+
+    #[test]
+    fn can_fill_and_erase_rect() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+
+        buffer.fill_rect(Rect { left: 1, top: 0, right: usize::MAX, bottom: 2 }, 'x', Style::default());
+        assert_eq!(" xx\n xx\n   ", buffer.visible_text(0));
+        buffer.erase_rect(Rect { left: 2, top: 1, right: 3, bottom: 2 });
+        assert_eq!(" xx\n x \n   ", buffer.visible_text(0));
+    }
+
+    #[test]
+    fn converts_between_logical_and_visual_positions() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        buffer.put_str("abcdefg\nhi");
+
+        // "abc", "def" and "g" are one logical line, then "hi".
+        let g = buffer.logical_position(2, 0).unwrap();
+        assert_eq!(LogicalPosition { line_id: 0, column: 6 }, g);
+        assert_eq!(Some(VisualPosition { row: 1, column: 2 }), buffer.logical_to_visual(g, 4));
+        assert_eq!(Some(VisualPosition { row: 0, column: 6 }), buffer.logical_to_visual(g, 80));
+
+        for width in 1..10 {
+            for line in buffer.iter_lines(buffer.line_ids()) {
+                let text_columns = line.cells.iter().enumerate().filter(|(_, cell)| cell.character != ' ');
+
+                for (column, _) in text_columns {
+                    let logical = buffer.logical_position(line.id, column).unwrap();
+                    let visual = buffer.logical_to_visual(logical, width).unwrap();
+                    assert_eq!(Some(logical), buffer.visual_to_logical(visual, width));
+                    assert_eq!(Some(visual), buffer.logical_to_visual(logical, width));
+                }
+            }
+        }
+
+        assert_eq!(Some(LogicalPosition { line_id: 3, column: 2 }),
+                   buffer.visual_to_logical(VisualPosition { row: 2, column: 3 }, 4));
+        assert_eq!(None, buffer.visual_to_logical(VisualPosition { row: 3, column: 0 }, 4));
+    }
+
+    #[test]
+    fn erases_in_line_and_display() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        buffer.put_str("abc\ndef\nghi");
+
+        buffer.set_cursor_xy(1, 1);
+        buffer.erase_in_line(EraseMode::ToEnd, Color::TRANSPARENT);
+        assert_eq!("abc\nd  \nghi", buffer.visible_text(0));
+        buffer.erase_in_display(EraseMode::ToStart, Color::TRANSPARENT);
+        assert_eq!("   \n   \nghi", buffer.visible_text(0));
+
+        buffer.set_cursor_xy(2, 2);
+        buffer.erase_in_line(EraseMode::ToStart, Color::RED);
+        assert_eq!("   \n   \n   ", buffer.visible_text(0));
+        assert_eq!(Color::RED, buffer.cell(0, 2).unwrap().style.background);
+    }
+
+    #[test]
+    fn saves_and_restores_the_cursor() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        let red = Style { color: Color::RED, ..Style::default() };
+
+        buffer.put_str_styled("ab", red.clone());
+        buffer.save_cursor();
+        buffer.set_cursor_xy(0, 2);
+        buffer.put_str("st");
+        buffer.restore_cursor();
+
+        assert_eq!((2, 0), buffer.cursor_xy());
+        assert_eq!(&red, buffer.current_style());
+
+        buffer.save_cursor();
+        buffer.soft_reset();
+        assert_eq!(&Style::default(), buffer.current_style());
+        buffer.restore_cursor();
+        assert_eq!((0, 0), buffer.cursor_xy());
+    }
+
+    #[test]
+    fn inserts_and_deletes_characters_and_lines() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        buffer.put_str("abc\ndef\nghi");
+
+        buffer.set_cursor_xy(1, 0);
+        buffer.insert_chars(1);
+        assert_eq!("a b\ndef\nghi", buffer.visible_text(0));
+        buffer.delete_chars(2);
+        assert_eq!("a  \ndef\nghi", buffer.visible_text(0));
+
+        buffer.set_cursor_xy(2, 1);
+        buffer.insert_lines(1);
+        assert_eq!("a  \n   \ndef", buffer.visible_text(0));
+        assert_eq!((0, 1), buffer.cursor_xy());
+        buffer.delete_lines(5);
+        assert_eq!("a  \n   \n   ", buffer.visible_text(0));
+    }
+
+    #[test]
+    fn scrolls_only_the_scroll_region() {
+        let mut buffer = ScrollBuffer::new(Settings { max_lines: 4, ..SMALL_SETTINGS });
+        buffer.put_str("a\nb\nc\nd");
+        buffer.set_scroll_region(1..3);
+
+        buffer.set_cursor_xy(0, 2);
+        buffer.put_str("\ne");
+        assert_eq!("a  \nc  \ne  \nd  ", buffer.visible_text(0));
+        assert_eq!(0, buffer.scrollback_line_count());
+
+        buffer.scroll_region_down(1);
+        assert_eq!("a  \n   \nc  \nd  ", buffer.visible_text(0));
+
+        buffer.set_scroll_region(0..4);
+        assert_eq!(0..4, buffer.scroll_region());
+        buffer.scroll_region_up(1);
+        assert_eq!(1, buffer.scrollback_line_count());
+    }
+
+    #[test]
+    fn finds_text_matching_style() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+
+        buffer.put_str("ab");
+        buffer.put_character_styled('b', Style { color: Color::RED, ..Style::default() });
+
+        assert_eq!(vec![
+            TextMatch { line_number: 0, columns: 1..2 },
+            TextMatch { line_number: 0, columns: 2..3 },
+        ], buffer.find_text(0, "b", |_| true));
+        assert_eq!(vec![TextMatch { line_number: 0, columns: 2..3 }],
+                   buffer.find_text(0, "b", |style| style.color == Color::RED));
+        assert_eq!(Vec::<TextMatch>::new(), buffer.find_text(0, "bb", |style| style.color == Color::RED));
+    }
+
+    #[test]
+    fn iterates_over_lines_by_id() {
+        let mut buffer = ScrollBuffer::new(Settings { lines_to_remember: 10, ..SMALL_SETTINGS });
+        buffer.put_str("a\nb\nc\nd\ne\nf");
+
+        assert_eq!(0..6, buffer.line_ids());
+        let lines: Vec<_> = buffer.iter_lines(1..3).map(|line| (line.id, line.cells[0].character)).collect();
+        assert_eq!(vec![(1, 'b'), (2, 'c')], lines);
+
+        buffer.evict_scrollback(2);
+        assert_eq!(2..6, buffer.line_ids());
+        let ids: Vec<_> = buffer.iter_lines(0..4).map(|line| line.id).collect();
+        assert_eq!(vec![2, 3], ids);
+    }
+
+    #[test]
+    fn pages_stop_at_the_ends_of_the_buffer() {
+        let mut buffer = ScrollBuffer::new(Settings { lines_to_remember: 10, ..SMALL_SETTINGS });
+        buffer.put_str("a\nb\nc\nd\ne\nf\ng\nh");
+
+        assert_eq!(5, buffer.visible_line_id(0, 0));
+        assert_eq!(2, buffer.page_up(5));
+        assert_eq!(0, buffer.page_up(2));
+        assert_eq!(3, buffer.page_down(0));
+        assert_eq!(5, buffer.page_down(3));
+
+        buffer.evict_scrollback(4);
+        assert_eq!(4, buffer.page_up(0));
+        assert_eq!(5, buffer.page_down(0));
+    }
+
+    #[test]
+    fn attributes_are_kept_in_slices() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        let bold = Style { bold: true, ..Style::default() };
+        let emphasized = Style { italic: true, underlined: true, ..Style::default() };
+
+        buffer.put_character_styled('a', bold.clone());
+        buffer.put_str_styled("bc", emphasized.clone());
+
+        let slices = buffer.visible_slices(0);
+        assert_eq!(("a", &bold), (slices[0].text.as_str(), &slices[0].style));
+        assert_eq!(("bc", &emphasized), (slices[1].text.as_str(), &slices[1].style));
+    }
+
+    #[test]
+    fn restyles_cells_before_splitting_slices() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        buffer.put_character_styled('a', Style { color: Color::RED, ..Style::default() });
+        buffer.put_character_styled('b', Style { color: Color::GREEN, ..Style::default() });
+
+        let mut columns = Vec::new();
+        let slices = buffer.visible_slices_styled(0, &SliceOptions::default(), &mut |style, context| {
+            columns.push((context.line_id, context.column));
+            Style { color: Color::WHITE, ..style.clone() }
+        });
+
+        assert_eq!("ab", slices[0].text);
+        assert_eq!(Color::WHITE, slices[0].style.color);
+        assert_eq!(vec![(0, 0), (0, 1)], columns[..2].to_vec());
+    }
+
+    #[test]
+    fn background_changes_split_slices() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        let highlighted = Style { background: Color::BLUE, ..Style::default() };
+
+        buffer.put_str("a");
+        buffer.put_str_styled("bc", highlighted.clone());
+
+        let slices = buffer.visible_slices(0);
+        assert_eq!("a", slices[0].text);
+        assert_eq!(Color::TRANSPARENT, slices[0].style.background);
+        assert_eq!("bc", slices[1].text);
+        assert_eq!(highlighted, slices[1].style);
+    }
+
+    #[test]
+    fn thumbnails_summarize_blocks_of_cells() {
+        let mut buffer = ScrollBuffer::new(Settings { max_columns: 4, max_lines: 2, ..SMALL_SETTINGS });
+        buffer.put_str_styled("ab", Style { color: Color::RED, background: Color::BLUE, ..Style::default() });
+        buffer.put_str(" c");
+
+        let thumbnail = buffer.thumbnail(2, 1);
+        assert_eq!((2, 1), (thumbnail.columns, thumbnail.rows));
+        assert_eq!(vec![
+            ThumbnailBlock { color: Some(Color::RED), background: Color::BLUE, density: 0.5 },
+            ThumbnailBlock { color: Some(Style::default().color), background: Color::TRANSPARENT, density: 0.25 },
+        ], thumbnail.blocks);
+
+        let capped = buffer.thumbnail(10, 10);
+        assert_eq!((4, 2, 8), (capped.columns, capped.rows, capped.blocks.len()));
+        assert!(buffer.thumbnail(0, 1).blocks.is_empty());
+    }
+
+    #[test]
+    fn memory_footprint_grows_with_scrollback() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        let initial = buffer.memory_footprint();
+
+        write!(buffer, "abcdefghij").unwrap();
+        let footprint = buffer.memory_footprint();
+        assert_eq!(initial.cells * 4 / 3, footprint.cells);
+        assert!(footprint.total() > initial.total());
+    }
+
+    #[test]
+    fn evicted_lines_are_reused() {
+        let mut buffer = ScrollBuffer::new(Settings { lines_to_remember: 1, ..SMALL_SETTINGS });
+        buffer.put_str("a\nb\nc\nd\ne");
+        assert!(buffer.memory_footprint().spare > 0);
+
+        let footprint = buffer.memory_footprint();
+        buffer.put_str("\nf\ng");
+        assert_eq!(footprint, buffer.memory_footprint());
+        assert_eq!("d  \ne  \nf  \ng  ", buffer.entire_text());
+
+        buffer.evict_scrollback(1);
+        assert_eq!(0, buffer.memory_footprint().spare);
+    }
+
+    #[test]
+    fn backspace_reverse_wraps_only_onto_wrapped_lines() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        buffer.set_reverse_wrap(true);
+
+        write!(buffer, "abcd").unwrap();
+        buffer.backspace();
+        buffer.backspace();
+        assert_eq!("ab \n   \n   ", buffer.visible_text(0));
+        assert_eq!((2, 0), buffer.cursor_xy());
+
+        write!(buffer, "\nx").unwrap();
+        buffer.backspace();
+        buffer.backspace();
+        assert_eq!("ab \n   \n   ", buffer.visible_text(0));
+        assert_eq!((0, 1), buffer.cursor_xy());
+    }
+
+    #[test]
+    fn splits_slices_at_class_boundaries() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+
+        write!(buffer, "abc\nd").unwrap();
+        buffer.add_class_range(CellClass::Selection, 0, 1..3);
+
+        let slices = buffer.visible_slices(0);
+        let texts: Vec<_> = slices.iter().map(|slice| slice.text.as_str()).collect();
+        assert_eq!(vec!["a", "bc", "\n", "d", " ", " ", "\n", "   ", "\n"], texts);
+        assert!(slices[1].classes.contains(CellClass::Selection));
+        assert!(slices[4].classes.contains(CellClass::Cursor));
+    }
+
+    #[test]
+    fn concealed_cells_are_blank_unless_revealed() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        buffer.put_str_styled("pw", Style { concealed: true, ..Style::default() });
+
+        assert_eq!("  ", buffer.visible_slices(0)[0].text);
+        assert_eq!("pw", buffer.visible_slices_with(0, &SliceOptions { reveal_concealed: true, ..SliceOptions::default() })[0].text);
+        assert_eq!('p', buffer.cell(0, 0).unwrap().character);
+    }
+
+    #[test]
+    fn ligature_safe_slices_ignore_other_classes() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+
+        write!(buffer, "->=
+a").unwrap();
+        buffer.add_class_range(CellClass::Prompt, 0, 0..1);
+        buffer.add_class_range(CellClass::Selection, 0, 2..3);
+
+        let slices = buffer.visible_slices_with(0, &SliceOptions::ligature_safe());
+        let texts: Vec<_> = slices.iter().map(|slice| slice.text.as_str()).collect();
+        assert_eq!(vec!["->", "=", "\n", "a", " ", " ", "\n", "   ", "\n"], texts);
+        assert!(!slices[0].classes.contains(CellClass::Prompt));
+        assert!(slices[4].classes.contains(CellClass::Cursor));
+    }
+
+    #[test]
+    fn out_of_bounds_accesses_fail_gracefully() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+
+        assert_eq!(None, buffer.cell(3, 0));
+        assert_eq!(None, buffer.cell(0, 3));
+        assert_eq!(Err(GridError::OutOfBounds { x: 0, y: 3 }), buffer.set_cell(0, 3, Cell::default()));
+
+        buffer.set_cursor_xy(100, 100);
+        buffer.put_character('a');
+        assert_eq!(Some('a'), buffer.cell(0, 2).map(|cell| cell.character));
+    }
+
+    #[test]
+    fn copies_the_selection_with_its_source() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        assert_eq!(None, buffer.copy_selection());
+
+        let bold = Style { bold: true, ..Style::default() };
+        buffer.put_str("abcde\n");
+        buffer.put_str_styled("f", bold.clone());
+        buffer.add_class_range(CellClass::Selection, 2, 0..3);
+        buffer.add_class_range(CellClass::Selection, 0, 1..3);
+        buffer.add_class_range(CellClass::Selection, 1, 0..3);
+
+        let copied = buffer.copy_selection().unwrap();
+        assert_eq!("bcde\nf", copied.text);
+        assert_eq!(0..3, copied.source_line_ids);
+        let styled: Vec<_> = copied.styled.iter().map(|slice| (slice.text.as_str(), slice.style.bold)).collect();
+        assert_eq!(vec![("bcde", false), ("\n", false), ("f", true)], styled);
+    }
+
+    #[test]
+    fn clearing_everything_keeps_a_full_viewport() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+
+        write!(buffer, "abcdefghijkl").unwrap();
+        buffer.clear_everything();
+        assert_eq!("   \n   \n   ", buffer.entire_text());
+    }
+
+    #[test]
+    fn line_text_follows_cell_changes() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+
+        write!(buffer, "abc").unwrap();
+        assert_eq!("abc", buffer.lines_text(0..1));
+
+        buffer.set_cell(1, 0, Cell { character: 'x', ..Cell::default() }).unwrap();
+        assert_eq!("axc", buffer.lines_text(0..1));
+
+        buffer.set_cursor_xy(0, 0);
+        buffer.delete_chars(1);
+        assert_eq!("xc", buffer.lines_text(0..1));
+    }
+
+    #[test]
+    fn encodes_lines_as_style_runs_with_stable_hashes() {
+        let mut buffer = ScrollBuffer::new(SMALL_SETTINGS);
+        let bold = Style { bold: true, ..Style::default() };
+
+        buffer.put_str_styled("ab", bold.clone());
+        buffer.put_str("c\n");
+        buffer.put_str_styled("ab", bold.clone());
+        buffer.put_str("c\nab");
+
+        let lines = buffer.encode_lines(0..3);
+        assert_eq!(EncodedLine {
+            id: 0,
+            text: "abc".to_owned(),
+            runs: vec![StyleRun { length: 2, style: bold }, StyleRun { length: 1, style: Style::default() }],
+            wrapped: false,
+        }, lines[0]);
+
+        assert_eq!(lines[0].content_hash(), lines[1].content_hash());
+        assert_ne!(lines[0].content_hash(), lines[2].content_hash());
+        // Remote clients may keep hashes from older versions, so they must not change.
+        assert_eq!(0xeb20_a16b_17c0_43b1, lines[0].content_hash());
+    }
+
+    #[test]
+    fn records_commands_between_shell_marks() {
+        let mut buffer = ScrollBuffer::new(Settings { max_columns: 8, max_lines: 4, lines_to_remember: 4, ..SMALL_SETTINGS });
+
+        buffer.record_shell_mark(ShellMark::PromptStart);
+        buffer.put_str("$ ");
+        buffer.record_shell_mark(ShellMark::CommandStart);
+        buffer.put_str("ls\n");
+        buffer.record_shell_mark(ShellMark::OutputStart);
+        buffer.put_str("a.txt\nb.txt\n");
+        buffer.record_shell_mark(ShellMark::CommandFinished { exit_code: Some(0) });
+        buffer.record_shell_mark(ShellMark::PromptStart);
+        buffer.put_str("$ ");
+        buffer.record_shell_mark(ShellMark::CommandStart);
+        buffer.put_str("cat\n");
+        buffer.record_shell_mark(ShellMark::OutputStart);
+        buffer.put_str("waiting");
+
+        let commands = buffer.commands();
+        assert_eq!(2, commands.len());
+        assert!(commands[0].duration.is_some());
+        assert_eq!(ShellCommand {
+            prompt_line_id: Some(0),
+            command: "ls".to_owned(),
+            output: "a.txt\nb.txt".to_owned(),
+            output_line_ids: 1..3,
+            finished: true,
+            exit_code: Some(0),
+            duration: commands[0].duration,
+        }, commands[0]);
+        assert_eq!(ShellCommand {
+            prompt_line_id: Some(3),
+            command: "cat".to_owned(),
+            output: "waiting".to_owned(),
+            output_line_ids: 4..5,
+            finished: false,
+            exit_code: None,
+            duration: None,
+        }, commands[1]);
+    }
+}
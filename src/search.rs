@@ -0,0 +1,227 @@
+//! Interactive search-as-you-type over the whole scroll buffer.
+
+use crate::{scroll_buffer::ScrollBuffer, CellClass};
+#[allow(unused_imports)]
+use crate::std_prelude::*;
+use core::ops::Range;
+
+/// An occurrence of the search query anywhere in the buffer, including the scrollback.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SearchMatch {
+    /// The stable id of the line, as given by `ScrollBuffer::visible_line_id`.
+    pub line_id: usize,
+    /// The range of matched cells within the line.
+    pub columns: Range<usize>,
+}
+
+/// The state of a search, kept by the scroll buffer between sessions.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SearchState {
+    query: String,
+    /// Matches ordered from the oldest line to the newest.
+    matches: Vec<SearchMatch>,
+    /// The index of the current match.
+    current: Option<usize>,
+}
+
+/// An interactive search session.
+///
+/// Every change to the query updates the matches and highlights them
+/// with `CellClass::SearchMatch`, and the current match with
+/// `CellClass::CurrentSearchMatch`. The query is kept when the session
+/// is dropped, so that it can be resumed; call `end` to clear it.
+pub struct Search<'a> {
+    buffer: &'a mut ScrollBuffer,
+}
+
+impl SearchState {
+    /// Checks if there is a search to keep up to date.
+    pub(crate) fn is_active(&self) -> bool {
+        !self.query.is_empty()
+    }
+
+    /// Moves matches at or after a line down by one, after a line is inserted.
+    pub(crate) fn shift_lines(&mut self, first_line_id: usize) {
+        for m in self.matches.iter_mut().filter(|m| m.line_id >= first_line_id) {
+            m.line_id += 1;
+        }
+    }
+}
+
+impl<'a> Search<'a> {
+    pub(crate) fn new(buffer: &'a mut ScrollBuffer) -> Self {
+        Search { buffer }
+    }
+
+    /// Gets the query.
+    pub fn query(&self) -> &str {
+        &self.buffer.search.query
+    }
+
+    /// Appends a character to the query.
+    pub fn push_char(&mut self, c: char) {
+        self.buffer.search.query.push(c);
+        self.refresh();
+    }
+
+    /// Removes the last character of the query.
+    pub fn pop_char(&mut self) -> Option<char> {
+        let c = self.buffer.search.query.pop();
+        self.refresh();
+        c
+    }
+
+    /// Replaces the query.
+    pub fn set_query(&mut self, query: &str) {
+        self.buffer.search.query = query.to_owned();
+        self.refresh();
+    }
+
+    /// Gets every match, ordered from the oldest line to the newest.
+    pub fn matches(&self) -> &[SearchMatch] {
+        &self.buffer.search.matches
+    }
+
+    /// Gets the current match, if there are any matches.
+    pub fn current_match(&self) -> Option<&SearchMatch> {
+        let state = &self.buffer.search;
+        state.current.map(|index| &state.matches[index])
+    }
+
+    /// Moves to the closest older match, wrapping around to the newest.
+    pub fn previous_match(&mut self) -> Option<&SearchMatch> {
+        let count = self.buffer.search.matches.len();
+        self.select(|current| current.map(|i| (i + count - 1) % count).unwrap_or(count - 1))
+    }
+
+    /// Moves to the closest newer match, wrapping around to the oldest.
+    pub fn next_match(&mut self) -> Option<&SearchMatch> {
+        let count = self.buffer.search.matches.len();
+        self.select(|current| current.map(|i| (i + 1) % count).unwrap_or(0))
+    }
+
+    /// Searches again with the same query, as when more output has arrived.
+    ///
+    /// The current match is kept where possible. Otherwise the closest
+    /// older match becomes current, as searches run back through history.
+    pub fn refresh(&mut self) {
+        let previous = self.current_match().map(|m| (m.line_id, m.columns.start));
+
+        let matches = self.buffer.find_search_matches(&self.buffer.search.query);
+        let current = match previous {
+            Some(previous) => matches.iter()
+                .rposition(|m| (m.line_id, m.columns.start) <= previous)
+                .or_else(|| matches.len().checked_sub(1)),
+            None => matches.len().checked_sub(1),
+        };
+
+        self.buffer.search.matches = matches;
+        self.buffer.search.current = current;
+        self.highlight();
+    }
+
+    /// Ends the search, clearing the query and the highlights.
+    pub fn end(self) {
+        self.buffer.search = SearchState::default();
+        self.buffer.clear_class(CellClass::SearchMatch);
+        self.buffer.clear_class(CellClass::CurrentSearchMatch);
+    }
+
+    fn select<F>(&mut self, f: F) -> Option<&SearchMatch>
+        where F: FnOnce(Option<usize>) -> usize {
+        if self.buffer.search.matches.is_empty() {
+            return None;
+        }
+
+        self.buffer.search.current = Some(f(self.buffer.search.current));
+        self.highlight();
+        self.current_match()
+    }
+
+    /// Replaces the search highlights with the current matches.
+    fn highlight(&mut self) {
+        self.buffer.clear_class(CellClass::SearchMatch);
+        self.buffer.clear_class(CellClass::CurrentSearchMatch);
+
+        let matches = self.buffer.search.matches.clone();
+        for m in matches.iter() {
+            self.buffer.add_class_range(CellClass::SearchMatch, m.line_id, m.columns.clone());
+        }
+
+        if let Some(m) = self.current_match().cloned() {
+            self.buffer.add_class_range(CellClass::CurrentSearchMatch, m.line_id, m.columns);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scroll_buffer::{Settings, TimestampGutter};
+
+    fn buffer(text: &str) -> ScrollBuffer {
+        let mut buffer = ScrollBuffer::new(Settings {
+            max_columns: 6,
+            max_lines: 2,
+            lines_to_remember: 10,
+            tab_width: 4,
+            record_timestamps: false,
+            timestamp_gutter: TimestampGutter::Hidden,
+        });
+        buffer.put_str(text);
+        buffer
+    }
+
+    fn columns(search: &Search) -> Vec<(usize, Range<usize>)> {
+        search.matches().iter().map(|m| (m.line_id, m.columns.clone())).collect()
+    }
+
+    #[test]
+    fn narrows_matches_as_the_query_grows() {
+        let mut buffer = buffer("cat\ncar\ncart\n");
+        let mut search = buffer.search();
+
+        search.push_char('c');
+        search.push_char('a');
+        assert_eq!(vec![(0, 0..2), (1, 0..2), (2, 0..2)], columns(&search));
+        // Searches start from the newest output.
+        assert_eq!(2, search.current_match().unwrap().line_id);
+
+        search.push_char('t');
+        assert_eq!(vec![(0, 0..3)], columns(&search));
+        assert_eq!(0, search.current_match().unwrap().line_id);
+
+        assert_eq!(Some('t'), search.pop_char());
+        assert_eq!("ca", search.query());
+        assert_eq!(0, search.current_match().unwrap().line_id);
+    }
+
+    #[test]
+    fn moves_between_matches_with_wrapping() {
+        let mut buffer = buffer("ab\nab\n");
+        let mut search = buffer.search();
+        search.set_query("ab");
+
+        assert_eq!(0, search.previous_match().unwrap().line_id);
+        assert_eq!(1, search.previous_match().unwrap().line_id);
+        assert_eq!(0, search.next_match().unwrap().line_id);
+    }
+
+    #[test]
+    fn highlights_matches_in_slices() {
+        let mut buffer = buffer("xabab");
+        buffer.search().set_query("ab");
+
+        let classes: Vec<_> = buffer.visible_slices(0).iter()
+            .map(|slice| (slice.text.clone(),
+                          slice.classes.contains(CellClass::SearchMatch),
+                          slice.classes.contains(CellClass::CurrentSearchMatch)))
+            .collect();
+        assert_eq!(("x".to_owned(), false, false), classes[0]);
+        assert_eq!(("ab".to_owned(), true, false), classes[1]);
+        assert_eq!(("ab".to_owned(), true, true), classes[2]);
+
+        buffer.search().end();
+        assert!(buffer.visible_slices(0).iter().all(|slice| !slice.classes.contains(CellClass::SearchMatch)));
+    }
+}
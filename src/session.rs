@@ -0,0 +1,97 @@
+//! A simpler way to drive a terminal, for the common case of running a
+//! program, typing into it and drawing what it shows.
+
+use crate::{
+    core::{Settings, Terminal},
+    encode::KeyEvent,
+    event::Event,
+    TextSlice,
+};
+use std::{io, time::Duration};
+
+/// A terminal running a program, with the most common workflow in a few
+/// methods: `spawn`, `send`, `pump` and `render`.
+///
+/// Everything else is available through `terminal` and `terminal_mut`.
+pub struct Session {
+    terminal: Terminal,
+}
+
+impl Session {
+    /// Runs a shell in a new terminal with the default settings.
+    pub fn spawn(shell: &str) -> Result<Self, io::Error> {
+        Session::spawn_with(Settings { shell: shell.to_owned(), ..Settings::default() })
+    }
+
+    /// Runs the shell given by the settings in a new terminal.
+    pub fn spawn_with(settings: Settings) -> Result<Self, io::Error> {
+        Terminal::new(settings).map(Session::from)
+    }
+
+    /// Types text into the running program.
+    pub fn send(&mut self, text: &str) -> Result<(), io::Error> {
+        self.terminal.write_text(text)
+    }
+
+    /// Presses a key, such as `Key::Enter` or ctrl+C.
+    pub fn send_key(&mut self, key: impl Into<KeyEvent>) -> Result<(), io::Error> {
+        let key = key.into();
+        self.terminal.send_key(key.key, key.modifiers)
+    }
+
+    /// Handles the program's output, waiting up to `timeout` for some to arrive.
+    pub fn pump(&mut self, timeout: Duration) -> Result<Vec<Event>, io::Error> {
+        self.terminal.update_timeout(timeout)
+    }
+
+    /// Gets the visible grid as styled slices, ready to draw.
+    pub fn render(&self) -> Vec<TextSlice> {
+        self.terminal.visible_slices()
+    }
+
+    /// Gets the visible grid as plain text.
+    pub fn text(&self) -> String {
+        self.terminal.visible_text()
+    }
+
+    /// Checks if the program has exited.
+    pub fn is_finished(&self) -> bool {
+        self.terminal.is_session_finished()
+    }
+
+    /// Gets the underlying terminal.
+    pub fn terminal(&self) -> &Terminal { &self.terminal }
+
+    /// Gets the underlying terminal mutably.
+    pub fn terminal_mut(&mut self) -> &mut Terminal { &mut self.terminal }
+
+    /// Gives back the underlying terminal.
+    pub fn into_terminal(self) -> Terminal { self.terminal }
+}
+
+impl From<Terminal> for Session {
+    fn from(terminal: Terminal) -> Self {
+        Session { terminal }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encode::Key;
+
+    #[test]
+    fn pumps_and_renders_output() {
+        let mut terminal = Terminal::headless(Settings { column_count: 10, line_count: 2, ..Settings::default() });
+        terminal.feed(b"\x1b[1mhi\x1b[0m there").unwrap();
+        let mut session = Session::from(terminal);
+
+        session.send("ignored").unwrap();
+        session.send_key(Key::Enter).unwrap();
+        session.pump(Duration::from_millis(0)).unwrap();
+
+        assert_eq!("hi there  \n          ", session.text());
+        assert!(session.render()[0].style.bold);
+        assert!(!session.is_finished());
+    }
+}
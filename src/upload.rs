@@ -0,0 +1,79 @@
+//! Writing files to the running program a chunk at a time, as with
+//! `cat > file`, without holding the whole file in memory.
+
+use crate::event::UploadProgress;
+use std::{
+    fmt,
+    io::{self, Read},
+    sync::{Mutex, PoisonError},
+};
+
+/// The most read from the file at once. The next chunk is only read once
+/// less than this is waiting to be written.
+pub const CHUNK_SIZE: usize = 4096;
+
+/// A file being written to the running program.
+pub struct Upload {
+    /// Locked only to make the upload `Sync`; it is only read with `&mut self`.
+    reader: Mutex<Box<dyn Read + Send>>,
+    progress: UploadProgress,
+}
+
+impl Upload {
+    /// Starts an upload of everything `reader` produces.
+    pub fn new(reader: Box<dyn Read + Send>, total_bytes: Option<u64>) -> Self {
+        Upload {
+            reader: Mutex::new(reader),
+            progress: UploadProgress { bytes_sent: 0, total_bytes, finished: false },
+        }
+    }
+
+    /// Reads the next chunk, or `None` once the file has been read.
+    pub fn read_chunk(&mut self) -> Result<Option<Vec<u8>>, io::Error> {
+        let reader = self.reader.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let mut chunk = vec![0; CHUNK_SIZE];
+
+        loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => {
+                    self.progress.finished = true;
+                    return Ok(None);
+                },
+                Ok(length) => {
+                    chunk.truncate(length);
+                    self.progress.bytes_sent += length as u64;
+                    return Ok(Some(chunk));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Gets how far the upload has got.
+    pub fn progress(&self) -> UploadProgress {
+        self.progress
+    }
+}
+
+impl fmt::Debug for Upload {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Upload").field("progress", &self.progress).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_in_chunks_until_the_end() {
+        let mut upload = Upload::new(Box::new(io::Cursor::new(vec![b'a'; CHUNK_SIZE + 1])), Some(CHUNK_SIZE as u64 + 1));
+
+        assert_eq!(CHUNK_SIZE, upload.read_chunk().unwrap().unwrap().len());
+        assert_eq!(Some(vec![b'a']), upload.read_chunk().unwrap());
+        assert_eq!(None, upload.read_chunk().unwrap());
+        assert_eq!(UploadProgress { bytes_sent: CHUNK_SIZE as u64 + 1, total_bytes: Some(CHUNK_SIZE as u64 + 1), finished: true },
+                   upload.progress());
+    }
+}
@@ -0,0 +1,31 @@
+//! Exercises the grid model, which is also available without the `std` feature.
+
+use readterm::scroll_buffer::{ScrollBuffer, Settings, TimestampGutter};
+
+fn buffer() -> ScrollBuffer {
+    ScrollBuffer::new(Settings {
+        max_columns: 5,
+        max_lines: 3,
+        tab_width: 4,
+        lines_to_remember: 10,
+        record_timestamps: false,
+        timestamp_gutter: TimestampGutter::Hidden,
+    })
+}
+
+#[test]
+fn writes_and_wraps_text() {
+    let mut buffer = buffer();
+    buffer.put_str("hello world");
+
+    assert_eq!("hello\n worl\nd    ", buffer.visible_text(0));
+    assert_eq!((1, 2), buffer.cursor_xy());
+}
+
+#[test]
+fn finds_text() {
+    let mut buffer = buffer();
+    buffer.put_str("abcab");
+
+    assert_eq!(2, buffer.find_text(0, "ab", |_| true).len());
+}
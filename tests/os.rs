@@ -1,3 +1,7 @@
+#![cfg(feature = "std")]
+
 mod os {
     mod default;
+    #[cfg(unix)]
+    mod unix;
 }
@@ -1,6 +1,7 @@
-use readterm::Settings;
+use readterm::{Event, ExitStatus, Settings};
 use readterm::os::default::Driver;
 use readterm::os::Driver as _;
+use std::time::{Duration, Instant};
 
 
 fn create_driver() -> Driver {
@@ -12,31 +13,88 @@ fn can_create_driver() {
     let _ = create_driver();
 }
 
+#[test]
+fn can_box_driver() {
+    let mut driver: Box<dyn readterm::os::Driver> = Box::new(create_driver());
+    driver.send_raw("exit 0\n").unwrap();
+}
+
+#[test]
+fn cannot_reconnect_a_local_shell() {
+    let mut driver = create_driver();
+    assert!(driver.reconnect().is_err());
+}
+
 #[test]
 fn can_echo_text() {
     let mut driver = create_driver();
 
-    driver.write_text("echo 1\n");
-    driver.write_text("exit 0\n");
+    driver.write_text("echo 1\n").unwrap();
+    driver.write_text("exit 0\n").unwrap();
 
-    let events = driver.update_blocking();
+    let events: Vec<_> = driver.update_blocking().unwrap().into_iter()
+        .filter(|event| !matches!(event, Event::SessionExited { .. }))
+        .collect();
     assert_eq!(events, build::events_for_plain_text("1\n"));
 }
 
+#[test]
+fn reports_the_exit_status() {
+    let mut driver = create_driver();
+    driver.write_text("exit 3\n").unwrap();
+
+    let mut events = Vec::new();
+    while !driver.is_session_finished() {
+        events.extend(driver.update_timeout(Duration::from_millis(10)).unwrap());
+    }
+
+    assert_eq!(Some(ExitStatus { code: Some(3), signal: None }), driver.exit_status());
+    assert!(events.contains(&Event::SessionExited { code: Some(3), signal: None }));
+}
+
+#[cfg(unix)]
+#[test]
+fn background_jobs_do_not_delay_the_exit() {
+    let mut driver = create_driver();
+    driver.write_text("sleep 4 &\nexit 0\n").unwrap();
+
+    let started = Instant::now();
+    while !driver.is_session_finished() && started.elapsed() < Duration::from_secs(2) {
+        driver.update_timeout(Duration::from_millis(10)).unwrap();
+    }
+    assert!(driver.is_session_finished(), "the exit was held up by the background job");
+
+    let dropped = Instant::now();
+    drop(driver);
+    assert!(dropped.elapsed() < Duration::from_secs(1), "dropping the driver waited on the background job");
+}
+
 mod build {
     use readterm::{Color, Event};
 
+    /// Builds the events for text on the first line, where a line feed
+    /// moves down without returning.
     pub fn events_for_plain_text(s: &str) -> Vec<Event> {
+        let (mut x, mut y) = (0, 0);
+
         s.chars().map(|character| {
+            let (character_x, character_y) = (x, y);
+            match character {
+                '\n' => y += 1,
+                _ => x += 1,
+            }
+
             Event::PutCharacter {
-                x: 0,
-                y: 0,
+                x: character_x,
+                y: character_y,
                 character,
                 bold: false,
                 italic: false,
                 underlined: false,
                 strikethrough: false,
+                overline: false,
                 color: Color::WHITE,
+                background: Color::TRANSPARENT,
             }
         }).collect()
     }
@@ -0,0 +1,144 @@
+use readterm::os::unix::Driver;
+use readterm::os::Driver as _;
+use readterm::{ExitStatus, PtyOwnership, Settings, Signal};
+use std::os::unix::io::RawFd;
+
+/// Opens a PTY, returning its master and slave sides.
+fn open_pty() -> (RawFd, RawFd) {
+    let (mut master, mut slave) = (0, 0);
+    let result = unsafe {
+        libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), std::ptr::null())
+    };
+    assert_eq!(0, result, "failed to open a PTY");
+    (master, slave)
+}
+
+fn is_open(fd: RawFd) -> bool {
+    unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 }
+}
+
+#[test]
+fn reads_from_an_adopted_pty_until_it_hangs_up() {
+    let (master, slave) = open_pty();
+    let mut driver = unsafe { Driver::adopt(&Settings::default(), master, None, PtyOwnership::Borrowed) }
+        .expect("failed to adopt PTY");
+
+    assert_eq!(2, unsafe { libc::write(slave, b"hi".as_ptr() as *const _, 2) });
+    let text: String = driver.update_blocking().unwrap().into_iter().filter_map(|event| match event {
+        readterm::Event::PutCharacter { character, .. } => Some(character),
+        _ => None,
+    }).collect();
+    assert_eq!("hi", text);
+
+    unsafe { libc::close(slave) };
+    driver.update().unwrap();
+    driver.update().unwrap();
+    assert!(driver.is_session_finished());
+    // Without the child's process ID, only the hang up can be seen.
+    assert_eq!(Some(ExitStatus { code: None, signal: None }), driver.exit_status());
+    // The embedder started the child, so only it can start another.
+    assert!(driver.respawn(&Settings::default()).is_err());
+
+    drop(driver);
+    assert!(is_open(master), "a borrowed PTY must be left open");
+    unsafe { libc::close(master) };
+}
+
+#[test]
+fn closes_an_owned_pty_on_drop() {
+    let (master, slave) = open_pty();
+    let driver = unsafe { Driver::adopt(&Settings::default(), master, None, PtyOwnership::Owned) }
+        .expect("failed to adopt PTY");
+
+    drop(driver);
+    assert!(!is_open(master));
+    unsafe { libc::close(slave) };
+}
+
+#[test]
+fn cannot_adopt_a_closed_descriptor() {
+    let (master, slave) = open_pty();
+    unsafe {
+        libc::close(master);
+        libc::close(slave);
+    }
+
+    assert!(unsafe { Driver::adopt(&Settings::default(), master, None, PtyOwnership::Owned) }.is_err());
+}
+
+#[test]
+fn signals_the_foreground_process_group() {
+    use std::os::unix::{io::FromRawFd, process::{CommandExt, ExitStatusExt}};
+    use std::process::{Command, Stdio};
+
+    let (master, slave) = open_pty();
+    let stdio = || unsafe { Stdio::from_raw_fd(libc::dup(slave)) };
+    let mut command = Command::new("sh");
+    command.args(["-c", "echo ready; exec sleep 10"]).stdin(stdio()).stdout(stdio()).stderr(stdio());
+    // Make the PTY the child's controlling terminal, as `forkpty` would.
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            libc::ioctl(0, libc::TIOCSCTTY, 0);
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().expect("failed to spawn child");
+    unsafe { libc::close(slave) };
+
+    let mut driver = unsafe { Driver::adopt(&Settings::default(), master, None, PtyOwnership::Owned) }
+        .expect("failed to adopt PTY");
+    // Once the child has written, it is the foreground process group.
+    driver.update_blocking().unwrap();
+    driver.signal(Signal::Terminate).unwrap();
+
+    assert_eq!(Some(libc::SIGTERM), child.wait().unwrap().signal());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn reaps_orphaned_jobs_in_their_own_process_groups() {
+    use std::os::unix::{io::FromRawFd, process::CommandExt};
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    // Orphans are reparented to this process, as `Driver::new` arranges.
+    assert_eq!(0, unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) });
+
+    let (master, slave) = open_pty();
+    let stdio = || unsafe { Stdio::from_raw_fd(libc::dup(slave)) };
+    // With job control, the subshell is a job with its own process group,
+    // and the command it backgrounds is orphaned once it exits.
+    let mut command = Command::new("sh");
+    command.args(["-c", "set -m; (sh -c 'sleep 0.2; exit 3' &); exec sleep 10"])
+        .stdin(stdio()).stdout(stdio()).stderr(stdio());
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            libc::ioctl(0, libc::TIOCSCTTY, 0);
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().expect("failed to spawn child");
+    unsafe { libc::close(slave) };
+
+    // An exited child of the embedder's own must not hold up the reaping, nor be reaped.
+    let mut unrelated = Command::new("true").spawn().expect("failed to spawn child");
+    std::thread::sleep(Duration::from_millis(50));
+
+    let settings = Settings { reap_orphaned_descendants: true, ..Settings::default() };
+    let mut driver = unsafe { Driver::adopt(&settings, master, Some(child.id() as libc::pid_t), PtyOwnership::Owned) }
+        .expect("failed to adopt PTY");
+
+    let started = Instant::now();
+    let mut reaped = false;
+    while !reaped && started.elapsed() < Duration::from_secs(5) {
+        reaped = driver.update_timeout(Duration::from_millis(10)).unwrap().iter()
+            .any(|event| matches!(event, readterm::Event::DescendantExited { code: Some(3), .. }));
+    }
+    assert!(reaped, "the orphaned job was not reaped");
+    assert!(unrelated.wait().unwrap().success());
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
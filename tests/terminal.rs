@@ -0,0 +1,62 @@
+//! Tests that run a real shell through the platform driver.
+
+#![cfg(feature = "std")]
+
+use readterm::{RunOptions, Settings, Terminal};
+use std::{thread, time::{Duration, Instant}};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn create_terminal() -> Terminal {
+    Terminal::new(Settings {
+        shell: "sh".to_owned(),
+        ..Settings::default()
+    }).expect("failed to create terminal")
+}
+
+/// Updates the terminal until the predicate holds.
+fn wait_until<F>(terminal: &mut Terminal, mut predicate: F)
+    where F: FnMut(&mut Terminal) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+
+    while !predicate(terminal) {
+        assert!(Instant::now() < deadline, "timed out, screen:\n{}", terminal.visible_text());
+
+        terminal.update().unwrap();
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn typed_text_appears_once_with_echo_on() {
+    let mut terminal = create_terminal();
+
+    terminal.write_text("echo one-two; echo done\n").unwrap();
+    wait_until(&mut terminal, |t| t.visible_text().contains("\ndone"));
+
+    assert_eq!(1, terminal.visible_text().matches("echo one-two").count());
+}
+
+#[test]
+fn typed_text_does_not_appear_with_echo_off() {
+    let mut terminal = create_terminal();
+
+    // The unechoed newline leaves the cursor after the prompt, so the marker
+    // gets a line of its own.
+    terminal.write_text("stty -echo; read secret; stty echo; echo; echo done\n").unwrap();
+    wait_until(&mut terminal, |t| t.is_echo_disabled());
+
+    terminal.write_text("hunter2\n").unwrap();
+    wait_until(&mut terminal, |t| t.visible_text().lines().any(|line| line.trim_end() == "done"));
+
+    assert!(!terminal.visible_text().contains("hunter2"));
+}
+
+#[test]
+fn run_command_captures_output_and_exit_code() {
+    let mut terminal = create_terminal();
+
+    let output = terminal.run_command("echo hello; false", &RunOptions::default()).unwrap();
+    assert_eq!("hello\n", output.output);
+    assert_eq!(1, output.exit_code);
+}